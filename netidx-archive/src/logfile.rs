@@ -1941,4 +1941,34 @@ mod test {
             fs::remove_file(file).unwrap();
         }
     }
+
+    #[test]
+    fn map_values() {
+        let file = FilePath::new("test-data-map");
+        let path = Path::from("/foo/map");
+        if FilePath::is_file(&file) {
+            fs::remove_file(file).unwrap();
+        }
+        let v: Value = r#"{"bid": 42.5, "qty": [1, 2], "sym": "abc"}"#.parse().unwrap();
+        {
+            let mut t = ArchiveWriter::open(&file).unwrap();
+            t.add_paths([&path]).unwrap();
+            let mut batch = BATCH_POOL.take();
+            let id = t.id_for_path(&path).unwrap();
+            batch.push(BatchItem(id, Event::Update(v.clone())));
+            t.add_batch(false, Utc::now(), &batch).unwrap();
+            t.flush().unwrap();
+        }
+        {
+            let t = ArchiveReader::open(&file).unwrap();
+            let mut cursor = Cursor::new();
+            let (_, mut batch) = t.read_deltas(None, &mut cursor, 1).unwrap();
+            let (_, b) = batch.pop_front().unwrap();
+            assert_eq!(Vec::len(&b), 1);
+            assert_eq!(b[0].1, Event::Update(v));
+        }
+        if FilePath::is_file(&file) {
+            fs::remove_file(file).unwrap();
+        }
+    }
 }
@@ -38,7 +38,7 @@ use tokio::{
 struct CTS(BTreeMap<Path, ChangeTracker>);
 
 impl CTS {
-    fn new(globs: &Vec<Glob>) -> CTS {
+    fn new<'a>(globs: impl IntoIterator<Item = &'a Glob>) -> CTS {
         let mut btm = BTreeMap::new();
         for glob in globs {
             let base = glob.base();
@@ -90,7 +90,7 @@ async fn list_task(
     spec: GlobSet,
 ) -> Result<()> {
     use rand::{thread_rng, Rng};
-    let mut cts = CTS::new(&spec);
+    let mut cts = CTS::new(spec.included());
     let max_jitter = interval.as_secs_f64() * 0.1;
     while let Some(reply) = rx.next().await {
         let wait = thread_rng().gen_range(0. ..max_jitter);
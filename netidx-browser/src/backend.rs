@@ -122,6 +122,44 @@ struct CtxInner {
     refreshing: bool,
 }
 
+// Send the table to the gui a page of rows at a time, so huge tables
+// are neither sent by the resolver in one message nor buffered here.
+// The first page is sent as the table, the rest are appended to it.
+// Resolvers that don't support paging get the whole table at once.
+async fn table_paged(
+    resolver: &ResolverRead,
+    to_gui: &glib::Sender<ToGui>,
+    path: Path,
+) -> Result<()> {
+    const PAGE_SIZE: u32 = 10_000;
+    let paged = match resolver.columns(path.clone()).await {
+        Err(_) => None,
+        Ok(cols) => {
+            let pages = resolver.list_stream(path.clone(), PAGE_SIZE);
+            let mut pages = Box::pin(pages);
+            match pages.next().await {
+                None => Some((cols, Pooled::orphan(vec![]), pages)),
+                Some(Err(_)) => None,
+                Some(Ok(rows)) => Some((cols, rows, pages)),
+            }
+        }
+    };
+    match paged {
+        None => {
+            let table = resolver.table(path.clone()).await?;
+            to_gui.send(ToGui::TableResolved(path, table))?;
+        }
+        Some((cols, rows, mut pages)) => {
+            let table = resolver::Table { rows, cols };
+            to_gui.send(ToGui::TableResolved(path.clone(), table))?;
+            while let Some(rows) = pages.next().await.transpose()? {
+                to_gui.send(ToGui::TableRows(path.clone(), rows))?;
+            }
+        }
+    }
+    Ok(())
+}
+
 impl CtxInner {
     fn new(
         subscriber: Subscriber,
@@ -158,6 +196,7 @@ impl CtxInner {
             generated: true,
         };
         self.to_gui.send(m)?;
+        self.resolve_metadata(base_path.clone());
         if !self.raw_view.load(Ordering::Relaxed) {
             let s = self.subscriber.subscribe(base_path.append(".view"));
             let (tx, rx) = mpsc::channel(2);
@@ -199,17 +238,28 @@ impl CtxInner {
         let resolver = self.resolver.clone();
         let to_gui = self.to_gui.clone();
         task::spawn(async move {
-            let table = match resolver.table(path.clone()).await {
-                Ok(table) => table,
-                Err(e) => {
-                    warn!("failed to resolve table {},  {}", path, e);
-                    resolver::Table {
-                        rows: Pooled::orphan(vec![]),
-                        cols: Pooled::orphan(vec![]),
-                    }
+            if let Err(e) = table_paged(&resolver, &to_gui, path.clone()).await {
+                warn!("failed to resolve table {},  {}", path, e);
+                let table = resolver::Table {
+                    rows: Pooled::orphan(vec![]),
+                    cols: Pooled::orphan(vec![]),
+                };
+                let _: result::Result<_, _> =
+                    to_gui.send(ToGui::TableResolved(path, table));
+            }
+        });
+    }
+
+    fn resolve_metadata(&self, path: Path) {
+        let resolver = self.resolver.clone();
+        let to_gui = self.to_gui.clone();
+        task::spawn(async move {
+            match resolver.metadata(path.clone()).await {
+                Err(e) => warn!("failed to resolve metadata {}, {}", path, e),
+                Ok(md) => {
+                    let _: result::Result<_, _> = to_gui.send(ToGui::Metadata(path, md));
                 }
-            };
-            let _: result::Result<_, _> = to_gui.send(ToGui::TableResolved(path, table));
+            }
         });
     }
 
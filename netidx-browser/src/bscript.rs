@@ -1,6 +1,8 @@
 use super::{util::ask_modal, ToGui, ViewLoc, WidgetCtx};
 use glib::thread_guard::ThreadGuard;
-use netidx::{chars::Chars, path::Path, resolver_client, subscriber::Value};
+use netidx::{
+    chars::Chars, path::Path, pool::Pooled, resolver_client, subscriber::Value,
+};
 use netidx_bscript::vm::{self, Apply, Ctx, ExecCtx, InitFn, Node, Register};
 use parking_lot::Mutex;
 use std::{cell::RefCell, mem, rc::Rc, result::Result, sync::Arc};
@@ -9,6 +11,8 @@ use std::{cell::RefCell, mem, rc::Rc, result::Result, sync::Arc};
 pub(crate) enum LocalEvent {
     Event(Value),
     TableResolved(Path, Rc<resolver_client::Table>),
+    // more rows of a table that was resolved a page at a time
+    TableRows(Path, Rc<Pooled<Vec<Path>>>),
     Poll(Path),
 }
 
@@ -49,6 +53,7 @@ impl Apply<WidgetCtx, LocalEvent> for Event {
             | vm::Event::Rpc(_, _)
             | vm::Event::Timer(_)
             | vm::Event::User(LocalEvent::TableResolved(_, _))
+            | vm::Event::User(LocalEvent::TableRows(_, _))
             | vm::Event::User(LocalEvent::Poll(_)) => None,
             vm::Event::User(LocalEvent::Event(value)) => {
                 self.cur = Some(value.clone());
@@ -369,6 +374,7 @@ impl Apply<WidgetCtx, LocalEvent> for Poll {
                     vm::Event::User(LocalEvent::Poll(_))
                     | vm::Event::User(LocalEvent::Event(_))
                     | vm::Event::User(LocalEvent::TableResolved(_, _))
+                    | vm::Event::User(LocalEvent::TableRows(_, _))
                     | vm::Event::Variable(_, _, _)
                     | vm::Event::Netidx(_, _)
                     | vm::Event::Rpc(_, _)
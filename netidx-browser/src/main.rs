@@ -153,6 +153,8 @@ enum ToGui {
     UpdateTimer(TimerId),
     UpdatePoll(Path),
     TableResolved(Path, resolver_client::Table),
+    TableRows(Path, Pooled<Vec<Path>>),
+    Metadata(Path, resolver_client::Metadata),
     ShowError(String),
     SaveError(String),
     Terminate,
@@ -257,6 +259,20 @@ enum ImageSpec {
 }
 
 impl ImageSpec {
+    fn from_alist(mut alist: HashMap<Chars, Value>) -> Result<Self> {
+        let bytes = alist
+            .remove("image")
+            .ok_or_else(|| anyhow!("missing bytes"))?
+            .cast_to::<Bytes>()?;
+        let width = alist.remove("width").and_then(|v| v.cast_to::<u32>().ok());
+        let height = alist.remove("height").and_then(|v| v.cast_to::<u32>().ok());
+        let keep_aspect = alist
+            .remove("keep-aspect")
+            .and_then(|v| v.cast_to::<bool>().ok())
+            .unwrap_or(true);
+        Ok(Self::PixBuf { bytes, width, height, keep_aspect })
+    }
+
     fn get_pixbuf(&self) -> Option<gdk_pixbuf::Pixbuf> {
         match self {
             Self::Icon { .. } => None,
@@ -313,25 +329,10 @@ impl FromValue for ImageSpec {
                     };
                     Ok(Self::Icon { name: name.clone(), size })
                 }
-                _ => {
-                    let mut alist =
-                        Value::Array(elts).cast_to::<HashMap<Chars, Value>>()?;
-                    let bytes = alist
-                        .remove("image")
-                        .ok_or_else(|| anyhow!("missing bytes"))?
-                        .cast_to::<Bytes>()?;
-                    let width =
-                        alist.remove("width").and_then(|v| v.cast_to::<u32>().ok());
-                    let height =
-                        alist.remove("height").and_then(|v| v.cast_to::<u32>().ok());
-                    let keep_aspect = alist
-                        .remove("keep-aspect")
-                        .and_then(|v| v.cast_to::<bool>().ok())
-                        .unwrap_or(true);
-                    Ok(Self::PixBuf { bytes, width, height, keep_aspect })
-                }
+                _ => Self::from_alist(Value::Array(elts).cast_to()?),
             },
-            _ => bail!("expected bytes or array"),
+            v @ Value::Map(_) => Self::from_alist(v.cast_to()?),
+            _ => bail!("expected bytes, array, or map"),
         }
     }
 
@@ -1099,6 +1100,23 @@ fn run_gui(ctx: BSCtx, app: Application, to_gui: glib::Receiver<ToGui>) {
             update_single(&current, &mut ctx.borrow_mut(), &e);
             Continue(true)
         }
+        ToGui::TableRows(path, rows) => {
+            let e = vm::Event::User(LocalEvent::TableRows(path, Rc::new(rows)));
+            update_single(&current, &mut ctx.borrow_mut(), &e);
+            Continue(true)
+        }
+        ToGui::Metadata(path, md) => {
+            if *current_loc.borrow() == ViewLoc::Netidx(path) {
+                let text = md
+                    .iter()
+                    .map(|(k, v)| format!("{}: {}", k, v))
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                let text = if text.is_empty() { None } else { Some(text.as_str()) };
+                ctx.borrow().user.window.set_tooltip_text(text);
+            }
+            Continue(true)
+        }
         ToGui::Navigate(loc) => {
             let (saved, window) = {
                 let ctx = ctx.borrow();
@@ -1148,6 +1166,7 @@ fn run_gui(ctx: BSCtx, app: Application, to_gui: glib::Receiver<ToGui>) {
             let cur = View::new(&ctx, &*current_loc.borrow(), spec);
             let window = ctx.borrow().user.window.clone();
             window.set_title(&format!("Netidx Browser {}", &*current_loc.borrow()));
+            window.set_tooltip_text(None);
             window.add(cur.root());
             window.show_all();
             let hl = highlight.borrow();
@@ -154,6 +154,34 @@ impl Table {
         }
     }
 
+    // append a page of rows to the table, pages arrive in sorted order
+    // so rows that aren't after the last one are from a stale or
+    // duplicate resolve of the same path.
+    fn append_rows(&self, path: &Path, rows: &[Path]) {
+        let current = match &*self.state.borrow() {
+            TableState::Raeified(table) => &table.path == path,
+            TableState::Refresh(rpath) => rpath == path,
+            TableState::Resolving(_) => false,
+        };
+        if current {
+            let appended = {
+                let mut descriptor = self.shared.original_descriptor.borrow_mut();
+                let descriptor = Rc::make_mut(&mut *descriptor);
+                let len = descriptor.rows.len();
+                for row in rows {
+                    match descriptor.rows.last() {
+                        Some(last) if row <= last => (),
+                        Some(_) | None => descriptor.rows.push(row.clone()),
+                    }
+                }
+                descriptor.rows.len() > len
+            };
+            if appended {
+                self.raeify()
+            }
+        }
+    }
+
     fn raeify(&self) {
         let state = &self.state;
         let visible = &self.visible;
@@ -206,6 +234,9 @@ impl BWidget for Table {
         if re || force_refresh {
             self.refresh(ctx, force_refresh);
         }
+        if let vm::Event::User(LocalEvent::TableRows(path, rows)) = event {
+            self.append_rows(path, rows);
+        }
         match &*self.state.borrow() {
             TableState::Raeified(table) => table.update(ctx, waits, event),
             TableState::Refresh(_) => self.raeify(),
@@ -215,6 +246,7 @@ impl BWidget for Table {
                 | vm::Event::Timer(_)
                 | vm::Event::Variable(_, _, _)
                 | vm::Event::User(LocalEvent::Event(_))
+                | vm::Event::User(LocalEvent::TableRows(_, _))
                 | vm::Event::User(LocalEvent::Poll(_)) => (),
                 vm::Event::User(LocalEvent::TableResolved(path, descriptor)) => {
                     if path == rpath {
@@ -83,7 +83,10 @@ impl FromValue for SortSpec {
                 let spec = a[1].clone().cast_to()?;
                 Ok(SortSpec::Column(column, spec))
             }
-            _ => anyhow::bail!("expected null, false, col, or a pair of [column, mode]"),
+            v @ Value::Map(_) => Ok(SortSpec::External(v.cast_to()?)),
+            _ => anyhow::bail!(
+                "expected null, false, col, a pair of [column, mode], or a map"
+            ),
         }
     }
 }
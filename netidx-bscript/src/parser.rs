@@ -206,6 +206,7 @@ pub fn parse_expr(s: &str) -> anyhow::Result<Expr> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::{collections::BTreeMap, sync::Arc};
 
     #[test]
     fn interp_parse() {
@@ -354,5 +355,15 @@ mod tests {
         let chs =
             r#"sum(f32:1., load("/foo/bar"), max(f32:675.6, load("/foo/baz")), rand())"#;
         assert_eq!(src, parse_expr(chs).unwrap());
+        let m = [(Chars::from("a"), Value::I64(1)), (Chars::from("b"), Value::True)];
+        let src = ExprKind::Apply {
+            args: vec![
+                ExprKind::Constant(Value::Map(Arc::new(BTreeMap::from(m)))).to_expr(),
+                ExprKind::Constant(Value::from("b")).to_expr(),
+            ],
+            function: "index".into(),
+        }
+        .to_expr();
+        assert_eq!(src, parse_expr(r#"index({"a": 1, "b": true}, "b")"#).unwrap());
     }
 }
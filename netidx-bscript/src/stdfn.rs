@@ -516,9 +516,13 @@ impl CachedCurEval for IndexEv {
                     Some(Value::Error(Chars::from("array index out of bounds")))
                 }
             }
+            [Some(Value::Map(m)), Some(Value::String(k))] => match m.get(k) {
+                Some(v) => Some(v.clone()),
+                None => Some(Value::Error(Chars::from("no such key in map"))),
+            },
             [None, _] | [_, None] => None,
             _ => Some(Value::Error(Chars::from(
-                "index(array, index): expected an array and a positive index",
+                "index(v, i): expected an array and a positive index, or a map and a key",
             ))),
         }
     }
@@ -976,6 +980,8 @@ impl CachedCurEval for IsaEv {
             (Typ::Result, Some(_)) => Some(Value::False),
             (Typ::Array, Some(Value::Array(_))) => Some(Value::True),
             (Typ::Array, Some(_)) => Some(Value::False),
+            (Typ::Map, Some(Value::Map(_))) => Some(Value::True),
+            (Typ::Map, Some(_)) => Some(Value::False),
             (Typ::DateTime, Some(Value::DateTime(_))) => Some(Value::True),
             (Typ::DateTime, Some(_)) => Some(Value::False),
             (Typ::Duration, Some(Value::Duration(_))) => Some(Value::True),
@@ -0,0 +1,280 @@
+#ifndef NETIDX_H
+#define NETIDX_H
+
+/* generated by cbindgen from the netidx-capi sources, do not edit */
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+/**
+ * The type of a value
+ */
+typedef enum NetidxType {
+  NETIDX_TYPE_U32,
+  NETIDX_TYPE_V32,
+  NETIDX_TYPE_I32,
+  NETIDX_TYPE_Z32,
+  NETIDX_TYPE_U64,
+  NETIDX_TYPE_V64,
+  NETIDX_TYPE_I64,
+  NETIDX_TYPE_Z64,
+  NETIDX_TYPE_F32,
+  NETIDX_TYPE_F64,
+  NETIDX_TYPE_DECIMAL,
+  NETIDX_TYPE_DATE_TIME,
+  NETIDX_TYPE_DURATION,
+  NETIDX_TYPE_BOOL,
+  NETIDX_TYPE_STRING,
+  NETIDX_TYPE_BYTES,
+  NETIDX_TYPE_RESULT,
+  NETIDX_TYPE_ARRAY,
+  NETIDX_TYPE_MAP,
+  NETIDX_TYPE_NULL,
+} NetidxType;
+
+/**
+ * A batch of updates
+ */
+typedef struct NetidxBatch NetidxBatch;
+
+/**
+ * A netidx client configuration
+ */
+typedef struct NetidxConfig NetidxConfig;
+
+/**
+ * A durable subscription. Freeing it unsubscribes.
+ */
+typedef struct NetidxDval NetidxDval;
+
+/**
+ * A publisher
+ */
+typedef struct NetidxPublisher NetidxPublisher;
+
+/**
+ * A subscriber
+ */
+typedef struct NetidxSubscriber NetidxSubscriber;
+
+/**
+ * A published value. Freeing it unpublishes the value.
+ */
+typedef struct NetidxVal NetidxVal;
+
+/**
+ * A netidx value
+ */
+typedef struct NetidxValue NetidxValue;
+
+/**
+ * A callback that receives a value. The first argument is the user
+ * data pointer passed when the callback was registered.
+ */
+typedef void (*NetidxValueCallback)(void*, const struct NetidxValue*);
+
+/**
+ * Return the message of the last error that occurred on the calling
+ * thread, or `NULL` if there hasn't been one. The string is valid
+ * until the next error on the same thread.
+ */
+const char *netidx_last_error(void);
+
+/**
+ * Free a string returned by the library
+ */
+void netidx_string_free(char *s);
+
+/**
+ * Load the client config from the file at `path`
+ */
+struct NetidxConfig *netidx_config_load(const char *path);
+
+/**
+ * Load the client config from the default location
+ */
+struct NetidxConfig *netidx_config_load_default(void);
+
+void netidx_config_free(struct NetidxConfig *cfg);
+
+/**
+ * Create a new publisher using the default auth mechanism from
+ * `cfg`. If `bind` is `NULL` the default bind config will be used,
+ * otherwise it is parsed as a bind config, e.g. "127.0.0.1/32".
+ */
+struct NetidxPublisher *netidx_publisher_new(const struct NetidxConfig *cfg, const char *bind);
+
+/**
+ * Free the publisher. Values it published stay published until
+ * they are freed.
+ */
+void netidx_publisher_free(struct NetidxPublisher *publisher);
+
+/**
+ * Publish `init` at `path`. `init` is not consumed.
+ */
+struct NetidxVal *netidx_publisher_publish(const struct NetidxPublisher *publisher,
+                                           const char *path,
+                                           const struct NetidxValue *init);
+
+/**
+ * Update a single value and wait for the update to be sent. `v` is
+ * not consumed.
+ */
+bool netidx_publisher_update(const struct NetidxPublisher *publisher,
+                             const struct NetidxVal *val,
+                             const struct NetidxValue *v);
+
+/**
+ * Start a new batch of updates. The batch is consumed by
+ * `netidx_publisher_commit`, or may be freed with
+ * `netidx_batch_free` to discard it.
+ */
+struct NetidxBatch *netidx_publisher_start_batch(const struct NetidxPublisher *publisher);
+
+/**
+ * Queue an update to `val` in `batch`. `v` is not consumed.
+ */
+bool netidx_batch_update(struct NetidxBatch *batch,
+                         const struct NetidxVal *val,
+                         const struct NetidxValue *v);
+
+void netidx_batch_free(struct NetidxBatch *batch);
+
+/**
+ * Send the updates in `batch`, consuming it. If `timeout_ms` is
+ * non negative then slow subscribers that don't accept the updates
+ * within it are disconnected.
+ */
+bool netidx_publisher_commit(const struct NetidxPublisher *publisher,
+                             struct NetidxBatch *batch,
+                             int64_t timeout_ms);
+
+/**
+ * Wait until all committed updates have been flushed to the os
+ */
+bool netidx_publisher_flushed(const struct NetidxPublisher *publisher);
+
+/**
+ * Call `cb` with each value written to `val`, from a background
+ * thread. The value passed to `cb` is only valid for the duration
+ * of the call. Registering a new callback replaces the previous one.
+ */
+bool netidx_publisher_on_write(const struct NetidxPublisher *publisher,
+                               struct NetidxVal *val,
+                               NetidxValueCallback cb,
+                               void *user);
+
+/**
+ * Unpublish the value and free it
+ */
+void netidx_val_free(struct NetidxVal *val);
+
+/**
+ * Create a new subscriber using the default auth mechanism from
+ * `cfg`.
+ */
+struct NetidxSubscriber *netidx_subscriber_new(const struct NetidxConfig *cfg);
+
+void netidx_subscriber_free(struct NetidxSubscriber *subscriber);
+
+/**
+ * Subscribe to `path`. If `timeout_ms` is negative return
+ * immediately without waiting for the subscription to succeed,
+ * otherwise wait at most `timeout_ms` for it, and fail if it
+ * doesn't. Either way the subscription is durable, and will be
+ * retried if it fails or is lost.
+ */
+struct NetidxDval *netidx_subscriber_subscribe(const struct NetidxSubscriber *subscriber,
+                                               const char *path,
+                                               int64_t timeout_ms);
+
+/**
+ * Return the last value received, or `NULL` if the subscription is
+ * not currently alive.
+ */
+struct NetidxValue *netidx_dval_last(const struct NetidxDval *dval);
+
+/**
+ * Write `v` to the publisher of `dval`. `v` is not consumed. If
+ * the subscription is not alive the write is queued until it is.
+ */
+bool netidx_dval_write(const struct NetidxDval *dval, const struct NetidxValue *v);
+
+/**
+ * Call `cb` with every update to `dval`, from a background thread,
+ * starting with the current value if it is subscribed. `cb` is
+ * called with `NULL` when the subscription dies. The value passed
+ * to `cb` is only valid for the duration of the call. Registering a
+ * new callback replaces the previous one.
+ */
+bool netidx_dval_on_update(struct NetidxDval *dval, NetidxValueCallback cb, void *user);
+
+/**
+ * Unsubscribe and free the subscription
+ */
+void netidx_dval_free(struct NetidxDval *dval);
+
+struct NetidxValue *netidx_value_null(void);
+
+struct NetidxValue *netidx_value_bool(bool b);
+
+struct NetidxValue *netidx_value_i64(int64_t i);
+
+struct NetidxValue *netidx_value_u64(uint64_t i);
+
+struct NetidxValue *netidx_value_f64(double f);
+
+/**
+ * Create a string value from a nul terminated utf8 string. The
+ * string is copied.
+ */
+struct NetidxValue *netidx_value_string(const char *s);
+
+/**
+ * Parse a value written in the netidx value syntax, e.g. `42`,
+ * `"hello"`, or `[1, 2, 3]`.
+ */
+struct NetidxValue *netidx_value_parse(const char *s);
+
+struct NetidxValue *netidx_value_clone(const struct NetidxValue *v);
+
+void netidx_value_free(struct NetidxValue *v);
+
+enum NetidxType netidx_value_type(const struct NetidxValue *v);
+
+/**
+ * Cast the value to a bool and store it in `out`
+ */
+bool netidx_value_get_bool(const struct NetidxValue *v, bool *out);
+
+/**
+ * Cast the value to an i64 and store it in `out`
+ */
+bool netidx_value_get_i64(const struct NetidxValue *v, int64_t *out);
+
+/**
+ * Cast the value to a u64 and store it in `out`
+ */
+bool netidx_value_get_u64(const struct NetidxValue *v, uint64_t *out);
+
+/**
+ * Cast the value to an f64 and store it in `out`
+ */
+bool netidx_value_get_f64(const struct NetidxValue *v, double *out);
+
+/**
+ * If the value is a string, store a pointer to it's utf8 bytes in
+ * `s` and it's length in `len`. The string is not nul terminated,
+ * and is valid as long as `v` is.
+ */
+bool netidx_value_get_string(const struct NetidxValue *v, const char **s, uintptr_t *len);
+
+/**
+ * Format the value in the netidx value syntax. The returned string
+ * must be freed with `netidx_string_free`.
+ */
+char *netidx_value_to_string(const struct NetidxValue *v);
+
+#endif /* NETIDX_H */
@@ -0,0 +1,199 @@
+//! C bindings for netidx.
+//!
+//! All objects are opaque handles, created by a `netidx_*_new` (or
+//! similar) function and destroyed by the matching `netidx_*_free`
+//! function. A function that returns a handle gives ownership of it
+//! to the caller, functions that take a `const` handle do not take
+//! ownership.
+//!
+//! Functions that can fail return `NULL` or `false` and set a per
+//! thread error message that can be read with `netidx_last_error`.
+//!
+//! Callbacks are called from a background thread owned by the
+//! library, there is one such thread per publisher and per
+//! subscriber, so a slow callback delays the others registered on
+//! the same one. Callbacks must not call the free function of the
+//! handle they were registered on.
+//!
+//! The header `include/netidx.h` is generated from this crate by
+//! cbindgen, run `cbindgen --config cbindgen.toml --output
+//! include/netidx.h` in the crate directory after changing the API.
+use anyhow::{anyhow, Result};
+use futures::{channel::mpsc, executor, prelude::*};
+use fxhash::FxHashMap;
+use netidx::{config::Config, subscriber::Value};
+use parking_lot::Mutex;
+use std::{
+    cell::RefCell,
+    ffi::{c_char, c_void, CStr, CString},
+    fmt,
+    hash::Hash,
+    ptr,
+    sync::Arc,
+    thread,
+};
+
+pub mod publisher;
+pub mod subscriber;
+pub mod value;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = RefCell::new(None);
+}
+
+fn set_error(e: impl fmt::Display) {
+    let msg = CString::new(e.to_string().replace('\0', ""))
+        .unwrap_or_else(|_| CString::new("unknown error").unwrap());
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(msg))
+}
+
+// convert the result to an owned pointer, or NULL and set the error
+fn ret<T>(r: Result<T>) -> *mut T {
+    match r {
+        Ok(t) => Box::into_raw(Box::new(t)),
+        Err(e) => {
+            set_error(e);
+            ptr::null_mut()
+        }
+    }
+}
+
+// convert the result to a bool, and set the error if it's false
+fn ret_bool(r: Result<()>) -> bool {
+    match r {
+        Ok(()) => true,
+        Err(e) => {
+            set_error(e);
+            false
+        }
+    }
+}
+
+unsafe fn str_arg<'a>(name: &str, s: *const c_char) -> Result<&'a str> {
+    if s.is_null() {
+        return Err(anyhow!("{} must not be NULL", name));
+    }
+    Ok(CStr::from_ptr(s).to_str()?)
+}
+
+unsafe fn ref_arg<'a, T>(name: &str, t: *const T) -> Result<&'a T> {
+    t.as_ref().ok_or_else(|| anyhow!("{} must not be NULL", name))
+}
+
+unsafe fn free<T>(t: *mut T) {
+    if !t.is_null() {
+        drop(Box::from_raw(t))
+    }
+}
+
+/// A callback that receives a value. The first argument is the user
+/// data pointer passed when the callback was registered.
+pub type NetidxValueCallback = extern "C" fn(*mut c_void, *const value::NetidxValue);
+
+// user data passed to a callback on another thread
+struct UserData(*mut c_void);
+
+unsafe impl Send for UserData {}
+
+type Callback = Arc<Mutex<Option<(NetidxValueCallback, UserData)>>>;
+
+// The callbacks registered on the values of one publisher or
+// subscriber, by the key of the value.
+struct Callbacks<K>(Arc<Mutex<FxHashMap<K, Callback>>>);
+
+impl<K> Clone for Callbacks<K> {
+    fn clone(&self) -> Self {
+        Callbacks(self.0.clone())
+    }
+}
+
+impl<K: Hash + Eq> Callbacks<K> {
+    fn new() -> Self {
+        Callbacks(Arc::new(Mutex::new(FxHashMap::default())))
+    }
+
+    // replace the callback of k, if the old one is running wait for
+    // it to return
+    fn register(&self, k: K, cb: NetidxValueCallback, user: *mut c_void) {
+        let cb = Arc::new(Mutex::new(Some((cb, UserData(user)))));
+        if let Some(old) = self.0.lock().insert(k, cb) {
+            *old.lock() = None;
+        }
+    }
+
+    // remove the callback of k, if it is running wait for it to
+    // return
+    fn unregister(&self, k: &K) {
+        if let Some(old) = self.0.lock().remove(k) {
+            *old.lock() = None;
+        }
+    }
+
+    // call the callback of k with v, or with NULL if v is None
+    fn call(&self, k: &K, v: Option<Value>) {
+        let cb = self.0.lock().get(k).cloned();
+        if let Some(cb) = cb {
+            if let Some((cb, user)) = &*cb.lock() {
+                match v {
+                    None => cb(user.0, ptr::null()),
+                    Some(v) => cb(user.0, &value::NetidxValue(v)),
+                }
+            }
+        }
+    }
+}
+
+// start the thread that calls the callbacks of a publisher or
+// subscriber, it runs `f` on each batch received until every sender
+// is dropped
+fn dispatcher<T, F>(name: &str, mut rx: mpsc::Receiver<T>, mut f: F) -> Result<()>
+where
+    T: Send + 'static,
+    F: FnMut(T) + Send + 'static,
+{
+    thread::Builder::new().name(name.into()).spawn(move || {
+        while let Some(batch) = executor::block_on(rx.next()) {
+            f(batch)
+        }
+    })?;
+    Ok(())
+}
+
+/// Return the message of the last error that occurred on the calling
+/// thread, or `NULL` if there hasn't been one. The string is valid
+/// until the next error on the same thread.
+#[no_mangle]
+pub extern "C" fn netidx_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| match &*last.borrow() {
+        Some(msg) => msg.as_ptr(),
+        None => ptr::null(),
+    })
+}
+
+/// Free a string returned by the library
+#[no_mangle]
+pub unsafe extern "C" fn netidx_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s))
+    }
+}
+
+/// A netidx client configuration
+pub struct NetidxConfig(pub(crate) Config);
+
+/// Load the client config from the file at `path`
+#[no_mangle]
+pub unsafe extern "C" fn netidx_config_load(path: *const c_char) -> *mut NetidxConfig {
+    ret(str_arg("path", path).and_then(|path| Ok(NetidxConfig(Config::load(path)?))))
+}
+
+/// Load the client config from the default location
+#[no_mangle]
+pub extern "C" fn netidx_config_load_default() -> *mut NetidxConfig {
+    ret(Config::load_default().map(NetidxConfig))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn netidx_config_free(cfg: *mut NetidxConfig) {
+    free(cfg)
+}
@@ -0,0 +1,208 @@
+//! Publishing values
+use crate::{
+    dispatcher, free, ref_arg, ret, ret_bool, str_arg, value::NetidxValue, Callbacks,
+    NetidxConfig, NetidxValueCallback,
+};
+use anyhow::anyhow;
+use futures::channel::mpsc;
+use netidx::{
+    blocking::Publisher,
+    path::Path,
+    pool::Pooled,
+    publisher::{BindCfg, Id, UpdateBatch, Val, WriteRequest},
+};
+use std::{
+    ffi::{c_char, c_void},
+    time::Duration,
+};
+
+/// A publisher
+pub struct NetidxPublisher {
+    publisher: Publisher,
+    // writes to every value with an on_write callback
+    writes: mpsc::Sender<Pooled<Vec<WriteRequest>>>,
+    on_write: Callbacks<Id>,
+}
+
+/// A published value. Freeing it unpublishes the value.
+pub struct NetidxVal {
+    val: Val,
+    // keeps the value published if the publisher is freed first. It
+    // is dropped after val, and the last publisher is dropped inside
+    // the runtime.
+    _publisher: Publisher,
+    on_write: Callbacks<Id>,
+}
+
+impl Drop for NetidxVal {
+    fn drop(&mut self) {
+        self.on_write.unregister(&self.val.id())
+    }
+}
+
+/// A batch of updates
+pub struct NetidxBatch(UpdateBatch);
+
+/// Create a new publisher using the default auth mechanism from
+/// `cfg`. If `bind` is `NULL` the default bind config will be used,
+/// otherwise it is parsed as a bind config, e.g. "127.0.0.1/32".
+#[no_mangle]
+pub unsafe extern "C" fn netidx_publisher_new(
+    cfg: *const NetidxConfig,
+    bind: *const c_char,
+) -> *mut NetidxPublisher {
+    ret((|| {
+        let cfg = &ref_arg("cfg", cfg)?.0;
+        let bind = if bind.is_null() {
+            None
+        } else {
+            Some(str_arg("bind", bind)?.parse::<BindCfg>()?)
+        };
+        let auth = cfg.default_auth();
+        let publisher = Publisher::new(cfg.clone(), auth, bind)?;
+        let (writes, rx) = mpsc::channel(3);
+        let on_write = Callbacks::new();
+        dispatcher("netidx-on-write", rx, {
+            let on_write = on_write.clone();
+            move |mut batch: Pooled<Vec<WriteRequest>>| {
+                for req in batch.drain(..) {
+                    on_write.call(&req.id, Some(req.value))
+                }
+            }
+        })?;
+        Ok(NetidxPublisher { publisher, writes, on_write })
+    })())
+}
+
+/// Free the publisher. Values it published stay published until
+/// they are freed.
+#[no_mangle]
+pub unsafe extern "C" fn netidx_publisher_free(publisher: *mut NetidxPublisher) {
+    free(publisher)
+}
+
+/// Publish `init` at `path`. `init` is not consumed.
+#[no_mangle]
+pub unsafe extern "C" fn netidx_publisher_publish(
+    publisher: *const NetidxPublisher,
+    path: *const c_char,
+    init: *const NetidxValue,
+) -> *mut NetidxVal {
+    ret((|| {
+        let publisher = ref_arg("publisher", publisher)?;
+        let path = Path::from(String::from(str_arg("path", path)?));
+        let init = ref_arg("init", init)?.0.clone();
+        Ok(NetidxVal {
+            val: publisher.publisher.publish(path, init)?,
+            _publisher: publisher.publisher.clone(),
+            on_write: publisher.on_write.clone(),
+        })
+    })())
+}
+
+/// Update a single value and wait for the update to be sent. `v` is
+/// not consumed.
+#[no_mangle]
+pub unsafe extern "C" fn netidx_publisher_update(
+    publisher: *const NetidxPublisher,
+    val: *const NetidxVal,
+    v: *const NetidxValue,
+) -> bool {
+    ret_bool((|| {
+        let publisher = &ref_arg("publisher", publisher)?.publisher;
+        let val = &ref_arg("val", val)?.val;
+        publisher.update(val, ref_arg("v", v)?.0.clone());
+        Ok(())
+    })())
+}
+
+/// Start a new batch of updates. The batch is consumed by
+/// `netidx_publisher_commit`, or may be freed with
+/// `netidx_batch_free` to discard it.
+#[no_mangle]
+pub unsafe extern "C" fn netidx_publisher_start_batch(
+    publisher: *const NetidxPublisher,
+) -> *mut NetidxBatch {
+    ret(ref_arg("publisher", publisher).map(|p| NetidxBatch(p.publisher.start_batch())))
+}
+
+/// Queue an update to `val` in `batch`. `v` is not consumed.
+#[no_mangle]
+pub unsafe extern "C" fn netidx_batch_update(
+    batch: *mut NetidxBatch,
+    val: *const NetidxVal,
+    v: *const NetidxValue,
+) -> bool {
+    ret_bool((|| {
+        let batch = batch.as_mut().ok_or_else(|| anyhow!("batch must not be NULL"))?;
+        let val = &ref_arg("val", val)?.val;
+        val.update(&mut batch.0, ref_arg("v", v)?.0.clone());
+        Ok(())
+    })())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn netidx_batch_free(batch: *mut NetidxBatch) {
+    free(batch)
+}
+
+/// Send the updates in `batch`, consuming it. If `timeout_ms` is
+/// non negative then slow subscribers that don't accept the updates
+/// within it are disconnected.
+#[no_mangle]
+pub unsafe extern "C" fn netidx_publisher_commit(
+    publisher: *const NetidxPublisher,
+    batch: *mut NetidxBatch,
+    timeout_ms: i64,
+) -> bool {
+    ret_bool((|| {
+        let publisher = &ref_arg("publisher", publisher)?.publisher;
+        if batch.is_null() {
+            return Err(anyhow!("batch must not be NULL"));
+        }
+        let batch = Box::from_raw(batch).0;
+        publisher.commit(batch, timeout(timeout_ms));
+        Ok(())
+    })())
+}
+
+/// Wait until all committed updates have been flushed to the os
+#[no_mangle]
+pub unsafe extern "C" fn netidx_publisher_flushed(
+    publisher: *const NetidxPublisher,
+) -> bool {
+    ret_bool(ref_arg("publisher", publisher).map(|p| p.publisher.flushed()))
+}
+
+/// Call `cb` with each value written to `val`, from a background
+/// thread. The value passed to `cb` is only valid for the duration
+/// of the call. Registering a new callback replaces the previous one.
+#[no_mangle]
+pub unsafe extern "C" fn netidx_publisher_on_write(
+    publisher: *const NetidxPublisher,
+    val: *mut NetidxVal,
+    cb: NetidxValueCallback,
+    user: *mut c_void,
+) -> bool {
+    ret_bool((|| {
+        let publisher = ref_arg("publisher", publisher)?;
+        let id = ref_arg("val", val)?.val.id();
+        publisher.on_write.register(id, cb, user);
+        publisher.publisher.inner().writes(id, publisher.writes.clone());
+        Ok(())
+    })())
+}
+
+/// Unpublish the value and free it
+#[no_mangle]
+pub unsafe extern "C" fn netidx_val_free(val: *mut NetidxVal) {
+    free(val)
+}
+
+pub(crate) fn timeout(ms: i64) -> Option<Duration> {
+    if ms < 0 {
+        None
+    } else {
+        Some(Duration::from_millis(ms as u64))
+    }
+}
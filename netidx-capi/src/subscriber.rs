@@ -0,0 +1,151 @@
+//! Subscribing to values
+use crate::{
+    dispatcher, free, publisher::timeout, ref_arg, ret, ret_bool, str_arg,
+    value::NetidxValue, Callbacks, NetidxConfig, NetidxValueCallback,
+};
+use futures::channel::mpsc;
+use netidx::{
+    blocking::{Dval, Subscriber},
+    path::Path,
+    pool::Pooled,
+    subscriber::{Event, SubId, UpdatesFlags},
+};
+use std::{
+    ffi::{c_char, c_void},
+    ptr,
+};
+
+type Updates = mpsc::Sender<Pooled<Vec<(SubId, Event)>>>;
+
+/// A subscriber
+pub struct NetidxSubscriber {
+    subscriber: Subscriber,
+    // updates to every subscription with an on_update callback
+    updates: Updates,
+    on_update: Callbacks<SubId>,
+}
+
+/// A durable subscription. Freeing it unsubscribes.
+pub struct NetidxDval {
+    dval: Dval,
+    updates: Updates,
+    on_update: Callbacks<SubId>,
+}
+
+impl Drop for NetidxDval {
+    fn drop(&mut self) {
+        self.on_update.unregister(&self.dval.id())
+    }
+}
+
+/// Create a new subscriber using the default auth mechanism from
+/// `cfg`.
+#[no_mangle]
+pub unsafe extern "C" fn netidx_subscriber_new(
+    cfg: *const NetidxConfig,
+) -> *mut NetidxSubscriber {
+    ret((|| {
+        let cfg = &ref_arg("cfg", cfg)?.0;
+        let auth = cfg.default_auth();
+        let subscriber = Subscriber::new(cfg.clone(), auth)?;
+        let (updates, rx) = mpsc::channel(3);
+        let on_update = Callbacks::new();
+        dispatcher("netidx-on-update", rx, {
+            let on_update = on_update.clone();
+            move |mut batch: Pooled<Vec<(SubId, Event)>>| {
+                for (id, ev) in batch.drain(..) {
+                    match ev {
+                        Event::Unsubscribed => on_update.call(&id, None),
+                        Event::Update(v) => on_update.call(&id, Some(v)),
+                    }
+                }
+            }
+        })?;
+        Ok(NetidxSubscriber { subscriber, updates, on_update })
+    })())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn netidx_subscriber_free(subscriber: *mut NetidxSubscriber) {
+    free(subscriber)
+}
+
+/// Subscribe to `path`. If `timeout_ms` is negative return
+/// immediately without waiting for the subscription to succeed,
+/// otherwise wait at most `timeout_ms` for it, and fail if it
+/// doesn't. Either way the subscription is durable, and will be
+/// retried if it fails or is lost.
+#[no_mangle]
+pub unsafe extern "C" fn netidx_subscriber_subscribe(
+    subscriber: *const NetidxSubscriber,
+    path: *const c_char,
+    timeout_ms: i64,
+) -> *mut NetidxDval {
+    ret((|| {
+        let subscriber = ref_arg("subscriber", subscriber)?;
+        let path = Path::from(String::from(str_arg("path", path)?));
+        let dval = if timeout_ms < 0 {
+            subscriber.subscriber.subscribe(path)
+        } else {
+            subscriber.subscriber.subscribe_one(path, timeout(timeout_ms))?
+        };
+        Ok(NetidxDval {
+            dval,
+            updates: subscriber.updates.clone(),
+            on_update: subscriber.on_update.clone(),
+        })
+    })())
+}
+
+/// Return the last value received, or `NULL` if the subscription is
+/// not currently alive.
+#[no_mangle]
+pub unsafe extern "C" fn netidx_dval_last(dval: *const NetidxDval) -> *mut NetidxValue {
+    match dval.as_ref() {
+        None => ptr::null_mut(),
+        Some(dv) => match dv.dval.last() {
+            Event::Unsubscribed => ptr::null_mut(),
+            Event::Update(v) => Box::into_raw(Box::new(NetidxValue(v))),
+        },
+    }
+}
+
+/// Write `v` to the publisher of `dval`. `v` is not consumed. If
+/// the subscription is not alive the write is queued until it is.
+#[no_mangle]
+pub unsafe extern "C" fn netidx_dval_write(
+    dval: *const NetidxDval,
+    v: *const NetidxValue,
+) -> bool {
+    ret_bool((|| {
+        let dval = &ref_arg("dval", dval)?.dval;
+        dval.write(ref_arg("v", v)?.0.clone());
+        Ok(())
+    })())
+}
+
+/// Call `cb` with every update to `dval`, from a background thread,
+/// starting with the current value if it is subscribed. `cb` is
+/// called with `NULL` when the subscription dies. The value passed
+/// to `cb` is only valid for the duration of the call. Registering a
+/// new callback replaces the previous one.
+#[no_mangle]
+pub unsafe extern "C" fn netidx_dval_on_update(
+    dval: *mut NetidxDval,
+    cb: NetidxValueCallback,
+    user: *mut c_void,
+) -> bool {
+    ret_bool((|| {
+        let dval = ref_arg("dval", dval)?;
+        dval.on_update.register(dval.dval.id(), cb, user);
+        let flags = UpdatesFlags::BEGIN_WITH_LAST;
+        dval.dval.inner().updates(flags, dval.updates.clone());
+        Ok(())
+    })())
+}
+
+/// Unsubscribe and free the subscription
+#[no_mangle]
+pub unsafe extern "C" fn netidx_dval_free(dval: *mut NetidxDval) {
+    free(dval)
+}
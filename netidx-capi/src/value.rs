@@ -0,0 +1,217 @@
+//! Values
+use crate::{free, ref_arg, ret, ret_bool, set_error, str_arg};
+use anyhow::bail;
+use netidx::subscriber::{FromValue, Typ, Value};
+use std::{
+    ffi::{c_char, CString},
+    ptr,
+};
+
+/// A netidx value
+pub struct NetidxValue(pub(crate) Value);
+
+/// The type of a value
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetidxType {
+    U32,
+    V32,
+    I32,
+    Z32,
+    U64,
+    V64,
+    I64,
+    Z64,
+    F32,
+    F64,
+    Decimal,
+    DateTime,
+    Duration,
+    Bool,
+    String,
+    Bytes,
+    Result,
+    Array,
+    Map,
+    Null,
+}
+
+impl From<Typ> for NetidxType {
+    fn from(typ: Typ) -> Self {
+        match typ {
+            Typ::U32 => NetidxType::U32,
+            Typ::V32 => NetidxType::V32,
+            Typ::I32 => NetidxType::I32,
+            Typ::Z32 => NetidxType::Z32,
+            Typ::U64 => NetidxType::U64,
+            Typ::V64 => NetidxType::V64,
+            Typ::I64 => NetidxType::I64,
+            Typ::Z64 => NetidxType::Z64,
+            Typ::F32 => NetidxType::F32,
+            Typ::F64 => NetidxType::F64,
+            Typ::Decimal => NetidxType::Decimal,
+            Typ::DateTime => NetidxType::DateTime,
+            Typ::Duration => NetidxType::Duration,
+            Typ::Bool => NetidxType::Bool,
+            Typ::String => NetidxType::String,
+            Typ::Bytes => NetidxType::Bytes,
+            Typ::Result => NetidxType::Result,
+            Typ::Array => NetidxType::Array,
+            Typ::Map => NetidxType::Map,
+            Typ::Null => NetidxType::Null,
+        }
+    }
+}
+
+fn new(v: Value) -> *mut NetidxValue {
+    Box::into_raw(Box::new(NetidxValue(v)))
+}
+
+#[no_mangle]
+pub extern "C" fn netidx_value_null() -> *mut NetidxValue {
+    new(Value::Null)
+}
+
+#[no_mangle]
+pub extern "C" fn netidx_value_bool(b: bool) -> *mut NetidxValue {
+    new(Value::from(b))
+}
+
+#[no_mangle]
+pub extern "C" fn netidx_value_i64(i: i64) -> *mut NetidxValue {
+    new(Value::I64(i))
+}
+
+#[no_mangle]
+pub extern "C" fn netidx_value_u64(i: u64) -> *mut NetidxValue {
+    new(Value::U64(i))
+}
+
+#[no_mangle]
+pub extern "C" fn netidx_value_f64(f: f64) -> *mut NetidxValue {
+    new(Value::F64(f))
+}
+
+/// Create a string value from a nul terminated utf8 string. The
+/// string is copied.
+#[no_mangle]
+pub unsafe extern "C" fn netidx_value_string(s: *const c_char) -> *mut NetidxValue {
+    ret(str_arg("s", s).map(|s| NetidxValue(Value::from(String::from(s)))))
+}
+
+/// Parse a value written in the netidx value syntax, e.g. `42`,
+/// `"hello"`, or `[1, 2, 3]`.
+#[no_mangle]
+pub unsafe extern "C" fn netidx_value_parse(s: *const c_char) -> *mut NetidxValue {
+    ret(str_arg("s", s).and_then(|s| Ok(NetidxValue(s.parse::<Value>()?))))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn netidx_value_clone(v: *const NetidxValue) -> *mut NetidxValue {
+    ret(ref_arg("v", v).map(|v| NetidxValue(v.0.clone())))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn netidx_value_free(v: *mut NetidxValue) {
+    free(v)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn netidx_value_type(v: *const NetidxValue) -> NetidxType {
+    match v.as_ref() {
+        Some(v) => Typ::get(&v.0).into(),
+        None => NetidxType::Null,
+    }
+}
+
+unsafe fn get<T: FromValue>(v: *const NetidxValue, out: *mut T) -> bool {
+    ret_bool((|| {
+        let v = ref_arg("v", v)?;
+        if out.is_null() {
+            bail!("out must not be NULL")
+        }
+        *out = v.0.clone().cast_to::<T>()?;
+        Ok(())
+    })())
+}
+
+/// Cast the value to a bool and store it in `out`
+#[no_mangle]
+pub unsafe extern "C" fn netidx_value_get_bool(
+    v: *const NetidxValue,
+    out: *mut bool,
+) -> bool {
+    get(v, out)
+}
+
+/// Cast the value to an i64 and store it in `out`
+#[no_mangle]
+pub unsafe extern "C" fn netidx_value_get_i64(
+    v: *const NetidxValue,
+    out: *mut i64,
+) -> bool {
+    get(v, out)
+}
+
+/// Cast the value to a u64 and store it in `out`
+#[no_mangle]
+pub unsafe extern "C" fn netidx_value_get_u64(
+    v: *const NetidxValue,
+    out: *mut u64,
+) -> bool {
+    get(v, out)
+}
+
+/// Cast the value to an f64 and store it in `out`
+#[no_mangle]
+pub unsafe extern "C" fn netidx_value_get_f64(
+    v: *const NetidxValue,
+    out: *mut f64,
+) -> bool {
+    get(v, out)
+}
+
+/// If the value is a string, store a pointer to it's utf8 bytes in
+/// `s` and it's length in `len`. The string is not nul terminated,
+/// and is valid as long as `v` is.
+#[no_mangle]
+pub unsafe extern "C" fn netidx_value_get_string(
+    v: *const NetidxValue,
+    s: *mut *const c_char,
+    len: *mut usize,
+) -> bool {
+    match (v.as_ref(), s.is_null() || len.is_null()) {
+        (Some(NetidxValue(Value::String(c))), false) => {
+            *s = c.as_ptr() as *const c_char;
+            *len = c.len();
+            true
+        }
+        (None, _) | (_, true) => {
+            set_error("v, s, and len must not be NULL");
+            false
+        }
+        (Some(_), false) => {
+            set_error("the value is not a string");
+            false
+        }
+    }
+}
+
+/// Format the value in the netidx value syntax. The returned string
+/// must be freed with `netidx_string_free`.
+#[no_mangle]
+pub unsafe extern "C" fn netidx_value_to_string(v: *const NetidxValue) -> *mut c_char {
+    match v.as_ref() {
+        None => {
+            set_error("v must not be NULL");
+            ptr::null_mut()
+        }
+        Some(v) => match CString::new(v.0.to_string()) {
+            Ok(s) => s.into_raw(),
+            Err(e) => {
+                set_error(e);
+                ptr::null_mut()
+            }
+        },
+    }
+}
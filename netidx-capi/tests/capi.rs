@@ -0,0 +1,47 @@
+//! Compile tests/capi_test.c against the library and run it against
+//! an in process resolver server.
+#![cfg(target_os = "linux")]
+use netidx::resolver_server::{config::Config as ServerConfig, Server};
+use std::{env, fs, path::PathBuf, process::Command};
+use tokio::runtime::Runtime;
+
+#[test]
+fn capi() {
+    let rt = Runtime::new().unwrap();
+    let server = rt.block_on(async {
+        let cfg = ServerConfig::load("../cfg/simple-server.json")
+            .expect("load simple server config");
+        Server::new(cfg, false, 0).await.expect("start server")
+    });
+    let tmp = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    let client_cfg = tmp.join("capi-client.json");
+    fs::write(
+        &client_cfg,
+        format!(
+            r#"{{"addrs": [["{}", "Anonymous"]], "base": "/", "default_auth": "Anonymous"}}"#,
+            server.local_addr()
+        ),
+    )
+    .unwrap();
+    let lib_dir =
+        env::current_exe().unwrap().parent().unwrap().parent().unwrap().to_owned();
+    let exe = tmp.join("capi_test");
+    let status = Command::new(env::var("CC").unwrap_or_else(|_| "cc".into()))
+        .args(["tests/capi_test.c", "-std=gnu11", "-Wall", "-Werror", "-I", "include"])
+        .arg("-L")
+        .arg(&lib_dir)
+        .args(["-lnetidx_capi", "-lpthread", "-o"])
+        .arg(&exe)
+        .status()
+        .expect("run the C compiler");
+    assert!(status.success(), "failed to compile capi_test.c");
+    let out = Command::new(&exe)
+        .arg(&client_cfg)
+        .env("LD_LIBRARY_PATH", &lib_dir)
+        .output()
+        .expect("run capi_test");
+    eprintln!("{}", String::from_utf8_lossy(&out.stderr));
+    assert!(out.status.success(), "capi_test failed");
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "ok\n");
+    drop(server);
+}
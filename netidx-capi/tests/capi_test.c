@@ -0,0 +1,140 @@
+/* Exercise the C api against a running resolver. The path of the
+   client config is the first argument. */
+#include <pthread.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <time.h>
+#include "netidx.h"
+
+#define CHECK(e)                                                        \
+    do {                                                                \
+        if (!(e)) {                                                     \
+            const char *err = netidx_last_error();                      \
+            fprintf(stderr, "%s:%d: check failed: %s: %s\n", __FILE__,  \
+                    __LINE__, #e, err ? err : "no error");              \
+            exit(1);                                                    \
+        }                                                               \
+    } while (0)
+
+struct state {
+    pthread_mutex_t lock;
+    pthread_cond_t cond;
+    int64_t last_update;
+    char *last_write;
+};
+
+static void on_update(void *user, const NetidxValue *v) {
+    struct state *st = user;
+    int64_t i;
+    if (v && netidx_value_get_i64(v, &i)) {
+        pthread_mutex_lock(&st->lock);
+        st->last_update = i;
+        pthread_cond_broadcast(&st->cond);
+        pthread_mutex_unlock(&st->lock);
+    }
+}
+
+static void on_write(void *user, const NetidxValue *v) {
+    struct state *st = user;
+    const char *s;
+    size_t len;
+    if (netidx_value_get_string(v, &s, &len)) {
+        pthread_mutex_lock(&st->lock);
+        free(st->last_write);
+        st->last_write = strndup(s, len);
+        pthread_cond_broadcast(&st->cond);
+        pthread_mutex_unlock(&st->lock);
+    }
+}
+
+static struct timespec deadline(void) {
+    struct timespec ts;
+    clock_gettime(CLOCK_REALTIME, &ts);
+    ts.tv_sec += 10;
+    return ts;
+}
+
+static void wait_update(struct state *st, int64_t i) {
+    struct timespec ts = deadline();
+    pthread_mutex_lock(&st->lock);
+    while (st->last_update != i)
+        CHECK(pthread_cond_timedwait(&st->cond, &st->lock, &ts) == 0);
+    pthread_mutex_unlock(&st->lock);
+}
+
+static void wait_write(struct state *st, const char *s) {
+    struct timespec ts = deadline();
+    pthread_mutex_lock(&st->lock);
+    while (!st->last_write || strcmp(st->last_write, s) != 0)
+        CHECK(pthread_cond_timedwait(&st->cond, &st->lock, &ts) == 0);
+    pthread_mutex_unlock(&st->lock);
+}
+
+int main(int argc, char **argv) {
+    struct state st;
+    int64_t i;
+
+    CHECK(argc == 2);
+    pthread_mutex_init(&st.lock, NULL);
+    pthread_cond_init(&st.cond, NULL);
+    st.last_update = -1;
+    st.last_write = NULL;
+
+    NetidxConfig *cfg = netidx_config_load(argv[1]);
+    CHECK(cfg);
+    NetidxPublisher *pub = netidx_publisher_new(cfg, "127.0.0.1/32");
+    CHECK(pub);
+    NetidxValue *v = netidx_value_i64(42);
+    NetidxVal *val = netidx_publisher_publish(pub, "/capi/v", v);
+    CHECK(val);
+    netidx_value_free(v);
+    CHECK(netidx_publisher_on_write(pub, val, on_write, &st));
+    CHECK(netidx_publisher_flushed(pub));
+
+    NetidxSubscriber *sub = netidx_subscriber_new(cfg);
+    CHECK(sub);
+    NetidxDval *dv = netidx_subscriber_subscribe(sub, "/capi/v", 10000);
+    CHECK(dv);
+    NetidxValue *last = netidx_dval_last(dv);
+    CHECK(last);
+    CHECK(netidx_value_type(last) == NETIDX_TYPE_I64);
+    CHECK(netidx_value_get_i64(last, &i) && i == 42);
+    netidx_value_free(last);
+
+    CHECK(netidx_dval_on_update(dv, on_update, &st));
+    wait_update(&st, 42);
+    for (i = 43; i < 53; i++) {
+        NetidxBatch *batch = netidx_publisher_start_batch(pub);
+        CHECK(batch);
+        v = netidx_value_i64(i);
+        CHECK(netidx_batch_update(batch, val, v));
+        CHECK(netidx_publisher_commit(pub, batch, -1));
+        netidx_value_free(v);
+        wait_update(&st, i);
+    }
+
+    v = netidx_value_string("hello");
+    CHECK(netidx_dval_write(dv, v));
+    netidx_value_free(v);
+    wait_write(&st, "hello");
+
+    v = netidx_value_parse("[1, \"two\", 3.5]");
+    CHECK(v);
+    CHECK(netidx_value_type(v) == NETIDX_TYPE_ARRAY);
+    char *s = netidx_value_to_string(v);
+    CHECK(s);
+    netidx_string_free(s);
+    netidx_value_free(v);
+    CHECK(!netidx_value_parse("[1, 2"));
+    CHECK(netidx_last_error());
+
+    netidx_dval_free(dv);
+    netidx_subscriber_free(sub);
+    netidx_val_free(val);
+    netidx_publisher_free(pub);
+    netidx_config_free(cfg);
+    free(st.last_write);
+    printf("ok\n");
+    return 0;
+}
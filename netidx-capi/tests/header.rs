@@ -0,0 +1,23 @@
+//! Check that include/netidx.h is what cbindgen generates from the
+//! current sources.
+use std::fs;
+
+#[test]
+fn header() {
+    let dir = env!("CARGO_MANIFEST_DIR");
+    let config = cbindgen::Config::from_file(format!("{}/cbindgen.toml", dir))
+        .expect("failed to read cbindgen.toml");
+    let mut generated = Vec::new();
+    cbindgen::Builder::new()
+        .with_crate(dir)
+        .with_config(config)
+        .generate()
+        .expect("failed to generate the C header")
+        .write(&mut generated);
+    let committed = fs::read(format!("{}/include/netidx.h", dir)).unwrap();
+    assert!(
+        committed == generated,
+        "include/netidx.h is out of date, regenerate it with \
+         `cbindgen --config cbindgen.toml --output include/netidx.h`"
+    );
+}
@@ -349,6 +349,47 @@ where
     r
 }
 
+/// The encoded length of a tagged field, the tag, the length of the
+/// field, and the field itself.
+pub fn tagged_len<T: Pack>(tag: u64, t: &T) -> usize {
+    let len = t.encoded_len();
+    varint_len(tag) + varint_len(len as u64) + len
+}
+
+/// Encode a field with it's tag and length
+pub fn tagged_encode<T: Pack>(
+    buf: &mut impl BufMut,
+    tag: u64,
+    t: &T,
+) -> Result<(), PackError> {
+    encode_varint(tag, buf);
+    encode_varint(t.encoded_len() as u64, buf);
+    t.encode(buf)
+}
+
+/// Decode the tag and length of a tagged field. The caller must then
+/// either decode the field with `tagged_decode`, or skip it by
+/// advancing the buffer by the length.
+pub fn tagged_decode_header(buf: &mut impl Buf) -> Result<(u64, usize), PackError> {
+    let tag = decode_varint(buf)?;
+    let len = decode_varint(buf)? as usize;
+    if len > buf.remaining() {
+        return Err(PackError::BufferShort);
+    }
+    Ok((tag, len))
+}
+
+/// Decode a field of length `len`, skipping anything it doesn't
+/// consume.
+pub fn tagged_decode<T: Pack>(buf: &mut impl Buf, len: usize) -> Result<T, PackError> {
+    let mut limited = buf.take(len);
+    let r = T::decode(&mut limited);
+    if limited.has_remaining() {
+        limited.advance(limited.remaining());
+    }
+    r
+}
+
 impl Pack for f32 {
     fn const_encoded_len() -> Option<usize> {
         Some(mem::size_of::<f32>())
@@ -1,10 +1,14 @@
-use proc_macro2::{TokenStream, TokenTree};
+use proc_macro2::{Literal, TokenStream, TokenTree};
 use quote::{format_ident, quote};
+use std::collections::HashSet;
 use syn::{
-    parse_macro_input, parse_quote, AttrStyle, Attribute, Data, DeriveInput, Field,
-    Fields, GenericParam, Ident, Index,
+    parse_macro_input, parse_quote, AttrStyle, Attribute, Data, DeriveInput, Expr, Field,
+    Fields, GenericParam, Ident, Index, ItemTrait, LitInt, Token, Variant,
 };
 
+mod rpc;
+mod value;
+
 fn is_attr(att: &Attribute, allowed: &[&str], s: &str) -> bool {
     if !allowed.contains(&s) {
         panic!("BUG: attribute '{}' is not included in '{:?}'", s, allowed);
@@ -34,7 +38,111 @@ fn is_attr(att: &Attribute, allowed: &[&str], s: &str) -> bool {
     }
 }
 
-static FIELD_ATTRS: [&'static str; 3] = ["skip", "default", "other"];
+static FIELD_ATTRS: [&'static str; 5] = ["skip", "default", "other", "tag", "since"];
+
+// the value of an integer pack attribute, e.g. #[pack(tag = 3)]
+fn attr_int(attrs: &[Attribute], s: &str) -> Option<u64> {
+    let mut res = None;
+    for att in attrs.iter().filter(|a| a.path().is_ident("pack")) {
+        att.parse_nested_meta(|meta| {
+            if meta.path.is_ident(s) {
+                res = Some(meta.value()?.parse::<LitInt>()?.base10_parse::<u64>()?);
+            } else if meta.input.peek(Token![=]) {
+                meta.value()?.parse::<Expr>()?;
+            }
+            Ok(())
+        })
+        .unwrap_or_else(|e| panic!("{}", e))
+    }
+    res
+}
+
+fn is_skipped(f: &Field) -> bool {
+    f.attrs.iter().any(|a| is_attr(a, &FIELD_ATTRS, "skip"))
+}
+
+// fields with tags are encoded as (tag, len, field) and may be in
+// any order, unknown tags are skipped when decoding.
+fn is_tagged(fields: &Fields) -> bool {
+    fields.iter().any(|f| attr_int(&f.attrs, "tag").is_some())
+}
+
+fn check_fields(no_wrap: bool, fields: &Fields) {
+    if is_tagged(fields) {
+        if no_wrap {
+            panic!("tagged fields require a length wrapper, remove unwrapped")
+        }
+        let mut tags = HashSet::new();
+        for f in fields.iter().filter(|f| !is_skipped(f)) {
+            match attr_int(&f.attrs, "tag") {
+                None => panic!("if any field has a tag then every field must"),
+                Some(tag) => {
+                    if !tags.insert(tag) {
+                        panic!("duplicate tag {}", tag)
+                    }
+                }
+            }
+        }
+    } else {
+        // fields added since the first version must be added at the
+        // end, in order
+        let mut since = None;
+        for f in fields.iter().filter(|f| !is_skipped(f)) {
+            match (since, attr_int(&f.attrs, "since")) {
+                (None, None) => (),
+                (Some(_), None) => {
+                    panic!("fields without since may not follow fields with since")
+                }
+                (Some(prev), Some(n)) if n < prev => {
+                    panic!("since must not decrease from one field to the next")
+                }
+                (Some(_) | None, Some(n)) => since = Some(n),
+            }
+        }
+    }
+}
+
+// the encoded enum discriminant for the variant at index i
+fn discriminant(v: &Variant, i: usize) -> Literal {
+    match attr_int(&v.attrs, "tag") {
+        None => Literal::u8_unsuffixed(i as u8),
+        Some(tag) if tag <= u8::MAX as u64 => Literal::u8_unsuffixed(tag as u8),
+        Some(tag) => panic!("variant tag {} does not fit in a u8", tag),
+    }
+}
+
+fn check_variants<'a>(variants: impl IntoIterator<Item = &'a Variant>) {
+    let mut tags = HashSet::new();
+    let mut n = 0;
+    for v in variants {
+        n += 1;
+        if let Some(tag) = attr_int(&v.attrs, "tag") {
+            if !tags.insert(tag) {
+                panic!("duplicate variant tag {}", tag)
+            }
+        }
+    }
+    if n > u8::MAX as usize + 1 {
+        panic!("too many variants")
+    }
+    if !tags.is_empty() && tags.len() != n {
+        panic!("if any variant has a tag then every variant must")
+    }
+}
+
+fn field_len(f: &Field, field: TokenStream) -> TokenStream {
+    match attr_int(&f.attrs, "tag") {
+        None => quote! { netidx_core::pack::Pack::encoded_len(#field) },
+        Some(tag) => quote! { netidx_core::pack::tagged_len(#tag, #field) },
+    }
+}
+
+fn field_encode(f: &Field, field: TokenStream) -> TokenStream {
+    match attr_int(&f.attrs, "tag") {
+        None => quote! { netidx_core::pack::Pack::encode(#field, buf)? },
+        Some(tag) => quote! { netidx_core::pack::tagged_encode(buf, #tag, #field)? },
+    }
+}
 
 fn encoded_len(no_wrap: bool, input: &Data) -> TokenStream {
     match input {
@@ -46,9 +154,7 @@ fn encoded_len(no_wrap: bool, input: &Data) -> TokenStream {
                     .filter(|f| !f.attrs.iter().any(|f| is_attr(f, &FIELD_ATTRS, "skip")))
                     .map(|f| {
                         let name = &f.ident;
-                        quote! {
-                            netidx_core::pack::Pack::encoded_len(&self.#name)
-                        }
+                        field_len(f, quote! { &self.#name })
                     });
                 if no_wrap {
                     quote! {
@@ -68,11 +174,9 @@ fn encoded_len(no_wrap: bool, input: &Data) -> TokenStream {
                     .filter(|(_, f)| {
                         !f.attrs.iter().any(|f| is_attr(f, &FIELD_ATTRS, "skip"))
                     })
-                    .map(|(i, _)| {
+                    .map(|(i, f)| {
                         let index = Index::from(i);
-                        quote! {
-                            netidx_core::pack::Pack::encoded_len(&self.#index)
-                        }
+                        field_len(f, quote! { &self.#index })
                     });
                 if no_wrap {
                     quote! {
@@ -104,9 +208,7 @@ fn encoded_len(no_wrap: bool, input: &Data) -> TokenStream {
                         })
                         .map(|f| {
                             let name = &f.ident;
-                            quote! {
-                                netidx_core::pack::Pack::encoded_len(#name)
-                            }
+                            field_len(f, quote! { #name })
                         });
                     let tag = &v.ident;
                     quote! {
@@ -130,11 +232,9 @@ fn encoded_len(no_wrap: bool, input: &Data) -> TokenStream {
                         .filter(|(_, f)| {
                             !f.attrs.iter().any(|a| is_attr(a, &FIELD_ATTRS, "skip"))
                         })
-                        .map(|(i, _)| {
+                        .map(|(i, f)| {
                             let name = format_ident!("field{}", i);
-                            quote! {
-                                netidx_core::pack::Pack::encoded_len(#name)
-                            }
+                            field_len(f, quote! { #name })
                         });
                     let tag = &v.ident;
                     quote! {
@@ -174,9 +274,7 @@ fn encode(no_wrap: bool, input: &Data) -> TokenStream {
                     .filter(|f| !f.attrs.iter().any(|a| is_attr(a, &FIELD_ATTRS, "skip")))
                     .map(|f| {
                         let name = &f.ident;
-                        quote! {
-                            netidx_core::pack::Pack::encode(&self.#name, buf)?
-                        }
+                        field_encode(f, quote! { &self.#name })
                     });
                 if no_wrap {
                     quote! {
@@ -200,11 +298,9 @@ fn encode(no_wrap: bool, input: &Data) -> TokenStream {
                     .filter(|(_, f)| {
                         !f.attrs.iter().any(|a| is_attr(a, &FIELD_ATTRS, "skip"))
                     })
-                    .map(|(i, _)| {
+                    .map(|(i, f)| {
                         let index = Index::from(i);
-                        quote! {
-                            netidx_core::pack::Pack::encode(&self.#index, buf)?
-                        }
+                        field_encode(f, quote! { &self.#index })
                     });
                 if no_wrap {
                     quote! {
@@ -240,12 +336,10 @@ fn encode(no_wrap: bool, input: &Data) -> TokenStream {
                         })
                         .map(|f| {
                             let name = &f.ident;
-                            quote! {
-                                netidx_core::pack::Pack::encode(#name, buf)?
-                            }
+                            field_encode(f, quote! { #name })
                         });
                     let tag = &v.ident;
-                    let i = Index::from(i);
+                    let i = discriminant(v, i);
                     quote! {
                         Self::#tag { #(#match_fields),*, .. } => {
                             <u8 as netidx_core::pack::Pack>::encode(&#i, buf)?;
@@ -269,14 +363,12 @@ fn encode(no_wrap: bool, input: &Data) -> TokenStream {
                         .filter(|(_, f)| {
                             !f.attrs.iter().any(|a| is_attr(a, &FIELD_ATTRS, "skip"))
                         })
-                        .map(|(i, _)| {
+                        .map(|(i, f)| {
                             let name = format_ident!("field{}", i);
-                            quote! {
-                                netidx_core::pack::Pack::encode(#name, buf)?
-                            }
+                            field_encode(f, quote! { #name })
                         });
                     let tag = &v.ident;
-                    let i = Index::from(i);
+                    let i = discriminant(v, i);
                     quote! {
                         Self::#tag(#(#match_fields),*) => {
                             <u8 as netidx_core::pack::Pack>::encode(&#i, buf)?;
@@ -287,7 +379,7 @@ fn encode(no_wrap: bool, input: &Data) -> TokenStream {
                 }
                 Fields::Unit => {
                     let tag = &v.ident;
-                    let i = Index::from(i);
+                    let i = discriminant(v, i);
                     quote! {
                         Self::#tag => <u8 as netidx_core::pack::Pack>::encode(&#i, buf),
                     }
@@ -339,7 +431,8 @@ fn decode_normal(name: &Option<Ident>) -> TokenStream {
 fn decode_named_field(f: &Field) -> TokenStream {
     let name = &f.ident;
     let is_skipped = f.attrs.iter().any(|a| is_attr(a, &FIELD_ATTRS, "skip"));
-    let is_default = f.attrs.iter().any(|a| is_attr(a, &FIELD_ATTRS, "default"));
+    let is_default = f.attrs.iter().any(|a| is_attr(a, &FIELD_ATTRS, "default"))
+        || attr_int(&f.attrs, "since").is_some();
     if is_skipped {
         decode_default(name)
     } else if is_default {
@@ -352,7 +445,8 @@ fn decode_named_field(f: &Field) -> TokenStream {
 fn decode_unnamed_field(f: &Field, i: usize) -> TokenStream {
     let name = Some(format_ident!("field{}", i));
     let is_skipped = f.attrs.iter().any(|a| is_attr(a, &FIELD_ATTRS, "skip"));
-    let is_default = f.attrs.iter().any(|a| is_attr(a, &FIELD_ATTRS, "default"));
+    let is_default = f.attrs.iter().any(|a| is_attr(a, &FIELD_ATTRS, "default"))
+        || attr_int(&f.attrs, "since").is_some();
     if is_skipped {
         decode_default(&name)
     } else if is_default {
@@ -362,12 +456,65 @@ fn decode_unnamed_field(f: &Field, i: usize) -> TokenStream {
     }
 }
 
+fn decode_tagged(fields: &Fields) -> TokenStream {
+    let mut declare = vec![];
+    let mut cases = vec![];
+    let mut finish = vec![];
+    for (i, f) in fields.iter().enumerate() {
+        let name = f.ident.clone().unwrap_or_else(|| format_ident!("field{}", i));
+        if is_skipped(f) {
+            finish.push(quote! { let #name = std::default::Default::default(); });
+            continue;
+        }
+        let tag = attr_int(&f.attrs, "tag").unwrap();
+        let is_default = f.attrs.iter().any(|a| is_attr(a, &FIELD_ATTRS, "default"))
+            || attr_int(&f.attrs, "since").is_some();
+        declare.push(quote! { let mut #name = None; });
+        cases.push(quote! {
+            #tag => #name = Some(netidx_core::pack::tagged_decode(buf, len)?),
+        });
+        if is_default {
+            finish.push(quote! { let #name = #name.unwrap_or_default(); });
+        } else {
+            finish.push(quote! {
+                let #name = #name.ok_or(netidx_core::pack::PackError::InvalidFormat)?;
+            });
+        }
+    }
+    quote! {
+        #(#declare)*
+        while bytes::Buf::has_remaining(buf) {
+            let (tag, len) = netidx_core::pack::tagged_decode_header(buf)?;
+            match tag {
+                #(#cases)*
+                _ => bytes::Buf::advance(buf, len),
+            }
+        }
+        #(#finish)*
+    }
+}
+
+fn decode_fields(fields: &Fields) -> Vec<TokenStream> {
+    if is_tagged(fields) {
+        vec![decode_tagged(fields)]
+    } else {
+        fields
+            .iter()
+            .enumerate()
+            .map(|(i, f)| match &f.ident {
+                Some(_) => decode_named_field(f),
+                None => decode_unnamed_field(f, i),
+            })
+            .collect()
+    }
+}
+
 fn decode(no_wrap: bool, input: &Data) -> TokenStream {
     match input {
         Data::Struct(st) => match &st.fields {
             Fields::Named(fields) => {
                 let name_fields = fields.named.iter().map(|f| &f.ident);
-                let decode_fields = fields.named.iter().map(decode_named_field);
+                let decode_fields = decode_fields(&st.fields);
                 if no_wrap {
                     quote! {
                         #(#decode_fields);*;
@@ -388,11 +535,7 @@ fn decode(no_wrap: bool, input: &Data) -> TokenStream {
                     .iter()
                     .enumerate()
                     .map(|(i, _)| format_ident!("field{}", i));
-                let decode_fields = fields
-                    .unnamed
-                    .iter()
-                    .enumerate()
-                    .map(|(i, f)| decode_unnamed_field(f, i));
+                let decode_fields = decode_fields(&st.fields);
                 if no_wrap {
                     quote! {
                         #(#decode_fields);*;
@@ -421,9 +564,9 @@ fn decode(no_wrap: bool, input: &Data) -> TokenStream {
                             panic!("other attribute must be applied to a unit variant")
                         }
                         let name_fields = f.named.iter().map(|f| &f.ident);
-                        let decode_fields = f.named.iter().map(decode_named_field);
+                        let decode_fields = decode_fields(&v.fields);
                         let tag = &v.ident;
-                        let i = Index::from(i);
+                        let i = discriminant(v, i);
                         quote! {
                             #i => {
                                 #(#decode_fields);*;
@@ -440,13 +583,9 @@ fn decode(no_wrap: bool, input: &Data) -> TokenStream {
                             .iter()
                             .enumerate()
                             .map(|(i, _)| format_ident!("field{}", i));
-                        let decode_fields = f
-                            .unnamed
-                            .iter()
-                            .enumerate()
-                            .map(|(i, f)| decode_unnamed_field(f, i));
+                        let decode_fields = decode_fields(&v.fields);
                         let tag = &v.ident;
-                        let i = Index::from(i);
+                        let i = discriminant(v, i);
                         quote! {
                             #i => {
                                 #(#decode_fields);*;
@@ -456,7 +595,7 @@ fn decode(no_wrap: bool, input: &Data) -> TokenStream {
                     }
                     Fields::Unit => {
                         let tag = &v.ident;
-                        let i = Index::from(i);
+                        let i = discriminant(v, i);
                         if v.attrs.iter().any(|a| is_attr(a, &FIELD_ATTRS, "other")) {
                             if other.is_some() {
                                 panic!("other attribute may be specified at most once")
@@ -507,11 +646,40 @@ fn decode(no_wrap: bool, input: &Data) -> TokenStream {
     }
 }
 
+/// Derive `Pack`. By default fields are encoded in order, and enum
+/// variants are identified by their index.
+///
+/// - `#[pack(skip)]` a field is not encoded, and is `Default` when decoded
+/// - `#[pack(default)]` a field is `Default` if the buffer ends before it
+/// - `#[pack(since = N)]` a field was added in version N of the type,
+///   it decodes to `Default` when reading data from earlier versions.
+///   Since fields must come after all other fields, in order of N.
+/// - `#[pack(tag = N)]` on fields, each field is encoded with it's tag
+///   and length, fields may then be added, removed, and reordered, and
+///   unknown tags are skipped when decoding. If any field is tagged then
+///   all fields must be. A missing field is an error unless it is also
+///   `since` or `default`.
+/// - `#[pack(tag = N)]` on variants sets the encoded discriminant (0 -
+///   255), so variants may be reordered. If any variant is tagged then
+///   all variants must be.
+/// - `#[pack(other)]` on a unit variant decodes any unknown discriminant
+/// - `#[pack(unwrapped)]` on the type omits the length wrapper, it can't
+///   be combined with tagged fields.
 #[proc_macro_derive(Pack, attributes(pack))]
 pub fn derive_pack(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let mut input = parse_macro_input!(input as DeriveInput);
     let no_wrap = input.attrs.iter().any(|a| is_attr(a, &["unwrapped"], "unwrapped"));
     let name = input.ident;
+    match &input.data {
+        Data::Struct(st) => check_fields(no_wrap, &st.fields),
+        Data::Enum(en) => {
+            check_variants(&en.variants);
+            for v in &en.variants {
+                check_fields(no_wrap, &v.fields)
+            }
+        }
+        Data::Union(_) => (),
+    }
     for param in &mut input.generics.params {
         if let GenericParam::Type(typ) = param {
             typ.bounds.push(parse_quote!(netidx_core::pack::Pack))
@@ -543,3 +711,74 @@ pub fn derive_pack(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     };
     proc_macro::TokenStream::from(expanded)
 }
+
+/// Derive `From<T> for Value`. Structs with named fields become a
+/// `Value::Map` keyed by field name, tuple structs become a
+/// `Value::Array` (or the inner value if there is only one field),
+/// fieldless enum variants become a `Value::String` of the variant
+/// name, and variants with fields become a single entry map from the
+/// variant name to it's fields.
+///
+/// Fields may be annotated with `#[value(rename = "name")]` and
+/// `#[value(skip)]`, variants may be renamed.
+#[proc_macro_derive(IntoValue, attributes(value))]
+pub fn derive_into_value(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let mut input = parse_macro_input!(input as DeriveInput);
+    let name = input.ident;
+    for param in &mut input.generics.params {
+        if let GenericParam::Type(typ) = param {
+            typ.bounds
+                .push(parse_quote!(std::convert::Into<netidx_netproto::value::Value>))
+        }
+    }
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let into_value = value::into_value(&name, &input.data);
+    let expanded = quote! {
+        impl #impl_generics std::convert::From<#name #ty_generics>
+            for netidx_netproto::value::Value #where_clause
+        {
+            fn from(t: #name #ty_generics) -> netidx_netproto::value::Value {
+                #into_value
+            }
+        }
+    };
+    proc_macro::TokenStream::from(expanded)
+}
+
+/// Derive `FromValue`, the inverse of `IntoValue`. Unknown map keys
+/// are ignored, missing fields are an error unless they are marked
+/// `#[value(default)]` or `#[value(skip)]`, in which case they are
+/// filled in with `Default::default()`.
+#[proc_macro_derive(FromValue, attributes(value))]
+pub fn derive_from_value(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let mut input = parse_macro_input!(input as DeriveInput);
+    let name = input.ident;
+    for param in &mut input.generics.params {
+        if let GenericParam::Type(typ) = param {
+            typ.bounds.push(parse_quote!(netidx_netproto::value::FromValue))
+        }
+    }
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let from_value = value::from_value(&input.data);
+    let expanded = quote! {
+        impl #impl_generics netidx_netproto::value::FromValue for #name #ty_generics
+            #where_clause
+        {
+            fn from_value(v: netidx_netproto::value::Value) -> anyhow::Result<Self> {
+                #from_value
+            }
+        }
+    };
+    proc_macro::TokenStream::from(expanded)
+}
+
+/// Define a typed rpc service from a trait, see
+/// `netidx_protocols::rpc::service`.
+#[proc_macro_attribute]
+pub fn rpc_service(
+    _attr: proc_macro::TokenStream,
+    input: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as ItemTrait);
+    proc_macro::TokenStream::from(rpc::service(input))
+}
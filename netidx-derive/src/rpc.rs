@@ -0,0 +1,299 @@
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::{
+    parse_quote, Attribute, Expr, FnArg, GenericArgument, Ident, ItemTrait, LitStr, Pat,
+    PathArguments, ReturnType, TraitItem, Type,
+};
+
+struct Arg {
+    name: Ident,
+    typ: Type,
+    doc: String,
+    default: Option<Expr>,
+}
+
+struct Method {
+    name: Ident,
+    attrs: Vec<Attribute>,
+    doc: String,
+    args: Vec<Arg>,
+    ret: Type,
+}
+
+// the doc comments of an item joined into one string
+fn doc(attrs: &[Attribute]) -> String {
+    let lines =
+        attrs.iter().filter(|a| a.path().is_ident("doc")).filter_map(|a| {
+            match &a.meta.require_name_value().ok()?.value {
+                Expr::Lit(l) => match &l.lit {
+                    syn::Lit::Str(s) => Some(s.value().trim().to_string()),
+                    _ => None,
+                },
+                _ => None,
+            }
+        });
+    lines.collect::<Vec<_>>().join("\n")
+}
+
+// parse and remove the #[rpc(doc = "...", default = expr)] attributes
+// of an argument
+fn arg_opts(attrs: &mut Vec<Attribute>) -> (String, Option<Expr>) {
+    let mut doc = String::new();
+    let mut default = None;
+    for att in attrs.iter().filter(|a| a.path().is_ident("rpc")) {
+        att.parse_nested_meta(|meta| {
+            if meta.path.is_ident("doc") {
+                doc = meta.value()?.parse::<LitStr>()?.value();
+            } else if meta.path.is_ident("default") {
+                default = Some(meta.value()?.parse::<Expr>()?);
+            } else {
+                return Err(meta.error("invalid attribute"));
+            }
+            Ok(())
+        })
+        .unwrap_or_else(|e| panic!("{}", e))
+    }
+    attrs.retain(|a| !a.path().is_ident("rpc"));
+    (doc, default)
+}
+
+// T in Result<T>
+fn result_type(ret: &ReturnType) -> Type {
+    let err = "rpc methods must return Result<T>";
+    let typ = match ret {
+        ReturnType::Type(_, typ) => typ,
+        ReturnType::Default => panic!("{}", err),
+    };
+    let seg = match &**typ {
+        Type::Path(p) => p.path.segments.last().expect(err),
+        _ => panic!("{}", err),
+    };
+    if seg.ident != "Result" {
+        panic!("{}", err)
+    }
+    match &seg.arguments {
+        PathArguments::AngleBracketed(a) => match a.args.first() {
+            Some(GenericArgument::Type(t)) => t.clone(),
+            _ => panic!("{}", err),
+        },
+        _ => panic!("{}", err),
+    }
+}
+
+// check the method, rewrite it's signature to return a Send future,
+// and return it's description
+fn method(item: &mut TraitItem) -> Method {
+    let f = match item {
+        TraitItem::Fn(f) => f,
+        _ => panic!("rpc services may only contain methods"),
+    };
+    if f.default.is_some() {
+        panic!("rpc methods may not have a default implementation")
+    }
+    if f.sig.asyncness.is_none() {
+        panic!("rpc methods must be async")
+    }
+    if !f.sig.generics.params.is_empty() {
+        panic!("rpc methods may not be generic")
+    }
+    let mut inputs = f.sig.inputs.iter_mut();
+    match inputs.next() {
+        Some(FnArg::Receiver(r)) if r.reference.is_some() && r.mutability.is_none() => (),
+        _ => panic!("the first argument of an rpc method must be &self"),
+    }
+    let args = inputs
+        .map(|arg| match arg {
+            FnArg::Receiver(_) => unreachable!(),
+            FnArg::Typed(pt) => {
+                let (doc, default) = arg_opts(&mut pt.attrs);
+                let name = match &*pt.pat {
+                    Pat::Ident(i) => i.ident.clone(),
+                    _ => panic!("rpc method arguments must be identifiers"),
+                };
+                Arg { name, typ: (*pt.ty).clone(), doc, default }
+            }
+        })
+        .collect::<Vec<_>>();
+    let ret = result_type(&f.sig.output);
+    let output = match &f.sig.output {
+        ReturnType::Type(_, t) => t.clone(),
+        ReturnType::Default => unreachable!(),
+    };
+    f.sig.asyncness = None;
+    f.sig.output = parse_quote! {
+        -> impl std::future::Future<Output = #output> + Send
+    };
+    let attrs = f.attrs.iter().filter(|a| a.path().is_ident("doc")).cloned().collect();
+    Method { name: f.sig.ident.clone(), doc: doc(&f.attrs), attrs, args, ret }
+}
+
+fn default_value(arg: &Arg) -> TokenStream {
+    match &arg.default {
+        None => quote!(netidx_protocols::rpc::__private::Value::Null),
+        Some(e) => quote!(netidx_protocols::rpc::__private::Value::from(#e)),
+    }
+}
+
+fn server_proc(m: &Method) -> TokenStream {
+    let p = quote!(netidx_protocols::rpc::__private);
+    let name = &m.name;
+    let name_str = name.to_string();
+    let doc = &m.doc;
+    let specs = m.args.iter().map(|a| {
+        let arg = a.name.to_string();
+        let doc = &a.doc;
+        let default = default_value(a);
+        quote! {
+            #p::ArgSpec {
+                name: #p::ArcStr::from(#arg),
+                doc: #p::Value::from(#doc),
+                default_value: #default,
+            }
+        }
+    });
+    let parse = m.args.iter().map(|a| {
+        let arg = &a.name;
+        let arg_str = arg.to_string();
+        let typ = &a.typ;
+        let default = default_value(a);
+        quote! {
+            let __v = __c.args.remove(#arg_str).unwrap_or_else(|| #default);
+            let #arg: #typ = match #p::FromValue::from_value(__v) {
+                Ok(t) => t,
+                Err(e) => {
+                    let e = format!("arg: {} invalid type conversion {}", #arg_str, e);
+                    __c.reply.send(#p::Value::Error(#p::Chars::from(e)));
+                    return None;
+                }
+            };
+        }
+    });
+    let args = m.args.iter().map(|a| &a.name);
+    quote! {{
+        let __service = service.clone();
+        procs.push(#p::ServerProc::new(
+            publisher,
+            base.append(#name_str),
+            #p::Value::from(#doc),
+            [#(#specs),*],
+            move |mut __c: #p::RpcCall| -> Option<()> {
+                #(#parse)*
+                if !__c.args.is_empty() {
+                    let e = format!(
+                        "unknown argument specified: {:?}",
+                        __c.args.keys().collect::<Vec<_>>()
+                    );
+                    __c.reply.send(#p::Value::Error(#p::Chars::from(e)));
+                    return None;
+                }
+                let __service = __service.clone();
+                // the handler is dropped if the call is cancelled
+                #p::spawn(async move {
+                    __c.reply_with(__service.#name(#(#args),*)).await
+                });
+                None
+            },
+            None,
+        )?);
+    }}
+}
+
+fn client_method(vis: &syn::Visibility, m: &Method) -> TokenStream {
+    let p = quote!(netidx_protocols::rpc::__private);
+    let name = &m.name;
+    let attrs = &m.attrs;
+    let ret = &m.ret;
+    let params = m.args.iter().map(|a| {
+        let arg = &a.name;
+        let typ = &a.typ;
+        quote!(#arg: #typ)
+    });
+    let n = m.args.len();
+    let args = m.args.iter().map(|a| {
+        let arg = &a.name;
+        let arg_str = arg.to_string();
+        quote!((#arg_str, std::convert::Into::<#p::Value>::into(#arg)))
+    });
+    quote! {
+        #(#attrs)*
+        #vis async fn #name(&self, #(#params),*) -> #p::Result<#ret> {
+            let __args: [(&'static str, #p::Value); #n] = [#(#args),*];
+            match self.#name.call(__args).await? {
+                #p::Value::Error(e) => Err(#p::anyhow!("{}", e)),
+                v => #p::FromValue::from_value(v),
+            }
+        }
+    }
+}
+
+pub(crate) fn service(mut input: ItemTrait) -> TokenStream {
+    if !input.generics.params.is_empty() {
+        panic!("rpc services may not be generic")
+    }
+    input.supertraits.push(parse_quote!(Send));
+    input.supertraits.push(parse_quote!(Sync));
+    input.supertraits.push(parse_quote!('static));
+    let methods = input.items.iter_mut().map(method).collect::<Vec<_>>();
+    let p = quote!(netidx_protocols::rpc::__private);
+    let vis = &input.vis;
+    let name = &input.ident;
+    let server = format_ident!("{}Server", name);
+    let client = format_ident!("{}Client", name);
+    let server_doc = format!(
+        "Publishes an implementation of `{}`, each method is an rpc \
+         under the base path. Dropping it unpublishes the procedures.",
+        name
+    );
+    let client_doc = format!("A typed client for the `{}` rpc service", name);
+    let procs = methods.iter().map(server_proc);
+    let fields = methods.iter().map(|m| {
+        let name = &m.name;
+        quote!(#name: #p::ClientProc)
+    });
+    let subscribe = methods.iter().map(|m| {
+        let name = &m.name;
+        let name_str = name.to_string();
+        quote!(#name: #p::ClientProc::new(subscriber, base.append(#name_str)).await?)
+    });
+    let calls = methods.iter().map(|m| client_method(vis, m));
+    quote! {
+        #input
+
+        #[doc = #server_doc]
+        #vis struct #server {
+            _procs: Vec<#p::ServerProc>,
+        }
+
+        impl #server {
+            /// Publish the procedures of `service` under `base`
+            #vis fn new<S: #name>(
+                publisher: &#p::Publisher,
+                base: #p::Path,
+                service: S,
+            ) -> #p::Result<Self> {
+                let service = std::sync::Arc::new(service);
+                let mut procs = Vec::new();
+                #(#procs)*
+                Ok(#server { _procs: procs })
+            }
+        }
+
+        #[doc = #client_doc]
+        #[derive(Debug, Clone)]
+        #vis struct #client {
+            #(#fields),*
+        }
+
+        impl #client {
+            /// Subscribe to the procedures published under `base`
+            #vis async fn new(
+                subscriber: &#p::Subscriber,
+                base: #p::Path,
+            ) -> #p::Result<Self> {
+                Ok(#client { #(#subscribe),* })
+            }
+
+            #(#calls)*
+        }
+    }
+}
@@ -0,0 +1,302 @@
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::{Attribute, Data, Fields, Ident, Index, LitStr, Variant};
+
+#[derive(Default)]
+struct Opts {
+    rename: Option<String>,
+    default: bool,
+    skip: bool,
+}
+
+fn opts(attrs: &[Attribute]) -> Opts {
+    let mut opts = Opts::default();
+    for att in attrs.iter().filter(|a| a.path().is_ident("value")) {
+        att.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename") {
+                opts.rename = Some(meta.value()?.parse::<LitStr>()?.value());
+            } else if meta.path.is_ident("default") {
+                opts.default = true;
+            } else if meta.path.is_ident("skip") {
+                opts.skip = true;
+            } else {
+                return Err(meta.error("invalid attribute"));
+            }
+            Ok(())
+        })
+        .unwrap_or_else(|e| panic!("{}", e))
+    }
+    opts
+}
+
+fn variant_name(v: &Variant) -> String {
+    let opts = opts(&v.attrs);
+    if opts.default || opts.skip {
+        panic!("only rename may be applied to a variant")
+    }
+    opts.rename.unwrap_or_else(|| v.ident.to_string())
+}
+
+// build a value from the fields, each field is read with the
+// corresponding expression in `exprs`
+fn fields_to_value(fields: &Fields, exprs: &[TokenStream]) -> TokenStream {
+    match fields {
+        Fields::Named(f) => {
+            let inserts = f.named.iter().zip(exprs.iter()).filter_map(|(f, e)| {
+                let opts = opts(&f.attrs);
+                if opts.skip {
+                    return None;
+                }
+                let key =
+                    opts.rename.unwrap_or_else(|| f.ident.as_ref().unwrap().to_string());
+                Some(quote! {
+                    __m.insert(
+                        netidx_core::chars::Chars::from(#key),
+                        std::convert::Into::<netidx_netproto::value::Value>::into(#e)
+                    )
+                })
+            });
+            quote! {{
+                let mut __m = std::collections::BTreeMap::new();
+                #(#inserts;)*
+                netidx_netproto::value::Value::Map(std::sync::Arc::new(__m))
+            }}
+        }
+        Fields::Unnamed(f) => {
+            let elts = f
+                .unnamed
+                .iter()
+                .zip(exprs.iter())
+                .filter(|(f, _)| !opts(&f.attrs).skip)
+                .map(|(_, e)| quote! { std::convert::Into::<netidx_netproto::value::Value>::into(#e) })
+                .collect::<Vec<_>>();
+            if elts.len() == 1 {
+                let e = &elts[0];
+                quote! { #e }
+            } else {
+                quote! {
+                    netidx_netproto::value::Value::Array(std::sync::Arc::from([#(#elts),*]))
+                }
+            }
+        }
+        Fields::Unit => quote! { netidx_netproto::value::Value::Null },
+    }
+}
+
+// build `ctor` from the value in `v`
+fn fields_from_value(fields: &Fields, ctor: TokenStream, v: TokenStream) -> TokenStream {
+    match fields {
+        Fields::Named(f) => {
+            let fields = f.named.iter().map(|f| {
+                let name = f.ident.as_ref().unwrap();
+                let opts = opts(&f.attrs);
+                let key = opts.rename.unwrap_or_else(|| name.to_string());
+                let get = quote! {
+                    netidx_netproto::value::FromValue::from_value(v.clone())
+                        .map_err(|e| anyhow::anyhow!("field {}: {}", #key, e))?
+                };
+                if opts.skip {
+                    quote! { #name: std::default::Default::default() }
+                } else if opts.default {
+                    quote! {
+                        #name: match __m.get(#key) {
+                            Some(v) => #get,
+                            None => std::default::Default::default(),
+                        }
+                    }
+                } else {
+                    quote! {
+                        #name: match __m.get(#key) {
+                            Some(v) => #get,
+                            None => anyhow::bail!("missing field {}", #key),
+                        }
+                    }
+                }
+            });
+            quote! {{
+                let __m = match #v.cast(netidx_netproto::value::Typ::Map) {
+                    Some(netidx_netproto::value::Value::Map(m)) => m,
+                    _ => anyhow::bail!("expected a map"),
+                };
+                Ok(#ctor { #(#fields),* })
+            }}
+        }
+        Fields::Unnamed(f) => {
+            let n = f.unnamed.iter().filter(|f| !opts(&f.attrs).skip).count();
+            let mut i = 0;
+            let fields = f
+                .unnamed
+                .iter()
+                .map(|f| {
+                    if opts(&f.attrs).skip {
+                        quote! { std::default::Default::default() }
+                    } else if n == 1 {
+                        quote! { netidx_netproto::value::FromValue::from_value(#v)? }
+                    } else {
+                        let idx = Index::from(i);
+                        i += 1;
+                        quote! {
+                            netidx_netproto::value::FromValue::from_value(
+                                __a[#idx].clone()
+                            )?
+                        }
+                    }
+                })
+                .collect::<Vec<_>>();
+            if n == 1 {
+                quote! { Ok(#ctor(#(#fields),*)) }
+            } else {
+                quote! {{
+                    let __a = match #v.cast(netidx_netproto::value::Typ::Array) {
+                        Some(netidx_netproto::value::Value::Array(a)) if a.len() == #n => a,
+                        _ => anyhow::bail!("expected an array of {} elements", #n),
+                    };
+                    Ok(#ctor(#(#fields),*))
+                }}
+            }
+        }
+        Fields::Unit => quote! { Ok(#ctor) },
+    }
+}
+
+// the expressions used to read the fields of a struct, or the
+// bindings of an enum variant
+fn field_exprs(fields: &Fields, bound: bool) -> Vec<TokenStream> {
+    match fields {
+        Fields::Named(f) => f
+            .named
+            .iter()
+            .map(|f| {
+                let name = &f.ident;
+                if bound {
+                    quote! { #name }
+                } else {
+                    quote! { t.#name }
+                }
+            })
+            .collect(),
+        Fields::Unnamed(f) => f
+            .unnamed
+            .iter()
+            .enumerate()
+            .map(|(i, _)| {
+                if bound {
+                    let name = format_ident!("field{}", i);
+                    quote! { #name }
+                } else {
+                    let index = Index::from(i);
+                    quote! { t.#index }
+                }
+            })
+            .collect(),
+        Fields::Unit => vec![],
+    }
+}
+
+// the patterns used to bind the fields of an enum variant
+fn field_pats(fields: &Fields) -> TokenStream {
+    match fields {
+        Fields::Named(f) => {
+            let names = f.named.iter().filter(|f| !opts(&f.attrs).skip).map(|f| &f.ident);
+            quote! { { #(#names,)* .. } }
+        }
+        Fields::Unnamed(f) => {
+            let names = f.unnamed.iter().enumerate().map(|(i, f)| {
+                if opts(&f.attrs).skip {
+                    format_ident!("_")
+                } else {
+                    format_ident!("field{}", i)
+                }
+            });
+            quote! { (#(#names),*) }
+        }
+        Fields::Unit => quote! {},
+    }
+}
+
+pub(crate) fn into_value(typ: &Ident, input: &Data) -> TokenStream {
+    match input {
+        Data::Struct(st) => fields_to_value(&st.fields, &field_exprs(&st.fields, false)),
+        Data::Enum(en) => {
+            let cases = en.variants.iter().map(|v| {
+                let tag = &v.ident;
+                let name = variant_name(v);
+                let pats = field_pats(&v.fields);
+                let val = fields_to_value(&v.fields, &field_exprs(&v.fields, true));
+                match &v.fields {
+                    Fields::Unit => quote! {
+                        #typ::#tag => netidx_netproto::value::Value::String(
+                            netidx_core::chars::Chars::from(#name)
+                        )
+                    },
+                    Fields::Named(_) | Fields::Unnamed(_) => quote! {
+                        #typ::#tag #pats => {
+                            let mut __v = std::collections::BTreeMap::new();
+                            __v.insert(netidx_core::chars::Chars::from(#name), #val);
+                            netidx_netproto::value::Value::Map(std::sync::Arc::new(__v))
+                        }
+                    },
+                }
+            });
+            quote! {
+                match t {
+                    #(#cases),*
+                }
+            }
+        }
+        Data::Union(_) => panic!("unions are not supported by IntoValue"),
+    }
+}
+
+pub(crate) fn from_value(input: &Data) -> TokenStream {
+    match input {
+        Data::Struct(st) => fields_from_value(&st.fields, quote! { Self }, quote! { v }),
+        Data::Enum(en) => {
+            let units =
+                en.variants.iter().filter(|v| matches!(v.fields, Fields::Unit)).map(
+                    |v| {
+                        let tag = &v.ident;
+                        let name = variant_name(v);
+                        quote! { #name => Ok(Self::#tag) }
+                    },
+                );
+            let data = en
+                .variants
+                .iter()
+                .filter(|v| !matches!(v.fields, Fields::Unit))
+                .map(|v| {
+                    let tag = &v.ident;
+                    let name = variant_name(v);
+                    let decode =
+                        fields_from_value(&v.fields, quote! { Self::#tag }, quote! { v });
+                    quote! { #name => #decode }
+                });
+            let data = data.collect::<Vec<_>>();
+            let data = if data.is_empty() {
+                quote! {}
+            } else {
+                quote! {
+                    netidx_netproto::value::Value::Map(m) if m.len() == 1 => {
+                        let (k, v) = m.iter().next().unwrap();
+                        let v = v.clone();
+                        match &**k {
+                            #(#data,)*
+                            k => anyhow::bail!("unknown variant {}", k),
+                        }
+                    }
+                }
+            };
+            quote! {
+                match v {
+                    netidx_netproto::value::Value::String(s) => match &*s {
+                        #(#units,)*
+                        s => anyhow::bail!("unknown variant {}", s),
+                    },
+                    #data
+                    _ => anyhow::bail!("expected a variant name or a single entry map"),
+                }
+            }
+        }
+        Data::Union(_) => panic!("unions are not supported by FromValue"),
+    }
+}
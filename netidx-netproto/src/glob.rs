@@ -9,9 +9,10 @@ use netidx_core::{
     pool::{Pool, Pooled},
     utils,
 };
+use regex::{Regex, RegexSet};
 use std::{
     cmp::{Eq, PartialEq},
-    ops::Deref,
+    ops::{Deref, Range},
     result,
     sync::Arc,
 };
@@ -44,26 +45,85 @@ impl Scope {
 /// * any of the above metacharacters can be escaped with a \, a
 /// literal \ may be produced with \\.
 ///
+/// A glob prefixed with `re:` is instead a regular expression, which
+/// must match the entire path. A glob (or regex) prefixed with `!` is
+/// an exclusion, it removes paths from the `GlobSet` it is part of.
+///
 /// e.g.
 /// `/solar/{stats,settings}/*` -> all leaf paths under /solar/stats or /solar/settings.
 /// `/s*/s*/**` -> any path who's first two levels start with s.
 /// `/**/?` -> any path who's final component is a single character
 /// `/marketdata/{IBM,MSFT,AMZN}/last`
-#[derive(Debug, Clone, PartialEq, Eq)]
+/// `re:/app/[0-9]+/v` -> /app/1/v, /app/42/v, but not /app/x/v or /app/1/v/x
+/// `!/app/**/debug` -> exclude any path under /app ending in debug
+#[derive(Debug, Clone)]
 pub struct Glob {
     raw: Chars,
     base: Path,
     scope: Scope,
-    glob: globset::Glob,
+    plain: Range<usize>,
+    negated: bool,
+    matcher: Matcher,
+}
+
+/// How a `Glob` matches paths
+#[derive(Debug, Clone)]
+pub enum Matcher {
+    /// a unix style glob, without the leading `!` if it is an exclusion
+    Glob(globset::Glob),
+    /// an anchored regular expression
+    Regex(Regex),
+}
+
+impl PartialEq for Glob {
+    fn eq(&self, other: &Self) -> bool {
+        self.raw == other.raw
+    }
+}
+
+impl Eq for Glob {}
+
+lazy_static! {
+    // paths are never empty
+    static ref NEVER: globset::Glob = globset::Glob::new("").unwrap();
+}
+
+// return the length of the literal prefix of the regex pat
+fn regex_plain(pat: &str) -> usize {
+    let mut depth = 0;
+    let mut class = false;
+    let mut escaped = false;
+    for c in pat.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '[' => class = true,
+            ']' => class = false,
+            '(' if !class => depth += 1,
+            ')' if !class => depth -= 1,
+            '|' if !class && depth == 0 => return 0,
+            _ => (),
+        }
+    }
+    match pat.find(
+        &['\\', '.', '^', '$', '*', '+', '?', '(', ')', '[', ']', '{', '}', '|'][..],
+    ) {
+        None => pat.len(),
+        Some(i) => match pat.as_bytes()[i] {
+            // the quantifier applies to the previous char
+            b'*' | b'+' | b'?' | b'{' => {
+                pat[..i].char_indices().next_back().map(|(i, _)| i).unwrap_or(0)
+            }
+            _ => i,
+        },
+    }
 }
 
 impl Glob {
-    /// return the longest plain string before the first glob char
+    /// return the longest plain string before the first glob char,
+    /// or for a regex, the longest literal prefix.
     pub fn plain(&self) -> &str {
-        match Self::first_glob_char(&*self.raw) {
-            None => &*self.raw,
-            Some(i) => &self.raw[0..i],
-        }
+        &self.raw[self.plain.clone()]
     }
 
     /// returns true if the specified string contains any non escaped
@@ -93,12 +153,37 @@ impl Glob {
     }
 
     pub fn new(raw: Chars) -> Result<Glob> {
-        if !Path::is_absolute(&raw) {
+        let (negated, pat) = match raw.strip_prefix('!') {
+            Some(pat) => (true, pat),
+            None => (false, &*raw),
+        };
+        let start = raw.len() - pat.len();
+        if let Some(pat) = pat.strip_prefix("re:") {
+            let start = start + 3;
+            let plain = start..start + regex_plain(pat);
+            let base = match raw[plain.clone()].rfind('/') {
+                Some(i) if i > 0 && raw[start..].starts_with('/') => {
+                    &raw[start..start + i]
+                }
+                Some(_) | None => "/",
+            };
+            let base = Path::from(ArcStr::from(base));
+            let matcher = Matcher::Regex(Regex::new(&format!("^(?:{})$", pat))?);
+            return Ok(Glob {
+                raw,
+                base,
+                scope: Scope::Subtree,
+                plain,
+                negated,
+                matcher,
+            });
+        }
+        if !Path::is_absolute(pat) {
             bail!("glob paths must be absolute")
         }
         let base = {
             let mut cur = "/";
-            let mut iter = Path::dirnames(&raw);
+            let mut iter = Path::dirnames(pat);
             loop {
                 match iter.next() {
                     None => break cur,
@@ -115,13 +200,17 @@ impl Glob {
         let lvl = Path::levels(base);
         let base = Path::from(ArcStr::from(base));
         let scope =
-            if Path::dirnames(&raw).skip(lvl).any(|p| Path::basename(p) == Some("**")) {
+            if Path::dirnames(pat).skip(lvl).any(|p| Path::basename(p) == Some("**")) {
                 Scope::Subtree
             } else {
-                Scope::Finite(Path::levels(&raw))
+                Scope::Finite(Path::levels(pat))
             };
-        let glob = globset::Glob::new(&*raw)?;
-        Ok(Glob { raw, base, scope, glob })
+        let plain = match Self::first_glob_char(pat) {
+            None => start..raw.len(),
+            Some(i) => start..start + i,
+        };
+        let matcher = Matcher::Glob(globset::Glob::new(pat)?);
+        Ok(Glob { raw, base, scope, plain, negated, matcher })
     }
 
     pub fn base(&self) -> &str {
@@ -132,17 +221,60 @@ impl Glob {
         &self.scope
     }
 
+    /// return the underlying glob. If this is a regex, return a
+    /// glob that matches no path.
+    #[deprecated(note = "a glob may be a regex, use matcher")]
     pub fn glob(&self) -> &globset::Glob {
-        &self.glob
+        match &self.matcher {
+            Matcher::Glob(g) => g,
+            Matcher::Regex(_) => &*NEVER,
+        }
     }
 
+    /// return the underlying glob. If this is a regex, return a
+    /// glob that matches no path.
+    #[deprecated(note = "a glob may be a regex, use matcher")]
     pub fn into_glob(self) -> globset::Glob {
-        self.glob
+        match self.matcher {
+            Matcher::Glob(g) => g,
+            Matcher::Regex(_) => NEVER.clone(),
+        }
+    }
+
+    /// return the underlying glob or regex
+    pub fn matcher(&self) -> &Matcher {
+        &self.matcher
+    }
+
+    /// return the underlying anchored regex, or None if this is a glob
+    pub fn regex(&self) -> Option<&Regex> {
+        match &self.matcher {
+            Matcher::Glob(_) => None,
+            Matcher::Regex(r) => Some(r),
+        }
+    }
+
+    /// true if this is an exclusion
+    pub fn negated(&self) -> bool {
+        self.negated
     }
 
     pub fn raw(&self) -> &Chars {
         &self.raw
     }
+
+    // true if every path starting with plain is matched by self
+    fn covers(&self, plain: &str) -> bool {
+        match &self.matcher {
+            Matcher::Regex(_) => false,
+            Matcher::Glob(_) => {
+                let own = self.plain();
+                own.ends_with('/')
+                    && self.raw[self.plain.end..] == *"**"
+                    && plain.starts_with(own)
+            }
+        }
+    }
 }
 
 impl Pack for Glob {
@@ -159,11 +291,46 @@ impl Pack for Glob {
     }
 }
 
+#[derive(Debug)]
+struct Matchers {
+    include: globset::GlobSet,
+    include_re: RegexSet,
+    exclude: globset::GlobSet,
+    exclude_re: RegexSet,
+}
+
+impl Matchers {
+    fn new(globs: &[Glob]) -> Result<Self> {
+        let mut include = globset::GlobSetBuilder::new();
+        let mut exclude = globset::GlobSetBuilder::new();
+        let mut include_re = vec![];
+        let mut exclude_re = vec![];
+        for glob in globs {
+            match (&glob.matcher, glob.negated) {
+                (Matcher::Glob(g), false) => {
+                    include.add(g.clone());
+                }
+                (Matcher::Glob(g), true) => {
+                    exclude.add(g.clone());
+                }
+                (Matcher::Regex(r), false) => include_re.push(r.as_str()),
+                (Matcher::Regex(r), true) => exclude_re.push(r.as_str()),
+            }
+        }
+        Ok(Matchers {
+            include: include.build()?,
+            include_re: RegexSet::new(include_re)?,
+            exclude: exclude.build()?,
+            exclude_re: RegexSet::new(exclude_re)?,
+        })
+    }
+}
+
 #[derive(Debug)]
 struct GlobSetInner {
     raw: Pooled<Vec<Glob>>,
     published_only: bool,
-    glob: globset::GlobSet,
+    matchers: Matchers,
 }
 
 #[derive(Debug, Clone)]
@@ -181,7 +348,8 @@ impl GlobSet {
     /// create a new globset from the specified globs. if
     /// published_only is true, then the globset will only match
     /// published paths, otherwise it will match both structural and
-    /// published paths.
+    /// published paths. A path matches the set if it matches at
+    /// least one glob, and doesn't match any exclusion.
     pub fn new(
         published_only: bool,
         globs: impl IntoIterator<Item = Glob>,
@@ -189,22 +357,32 @@ impl GlobSet {
         lazy_static! {
             static ref GLOB: Pool<Vec<Glob>> = Pool::new(10, 100);
         }
-        let mut builder = globset::GlobSetBuilder::new();
         let mut raw = GLOB.take();
-        for glob in globs {
-            builder.add(glob.glob.clone());
-            raw.push(glob);
-        }
+        raw.extend(globs);
         raw.sort_unstable_by(|g0, g1| g0.base().cmp(g1.base()));
-        Ok(GlobSet(Arc::new(GlobSetInner {
-            raw,
-            published_only,
-            glob: builder.build()?,
-        })))
+        let matchers = Matchers::new(&raw)?;
+        Ok(GlobSet(Arc::new(GlobSetInner { raw, published_only, matchers })))
     }
 
     pub fn is_match(&self, path: &Path) -> bool {
-        self.0.glob.is_match(path.as_ref())
+        let m = &self.0.matchers;
+        (m.include.is_match(path.as_ref()) || m.include_re.is_match(path))
+            && !(m.exclude.is_match(path.as_ref()) || m.exclude_re.is_match(path))
+    }
+
+    /// the globs that are not exclusions, in order of base
+    pub fn included(&self) -> impl Iterator<Item = &Glob> {
+        self.0.raw.iter().filter(|g| !g.negated)
+    }
+
+    /// the exclusions, in order of base
+    pub fn excluded(&self) -> impl Iterator<Item = &Glob> {
+        self.0.raw.iter().filter(|g| g.negated)
+    }
+
+    // true if every path matched by g is definitely excluded
+    fn excludes(&self, g: &Glob) -> bool {
+        self.excluded().any(|n| n.covers(g.plain()))
     }
 
     pub fn published_only(&self) -> bool {
@@ -218,9 +396,15 @@ impl GlobSet {
     /// possible that this returns true and the globsets are in fact
     /// disjoint.
     pub fn disjoint(&self, other: &Self) -> bool {
-        for g0 in self.0.raw.iter() {
+        for g0 in self.included() {
+            if other.excludes(g0) {
+                continue;
+            }
             let plain0 = g0.plain();
-            for g1 in other.0.raw.iter() {
+            for g1 in other.included() {
+                if self.excludes(g1) {
+                    continue;
+                }
                 let plain1 = g1.plain();
                 if plain0.starts_with(plain1)
                     || plain1.starts_with(plain0)
@@ -256,12 +440,8 @@ impl Pack for GlobSet {
     fn decode(buf: &mut impl Buf) -> result::Result<Self, PackError> {
         let published_only = <bool as Pack>::decode(buf)?;
         let mut raw = <Pooled<Vec<Glob>> as Pack>::decode(buf)?;
-        let mut builder = globset::GlobSetBuilder::new();
-        for glob in raw.iter() {
-            builder.add(glob.glob.clone());
-        }
         raw.sort_unstable_by(|g0, g1| g0.base().cmp(g1.base()));
-        let glob = builder.build().map_err(|_| PackError::InvalidFormat)?;
-        Ok(GlobSet(Arc::new(GlobSetInner { raw, published_only, glob })))
+        let matchers = Matchers::new(&raw).map_err(|_| PackError::InvalidFormat)?;
+        Ok(GlobSet(Arc::new(GlobSetInner { raw, published_only, matchers })))
     }
 }
@@ -0,0 +1,227 @@
+//! The canonical JSON encoding of `Value`.
+//!
+//! - `Null`, `True`, `False`, `String` and `Array` are their JSON
+//!   equivalents
+//! - `I64` and finite `F64` values are JSON numbers
+//! - `Map` is a JSON object
+//! - every other type is an object with exactly one key, the type
+//!   name prefixed with `$`, e.g.
+//!   - `{"$u32": 42}`, and likewise for `$v32`, `$i32`, `$z32`,
+//!     `$u64`, `$v64`, and `$z64`
+//!   - `{"$f32": 4.2}`, non finite floats are the strings `"NaN"`,
+//!     `"inf"`, and `"-inf"`, e.g. `{"$f64": "NaN"}`
+//!   - `{"$decimal": "3.1415"}`
+//!   - `{"$datetime": "2023-06-01T12:00:00.5Z"}` (RFC 3339), or
+//!     `{"$datetime": [secs, nanos]}` since the unix epoch for years
+//!     RFC 3339 can't represent
+//!   - `{"$duration": [secs, nanos]}`
+//!   - `{"$bytes": "aGVsbG8="}` (standard base64)
+//!   - `{"$ok": null}` and `{"$error": "message"}`
+//!   - a `Map` that would be confused with one of the above, because
+//!     it has exactly one key and that key starts with `$`, is
+//!     wrapped as `{"$map": {...}}`
+//!
+//! `from_json` accepts any JSON, objects that aren't tagged as above
+//! are maps, integers that don't fit in an `i64` become `U64`, and
+//! all other numbers become `F64`. Converting a `Value` to JSON and
+//! back is lossless.
+//!
+//! This module may also be used with serde's `with` attribute to
+//! serialize a `Value` field using the canonical encoding.
+use crate::value::Value;
+use anyhow::Result;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use bytes::Bytes;
+use chrono::{prelude::*, SecondsFormat};
+use netidx_core::chars::Chars;
+use rust_decimal::Decimal;
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{Map, Number, Value as JValue};
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+fn tagged(tag: &str, v: JValue) -> JValue {
+    let mut m = Map::new();
+    m.insert(tag.into(), v);
+    JValue::Object(m)
+}
+
+fn float(tag: &str, f: f64) -> JValue {
+    match Number::from_f64(f) {
+        Some(n) => tagged(tag, JValue::Number(n)),
+        None if f.is_nan() => tagged(tag, JValue::from("NaN")),
+        None if f.is_sign_positive() => tagged(tag, JValue::from("inf")),
+        None => tagged(tag, JValue::from("-inf")),
+    }
+}
+
+/// Convert `v` to it's canonical JSON representation
+pub fn to_json(v: &Value) -> JValue {
+    match v {
+        Value::Null => JValue::Null,
+        Value::True => JValue::Bool(true),
+        Value::False => JValue::Bool(false),
+        Value::String(s) => JValue::String(String::from(&**s)),
+        Value::I64(i) => JValue::from(*i),
+        Value::F64(f) => match Number::from_f64(*f) {
+            Some(n) => JValue::Number(n),
+            None => float("$f64", *f),
+        },
+        Value::U32(i) => tagged("$u32", JValue::from(*i)),
+        Value::V32(i) => tagged("$v32", JValue::from(*i)),
+        Value::I32(i) => tagged("$i32", JValue::from(*i)),
+        Value::Z32(i) => tagged("$z32", JValue::from(*i)),
+        Value::U64(i) => tagged("$u64", JValue::from(*i)),
+        Value::V64(i) => tagged("$v64", JValue::from(*i)),
+        Value::Z64(i) => tagged("$z64", JValue::from(*i)),
+        Value::F32(f) => float("$f32", *f as f64),
+        Value::Decimal(d) => tagged("$decimal", JValue::String(d.to_string())),
+        Value::DateTime(d) if (0..=9999).contains(&d.year()) => {
+            let s = d.to_rfc3339_opts(SecondsFormat::AutoSi, true);
+            tagged("$datetime", JValue::String(s))
+        }
+        Value::DateTime(d) => {
+            let a = vec![
+                JValue::from(d.timestamp()),
+                JValue::from(d.timestamp_subsec_nanos()),
+            ];
+            tagged("$datetime", JValue::Array(a))
+        }
+        Value::Duration(d) => {
+            let a = vec![JValue::from(d.as_secs()), JValue::from(d.subsec_nanos())];
+            tagged("$duration", JValue::Array(a))
+        }
+        Value::Bytes(b) => tagged("$bytes", JValue::String(BASE64.encode(b))),
+        Value::Ok => tagged("$ok", JValue::Null),
+        Value::Error(e) => tagged("$error", JValue::String(String::from(&**e))),
+        Value::Array(a) => JValue::Array(a.iter().map(to_json).collect()),
+        Value::Map(m) => {
+            let o = JValue::Object(
+                m.iter().map(|(k, v)| (String::from(&**k), to_json(v))).collect(),
+            );
+            if m.len() == 1 && m.keys().all(|k| k.starts_with('$')) {
+                tagged("$map", o)
+            } else {
+                o
+            }
+        }
+    }
+}
+
+fn get_float(v: &JValue) -> Result<f64> {
+    match v {
+        JValue::Number(n) => n.as_f64().ok_or_else(|| anyhow!("invalid float")),
+        JValue::String(s) => match s.as_str() {
+            "NaN" => Ok(f64::NAN),
+            "inf" => Ok(f64::INFINITY),
+            "-inf" => Ok(f64::NEG_INFINITY),
+            s => bail!("invalid float {}", s),
+        },
+        v => bail!("expected a float, got {}", v),
+    }
+}
+
+fn get_u64(v: &JValue) -> Result<u64> {
+    v.as_u64().ok_or_else(|| anyhow!("expected an unsigned integer, got {}", v))
+}
+
+fn get_i64(v: &JValue) -> Result<i64> {
+    v.as_i64().ok_or_else(|| anyhow!("expected an integer, got {}", v))
+}
+
+// [secs, nanos]
+fn get_secs_nanos(v: &JValue) -> Result<(&JValue, u32)> {
+    match v {
+        JValue::Array(a) if a.len() == 2 => match get_u64(&a[1])? {
+            n if n < 1_000_000_000 => Ok((&a[0], n as u32)),
+            n => bail!("invalid nanos {}", n),
+        },
+        v => bail!("expected [secs, nanos], got {}", v),
+    }
+}
+
+fn get_str(v: &JValue) -> Result<&str> {
+    v.as_str().ok_or_else(|| anyhow!("expected a string, got {}", v))
+}
+
+fn from_map(m: &Map<String, JValue>) -> Result<Value> {
+    let m = m
+        .iter()
+        .map(|(k, v)| Ok((Chars::from(k.clone()), from_json(v)?)))
+        .collect::<Result<BTreeMap<_, _>>>()?;
+    Ok(Value::Map(Arc::new(m)))
+}
+
+fn from_tagged(tag: &str, v: &JValue) -> Result<Value> {
+    Ok(match tag {
+        "$u32" => Value::U32(get_u64(v)?.try_into()?),
+        "$v32" => Value::V32(get_u64(v)?.try_into()?),
+        "$i32" => Value::I32(get_i64(v)?.try_into()?),
+        "$z32" => Value::Z32(get_i64(v)?.try_into()?),
+        "$u64" => Value::U64(get_u64(v)?),
+        "$v64" => Value::V64(get_u64(v)?),
+        "$z64" => Value::Z64(get_i64(v)?),
+        "$f32" => Value::F32(get_float(v)? as f32),
+        "$f64" => Value::F64(get_float(v)?),
+        "$decimal" => Value::Decimal(get_str(v)?.parse::<Decimal>()?),
+        "$datetime" => match v {
+            JValue::String(s) => {
+                Value::DateTime(DateTime::parse_from_rfc3339(s)?.with_timezone(&Utc))
+            }
+            v => {
+                let (secs, nanos) = get_secs_nanos(v)?;
+                match Utc.timestamp_opt(get_i64(secs)?, nanos).single() {
+                    Some(d) => Value::DateTime(d),
+                    None => bail!("datetime out of range"),
+                }
+            }
+        },
+        "$duration" => {
+            let (secs, nanos) = get_secs_nanos(v)?;
+            Value::Duration(Duration::new(get_u64(secs)?, nanos))
+        }
+        "$bytes" => Value::Bytes(Bytes::from(BASE64.decode(get_str(v)?)?)),
+        "$ok" => Value::Ok,
+        "$error" => Value::Error(Chars::from(String::from(get_str(v)?))),
+        "$map" => match v {
+            JValue::Object(m) => from_map(m)?,
+            v => bail!("expected an object, got {}", v),
+        },
+        tag => bail!("unknown type tag {}", tag),
+    })
+}
+
+/// Convert JSON to a `Value`, see the module documentation for
+/// details.
+pub fn from_json(v: &JValue) -> Result<Value> {
+    Ok(match v {
+        JValue::Null => Value::Null,
+        JValue::Bool(true) => Value::True,
+        JValue::Bool(false) => Value::False,
+        JValue::String(s) => Value::String(Chars::from(s.clone())),
+        JValue::Number(n) => match (n.as_i64(), n.as_u64(), n.as_f64()) {
+            (Some(i), _, _) => Value::I64(i),
+            (None, Some(u), _) => Value::U64(u),
+            (None, None, Some(f)) => Value::F64(f),
+            (None, None, None) => bail!("invalid number {}", n),
+        },
+        JValue::Array(a) => {
+            Value::Array(a.iter().map(from_json).collect::<Result<Arc<[Value]>>>()?)
+        }
+        JValue::Object(m) => match m.iter().next() {
+            Some((k, v)) if m.len() == 1 && k.starts_with('$') => from_tagged(k, v)?,
+            Some(_) | None => from_map(m)?,
+        },
+    })
+}
+
+/// Serialize `v` using the canonical JSON encoding, for use with
+/// `#[serde(with = "netidx_netproto::json")]`.
+pub fn serialize<S: Serializer>(v: &Value, s: S) -> Result<S::Ok, S::Error> {
+    to_json(v).serialize(s)
+}
+
+/// Deserialize a `Value` from the canonical JSON encoding, for use
+/// with `#[serde(with = "netidx_netproto::json")]`.
+pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Value, D::Error> {
+    from_json(&JValue::deserialize(d)?).map_err(D::Error::custom)
+}
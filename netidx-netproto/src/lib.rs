@@ -9,6 +9,12 @@ pub mod publisher;
 pub mod value_parser;
 pub mod value;
 pub mod resolver;
+pub mod json;
+pub mod msgpack;
+
+// so the value derives can be tested here
+#[cfg(test)]
+extern crate self as netidx_netproto;
 
 #[cfg(test)]
 mod test;
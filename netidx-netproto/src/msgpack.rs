@@ -0,0 +1,178 @@
+//! The canonical MessagePack encoding of `Value`.
+//!
+//! - `Null`, `True`, `False`, `I64`, `F32`, `F64`, `String`, `Bytes`,
+//!   and `Array` are their MessagePack equivalents (nil, boolean,
+//!   integer, float 32, float 64, str, bin, and array)
+//! - `Map` is a MessagePack map with str keys
+//! - `DateTime` is the standard timestamp extension (type -1)
+//! - every other type is an extension type with a fixed layout, see
+//!   the `EXT_*` constants. Integers are big endian.
+//!
+//! `from_msgpack` accepts any MessagePack except maps with non string
+//! keys and unknown extension types. Integers that don't fit in an
+//! `i64` become `U64`. Converting a `Value` to MessagePack and back is
+//! lossless.
+use crate::value::Value;
+use anyhow::Result;
+use bytes::Bytes;
+use chrono::prelude::*;
+use netidx_core::chars::Chars;
+use rmpv::{Integer, Utf8String, Value as MValue};
+use rust_decimal::Decimal;
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+/// the standard timestamp extension, 32 bit nanoseconds followed by
+/// 64 bit signed seconds. The 4 and 8 byte forms are also accepted.
+pub const EXT_TIMESTAMP: i8 = -1;
+/// 64 bit seconds followed by 32 bit nanoseconds
+pub const EXT_DURATION: i8 = 1;
+/// the 16 byte `Decimal::serialize` form
+pub const EXT_DECIMAL: i8 = 2;
+/// empty
+pub const EXT_OK: i8 = 3;
+/// the utf8 error message
+pub const EXT_ERROR: i8 = 4;
+pub const EXT_U32: i8 = 5;
+pub const EXT_V32: i8 = 6;
+pub const EXT_I32: i8 = 7;
+pub const EXT_Z32: i8 = 8;
+pub const EXT_U64: i8 = 9;
+pub const EXT_V64: i8 = 10;
+pub const EXT_Z64: i8 = 11;
+
+fn timestamp(d: &DateTime<Utc>) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(12);
+    buf.extend_from_slice(&d.timestamp_subsec_nanos().to_be_bytes());
+    buf.extend_from_slice(&d.timestamp().to_be_bytes());
+    buf
+}
+
+fn duration(d: &Duration) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(12);
+    buf.extend_from_slice(&d.as_secs().to_be_bytes());
+    buf.extend_from_slice(&d.subsec_nanos().to_be_bytes());
+    buf
+}
+
+/// Convert `v` to it's canonical MessagePack representation
+pub fn to_msgpack(v: &Value) -> MValue {
+    match v {
+        Value::Null => MValue::Nil,
+        Value::True => MValue::Boolean(true),
+        Value::False => MValue::Boolean(false),
+        Value::I64(i) => MValue::Integer(Integer::from(*i)),
+        Value::F32(f) => MValue::F32(*f),
+        Value::F64(f) => MValue::F64(*f),
+        Value::String(s) => MValue::String(Utf8String::from(&**s)),
+        Value::Bytes(b) => MValue::Binary(b.to_vec()),
+        Value::U32(i) => MValue::Ext(EXT_U32, i.to_be_bytes().to_vec()),
+        Value::V32(i) => MValue::Ext(EXT_V32, i.to_be_bytes().to_vec()),
+        Value::I32(i) => MValue::Ext(EXT_I32, i.to_be_bytes().to_vec()),
+        Value::Z32(i) => MValue::Ext(EXT_Z32, i.to_be_bytes().to_vec()),
+        Value::U64(i) => MValue::Ext(EXT_U64, i.to_be_bytes().to_vec()),
+        Value::V64(i) => MValue::Ext(EXT_V64, i.to_be_bytes().to_vec()),
+        Value::Z64(i) => MValue::Ext(EXT_Z64, i.to_be_bytes().to_vec()),
+        Value::DateTime(d) => MValue::Ext(EXT_TIMESTAMP, timestamp(d)),
+        Value::Duration(d) => MValue::Ext(EXT_DURATION, duration(d)),
+        Value::Decimal(d) => MValue::Ext(EXT_DECIMAL, d.serialize().to_vec()),
+        Value::Ok => MValue::Ext(EXT_OK, vec![]),
+        Value::Error(e) => MValue::Ext(EXT_ERROR, e.as_bytes().to_vec()),
+        Value::Array(a) => MValue::Array(a.iter().map(to_msgpack).collect()),
+        Value::Map(m) => MValue::Map(
+            m.iter()
+                .map(|(k, v)| (MValue::String(Utf8String::from(&**k)), to_msgpack(v)))
+                .collect(),
+        ),
+    }
+}
+
+fn array<const N: usize>(b: &[u8]) -> Result<[u8; N]> {
+    match b.try_into() {
+        Ok(a) => Ok(a),
+        Err(_) => bail!("expected {} bytes, got {}", N, b.len()),
+    }
+}
+
+fn from_ext(typ: i8, b: &[u8]) -> Result<Value> {
+    Ok(match typ {
+        EXT_U32 => Value::U32(u32::from_be_bytes(array(b)?)),
+        EXT_V32 => Value::V32(u32::from_be_bytes(array(b)?)),
+        EXT_I32 => Value::I32(i32::from_be_bytes(array(b)?)),
+        EXT_Z32 => Value::Z32(i32::from_be_bytes(array(b)?)),
+        EXT_U64 => Value::U64(u64::from_be_bytes(array(b)?)),
+        EXT_V64 => Value::V64(u64::from_be_bytes(array(b)?)),
+        EXT_Z64 => Value::Z64(i64::from_be_bytes(array(b)?)),
+        EXT_TIMESTAMP => {
+            let (secs, nanos) = match b.len() {
+                4 => (u32::from_be_bytes(array(b)?) as i64, 0),
+                8 => {
+                    let v = u64::from_be_bytes(array(b)?);
+                    ((v & 0x3_ffff_ffff) as i64, (v >> 34) as u32)
+                }
+                12 => (
+                    i64::from_be_bytes(array(&b[4..])?),
+                    u32::from_be_bytes(array(&b[..4])?),
+                ),
+                n => bail!("invalid timestamp length {}", n),
+            };
+            if nanos >= 1_000_000_000 {
+                bail!("invalid timestamp nanos {}", nanos)
+            }
+            match Utc.timestamp_opt(secs, nanos).single() {
+                Some(d) => Value::DateTime(d),
+                None => bail!("timestamp out of range"),
+            }
+        }
+        EXT_DURATION => {
+            if b.len() != 12 {
+                bail!("invalid duration length {}", b.len())
+            }
+            let secs = u64::from_be_bytes(array(&b[..8])?);
+            let nanos = u32::from_be_bytes(array(&b[8..])?);
+            if nanos >= 1_000_000_000 {
+                bail!("invalid duration nanos {}", nanos)
+            }
+            Value::Duration(Duration::new(secs, nanos))
+        }
+        EXT_DECIMAL => Value::Decimal(Decimal::deserialize(array(b)?)),
+        EXT_OK => Value::Ok,
+        EXT_ERROR => Value::Error(Chars::from(String::from_utf8(b.to_vec())?)),
+        typ => bail!("unknown extension type {}", typ),
+    })
+}
+
+/// Convert MessagePack to a `Value`, see the module documentation
+/// for details.
+pub fn from_msgpack(v: &MValue) -> Result<Value> {
+    Ok(match v {
+        MValue::Nil => Value::Null,
+        MValue::Boolean(true) => Value::True,
+        MValue::Boolean(false) => Value::False,
+        MValue::Integer(i) => match (i.as_i64(), i.as_u64()) {
+            (Some(i), _) => Value::I64(i),
+            (None, Some(u)) => Value::U64(u),
+            (None, None) => bail!("invalid integer {}", i),
+        },
+        MValue::F32(f) => Value::F32(*f),
+        MValue::F64(f) => Value::F64(*f),
+        MValue::String(s) => match s.as_str() {
+            Some(s) => Value::String(Chars::from(String::from(s))),
+            None => bail!("invalid utf8 in string"),
+        },
+        MValue::Binary(b) => Value::Bytes(Bytes::copy_from_slice(b)),
+        MValue::Array(a) => {
+            Value::Array(a.iter().map(from_msgpack).collect::<Result<Arc<[Value]>>>()?)
+        }
+        MValue::Map(m) => {
+            let m = m
+                .iter()
+                .map(|(k, v)| match k.as_str() {
+                    Some(k) => Ok((Chars::from(String::from(k)), from_msgpack(v)?)),
+                    None => bail!("map keys must be strings, got {}", k),
+                })
+                .collect::<Result<BTreeMap<_, _>>>()?;
+            Value::Map(Arc::new(m))
+        }
+        MValue::Ext(typ, b) => from_ext(*typ, b)?,
+    })
+}
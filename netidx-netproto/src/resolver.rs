@@ -75,6 +75,10 @@ pub struct ServerHelloWrite {
     pub ttl_expired: bool,
     pub auth: AuthWrite,
     pub resolver_id: SocketAddr,
+    /// The member servers replicate writes among themselves, so
+    /// publishers should only write to one of them.
+    #[pack(default)]
+    pub replicated: bool,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Pack)]
@@ -112,6 +116,70 @@ pub enum ToRead {
     ListMatching(GlobSet),
     /// Get the change nr for the specified path
     GetChangeNr(Path),
+    /// Get the metadata attributes attached to the specified path
+    Metadata(Path),
+    /// Search for published paths by name and metadata
+    Search(Search),
+    /// List one page of the paths published under the specified root path
+    ListPage(Path, Cursor),
+    /// List one page of the paths matching the specified glob set
+    ListMatchingPage(GlobSet, Cursor),
+    /// Get the columns of the table rooted at the specified path
+    Columns(Path),
+}
+
+/// A position in a paginated listing. To get the first page set
+/// `after` to `None`, to get the next page set it to the last path
+/// of the previous page.
+#[derive(Clone, Debug, PartialEq, Eq, Pack)]
+pub struct Cursor {
+    pub after: Option<Path>,
+    pub limit: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Pack)]
+pub enum PathMatch {
+    /// A component of the path below the search base contains the
+    /// string
+    Substring(Chars),
+    /// A component of the path below the search base matches the
+    /// regex
+    Regex(Chars),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Pack)]
+pub enum MetadataMatch {
+    /// The metadata contains the key
+    Has(Chars),
+    /// The metadata contains the key with exactly the value
+    Eq(Chars, Chars),
+}
+
+/// Search the published paths under `base`. A path is returned if it
+/// matches `path` (when specified) and all of the `metadata`
+/// predicates. At least one of `path` or `metadata` must be
+/// specified. Results are returned in sorted order, at most `limit`
+/// at a time. To get the next page, search again with `after` set
+/// to the last path returned.
+///
+/// If there are metadata predicates the first one is looked up in
+/// an index. Otherwise the server scans every distinct path component
+/// it knows about with `path`, so a path only search costs time in
+/// proportion to the number of distinct components in the cluster.
+#[derive(Clone, Debug, PartialEq, Eq, Pack)]
+pub struct Search {
+    pub base: Path,
+    pub path: Option<PathMatch>,
+    pub metadata: Pooled<Vec<MetadataMatch>>,
+    pub after: Option<Path>,
+    pub limit: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Pack)]
+pub struct SearchResult {
+    pub paths: Pooled<Vec<Path>>,
+    /// true if there are more results after the last path
+    pub more: bool,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Pack)]
@@ -225,6 +293,17 @@ pub struct ListMatching {
     pub referrals: Pooled<Vec<Referral>>,
 }
 
+/// One page of a paginated listing. Paths are sorted by depth, and
+/// then by name.
+#[derive(Clone, Debug, PartialEq, Eq, Pack)]
+pub struct ListPage {
+    pub paths: Pooled<Vec<Path>>,
+    /// child clusters in scope, only sent with the first page
+    pub referrals: Pooled<Vec<Referral>>,
+    /// true if there are more paths after the last path
+    pub more: bool,
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Pack)]
 pub struct GetChangeNr {
     pub change_number: Z64,
@@ -232,6 +311,10 @@ pub struct GetChangeNr {
     pub referrals: Pooled<Vec<Referral>>,
 }
 
+/// Small key/value attributes attached to a published path,
+/// e.g. description, units, owner, tags.
+pub type Metadata = Pooled<Vec<(Chars, Chars)>>;
+
 #[derive(Clone, Debug, PartialEq, Eq, Pack)]
 pub enum FromRead {
     Publisher(Publisher),
@@ -243,6 +326,10 @@ pub enum FromRead {
     Error(Chars),
     ListMatching(ListMatching),
     GetChangeNr(GetChangeNr),
+    Metadata(Metadata),
+    Search(SearchResult),
+    ListPage(ListPage),
+    Columns(Pooled<Vec<(Path, Z64)>>),
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash, Pack)]
@@ -263,6 +350,13 @@ pub enum ToWrite {
     PublishDefaultWithFlags(Path, u32),
     /// Unpublish a default publisher
     UnpublishDefault(Path),
+    /// Attach metadata to a path you've published, replacing any
+    /// metadata already attached to it
+    SetMetadata(Path, Metadata),
+    /// Register a child cluster at the referral's path, or renew an
+    /// existing registration. The registration expires if it isn't
+    /// renewed within the referral's ttl.
+    RegisterReferral(Referral),
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash, Pack)]
@@ -273,3 +367,99 @@ pub enum FromWrite {
     Denied,
     Error(Chars),
 }
+
+/// Identifies the session of one publisher with one incarnation of a
+/// member server.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Pack)]
+pub struct SessionId {
+    pub member: u64,
+    pub incarnation: u64,
+    pub id: PublisherId,
+}
+
+/// A change to the resolver store, replicated between the member
+/// servers of a cluster.
+#[derive(Clone, Debug, PartialEq, Eq, Pack)]
+pub enum ReplicaCmd {
+    /// Committed by a newly elected leader, changes nothing
+    Noop,
+    /// Start a new session for the publisher, ending any session it
+    /// had with another member server
+    Open(SessionId, Box<Publisher>),
+    /// Apply a batch of writes from the publisher of the session
+    Write(SessionId, Pooled<Vec<ToWrite>>),
+    /// Clear everything published in the session
+    Clear(SessionId),
+    /// Clear everything published in the session and end it
+    Close(SessionId),
+    /// Close every session with `member`, except those with
+    /// incarnation `keep`
+    Expire { member: u64, keep: u64 },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Pack)]
+pub struct ReplicaEntry {
+    pub term: u64,
+    pub cmd: ReplicaCmd,
+}
+
+/// The state of one session in a snapshot of the replicated store
+#[derive(Clone, Debug, PartialEq, Eq, Pack)]
+pub struct ReplicaSession {
+    pub session: SessionId,
+    pub publisher: Publisher,
+    pub writes: Pooled<Vec<ToWrite>>,
+}
+
+/// Messages exchanged between member servers to maintain the
+/// replicated log.
+#[derive(Clone, Debug, PartialEq, Eq, Pack)]
+pub enum ReplicaMsg {
+    /// The first message on every connection
+    Hello {
+        member: u64,
+    },
+    RequestVote {
+        term: u64,
+        last_index: u64,
+        last_term: u64,
+    },
+    Vote {
+        term: u64,
+        granted: bool,
+    },
+    Append {
+        term: u64,
+        prev_index: u64,
+        prev_term: u64,
+        commit: u64,
+        entries: Pooled<Vec<ReplicaEntry>>,
+    },
+    /// If success is false `last_index` is the follower's last log
+    /// index, otherwise it is the index of the last entry appended.
+    AppendReply {
+        term: u64,
+        success: bool,
+        last_index: u64,
+    },
+    /// A chunk of a snapshot of the store at `index`. The snapshot
+    /// is complete when `done` is true.
+    Snapshot {
+        term: u64,
+        index: u64,
+        index_term: u64,
+        sessions: Pooled<Vec<ReplicaSession>>,
+        done: bool,
+    },
+    /// Ask the leader to append `cmd` to the log
+    Propose {
+        id: u64,
+        cmd: ReplicaCmd,
+    },
+    /// The reply to `Propose`, index is 0 if the proposal was rejected
+    Proposed {
+        id: u64,
+        index: u64,
+        term: u64,
+    },
+}
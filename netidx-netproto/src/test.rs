@@ -64,9 +64,11 @@ mod resolver {
         glob::{Glob, GlobSet},
         resolver::{
             Auth, AuthChallenge, AuthRead, AuthWrite, ClientHello, ClientHelloWrite,
-            FromRead, FromWrite, GetChangeNr, HashMethod, ListMatching, Publisher,
-            PublisherId, PublisherRef, ReadyForOwnershipCheck, Referral, Resolved,
-            Secret, ServerHelloWrite, Table, TargetAuth, ToRead, ToWrite,
+            Cursor, FromRead, FromWrite, GetChangeNr, HashMethod, ListMatching, ListPage,
+            Metadata, MetadataMatch, PathMatch, Publisher, PublisherId, PublisherRef,
+            ReadyForOwnershipCheck, Referral, ReplicaCmd, ReplicaEntry, ReplicaMsg,
+            ReplicaSession, Resolved, Search, SearchResult, Secret, ServerHelloWrite,
+            SessionId, Table, TargetAuth, ToRead, ToWrite,
         },
     };
     use netidx_core::pack::PackError;
@@ -91,6 +93,7 @@ mod resolver {
         let _: Result<PublisherRef> = Pack::decode(&mut &*b);
         let _: Result<ReadyForOwnershipCheck> = Pack::decode(&mut &*b);
         let _: Result<Referral> = Pack::decode(&mut &*b);
+        let _: Result<ReplicaMsg> = Pack::decode(&mut &*b);
         let _: Result<Resolved> = Pack::decode(&mut &*b);
         let _: Result<Secret> = Pack::decode(&mut &*b);
         let _: Result<ServerHelloWrite> = Pack::decode(&mut &*b);
@@ -146,18 +149,18 @@ mod resolver {
     }
 
     fn server_hello_write() -> impl Strategy<Value = ServerHelloWrite> {
-        (any::<u64>(), any::<bool>(), any::<SocketAddr>(), auth_write()).prop_map(
-            |(ttl, ttl_expired, resolver_id, auth)| ServerHelloWrite {
-                ttl,
-                ttl_expired,
-                auth,
-                resolver_id,
-            },
-        )
+        (any::<u64>(), any::<bool>(), any::<SocketAddr>(), auth_write(), any::<bool>())
+            .prop_map(|(ttl, ttl_expired, resolver_id, auth, replicated)| {
+                ServerHelloWrite { ttl, ttl_expired, auth, resolver_id, replicated }
+            })
     }
 
     fn glob() -> impl Strategy<Value = Glob> {
-        chars_regex("/[a-zA-Z0-9*/]+").prop_map(|c| Glob::new(c).unwrap())
+        prop_oneof![
+            chars_regex("!?/[a-zA-Z0-9*/]+"),
+            chars_regex("!?re:/[a-zA-Z0-9/]+(\\.\\*|\\[0-9\\]\\+)?"),
+        ]
+        .prop_map(|c| Glob::new(c).unwrap())
     }
 
     fn globset() -> impl Strategy<Value = GlobSet> {
@@ -175,9 +178,49 @@ mod resolver {
             path().prop_map(ToRead::Table),
             globset().prop_map(ToRead::ListMatching),
             path().prop_map(ToRead::GetChangeNr),
+            path().prop_map(ToRead::Metadata),
+            search().prop_map(ToRead::Search),
+            (path(), cursor()).prop_map(|(p, c)| ToRead::ListPage(p, c)),
+            (globset(), cursor()).prop_map(|(g, c)| ToRead::ListMatchingPage(g, c)),
+            path().prop_map(ToRead::Columns),
+        ]
+    }
+
+    fn cursor() -> impl Strategy<Value = Cursor> {
+        (option(path()), any::<u32>()).prop_map(|(after, limit)| Cursor { after, limit })
+    }
+
+    fn path_match() -> impl Strategy<Value = PathMatch> {
+        prop_oneof![
+            chars().prop_map(PathMatch::Substring),
+            chars().prop_map(PathMatch::Regex),
         ]
     }
 
+    fn metadata_match() -> impl Strategy<Value = MetadataMatch> {
+        prop_oneof![
+            chars().prop_map(MetadataMatch::Has),
+            (chars(), chars()).prop_map(|(k, v)| MetadataMatch::Eq(k, v)),
+        ]
+    }
+
+    fn search() -> impl Strategy<Value = Search> {
+        (
+            path(),
+            option(path_match()),
+            collection::vec(metadata_match(), (0, 10)),
+            option(path()),
+            any::<u32>(),
+        )
+            .prop_map(|(base, path, metadata, after, limit)| Search {
+                base,
+                path,
+                metadata: Pooled::orphan(metadata),
+                after,
+                limit,
+            })
+    }
+
     fn publisher_id() -> impl Strategy<Value = PublisherId> {
         any::<u64>().prop_map(PublisherId::mk)
     }
@@ -270,6 +313,16 @@ mod resolver {
             .prop_map(|(matched, referrals)| ListMatching { matched, referrals })
     }
 
+    fn list_page() -> impl Strategy<Value = ListPage> {
+        let paths = collection::vec(path(), (0, 100)).prop_map(Pooled::orphan);
+        let referrals = collection::vec(referral(), (0, 10)).prop_map(Pooled::orphan);
+        (paths, referrals, any::<bool>()).prop_map(|(paths, referrals, more)| ListPage {
+            paths,
+            referrals,
+            more,
+        })
+    }
+
     fn get_change_nr() -> impl Strategy<Value = GetChangeNr> {
         let change_number = any::<u64>().prop_map(|v| Z64(v));
         let resolver = any::<SocketAddr>();
@@ -283,6 +336,15 @@ mod resolver {
         )
     }
 
+    fn metadata() -> impl Strategy<Value = Metadata> {
+        collection::vec((chars(), chars()), (0, 10)).prop_map(Pooled::orphan)
+    }
+
+    fn search_result() -> impl Strategy<Value = SearchResult> {
+        (collection::vec(path(), (0, 100)), any::<bool>())
+            .prop_map(|(paths, more)| SearchResult { paths: Pooled::orphan(paths), more })
+    }
+
     fn from_read() -> impl Strategy<Value = FromRead> {
         prop_oneof![
             publisher().prop_map(FromRead::Publisher),
@@ -294,7 +356,12 @@ mod resolver {
             table().prop_map(FromRead::Table),
             referral().prop_map(FromRead::Referral),
             Just(FromRead::Denied),
-            chars().prop_map(FromRead::Error)
+            chars().prop_map(FromRead::Error),
+            metadata().prop_map(FromRead::Metadata),
+            search_result().prop_map(FromRead::Search),
+            list_page().prop_map(FromRead::ListPage),
+            collection::vec((path(), any::<u64>().prop_map(Z64)), (0, 100))
+                .prop_map(|v| FromRead::Columns(Pooled::orphan(v))),
         ]
     }
 
@@ -317,7 +384,10 @@ mod resolver {
                 .prop_map(|(path, flags)| ToWrite::PublishWithFlags(path, flags)),
             (path(), any::<u32>())
                 .prop_map(|(path, flags)| ToWrite::PublishDefaultWithFlags(path, flags)),
-            path().prop_map(ToWrite::UnpublishDefault)
+            path().prop_map(ToWrite::UnpublishDefault),
+            (path(), metadata())
+                .prop_map(|(path, metadata)| ToWrite::SetMetadata(path, metadata)),
+            referral().prop_map(ToWrite::RegisterReferral)
         ]
     }
 
@@ -331,6 +401,99 @@ mod resolver {
         ]
     }
 
+    fn session_id() -> impl Strategy<Value = SessionId> {
+        (any::<u64>(), any::<u64>(), publisher_id())
+            .prop_map(|(member, incarnation, id)| SessionId { member, incarnation, id })
+    }
+
+    fn replica_cmd() -> impl Strategy<Value = ReplicaCmd> {
+        prop_oneof![
+            Just(ReplicaCmd::Noop),
+            (session_id(), publisher())
+                .prop_map(|(s, p)| ReplicaCmd::Open(s, Box::new(p))),
+            (session_id(), collection::vec(to_write(), (0, 10)))
+                .prop_map(|(s, b)| ReplicaCmd::Write(s, Pooled::orphan(b))),
+            session_id().prop_map(ReplicaCmd::Clear),
+            session_id().prop_map(ReplicaCmd::Close),
+            (any::<u64>(), any::<u64>())
+                .prop_map(|(member, keep)| ReplicaCmd::Expire { member, keep })
+        ]
+    }
+
+    fn replica_entry() -> impl Strategy<Value = ReplicaEntry> {
+        (any::<u64>(), replica_cmd()).prop_map(|(term, cmd)| ReplicaEntry { term, cmd })
+    }
+
+    fn replica_session() -> impl Strategy<Value = ReplicaSession> {
+        (session_id(), publisher(), collection::vec(to_write(), (0, 10))).prop_map(
+            |(session, publisher, writes)| ReplicaSession {
+                session,
+                publisher,
+                writes: Pooled::orphan(writes),
+            },
+        )
+    }
+
+    fn replica_msg() -> impl Strategy<Value = ReplicaMsg> {
+        prop_oneof![
+            any::<u64>().prop_map(|member| ReplicaMsg::Hello { member }),
+            (any::<u64>(), any::<u64>(), any::<u64>()).prop_map(
+                |(term, last_index, last_term)| ReplicaMsg::RequestVote {
+                    term,
+                    last_index,
+                    last_term
+                }
+            ),
+            (any::<u64>(), any::<bool>())
+                .prop_map(|(term, granted)| ReplicaMsg::Vote { term, granted }),
+            (
+                any::<u64>(),
+                any::<u64>(),
+                any::<u64>(),
+                any::<u64>(),
+                collection::vec(replica_entry(), (0, 10))
+            )
+                .prop_map(
+                    |(term, prev_index, prev_term, commit, entries)| {
+                        ReplicaMsg::Append {
+                            term,
+                            prev_index,
+                            prev_term,
+                            commit,
+                            entries: Pooled::orphan(entries),
+                        }
+                    },
+                ),
+            (any::<u64>(), any::<bool>(), any::<u64>()).prop_map(
+                |(term, success, last_index)| ReplicaMsg::AppendReply {
+                    term,
+                    success,
+                    last_index
+                }
+            ),
+            (
+                any::<u64>(),
+                any::<u64>(),
+                any::<u64>(),
+                collection::vec(replica_session(), (0, 10)),
+                any::<bool>()
+            )
+                .prop_map(|(term, index, index_term, sessions, done)| {
+                    ReplicaMsg::Snapshot {
+                        term,
+                        index,
+                        index_term,
+                        sessions: Pooled::orphan(sessions),
+                        done,
+                    }
+                }),
+            (any::<u64>(), replica_cmd())
+                .prop_map(|(id, cmd)| ReplicaMsg::Propose { id, cmd }),
+            (any::<u64>(), any::<u64>(), any::<u64>())
+                .prop_map(|(id, index, term)| ReplicaMsg::Proposed { id, index, term })
+        ]
+    }
+
     proptest! {
         #[test]
         fn test_fuzz(b in bytes()) {
@@ -381,12 +544,18 @@ mod resolver {
         fn test_read_for_ownership_check(a in ready_for_ownership_check()) {
             check(a)
         }
+
+        #[test]
+        fn test_replica_msg(a in replica_msg()) {
+            check(a)
+        }
     }
 }
 
 mod publisher {
     use super::*;
     use crate::{
+        json, msgpack,
         publisher::{From, Hello, Id, To},
         value::Value,
     };
@@ -470,8 +639,12 @@ mod publisher {
             chars().prop_map(Value::Error),
         ];
         leaf.prop_recursive(10, 1000, 100, |inner| {
-            prop_oneof![collection::vec(inner.clone(), 0..100)
-                .prop_map(|e| Value::Array(Arc::from(e)))]
+            prop_oneof![
+                collection::vec(inner.clone(), 0..100)
+                    .prop_map(|e| Value::Array(Arc::from(e))),
+                collection::btree_map(chars(), inner.clone(), 0..100)
+                    .prop_map(|m| Value::Map(Arc::new(m)))
+            ]
         })
     }
 
@@ -504,6 +677,13 @@ mod publisher {
                 e0.len() == e1.len()
                     && e0.iter().zip(e1.iter()).all(|(v0, v1)| vequiv(v0, v1))
             }
+            (Value::Map(m0), Value::Map(m1)) => {
+                m0.len() == m1.len()
+                    && m0
+                        .iter()
+                        .zip(m1.iter())
+                        .all(|((k0, v0), (k1, v1))| k0 == k1 && vequiv(v0, v1))
+            }
             (v0, v1) => v0 == v1,
         }
     }
@@ -514,6 +694,63 @@ mod publisher {
         assert!(vequiv(&v, &v_))
     }
 
+    fn round_trip_json(v: Value) {
+        let s = serde_json::to_string(&json::to_json(&v)).unwrap();
+        let j = serde_json::from_str::<serde_json::Value>(&s).unwrap();
+        let v_ = json::from_json(&j).unwrap();
+        assert!(vequiv(&v, &v_))
+    }
+
+    fn round_trip_msgpack(v: Value) {
+        let mut buf = Vec::new();
+        rmpv::encode::write_value(&mut buf, &msgpack::to_msgpack(&v)).unwrap();
+        let m = rmpv::decode::read_value(&mut &buf[..]).unwrap();
+        let v_ = msgpack::from_msgpack(&m).unwrap();
+        assert!(vequiv(&v, &v_))
+    }
+
+    #[test]
+    fn json_encoding() {
+        use serde_json::json;
+        let map = |kvs: &[(&str, Value)]| {
+            Value::Map(Arc::new(
+                kvs.iter()
+                    .map(|(k, v)| (Chars::from(String::from(*k)), v.clone()))
+                    .collect(),
+            ))
+        };
+        let cases = [
+            (Value::I64(-42), json!(-42)),
+            (Value::F64(4.5), json!(4.5)),
+            (Value::U32(42), json!({"$u32": 42})),
+            (Value::Z64(-42), json!({"$z64": -42})),
+            (Value::F64(f64::NAN), json!({"$f64": "NaN"})),
+            (Value::F32(f32::NEG_INFINITY), json!({"$f32": "-inf"})),
+            (Value::Decimal(Decimal::new(31415, 4)), json!({"$decimal": "3.1415"})),
+            (
+                Value::DateTime(Utc.timestamp_opt(1685620800, 500_000_000).unwrap()),
+                json!({"$datetime": "2023-06-01T12:00:00.500Z"}),
+            ),
+            (Value::Duration(Duration::new(3, 5)), json!({"$duration": [3, 5]})),
+            (Value::Bytes(Bytes::from_static(b"hello")), json!({"$bytes": "aGVsbG8="})),
+            (Value::Ok, json!({"$ok": null})),
+            (
+                map(&[("a", Value::I64(1)), ("$b", Value::Null)]),
+                json!({"a": 1, "$b": null}),
+            ),
+            (map(&[("$u32", Value::I64(1))]), json!({"$map": {"$u32": 1}})),
+        ];
+        for (v, j) in cases {
+            assert_eq!(json::to_json(&v), j);
+            let v_ = json::from_json(&j).unwrap();
+            assert!(vequiv(&v, &v_) || format!("{v:?}") == format!("{v_:?}"));
+        }
+        assert_eq!(json::from_json(&json!(u64::MAX)).unwrap(), Value::U64(u64::MAX));
+        assert_eq!(json::from_json(&json!(1.5)).unwrap(), Value::F64(1.5));
+        assert!(json::from_json(&json!({"$nope": 1})).is_err());
+        assert!(json::from_json(&json!({"$u32": -1})).is_err());
+    }
+
     proptest! {
         #[test]
         fn test_fuzz(b in bytes()) {
@@ -539,5 +776,358 @@ mod publisher {
         fn test_value_roundtrip(v in value()) {
             round_trip(v)
         }
+
+        #[test]
+        fn test_value_json(v in value()) {
+            round_trip_json(v)
+        }
+
+        #[test]
+        fn test_value_msgpack(v in value()) {
+            round_trip_msgpack(v)
+        }
+    }
+}
+
+mod value_derive {
+    use crate::value::{FromValue, Value};
+    use netidx_derive::{FromValue, IntoValue};
+    use std::collections::HashMap;
+
+    #[derive(Debug, Clone, PartialEq, Eq, IntoValue, FromValue)]
+    enum Side {
+        Buy,
+        #[value(rename = "sell")]
+        Sell,
+    }
+
+    #[derive(Debug, Clone, PartialEq, IntoValue, FromValue)]
+    struct Order {
+        id: u64,
+        side: Side,
+        #[value(rename = "qty")]
+        quantity: i64,
+        #[value(default)]
+        note: Option<String>,
+        #[value(skip)]
+        cache: Vec<u64>,
+    }
+
+    #[derive(Debug, Clone, PartialEq, IntoValue, FromValue)]
+    struct Point(f64, f64);
+
+    #[derive(Debug, Clone, PartialEq, IntoValue, FromValue)]
+    struct Wrapped<T>(T);
+
+    #[derive(Debug, Clone, PartialEq, IntoValue, FromValue)]
+    enum Shape {
+        Empty,
+        Circle { center: Point, radius: f64 },
+        Poly(Vec<Point>),
+    }
+
+    fn round_trip<T: FromValue + Into<Value> + Clone + PartialEq + std::fmt::Debug>(
+        t: T,
+    ) -> Value {
+        let v: Value = t.clone().into();
+        assert_eq!(t, v.clone().cast_to::<T>().unwrap());
+        v
+    }
+
+    #[test]
+    fn structs() {
+        let o = Order {
+            id: 42,
+            side: Side::Sell,
+            quantity: -3,
+            note: Some("rush".into()),
+            cache: vec![],
+        };
+        let v = round_trip(o.clone());
+        let expected: Value =
+            r#"{"id": u64:42, "note": "rush", "qty": -3, "side": "sell"}"#.parse().unwrap();
+        assert_eq!(v, expected);
+        // defaulted fields may be missing, unknown fields are ignored
+        let v: Value =
+            r#"{"id": 1, "side": "Buy", "qty": 7, "extra": null}"#.parse().unwrap();
+        let o = v.cast_to::<Order>().unwrap();
+        assert_eq!(o.note, None);
+        assert_eq!(o.side, Side::Buy);
+        // the old array of pairs encoding is still accepted
+        let v: Value = r#"[["id", 1], ["side", "Buy"], ["qty", 7]]"#.parse().unwrap();
+        assert_eq!(v.cast_to::<Order>().unwrap().quantity, 7);
+        let v: Value = r#"{"id": 1, "side": "Buy"}"#.parse().unwrap();
+        assert!(v.cast_to::<Order>().is_err());
+        let v: Value = r#"{"id": 1, "side": "Hold", "qty": 1}"#.parse().unwrap();
+        assert!(v.cast_to::<Order>().is_err());
+        assert_eq!(round_trip(Point(1., 2.)), Value::from((1., 2.)));
+        assert_eq!(round_trip(Wrapped(3u32)), Value::U32(3));
+        let m = HashMap::from([("a".to_string(), 1i64), ("b".to_string(), 2)]);
+        assert_eq!(
+            round_trip(Wrapped(m.clone())).cast_to::<HashMap<String, i64>>().unwrap(),
+            m
+        );
+    }
+
+    #[test]
+    fn enums() {
+        assert_eq!(round_trip(Side::Buy), Value::from("Buy"));
+        assert_eq!(round_trip(Shape::Empty), Value::from("Empty"));
+        let v = round_trip(Shape::Circle { center: Point(0., 1.), radius: 2. });
+        let expected: Value =
+            r#"{"Circle": {"center": [0., 1.], "radius": 2.}}"#.parse().unwrap();
+        assert_eq!(v, expected);
+        round_trip(Shape::Poly(vec![Point(0., 0.), Point(1., 1.)]));
+        assert!(Value::from("Square").cast_to::<Shape>().is_err());
+    }
+}
+
+mod pack_derive {
+    use bytes::BytesMut;
+    use netidx_core::pack::{Pack, PackError};
+
+    // the types as they were first written
+    mod v1 {
+        use netidx_derive::Pack;
+
+        #[derive(Debug, Clone, PartialEq, Pack)]
+        pub struct Account {
+            #[pack(tag = 1)]
+            pub id: u64,
+            #[pack(tag = 2)]
+            pub name: String,
+        }
+
+        #[derive(Debug, Clone, PartialEq, Pack)]
+        pub enum Status {
+            #[pack(tag = 0)]
+            Active,
+            #[pack(tag = 1)]
+            Closed(String),
+        }
+
+        #[derive(Debug, Clone, PartialEq, Pack)]
+        pub struct Position {
+            pub id: u64,
+            pub qty: i64,
+        }
+    }
+
+    // the types after some evolution
+    mod v2 {
+        use netidx_derive::Pack;
+
+        #[derive(Debug, Clone, PartialEq, Pack)]
+        pub struct Account {
+            #[pack(tag = 2)]
+            pub name: String,
+            #[pack(tag = 3, since = 2)]
+            pub email: Option<String>,
+            #[pack(tag = 1)]
+            pub id: u64,
+            #[pack(skip)]
+            pub cached: bool,
+        }
+
+        #[derive(Debug, Clone, PartialEq, Pack)]
+        pub struct Strict {
+            #[pack(tag = 1)]
+            pub id: u64,
+            #[pack(tag = 5)]
+            pub required: u64,
+        }
+
+        #[derive(Debug, Clone, PartialEq, Pack)]
+        pub enum Status {
+            #[pack(tag = 2)]
+            Frozen { until: u64 },
+            #[pack(tag = 1)]
+            Closed(String),
+            #[pack(tag = 0)]
+            Active,
+        }
+
+        #[derive(Debug, Clone, PartialEq, Pack)]
+        pub struct Position {
+            pub id: u64,
+            pub qty: i64,
+            #[pack(since = 2)]
+            pub price: f64,
+            #[pack(since = 3)]
+            pub venue: Option<String>,
+        }
+    }
+
+    fn encode<T: Pack>(t: &T) -> Vec<u8> {
+        let mut buf = BytesMut::new();
+        t.encode(&mut buf).unwrap();
+        assert_eq!(buf.len(), t.encoded_len());
+        buf.to_vec()
+    }
+
+    fn decode<T: Pack>(mut bytes: &[u8]) -> Result<T, PackError> {
+        let t = T::decode(&mut bytes)?;
+        assert_eq!(bytes.len(), 0);
+        Ok(t)
+    }
+
+    // encoded by the v1 types, these must never change
+    const V1_ACCOUNT: [u8; 19] =
+        [19, 1, 8, 0, 0, 0, 0, 0, 0, 0, 42, 2, 6, 5, 97, 108, 105, 99, 101];
+    const V1_ACTIVE: [u8; 2] = [2, 0];
+    const V1_CLOSED: [u8; 8] = [8, 1, 5, 102, 114, 97, 117, 100];
+    const V1_POSITION: [u8; 17] =
+        [17, 0, 0, 0, 0, 0, 0, 0, 7, 255, 255, 255, 255, 255, 255, 255, 156];
+
+    // encoded by the v2 types
+    const V2_ACCOUNT: [u8; 28] = [
+        28, 2, 6, 5, 97, 108, 105, 99, 101, 3, 7, 1, 5, 97, 64, 98, 46, 99, 1, 8, 0, 0,
+        0, 0, 0, 0, 0, 42,
+    ];
+    const V2_FROZEN: [u8; 10] = [10, 2, 0, 0, 0, 0, 0, 0, 0, 99];
+    const V2_POSITION: [u8; 28] = [
+        28, 0, 0, 0, 0, 0, 0, 0, 7, 255, 255, 255, 255, 255, 255, 255, 156, 63, 248, 0,
+        0, 0, 0, 0, 0, 1, 1, 120,
+    ];
+
+    fn v2_account() -> v2::Account {
+        v2::Account {
+            id: 42,
+            name: "alice".into(),
+            email: Some("a@b.c".into()),
+            cached: false,
+        }
+    }
+
+    fn v2_position() -> v2::Position {
+        v2::Position { id: 7, qty: -100, price: 1.5, venue: Some("x".into()) }
+    }
+
+    #[test]
+    fn stable_encoding() {
+        assert_eq!(encode(&v1::Account { id: 42, name: "alice".into() }), V1_ACCOUNT);
+        assert_eq!(encode(&v1::Status::Active), V1_ACTIVE);
+        assert_eq!(encode(&v1::Status::Closed("fraud".into())), V1_CLOSED);
+        assert_eq!(encode(&v1::Position { id: 7, qty: -100 }), V1_POSITION);
+        assert_eq!(encode(&v2_account()), V2_ACCOUNT);
+        assert_eq!(encode(&v2::Status::Frozen { until: 99 }), V2_FROZEN);
+        // reordering the variants doesn't change the discriminants
+        assert_eq!(encode(&v2::Status::Active), V1_ACTIVE);
+        assert_eq!(encode(&v2::Status::Closed("fraud".into())), V1_CLOSED);
+        assert_eq!(encode(&v2_position()), V2_POSITION);
+        assert_eq!(decode::<v2::Account>(&V2_ACCOUNT).unwrap(), v2_account());
+        assert_eq!(decode::<v2::Position>(&V2_POSITION).unwrap(), v2_position());
+    }
+
+    #[test]
+    fn new_reads_old() {
+        let a = decode::<v2::Account>(&V1_ACCOUNT).unwrap();
+        assert_eq!(a, v2::Account { email: None, ..v2_account() });
+        assert_eq!(decode::<v2::Status>(&V1_ACTIVE).unwrap(), v2::Status::Active);
+        assert_eq!(
+            decode::<v2::Status>(&V1_CLOSED).unwrap(),
+            v2::Status::Closed("fraud".into())
+        );
+        let p = decode::<v2::Position>(&V1_POSITION).unwrap();
+        assert_eq!(p, v2::Position { id: 7, qty: -100, price: 0., venue: None });
+        // a missing field that is not since or default is an error
+        assert!(matches!(
+            decode::<v2::Strict>(&V1_ACCOUNT),
+            Err(PackError::InvalidFormat)
+        ));
+    }
+
+    #[test]
+    fn old_reads_new() {
+        let a = decode::<v1::Account>(&V2_ACCOUNT).unwrap();
+        assert_eq!(a, v1::Account { id: 42, name: "alice".into() });
+        assert_eq!(
+            decode::<v1::Status>(&encode(&v2::Status::Closed("fraud".into()))).unwrap(),
+            v1::Status::Closed("fraud".into())
+        );
+        assert!(matches!(decode::<v1::Status>(&V2_FROZEN), Err(PackError::UnknownTag)));
+        let p = decode::<v1::Position>(&V2_POSITION).unwrap();
+        assert_eq!(p, v1::Position { id: 7, qty: -100 });
+    }
+}
+
+mod glob {
+    use super::{check, pack};
+    use crate::glob::{Glob, GlobSet, Matcher};
+    use netidx_core::{chars::Chars, path::Path};
+
+    fn glob(s: &'static str) -> Glob {
+        Glob::new(Chars::from(s)).unwrap()
+    }
+
+    fn set(globs: &[&'static str]) -> GlobSet {
+        GlobSet::new(true, globs.iter().map(|s| glob(s))).unwrap()
+    }
+
+    fn matches(set: &GlobSet, path: &'static str) -> bool {
+        set.is_match(&Path::from(path))
+    }
+
+    #[test]
+    fn parse() {
+        let g = glob("!/app/debug/**");
+        assert!(g.negated());
+        assert_eq!(g.base(), "/app/debug");
+        assert_eq!(g.plain(), "/app/debug/");
+        let g = glob("re:/app/[0-9]+/v");
+        assert!(!g.negated());
+        assert!(matches!(g.matcher(), Matcher::Regex(_)) && g.regex().is_some());
+        #[allow(deprecated)]
+        let m = g.glob().compile_matcher();
+        assert!(!m.is_match("/app/1/v"));
+        assert_eq!(g.base(), "/app");
+        assert_eq!(g.plain(), "/app/");
+        assert_eq!(glob("re:/app/xy?").plain(), "/app/x");
+        assert_eq!(glob("re:/app/x|/other").plain(), "");
+        assert_eq!(glob("re:/app/x|/other").base(), "/");
+        assert_eq!(glob("!re:(/a|/b)/c").base(), "/");
+        assert!(Glob::new(Chars::from("!app")).is_err());
+        assert!(Glob::new(Chars::from("re:/app/(")).is_err());
+    }
+
+    #[test]
+    fn is_match() {
+        let s = set(&["/app/**", "!/app/**/debug"]);
+        assert!(matches(&s, "/app/a/x"));
+        assert!(!matches(&s, "/app/a/debug"));
+        assert!(!matches(&s, "/other/x"));
+        let s = set(&["re:/app/[0-9]+/v"]);
+        assert!(matches(&s, "/app/42/v"));
+        assert!(!matches(&s, "/app/x/v"));
+        assert!(!matches(&s, "/app/42/v/x"));
+        assert!(!matches(&s, "/x/app/42/v"));
+        let s = set(&["/app/*/v", "!re:/app/[0-9]+/v"]);
+        assert!(matches(&s, "/app/x/v"));
+        assert!(!matches(&s, "/app/42/v"));
+        let s = set(&["!/app/debug/**"]);
+        assert!(!matches(&s, "/app/x"));
+    }
+
+    #[test]
+    fn disjoint() {
+        let s = set(&["/app/**", "!/app/debug/**"]);
+        assert!(s.disjoint(&set(&["/app/debug/**"])));
+        assert!(s.disjoint(&set(&["re:/app/debug/[0-9]+"])));
+        assert!(!s.disjoint(&set(&["/app/debugger/**"])));
+        assert!(!s.disjoint(&set(&["/app/*/x"])));
+        assert!(set(&["re:/app/x.*"]).disjoint(&set(&["/other/*"])));
+        assert!(!set(&["re:/app/x.*"]).disjoint(&set(&["/app/*"])));
+        assert!(!set(&["re:/a|/b"]).disjoint(&set(&["/b/*"])));
+    }
+
+    #[test]
+    fn encoding() {
+        // plain globs are encoded exactly as before
+        let g = glob("/app/*/v");
+        assert_eq!(pack(&g).unwrap(), pack(g.raw()).unwrap());
+        for g in ["/app/**", "!/app/**/debug", "re:/app/[0-9]+/v", "!re:/a/.*"] {
+            check(glob(g))
+        }
+        check(set(&["/app/**", "!/app/**/debug", "re:/app/[0-9]+/v"]))
     }
 }
@@ -54,10 +54,11 @@ pub enum Typ {
     Bytes,
     Result,
     Array,
+    Map,
     Null,
 }
 
-static TYPES: [Typ; 19] = [
+static TYPES: [Typ; 20] = [
     Typ::U32,
     Typ::V32,
     Typ::I32,
@@ -76,6 +77,7 @@ static TYPES: [Typ; 19] = [
     Typ::Bytes,
     Typ::Result,
     Typ::Array,
+    Typ::Map,
     Typ::Null,
 ];
 
@@ -111,6 +113,7 @@ impl Typ {
             }
             Typ::Result => Ok(s.parse::<Value>()?),
             Typ::Array => Ok(s.parse::<Value>()?),
+            Typ::Map => Ok(s.parse::<Value>()?),
             Typ::Null => {
                 if s.trim() == "null" {
                     Ok(Value::Null)
@@ -141,6 +144,7 @@ impl Typ {
             Typ::Bytes => "bytes",
             Typ::Result => "result",
             Typ::Array => "array",
+            Typ::Map => "map",
             Typ::Null => "null",
         }
     }
@@ -166,6 +170,7 @@ impl Typ {
             Value::Null => Typ::Null,
             Value::Ok | Value::Error(_) => Typ::Result,
             Value::Array(_) => Typ::Array,
+            Value::Map(_) => Typ::Map,
         }
     }
 
@@ -193,6 +198,7 @@ impl Typ {
             | Typ::Bytes
             | Typ::Result
             | Typ::Array
+            | Typ::Map
             | Typ::Null => false,
         }
     }
@@ -217,6 +223,7 @@ impl Typ {
             | Typ::Bytes
             | Typ::Result
             | Typ::Array
+            | Typ::Map
             | Typ::Null => false,
         }
     }
@@ -238,6 +245,7 @@ impl Typ {
             | Typ::Bytes
             | Typ::Result
             | Typ::Array
+            | Typ::Map
             | Typ::Null => false,
         }
     }
@@ -259,6 +267,7 @@ impl Typ {
             | Typ::Bytes
             | Typ::Result
             | Typ::Array
+            | Typ::Map
             | Typ::Null => false,
         }
     }
@@ -281,6 +290,7 @@ impl Typ {
             | Typ::Bytes
             | Typ::Result
             | Typ::Array
+            | Typ::Map
             | Typ::Null => false,
         }
     }
@@ -309,9 +319,10 @@ impl FromStr for Typ {
             "bytes" => Ok(Typ::Bytes),
             "result" => Ok(Typ::Result),
             "array" => Ok(Typ::Array),
+            "map" => Ok(Typ::Map),
             "null" => Ok(Typ::Null),
             s => Err(anyhow!(
-                "invalid type, {}, valid types: u32, i32, u64, i64, f32, f64, bool, string, bytes, result, array, null", s))
+                "invalid type, {}, valid types: u32, i32, u64, i64, f32, f64, bool, string, bytes, result, array, map, null", s))
         }
     }
 }
@@ -367,6 +378,8 @@ pub enum Value {
     Error(Chars),
     /// An array of values
     Array(Arc<[Value]>),
+    /// A map of string keys to values, ordered by key
+    Map(Arc<BTreeMap<Chars, Value>>),
     /// fixed point decimal type
     Decimal(Decimal),
 }
@@ -457,6 +470,13 @@ impl Hash for Value {
                 20u8.hash(state);
                 d.hash(state);
             }
+            Value::Map(m) => {
+                21u8.hash(state);
+                for (k, v) in m.iter() {
+                    k.hash(state);
+                    v.hash(state)
+                }
+            }
         }
     }
 }
@@ -493,6 +513,8 @@ impl PartialEq for Value {
             (Value::Ok | Value::Error(_), Value::Ok | Value::Error(_)) => false,
             (Value::Array(l), Value::Array(r)) => l == r,
             (Value::Array(_), _) | (_, Value::Array(_)) => false,
+            (Value::Map(l), Value::Map(r)) => l == r,
+            (Value::Map(_), _) | (_, Value::Map(_)) => false,
             (l, r) if l.number() || r.number() => {
                 match (l.clone().cast_to::<f64>(), r.clone().cast_to::<f64>()) {
                     (Ok(l), Ok(r)) => match (l.classify(), r.classify()) {
@@ -557,6 +579,9 @@ impl PartialOrd for Value {
             (Value::Array(l), Value::Array(r)) => l.partial_cmp(r),
             (Value::Array(_), _) => Some(Ordering::Less),
             (_, Value::Array(_)) => Some(Ordering::Greater),
+            (Value::Map(l), Value::Map(r)) => l.partial_cmp(r),
+            (Value::Map(_), _) => Some(Ordering::Less),
+            (_, Value::Map(_)) => Some(Ordering::Greater),
             (l, r) if l.number() || r.number() => {
                 match (l.clone().cast_to::<f64>(), r.clone().cast_to::<f64>()) {
                     (Ok(l), Ok(r)) => match (l.classify(), r.classify()) {
@@ -717,6 +742,9 @@ macro_rules! apply_op {
                 Err(e) => Value::Error(Chars::from(format!("{}", e))),
                 Ok(s) => n $op s,
             },
+            (Value::Map(_), _) | (_, Value::Map(_)) => {
+                Value::Error(Chars::from("can't add map"))
+            }
             (Value::Array(e0), Value::Array(e1)) => {
                 let (e0, e1) = if e0.len() < e1.len() { (e0, e1) } else { (e1, e0) };
                 let iter = e0
@@ -908,6 +936,9 @@ impl Not for Value {
             Value::Array(elts) => {
                 Value::Array(elts.iter().cloned().map(|v| !v).collect())
             }
+            Value::Map(m) => Value::Map(Arc::new(
+                m.iter().map(|(k, v)| (k.clone(), !v.clone())).collect(),
+            )),
         }
     }
 }
@@ -937,6 +968,12 @@ impl Pack for Value {
                     + elts.iter().fold(0, |sum, v| sum + Pack::encoded_len(v))
             }
             Value::Decimal(d) => <Decimal as Pack>::encoded_len(d),
+            Value::Map(m) => {
+                pack::varint_len(m.len() as u64)
+                    + m.iter().fold(0, |sum, (k, v)| {
+                        sum + <Chars as Pack>::encoded_len(k) + Pack::encoded_len(v)
+                    })
+            }
         }
     }
 
@@ -1020,6 +1057,15 @@ impl Pack for Value {
                 buf.put_u8(20);
                 <Decimal as Pack>::encode(d, buf)
             }
+            Value::Map(m) => {
+                buf.put_u8(21);
+                pack::encode_varint(m.len() as u64, buf);
+                for (k, v) in m.iter() {
+                    <Chars as Pack>::encode(k, buf)?;
+                    <Value as Pack>::encode(v, buf)?
+                }
+                Ok(())
+            }
         }
     }
 
@@ -1053,6 +1099,16 @@ impl Pack for Value {
                 Ok(Value::Array(Arc::from(elts)))
             }
             20 => Ok(Value::Decimal(<Decimal as Pack>::decode(buf)?)),
+            21 => {
+                let len = pack::decode_varint(buf)? as usize;
+                let mut m = BTreeMap::new();
+                for _ in 0..len {
+                    let k = <Chars as Pack>::decode(buf)?;
+                    let v = <Value as Pack>::decode(buf)?;
+                    m.insert(k, v);
+                }
+                Ok(Value::Map(Arc::new(m)))
+            }
             _ => Err(PackError::UnknownTag),
         }
     }
@@ -1111,6 +1167,7 @@ impl Value {
             Value::Ok => write!(f, "ok"),
             v @ Value::Error(_) => write!(f, "{}", v),
             v @ Value::Array(_) => write!(f, "{}", v),
+            v @ Value::Map(_) => write!(f, "{}", v),
         }
     }
 
@@ -1246,6 +1303,17 @@ impl Value {
                 }
                 write!(f, "]")
             }
+            Value::Map(m) => {
+                write!(f, "{{")?;
+                for (i, (k, v)) in m.iter().enumerate() {
+                    write!(f, r#""{}": "#, utils::escape(&**k, '\\', esc))?;
+                    v.fmt_ext(f, esc, types)?;
+                    if i < m.len() - 1 {
+                        write!(f, ", ")?
+                    }
+                }
+                write!(f, "}}")
+            }
         }
     }
 
@@ -1284,6 +1352,7 @@ impl Value {
                     Typ::Array => {
                         Some(Value::Array(Arc::from(Vec::from([self.clone()]))))
                     }
+                    Typ::Map => None,
                     Typ::Null => Some(Value::Null),
                 }
             };
@@ -1294,10 +1363,25 @@ impl Value {
             }
             v @ Value::String(_) => Some(v),
             v if typ == Typ::String => Some(Value::String(Chars::from(format!("{}", v)))),
+            Value::Array(elts) if typ == Typ::Map => {
+                let mut m = BTreeMap::new();
+                for v in elts.iter() {
+                    let (k, v) = v.clone().get_as::<(Chars, Value)>()?;
+                    m.insert(k, v);
+                }
+                Some(Value::Map(Arc::new(m)))
+            }
             Value::Array(elts) if typ != Typ::Array => {
                 elts.first().and_then(|v| v.clone().cast(typ))
             }
             v @ Value::Array(_) => Some(v),
+            Value::Map(m) if typ == Typ::Array => Some(Value::Array(
+                m.iter()
+                    .map(|(k, v)| Value::from((Value::String(k.clone()), v.clone())))
+                    .collect(),
+            )),
+            v @ Value::Map(_) if typ == Typ::Map => Some(v),
+            Value::Map(_) => None,
             Value::U32(v) | Value::V32(v) => cast_number!(v, typ),
             Value::I32(v) | Value::Z32(v) => cast_number!(v, typ),
             Value::U64(v) | Value::V64(v) => cast_number!(v, typ),
@@ -1319,6 +1403,7 @@ impl Value {
                 Typ::String => Some(Value::String(Chars::from(format!("{}", v)))),
                 Typ::Bool
                 | Typ::Array
+                | Typ::Map
                 | Typ::Bytes
                 | Typ::DateTime
                 | Typ::Duration
@@ -1380,6 +1465,7 @@ impl Value {
                 Typ::Bytes => None,
                 Typ::Result => Some(Value::Ok),
                 Typ::Array => Some(Value::Array(Arc::from(Vec::from([self])))),
+                Typ::Map => None,
                 Typ::Null => Some(Value::Null),
                 Typ::String => unreachable!(),
             },
@@ -1401,6 +1487,7 @@ impl Value {
                 Typ::Bytes => None,
                 Typ::Result => Some(Value::Ok),
                 Typ::Array => Some(Value::Array(Arc::from(Vec::from([self])))),
+                Typ::Map => None,
                 Typ::Null => Some(Value::Null),
                 Typ::String => unreachable!(),
             },
@@ -1424,6 +1511,7 @@ impl Value {
                     Typ::Bytes => None,
                     Typ::Result => Some(Value::Ok),
                     Typ::Array => Some(Value::Array(Arc::from(Vec::from([self])))),
+                    Typ::Map => None,
                     Typ::Null => Some(Value::Null),
                     Typ::String => unreachable!(),
                 }
@@ -1474,7 +1562,8 @@ impl Value {
             | Value::Null
             | Value::Ok
             | Value::Error(_)
-            | Value::Array(_) => false,
+            | Value::Array(_)
+            | Value::Map(_) => false,
         }
     }
 
@@ -2014,6 +2103,28 @@ impl convert::From<Arc<[Value]>> for Value {
     }
 }
 
+impl FromValue for Arc<BTreeMap<Chars, Value>> {
+    fn from_value(v: Value) -> Res<Self> {
+        v.cast(Typ::Map).ok_or_else(|| anyhow!("can't cast")).and_then(|v| match v {
+            Value::Map(m) => Ok(m),
+            _ => bail!("can't cast"),
+        })
+    }
+
+    fn get(v: Value) -> Option<Self> {
+        match v {
+            Value::Map(m) => Some(m),
+            _ => None,
+        }
+    }
+}
+
+impl convert::From<Arc<BTreeMap<Chars, Value>>> for Value {
+    fn from(v: Arc<BTreeMap<Chars, Value>>) -> Value {
+        Value::Map(v)
+    }
+}
+
 /* specialization someday
 
 impl FromValue for Vec<u8> {
@@ -2195,16 +2306,34 @@ impl<K: FromValue + Eq + Hash, V: FromValue, S: BuildHasher + Default> FromValue
     for HashMap<K, V, S>
 {
     fn from_value(v: Value) -> Res<Self> {
-        v.cast(Typ::Array).ok_or_else(|| anyhow!("can't cast")).and_then(|v| match v {
-            Value::Array(elts) => {
-                elts.iter().map(|v| v.clone().cast_to::<(K, V)>()).collect()
-            }
-            _ => bail!("can't cast"),
-        })
+        match v {
+            Value::Map(m) => m
+                .iter()
+                .map(|(k, v)| {
+                    let k = Value::String(k.clone()).cast_to::<K>()?;
+                    Ok((k, v.clone().cast_to::<V>()?))
+                })
+                .collect(),
+            v => v.cast(Typ::Array).ok_or_else(|| anyhow!("can't cast")).and_then(|v| {
+                match v {
+                    Value::Array(elts) => {
+                        elts.iter().map(|v| v.clone().cast_to::<(K, V)>()).collect()
+                    }
+                    _ => bail!("can't cast"),
+                }
+            }),
+        }
     }
 
     fn get(v: Value) -> Option<Self> {
         match v {
+            Value::Map(m) => m
+                .iter()
+                .map(|(k, v)| {
+                    let k = Value::String(k.clone()).get_as::<K>()?;
+                    Some((k, v.clone().get_as::<V>()?))
+                })
+                .collect(),
             Value::Array(elts) => {
                 elts.iter().map(|v| v.clone().get_as::<(K, V)>()).collect()
             }
@@ -2223,16 +2352,34 @@ impl<K: convert::Into<Value>, V: convert::Into<Value>, S: BuildHasher + Default>
 
 impl<K: FromValue + Ord, V: FromValue> FromValue for BTreeMap<K, V> {
     fn from_value(v: Value) -> Res<Self> {
-        v.cast(Typ::Array).ok_or_else(|| anyhow!("can't cast")).and_then(|v| match v {
-            Value::Array(elts) => {
-                elts.iter().map(|v| v.clone().cast_to::<(K, V)>()).collect()
-            }
-            _ => bail!("can't cast"),
-        })
+        match v {
+            Value::Map(m) => m
+                .iter()
+                .map(|(k, v)| {
+                    let k = Value::String(k.clone()).cast_to::<K>()?;
+                    Ok((k, v.clone().cast_to::<V>()?))
+                })
+                .collect(),
+            v => v.cast(Typ::Array).ok_or_else(|| anyhow!("can't cast")).and_then(|v| {
+                match v {
+                    Value::Array(elts) => {
+                        elts.iter().map(|v| v.clone().cast_to::<(K, V)>()).collect()
+                    }
+                    _ => bail!("can't cast"),
+                }
+            }),
+        }
     }
 
     fn get(v: Value) -> Option<Self> {
         match v {
+            Value::Map(m) => m
+                .iter()
+                .map(|(k, v)| {
+                    let k = Value::String(k.clone()).get_as::<K>()?;
+                    Some((k, v.clone().get_as::<V>()?))
+                })
+                .collect(),
             Value::Array(elts) => {
                 elts.iter().map(|v| v.clone().get_as::<(K, V)>()).collect()
             }
@@ -2299,16 +2446,34 @@ impl<K: FromValue + Eq + Hash, V: FromValue, S: BuildHasher + Default> FromValue
     for IndexMap<K, V, S>
 {
     fn from_value(v: Value) -> Res<Self> {
-        v.cast(Typ::Array).ok_or_else(|| anyhow!("can't cast")).and_then(|v| match v {
-            Value::Array(elts) => {
-                elts.iter().map(|v| v.clone().cast_to::<(K, V)>()).collect()
-            }
-            _ => bail!("can't cast"),
-        })
+        match v {
+            Value::Map(m) => m
+                .iter()
+                .map(|(k, v)| {
+                    let k = Value::String(k.clone()).cast_to::<K>()?;
+                    Ok((k, v.clone().cast_to::<V>()?))
+                })
+                .collect(),
+            v => v.cast(Typ::Array).ok_or_else(|| anyhow!("can't cast")).and_then(|v| {
+                match v {
+                    Value::Array(elts) => {
+                        elts.iter().map(|v| v.clone().cast_to::<(K, V)>()).collect()
+                    }
+                    _ => bail!("can't cast"),
+                }
+            }),
+        }
     }
 
     fn get(v: Value) -> Option<Self> {
         match v {
+            Value::Map(m) => m
+                .iter()
+                .map(|(k, v)| {
+                    let k = Value::String(k.clone()).get_as::<K>()?;
+                    Some((k, v.clone().get_as::<V>()?))
+                })
+                .collect(),
             Value::Array(elts) => {
                 elts.iter().map(|v| v.clone().get_as::<(K, V)>()).collect()
             }
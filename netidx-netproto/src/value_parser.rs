@@ -152,6 +152,20 @@ where
             between(token('['), token(']'), sep_by(value(esc), token(',')))
                 .map(|vals: Vec<Value>| Value::Array(Arc::from(vals))),
         ),
+        attempt(
+            between(
+                token('{').skip(spaces()),
+                spaces().with(token('}')),
+                sep_by(
+                    (quoted(esc).skip(spaces()).skip(token(':')), value(esc)),
+                    token(','),
+                ),
+            )
+            .map(|kvs: Vec<(String, Value)>| {
+                let m = kvs.into_iter().map(|(k, v)| (Chars::from(k), v)).collect();
+                Value::Map(Arc::new(m))
+            }),
+        ),
         attempt(quoted(esc)).map(|s| Value::String(Chars::from(s))),
         attempt(from_str(flt()).map(|v| Value::F64(v))),
         attempt(from_str(int()).map(|v| Value::I64(v))),
@@ -221,6 +235,7 @@ pub fn parse_value(s: &str) -> anyhow::Result<Value> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::BTreeMap;
 
     #[test]
     fn parse() {
@@ -268,5 +283,12 @@ mod tests {
             Value::Error(Chars::from("error")),
             parse_value(r#"error:"error""#).unwrap()
         );
+        let m = BTreeMap::from_iter([
+            (Chars::from("a"), Value::I64(1)),
+            (Chars::from("b \"c\""), Value::Array(Arc::from([Value::Null]))),
+        ]);
+        let s = r#"{"b \"c\"": [null], "a" : 1}"#;
+        assert_eq!(Value::Map(Arc::new(m)), parse_value(s).unwrap());
+        assert_eq!(Value::Map(Arc::new(BTreeMap::new())), parse_value("{ }").unwrap());
     }
 }
@@ -1,14 +1,16 @@
+use super::resume::{Msg, Resume};
 use anyhow::{anyhow, Result};
-use futures::{channel::mpsc, prelude::*};
+use futures::{channel::mpsc, pin_mut, prelude::*, select_biased};
 use netidx::{
     path::Path,
     pool::{Pool, Pooled},
     subscriber::{Event, SubId, Subscriber, UpdatesFlags, Val, Value},
 };
+use parking_lot::Mutex as SyncMutex;
 use std::{
     collections::VecDeque,
     sync::atomic::{AtomicBool, Ordering},
-    time::Duration,
+    time::{Duration, Instant},
 };
 use tokio::{sync::Mutex, time};
 
@@ -75,13 +77,19 @@ impl Receiver {
     }
 }
 
+type Updates = mpsc::Receiver<Pooled<Vec<(SubId, Event)>>>;
+
 /// A connection is a bidirectional channel between two endpoints.
 pub struct Connection {
-    _subscriber: Subscriber,
-    con: Val,
+    subscriber: Subscriber,
+    path: Path,
+    // a resumable session replaces the subscription when it
+    // reconnects
+    con: SyncMutex<Val>,
     receiver: Mutex<Receiver>,
     dead: AtomicBool,
     dirty: AtomicBool,
+    resume: Option<Resume>,
 }
 
 impl Connection {
@@ -105,13 +113,19 @@ impl Connection {
                 }
             }
         };
+        let resume = match con.last() {
+            Event::Update(v) => Resume::from_anchor(&v),
+            Event::Unsubscribed => None,
+        };
         con.updates(UpdatesFlags::empty(), tx);
-        let con = Connection {
-            _subscriber: subscriber.clone(),
-            con,
+        let mut con = Connection {
+            subscriber: subscriber.clone(),
+            path,
+            con: SyncMutex::new(con),
             dead: AtomicBool::new(false),
             dirty: AtomicBool::new(false),
             receiver: Mutex::new(Receiver { updates: rx, queued: VecDeque::new() }),
+            resume: None,
         };
         con.send(Value::from("ready"))?;
         match time::timeout(Duration::from_secs(15), con.recv_one()).await {
@@ -123,11 +137,14 @@ impl Connection {
                 v
             ),
         }
+        con.resume = resume;
         Ok(con)
     }
 
     /// Connect to the endpoint at the specified path. The endpoint
-    /// may be either a listener or a singleton.
+    /// may be either a listener or a singleton. If the endpoint is
+    /// resumable then the connection will transparently reconnect
+    /// and resume the session if the subscription is lost.
     pub async fn connect(subscriber: &Subscriber, path: Path) -> Result<Self> {
         let to = Duration::from_secs(15);
         let acceptor = subscriber.subscribe(path.clone());
@@ -148,6 +165,9 @@ impl Connection {
                     Ok(_) => bail!("unexpected response from publisher"),
                 }
             }
+            Event::Update(v) if Resume::from_anchor(&v).is_some() => {
+                Self::connect_singleton(subscriber, path).await
+            }
             Event::Update(_) => bail!("not a channel or connection"),
         }
     }
@@ -169,7 +189,14 @@ impl Connection {
     pub fn send(&self, v: Value) -> Result<()> {
         self.check_dead()?;
         self.dirty.store(true, Ordering::Relaxed);
-        Ok(self.con.write(v))
+        // wrap under the lock so a concurrent reconnect either
+        // replays v, or we write it to the new connection
+        let con = self.con.lock();
+        let v = match &self.resume {
+            None => v,
+            Some(resume) => resume.wrap(v),
+        };
+        Ok(con.write(v))
     }
 
     /// True if you have sent values, but have not called flush.
@@ -178,17 +205,191 @@ impl Connection {
     }
 
     /// Wait for previously sent values to be flushed out to os
-    /// buffers.
+    /// buffers. If the connection is resumable, also wait until the
+    /// other side has acknowledged enough values that the replay
+    /// buffer isn't full.
     pub async fn flush(&self) -> Result<()> {
         self.check_dead()?;
-        let r = self.con.flush().await.map_err(|_| {
-            self.dead.store(true, Ordering::Relaxed);
-            anyhow!("connection is dead")
-        });
+        let con = self.con.lock().clone();
+        let r = match (&self.resume, con.flush().await) {
+            (None, Ok(())) => Ok(()),
+            (None, Err(_)) => {
+                self.dead.store(true, Ordering::Relaxed);
+                Err(anyhow!("connection is dead"))
+            }
+            (Some(resume), r) => {
+                if r.is_err() {
+                    let mut recv = self.receiver.lock().await;
+                    if self.con.lock().id() == con.id() {
+                        self.reconnect(resume, &mut recv).await?
+                    }
+                }
+                self.wait_acked(resume).await
+            }
+        };
         self.dirty.store(false, Ordering::Relaxed);
         r
     }
 
+    async fn wait_acked(&self, resume: &Resume) -> Result<()> {
+        let mut acked = resume.subscribe();
+        while resume.unacked() > resume.replay {
+            self.check_dead()?;
+            // someone must process the acks, if a receive is already
+            // in progress it will do it.
+            let recv = self.receiver.lock().fuse();
+            pin_mut!(recv);
+            select_biased! {
+                r = acked.changed().fuse() => if r.is_err() {
+                    bail!("connection is dead")
+                },
+                mut recv = recv => self.fill_queue(&mut recv).await?,
+            }
+        }
+        Ok(())
+    }
+
+    // subscribe to the session again, and wait for the other side to
+    // tell us where to resume from.
+    async fn try_resume(
+        &self,
+        resume: &Resume,
+    ) -> Result<(Val, Updates, u64, Vec<Value>)> {
+        let to = Duration::from_secs(15);
+        let con =
+            self.subscriber.subscribe_nondurable_one(self.path.clone(), Some(to)).await?;
+        let (tx, mut rx) = mpsc::channel(5);
+        con.updates(UpdatesFlags::empty(), tx);
+        con.write(resume.resume());
+        let mut rest = Vec::new();
+        let mut seq = None;
+        while seq.is_none() {
+            let mut batch = time::timeout(to, rx.next())
+                .await?
+                .ok_or_else(|| anyhow!("connection is dead"))?;
+            for (_, ev) in batch.drain(..) {
+                match ev {
+                    Event::Unsubscribed => bail!("connection is dead"),
+                    Event::Update(v) => match seq {
+                        Some(_) => rest.push(v),
+                        None => seq = Resume::resume_seq(&v),
+                    },
+                }
+            }
+        }
+        Ok((con, rx, seq.unwrap(), rest))
+    }
+
+    // the subscription was lost, try to resume the session until the
+    // session timeout expires.
+    async fn reconnect(&self, resume: &Resume, recv: &mut Receiver) -> Result<()> {
+        let start = Instant::now();
+        let (con, updates, seq, rest) = loop {
+            match self.try_resume(resume).await {
+                Ok(r) => break r,
+                Err(e) => {
+                    if start.elapsed() > resume.timeout {
+                        self.dead.store(true, Ordering::Relaxed);
+                        return Err(e);
+                    }
+                    time::sleep(Duration::from_millis(250)).await
+                }
+            }
+        };
+        {
+            let mut cur = self.con.lock();
+            let replay = resume.replay(seq).map_err(|e| {
+                self.dead.store(true, Ordering::Relaxed);
+                e
+            })?;
+            for v in replay {
+                con.write(v)
+            }
+            *cur = con;
+        }
+        recv.updates = updates;
+        for v in rest {
+            self.process_resumable(resume, recv, v)?
+        }
+        Ok(())
+    }
+
+    fn process_resumable(
+        &self,
+        resume: &Resume,
+        recv: &mut Receiver,
+        v: Value,
+    ) -> Result<()> {
+        match resume.process(v) {
+            Ok(Msg::Data(v)) => Ok(recv.queued.push_back(v)),
+            Ok(Msg::Duplicate | Msg::Ack | Msg::Resume(_)) => Ok(()),
+            Err(e) => {
+                self.dead.store(true, Ordering::Relaxed);
+                Err(e)
+            }
+        }
+    }
+
+    // process a batch of updates to a resumable connection. Returns
+    // false if the subscription was lost.
+    fn process_batch(
+        &self,
+        resume: &Resume,
+        recv: &mut Receiver,
+        r: Option<Pooled<Vec<(SubId, Event)>>>,
+    ) -> Result<bool> {
+        match r {
+            None => Ok(false),
+            Some(mut batch) => {
+                let mut alive = true;
+                for (_, ev) in batch.drain(..) {
+                    match ev {
+                        Event::Update(v) => self.process_resumable(resume, recv, v)?,
+                        Event::Unsubscribed => alive = false,
+                    }
+                }
+                if let Some(ack) = resume.ack() {
+                    self.con.lock().write(ack)
+                }
+                Ok(alive)
+            }
+        }
+    }
+
+    // wait for at least one batch from the other side
+    async fn fill_queue(&self, recv: &mut Receiver) -> Result<()> {
+        match &self.resume {
+            None => recv.fill_queue(&self.dead).await,
+            Some(resume) => {
+                let r = recv.updates.next().await;
+                if !self.process_batch(resume, recv, r)? {
+                    self.reconnect(resume, recv).await?
+                }
+                Ok(())
+            }
+        }
+    }
+
+    // process any batches from the other side that are available now
+    async fn try_fill_queue(&self, recv: &mut Receiver) -> Result<()> {
+        match &self.resume {
+            None => recv.try_fill_queue(&self.dead),
+            Some(resume) => {
+                for _ in 0..10 {
+                    match recv.updates.try_next() {
+                        Err(_) => break,
+                        Ok(r) => {
+                            if !self.process_batch(resume, recv, r)? {
+                                self.reconnect(resume, recv).await?
+                            }
+                        }
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+
     /// Wait for a value to arrive from the other side and return it
     /// when it does.
     pub async fn recv_one(&self) -> Result<Value> {
@@ -198,7 +399,7 @@ impl Connection {
                 Some(v) => break Ok(v),
                 None => {
                     self.check_dead()?;
-                    recv.fill_queue(&self.dead).await?
+                    self.fill_queue(&mut recv).await?
                 }
             }
         }
@@ -210,7 +411,7 @@ impl Connection {
     pub async fn try_recv_one(&self) -> Result<Option<Value>> {
         let mut recv = self.receiver.lock().await;
         if recv.queued.len() == 0 {
-            recv.try_fill_queue(&self.dead)?
+            self.try_fill_queue(&mut recv).await?
         }
         Ok(recv.queued.pop_front())
     }
@@ -219,13 +420,13 @@ impl Connection {
     /// put them in the specified data structure.
     pub async fn recv(&self, dst: &mut impl Extend<Value>) -> Result<()> {
         let mut recv = self.receiver.lock().await;
-        recv.try_fill_queue(&self.dead)?;
+        self.try_fill_queue(&mut recv).await?;
         loop {
             if recv.queued.len() > 0 {
                 break Ok(dst.extend(recv.queued.drain(..)));
             } else {
                 self.check_dead()?;
-                recv.fill_queue(&self.dead).await?
+                self.fill_queue(&mut recv).await?
             }
         }
     }
@@ -235,7 +436,7 @@ impl Connection {
     /// progress concurrently.
     pub async fn try_recv(&self, dst: &mut impl Extend<Value>) -> Result<bool> {
         let mut recv = self.receiver.lock().await;
-        recv.try_fill_queue(&self.dead)?;
+        self.try_fill_queue(&mut recv).await?;
         if recv.queued.len() > 0 {
             dst.extend(recv.queued.drain(..));
             Ok(true)
@@ -1,5 +1,6 @@
 pub mod server;
 pub mod client;
+mod resume;
 
 #[cfg(test)]
 pub mod test;
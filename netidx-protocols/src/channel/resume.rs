@@ -0,0 +1,161 @@
+// Sequence numbers, acknowledgements, and the replay buffer shared by
+// both ends of a resumable channel.
+//
+// In a resumable session every message is sent as (seq, value), and
+// each side periodically acknowledges what it has received with
+// ("ack", seq). When the subscription is lost the client resubscribes
+// to the session and sends ("resume", seq), the server replies with
+// ("resume", seq), and then each side replays the messages the other
+// side has not received.
+use anyhow::Result;
+use netidx::subscriber::Value;
+use parking_lot::Mutex;
+use std::{cmp::max, collections::VecDeque, time::Duration};
+use tokio::sync::watch;
+
+pub(super) enum Msg {
+    Data(Value),
+    Duplicate,
+    Ack,
+    Resume(u64),
+}
+
+struct Inner {
+    next: u64,
+    unacked: VecDeque<(u64, Value)>,
+    received: u64,
+    ack_sent: u64,
+}
+
+pub(super) struct Resume {
+    pub(super) replay: usize,
+    pub(super) timeout: Duration,
+    inner: Mutex<Inner>,
+    acked: watch::Sender<u64>,
+}
+
+impl Resume {
+    pub(super) fn new(replay: usize, timeout: Duration) -> Self {
+        let (acked, _) = watch::channel(0);
+        let inner = Inner { next: 1, unacked: VecDeque::new(), received: 0, ack_sent: 0 };
+        Resume { replay: max(replay, 1), timeout, inner: Mutex::new(inner), acked }
+    }
+
+    /// The value of the session anchor of a resumable session
+    pub(super) fn anchor(replay: usize, timeout: Duration) -> Value {
+        Value::from(("resumable", replay as u64, timeout))
+    }
+
+    /// Parse the anchor of a resumable session
+    pub(super) fn from_anchor(v: &Value) -> Option<Self> {
+        match v.clone().cast_to::<(String, u64, Duration)>() {
+            Ok((kind, replay, timeout)) if kind == "resumable" => {
+                Some(Self::new(replay as usize, timeout))
+            }
+            Ok(_) | Err(_) => None,
+        }
+    }
+
+    /// Assign the next sequence number to `v`, keep it until it is
+    /// acknowledged, and return the message to send.
+    pub(super) fn wrap(&self, v: Value) -> Value {
+        let mut inner = self.inner.lock();
+        let seq = inner.next;
+        inner.next += 1;
+        inner.unacked.push_back((seq, v.clone()));
+        Value::from((seq, v))
+    }
+
+    /// The number of messages sent but not yet acknowledged
+    pub(super) fn unacked(&self) -> usize {
+        self.inner.lock().unacked.len()
+    }
+
+    /// Subscribe to changes in the acknowledged sequence number
+    pub(super) fn subscribe(&self) -> watch::Receiver<u64> {
+        self.acked.subscribe()
+    }
+
+    fn ack_to(&self, seq: u64) {
+        let mut inner = self.inner.lock();
+        while let Some((s, _)) = inner.unacked.front() {
+            if *s <= seq {
+                inner.unacked.pop_front();
+            } else {
+                break;
+            }
+        }
+        drop(inner);
+        self.acked.send_replace(seq);
+    }
+
+    /// If `v` is a resume message return the sequence number
+    pub(super) fn resume_seq(v: &Value) -> Option<u64> {
+        match v {
+            Value::Array(a) if a.len() == 2 => match (&a[0], &a[1]) {
+                (Value::String(s), Value::U64(seq)) if &**s == "resume" => Some(*seq),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    pub(super) fn process(&self, v: Value) -> Result<Msg> {
+        match &v {
+            Value::Array(a) if a.len() == 2 => match (&a[0], &a[1]) {
+                (Value::U64(seq), m) => {
+                    let mut inner = self.inner.lock();
+                    if *seq <= inner.received {
+                        Ok(Msg::Duplicate)
+                    } else if *seq == inner.received + 1 {
+                        inner.received = *seq;
+                        Ok(Msg::Data(m.clone()))
+                    } else {
+                        bail!("expected message {} got {}", inner.received + 1, seq)
+                    }
+                }
+                (Value::String(s), Value::U64(seq)) if &**s == "ack" => {
+                    self.ack_to(*seq);
+                    Ok(Msg::Ack)
+                }
+                (Value::String(s), Value::U64(seq)) if &**s == "resume" => {
+                    Ok(Msg::Resume(*seq))
+                }
+                _ => bail!("unexpected message {}", v),
+            },
+            _ => bail!("unexpected message {}", v),
+        }
+    }
+
+    /// If enough messages have been received since the last ack,
+    /// return an ack to send to the other side.
+    pub(super) fn ack(&self) -> Option<Value> {
+        let mut inner = self.inner.lock();
+        if inner.received - inner.ack_sent >= max(self.replay / 2, 1) as u64 {
+            inner.ack_sent = inner.received;
+            Some(Value::from(("ack", inner.received)))
+        } else {
+            None
+        }
+    }
+
+    /// The message that tells the other side where to resume from
+    pub(super) fn resume(&self) -> Value {
+        let mut inner = self.inner.lock();
+        inner.ack_sent = inner.received;
+        Value::from(("resume", inner.received))
+    }
+
+    /// The other side has received every message up to and
+    /// including `seq`, return the messages that must be sent
+    /// again. Fails if messages the other side needs are gone.
+    pub(super) fn replay(&self, seq: u64) -> Result<Vec<Value>> {
+        self.ack_to(seq);
+        let inner = self.inner.lock();
+        let first = inner.unacked.front().map(|(s, _)| *s).unwrap_or(inner.next);
+        if seq + 1 != first {
+            bail!("can't resume from message {}, next available is {}", seq + 1, first)
+        }
+        Ok(inner.unacked.iter().map(|(s, v)| Value::from((*s, v.clone()))).collect())
+    }
+}
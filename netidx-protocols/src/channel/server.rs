@@ -1,18 +1,22 @@
+use super::resume::{Msg, Resume};
 use anyhow::Result;
-use futures::{channel::mpsc, prelude::*};
+use arcstr::ArcStr;
+use futures::{channel::mpsc, pin_mut, prelude::*, select_biased};
 use netidx::{
     path::Path,
     pool::Pooled,
     protocol::resolver::UserInfo,
     publisher::{ClId, PublishFlags, Publisher, UpdateBatch, Val, Value, WriteRequest},
 };
+use parking_lot::Mutex as SyncMutex;
 use std::{
     collections::VecDeque,
+    mem,
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc,
     },
-    time::Duration,
+    time::{Duration, Instant},
 };
 use tokio::{sync::Mutex, time};
 
@@ -28,12 +32,18 @@ pub struct Batch {
     anchor: Arc<Val>,
     client: ClId,
     queued: UpdateBatch,
+    // resumable connections assign sequence numbers when the batch
+    // is sent
+    resumable: Option<Vec<Value>>,
 }
 
 impl Batch {
     /// queue a value in the batch
     pub fn queue(&mut self, v: Value) {
-        self.anchor.update_subscriber(&mut self.queued, self.client, v);
+        match &mut self.resumable {
+            Some(queued) => queued.push(v),
+            None => self.anchor.update_subscriber(&mut self.queued, self.client, v),
+        }
     }
 }
 
@@ -99,6 +109,7 @@ pub struct Singleton {
     publisher: Publisher,
     anchor: Arc<Val>,
     timeout: Option<Duration>,
+    resume: Option<(usize, Duration)>,
     writes: mpsc::Receiver<Pooled<Vec<WriteRequest>>>,
 }
 
@@ -111,18 +122,56 @@ pub async fn singleton_with_flags(
     flags: PublishFlags,
     timeout: Option<Duration>,
     path: Path,
+) -> Result<Singleton> {
+    singleton_inner(publisher, flags, timeout, None, path).await
+}
+
+/// Create a new resumable single connection at path. Every message
+/// sent over a resumable connection has a sequence number, and is
+/// kept until the other side acknowledges it. If the client loses
+/// it's subscription it will resubscribe and the session will resume
+/// where it left off, as long as it does so within `resume_timeout`.
+/// Only a client authenticated as the same user as the original
+/// client may resume the session.
+///
+/// `replay` is the maximum number of unacknowledged messages either
+/// side may have outstanding, when it is reached sending will wait
+/// for the other side to catch up.
+pub async fn singleton_resumable(
+    publisher: &Publisher,
+    flags: PublishFlags,
+    timeout: Option<Duration>,
+    replay: usize,
+    resume_timeout: Duration,
+    path: Path,
+) -> Result<Singleton> {
+    let resume = Some((replay, resume_timeout));
+    singleton_inner(publisher, flags, timeout, resume, path).await
+}
+
+async fn singleton_inner(
+    publisher: &Publisher,
+    flags: PublishFlags,
+    timeout: Option<Duration>,
+    resume: Option<(usize, Duration)>,
+    path: Path,
 ) -> Result<Singleton> {
     let (tx, rx) = mpsc::channel(5);
+    let anchor = match resume {
+        None => Value::from("connection"),
+        Some((replay, timeout)) => Resume::anchor(replay, timeout),
+    };
     let val = publisher.publish_with_flags_and_writes(
         flags | PublishFlags::ISOLATED,
         path.clone(),
-        Value::from("connection"),
+        anchor,
         Some(tx),
     )?;
     publisher.flushed().await;
     Ok(Singleton {
         publisher: publisher.clone(),
         timeout,
+        resume,
         anchor: Arc::new(val),
         writes: rx,
     })
@@ -147,16 +196,22 @@ impl Singleton {
                 break subs;
             }
         };
-        let con = Connection {
+        let client = subscribed.pop().unwrap();
+        let user = self.publisher.user(&client).map(|u| u.name);
+        let mut con = Connection {
             publisher: self.publisher,
             anchor: self.anchor,
-            client: subscribed.pop().unwrap(),
+            client: SyncMutex::new(client),
+            user,
             dead: AtomicBool::new(false),
             timeout: self.timeout,
             receiver: Mutex::new(Receiver {
                 writes: self.writes,
                 queued: VecDeque::new(),
             }),
+            resume: None,
+            lost: SyncMutex::new(None),
+            sending: Mutex::new(()),
         };
         let to = Duration::from_secs(15);
         match time::timeout(to, con.recv_one()).await?? {
@@ -166,7 +221,20 @@ impl Singleton {
             v => bail!("unexpected handshake, expected String(\"ready\") got {}", v),
         }
         match time::timeout(to, con.recv_one()).await?? {
-            Value::String(s) if &*s == "go" => return Ok(con),
+            Value::String(s) if &*s == "go" => {
+                if let Some((replay, timeout)) = self.resume {
+                    let resume = Resume::new(replay, timeout);
+                    // the client may have already sent messages
+                    let recv = con.receiver.get_mut();
+                    for v in mem::take(&mut recv.queued) {
+                        if let Msg::Data(v) = resume.process(v)? {
+                            recv.queued.push_back(v)
+                        }
+                    }
+                    con.resume = Some(resume);
+                }
+                return Ok(con);
+            }
             v => bail!("unexpected handshake, expected String(\"go\") got {}", v),
         }
     }
@@ -176,10 +244,20 @@ impl Singleton {
 pub struct Connection {
     publisher: Publisher,
     anchor: Arc<Val>,
-    client: ClId,
+    // a resumable session may move to a new client
+    client: SyncMutex<ClId>,
+    // the user of the client that started the session, None if
+    // anonymous. Only the same user may resume it.
+    user: Option<ArcStr>,
     dead: AtomicBool,
     timeout: Option<Duration>,
     receiver: Mutex<Receiver>,
+    resume: Option<Resume>,
+    // when a resumable session lost it's client
+    lost: SyncMutex<Option<Instant>>,
+    // held while sending to a resumable session, so a resume can't
+    // be overtaken by messages sent to the new client
+    sending: Mutex<()>,
 }
 
 impl Connection {
@@ -188,26 +266,50 @@ impl Connection {
     pub fn start_batch(&self) -> Batch {
         Batch {
             anchor: self.anchor.clone(),
-            client: self.client,
+            client: *self.client.lock(),
             queued: self.publisher.start_batch(),
+            resumable: self.resume.as_ref().map(|_| Vec::new()),
         }
     }
 
     /// Return true of the channel has been disconnected. A
-    /// disconnected channel is permanently dead.
+    /// disconnected channel is permanently dead. A resumable channel
+    /// is only dead if it's client doesn't resume the session in
+    /// time.
     pub fn is_dead(&self) -> bool {
-        if !self.publisher.is_subscribed(&self.anchor.id(), &self.client) {
-            self.dead.store(true, Ordering::Relaxed);
+        if self.dead.load(Ordering::Relaxed) {
+            return true;
+        }
+        let client = *self.client.lock();
+        let subscribed = self.publisher.is_subscribed(&self.anchor.id(), &client);
+        match &self.resume {
+            None => {
+                if !subscribed {
+                    self.dead.store(true, Ordering::Relaxed);
+                }
+            }
+            Some(resume) => {
+                let mut lost = self.lost.lock();
+                if subscribed {
+                    *lost = None;
+                } else if lost.get_or_insert_with(Instant::now).elapsed() > resume.timeout
+                {
+                    self.dead.store(true, Ordering::Relaxed);
+                }
+            }
         }
         self.dead.load(Ordering::Relaxed)
     }
 
     /// Send a batch of message to the other side
-    pub async fn send(&self, batch: Batch) -> Result<()> {
+    pub async fn send(&self, mut batch: Batch) -> Result<()> {
         if self.is_dead() {
             bail!("connection is dead")
         }
-        Ok(batch.queued.commit(self.timeout).await)
+        match (&self.resume, batch.resumable.take()) {
+            (Some(resume), Some(queued)) => self.send_resumable(resume, queued).await,
+            (_, _) => Ok(batch.queued.commit(self.timeout).await),
+        }
     }
 
     /// Send just one message to the other side. This is less
@@ -216,8 +318,41 @@ impl Connection {
         if self.is_dead() {
             bail!("connection is dead")
         }
+        match &self.resume {
+            Some(resume) => self.send_resumable(resume, vec![v]).await,
+            None => {
+                let mut batch = self.publisher.start_batch();
+                self.anchor.update_subscriber(&mut batch, *self.client.lock(), v);
+                Ok(batch.commit(self.timeout).await)
+            }
+        }
+    }
+
+    async fn send_resumable(&self, resume: &Resume, queued: Vec<Value>) -> Result<()> {
+        let mut acked = resume.subscribe();
+        while resume.unacked() > 0 && resume.unacked() + queued.len() > resume.replay {
+            self.check_dead()?;
+            // someone must process the acks, if a receive is already
+            // in progress it will do it.
+            let recv = self.receiver.lock().fuse();
+            pin_mut!(recv);
+            select_biased! {
+                r = acked.changed().fuse() => if r.is_err() {
+                    bail!("connection is dead")
+                },
+                mut recv = recv => self.fill_queue(&mut recv).await?,
+            }
+        }
+        let _sending = self.sending.lock().await;
         let mut batch = self.publisher.start_batch();
-        self.anchor.update_subscriber(&mut batch, self.client, v);
+        {
+            // wrap under the lock so a concurrent resume either
+            // replays the messages, or we send them to the new client
+            let client = self.client.lock();
+            for v in queued {
+                self.anchor.update_subscriber(&mut batch, *client, resume.wrap(v));
+            }
+        }
         Ok(batch.commit(self.timeout).await)
     }
 
@@ -228,6 +363,128 @@ impl Connection {
         Ok(())
     }
 
+    // process a batch of writes to a resumable session. If the client
+    // asked to resume, record the new client and where it resumes
+    // from in resumed, the switch is done by reply_resumable.
+    fn process_resumable(
+        &self,
+        resume: &Resume,
+        queued: &mut VecDeque<Value>,
+        resumed: &mut Option<(ClId, u64)>,
+        mut batch: Pooled<Vec<WriteRequest>>,
+    ) -> Result<()> {
+        for req in batch.drain(..) {
+            let current = match resumed {
+                Some((client, _)) => *client,
+                None => *self.client.lock(),
+            };
+            if req.client != current {
+                if Resume::resume_seq(&req.value).is_none() {
+                    continue; // left over from a previous subscription
+                }
+                let user = self.publisher.user(&req.client).map(|u| u.name);
+                if user != self.user {
+                    continue; // someone else is trying to take over the session
+                }
+            }
+            match resume.process(req.value) {
+                Ok(Msg::Data(v)) => queued.push_back(v),
+                Ok(Msg::Duplicate | Msg::Ack) => (),
+                // the client resubscribed, possibly with a new id
+                Ok(Msg::Resume(seq)) => *resumed = Some((req.client, seq)),
+                Err(e) => {
+                    self.dead.store(true, Ordering::Relaxed);
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // switch to the new client if the session was resumed, and send
+    // it the resume message and the replay before anything else.
+    // Then send an ack if one is due.
+    async fn reply_resumable(
+        &self,
+        resume: &Resume,
+        resumed: Option<(ClId, u64)>,
+    ) -> Result<()> {
+        let _sending = self.sending.lock().await;
+        let mut batch = self.publisher.start_batch();
+        {
+            let mut client = self.client.lock();
+            if let Some((new_client, seq)) = resumed {
+                let replay = resume.replay(seq).map_err(|e| {
+                    self.dead.store(true, Ordering::Relaxed);
+                    e
+                })?;
+                *client = new_client;
+                *self.lost.lock() = None;
+                self.anchor.update_subscriber(&mut batch, *client, resume.resume());
+                for v in replay {
+                    self.anchor.update_subscriber(&mut batch, *client, v);
+                }
+            }
+            if let Some(v) = resume.ack() {
+                self.anchor.update_subscriber(&mut batch, *client, v);
+            }
+        }
+        Ok(batch.commit(self.timeout).await)
+    }
+
+    // wait for at least one message from the other side
+    async fn fill_queue(&self, recv: &mut Receiver) -> Result<()> {
+        let client = *self.client.lock();
+        match &self.resume {
+            None => recv.fill_queue(&self.dead, client).await,
+            Some(resume) => {
+                let batch = loop {
+                    // check periodically that the client hasn't been
+                    // gone for too long
+                    let to = Duration::from_secs(1);
+                    match time::timeout(to, recv.writes.next()).await {
+                        Ok(Some(batch)) => break batch,
+                        Ok(None) => {
+                            self.dead.store(true, Ordering::Relaxed);
+                            bail!("connection is dead")
+                        }
+                        Err(_) => self.check_dead()?,
+                    }
+                };
+                let mut resumed = None;
+                self.process_resumable(resume, &mut recv.queued, &mut resumed, batch)?;
+                self.reply_resumable(resume, resumed).await
+            }
+        }
+    }
+
+    // process any messages from the other side that are available now
+    async fn try_fill_queue(&self, recv: &mut Receiver) -> Result<()> {
+        let client = *self.client.lock();
+        match &self.resume {
+            None => recv.try_fill_queue(&self.dead, client),
+            Some(resume) => {
+                let mut resumed = None;
+                for _ in 0..10 {
+                    match recv.writes.try_next() {
+                        Err(_) => break,
+                        Ok(None) => {
+                            self.dead.store(true, Ordering::Relaxed);
+                            break;
+                        }
+                        Ok(Some(batch)) => self.process_resumable(
+                            resume,
+                            &mut recv.queued,
+                            &mut resumed,
+                            batch,
+                        )?,
+                    }
+                }
+                self.reply_resumable(resume, resumed).await
+            }
+        }
+    }
+
     /// Wait for one message from the other side, and return it when
     /// it arrives.
     pub async fn recv_one(&self) -> Result<Value> {
@@ -237,7 +494,7 @@ impl Connection {
                 Some(v) => break Ok(v),
                 None => {
                     self.check_dead()?;
-                    recv.fill_queue(&self.dead, self.client).await?
+                    self.fill_queue(&mut recv).await?
                 }
             }
         }
@@ -249,7 +506,7 @@ impl Connection {
     pub async fn try_recv_one(&self) -> Result<Option<Value>> {
         let mut recv = self.receiver.lock().await;
         if recv.queued.len() == 0 {
-            recv.try_fill_queue(&self.dead, self.client)?
+            self.try_fill_queue(&mut recv).await?
         }
         Ok(recv.queued.pop_front())
     }
@@ -260,13 +517,13 @@ impl Connection {
     /// arrives.
     pub async fn recv(&self, dst: &mut impl Extend<Value>) -> Result<()> {
         let mut recv = self.receiver.lock().await;
-        recv.try_fill_queue(&self.dead, self.client)?;
+        self.try_fill_queue(&mut recv).await?;
         loop {
             if recv.queued.len() > 0 {
                 break Ok(dst.extend(recv.queued.drain(..)));
             } else {
                 self.check_dead()?;
-                recv.fill_queue(&self.dead, self.client).await?
+                self.fill_queue(&mut recv).await?
             }
         }
     }
@@ -276,7 +533,7 @@ impl Connection {
     /// in progress. Returns true if any messages were received.
     pub async fn try_recv(&self, dst: &mut impl Extend<Value>) -> Result<bool> {
         let mut recv = self.receiver.lock().await;
-        recv.try_fill_queue(&self.dead, self.client)?;
+        self.try_fill_queue(&mut recv).await?;
         if recv.queued.len() > 0 {
             dst.extend(recv.queued.drain(..));
             Ok(true)
@@ -287,7 +544,14 @@ impl Connection {
 
     /// Return the user connected to this channel, if known
     pub fn user(&self) -> Option<UserInfo> {
-        self.publisher.user(&self.client)
+        self.publisher.user(&*self.client.lock())
+    }
+
+    #[cfg(test)]
+    pub(super) async fn drop_subscription(&self) {
+        let mut batch = self.publisher.start_batch();
+        self.anchor.unsubscribe(&mut batch, *self.client.lock());
+        batch.commit(None).await
     }
 }
 
@@ -301,6 +565,7 @@ pub struct Listener {
     base: Path,
     timeout: Option<Duration>,
     flags: PublishFlags,
+    resume: Option<(usize, Duration)>,
 }
 
 impl Listener {
@@ -328,9 +593,25 @@ impl Listener {
             base: path,
             timeout,
             flags,
+            resume: None,
         })
     }
 
+    /// just like new_with_flags, but every accepted connection will
+    /// be resumable. See `singleton_resumable`.
+    pub async fn new_resumable(
+        publisher: &Publisher,
+        flags: PublishFlags,
+        timeout: Option<Duration>,
+        replay: usize,
+        resume_timeout: Duration,
+        path: Path,
+    ) -> Result<Listener> {
+        let mut t = Self::new_with_flags(publisher, flags, timeout, path).await?;
+        t.resume = Some((replay, resume_timeout));
+        Ok(t)
+    }
+
     /// Create a new listener at the specified path. The actual
     /// connections will be randomly generated uuids under the
     /// specified path.
@@ -365,7 +646,13 @@ impl Listener {
         };
         let session = session(&self.base);
         send_result.send(Value::from(session.clone()));
-        Ok(singleton_with_flags(&self.publisher, self.flags, self.timeout, session)
-            .await?)
+        Ok(singleton_inner(
+            &self.publisher,
+            self.flags,
+            self.timeout,
+            self.resume,
+            session,
+        )
+        .await?)
     }
 }
@@ -2,7 +2,7 @@ use super::*;
 use netidx::{
     config::Config as ClientConfig,
     path::Path,
-    publisher::Publisher,
+    publisher::{PublishFlags, Publisher},
     resolver_client::DesiredAuth,
     resolver_server::{config::Config as ServerConfig, Server},
     subscriber::{Subscriber, Value},
@@ -94,6 +94,87 @@ fn singleton() {
     })
 }
 
+#[test]
+fn resume() {
+    Runtime::new().unwrap().block_on(async move {
+        let ctx = Ctx::new().await;
+        let path = ctx.base.clone();
+        let to = Duration::from_secs(10);
+        let flags = PublishFlags::empty();
+        let acceptor =
+            server::singleton_resumable(&ctx.publisher, flags, None, 8, to, ctx.base)
+                .await
+                .unwrap();
+        let (tx, rx) = oneshot::channel();
+        task::spawn(async move {
+            let con = client::Connection::connect(&ctx.subscriber, path).await.unwrap();
+            for i in 0..100 {
+                con.send(Value::U64(i as u64)).unwrap();
+                con.flush().await.unwrap();
+                match con.recv_one().await.unwrap() {
+                    Value::U64(j) => assert_eq!(j, i),
+                    _ => panic!("expected u64"),
+                }
+            }
+            let _ = tx.send(());
+        });
+        let con = acceptor.wait_connected().await.unwrap();
+        for i in 0..100 {
+            if i == 50 {
+                con.drop_subscription().await;
+            }
+            match con.recv_one().await.unwrap() {
+                Value::U64(i) => con.send_one(Value::U64(i)).await.unwrap(),
+                _ => panic!("expected u64"),
+            }
+        }
+        time::timeout(to, rx).await.unwrap().unwrap();
+        assert!(!con.is_dead())
+    })
+}
+
+#[test]
+fn resume_while_sending() {
+    Runtime::new().unwrap().block_on(async move {
+        let ctx = Ctx::new().await;
+        let path = ctx.base.clone();
+        let to = Duration::from_secs(10);
+        let flags = PublishFlags::empty();
+        let acceptor =
+            server::singleton_resumable(&ctx.publisher, flags, None, 8, to, ctx.base)
+                .await
+                .unwrap();
+        let (tx, rx) = oneshot::channel();
+        task::spawn(async move {
+            let con = client::Connection::connect(&ctx.subscriber, path).await.unwrap();
+            for i in 0..1000 {
+                match con.recv_one().await.unwrap() {
+                    Value::U64(j) => assert_eq!(j, i),
+                    _ => panic!("expected u64"),
+                }
+            }
+            let _ = tx.send(());
+        });
+        let con = Arc::new(acceptor.wait_connected().await.unwrap());
+        // keep sending while the client reconnects
+        let sender = task::spawn({
+            let con = con.clone();
+            async move {
+                for i in 0..1000 {
+                    con.send_one(Value::U64(i)).await.unwrap()
+                }
+            }
+        });
+        for _ in 0..3 {
+            time::sleep(Duration::from_millis(20)).await;
+            con.drop_subscription().await;
+        }
+        sender.await.unwrap();
+        time::timeout(to, rx).await.unwrap().unwrap();
+        assert!(!con.is_dead())
+    })
+}
+
 #[test]
 fn hang() {
     Runtime::new().unwrap().block_on(async move {
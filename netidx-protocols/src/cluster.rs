@@ -1,6 +1,10 @@
 use anyhow::Result;
 use bytes::Bytes;
-use futures::{channel::mpsc, prelude::*};
+use futures::{
+    channel::{mpsc, oneshot},
+    prelude::*,
+    select_biased,
+};
 use log::{info, warn};
 use netidx::{
     pack::Pack,
@@ -11,12 +15,17 @@ use netidx::{
     subscriber::{Dval, Event, Subscriber},
     utils,
 };
+use netidx_derive::Pack;
+use parking_lot::Mutex;
 use std::{
+    cmp::{max, min},
     collections::{HashMap, HashSet},
     iter,
     marker::PhantomData,
+    sync::{Arc, Weak},
+    time::{Duration, Instant},
 };
-use tokio::time;
+use tokio::{sync::watch, task, time};
 use uuid::Uuid;
 
 pub fn uuid_string(id: Uuid) -> String {
@@ -37,7 +46,9 @@ pub fn uuid_string(id: Uuid) -> String {
 /// A random cluster member is elected 'primary' by an common
 /// algorithm, the primary may change as members join and leave the
 /// cluster, but with a stable member set all members will agree on
-/// which one is the primary.
+/// which one is the primary. During a partition more than one member
+/// may believe it is the primary, if that isn't acceptable use
+/// `campaign` instead.
 pub struct Cluster<T: Pack> {
     t: PhantomData<T>,
     ctrack: ChangeTracker,
@@ -48,6 +59,8 @@ pub struct Cluster<T: Pack> {
     others: HashMap<Path, Dval>,
     cmd: mpsc::Receiver<Pooled<Vec<WriteRequest>>>,
     primary: bool,
+    election: Arc<Mutex<Election>>,
+    _election: Val,
 }
 
 impl<T: Pack> Cluster<T> {
@@ -66,6 +79,15 @@ impl<T: Pack> Cluster<T> {
         let us = publisher.publish(our_path.clone(), Value::Null)?;
         let ctrack = ChangeTracker::new(base);
         publisher.writes(us.id(), tx);
+        let (tx_election, rx_election) = mpsc::channel(3);
+        let _election = publisher.publish(our_path.append("election"), Value::Null)?;
+        publisher.writes(_election.id(), tx_election);
+        let election = Arc::new(Mutex::new(Election::new(
+            subscriber.clone(),
+            our_path.clone(),
+            shards,
+        )));
+        task::spawn(Election::run(Arc::downgrade(&election), rx_election));
         publisher.flushed().await;
         let others = HashMap::new();
         let t = PhantomData;
@@ -79,6 +101,8 @@ impl<T: Pack> Cluster<T> {
             cmd,
             others,
             primary: true,
+            election,
+            _election,
         };
         while t.subscribed_others() < shards {
             info!("waiting for {} other shards", shards);
@@ -104,6 +128,12 @@ impl<T: Pack> Cluster<T> {
             - self.others.values().filter(|d| d.last() == Event::Unsubscribed).count()
     }
 
+    /// Returns true if `path` is a known member of the cluster other
+    /// than us. May change after `poll_members`.
+    pub fn is_member(&self, path: &Path) -> bool {
+        self.others.contains_key(path)
+    }
+
     pub fn others(&self) -> usize {
         self.publisher.subscribed_len(&self.us.id())
     }
@@ -125,6 +155,13 @@ impl<T: Pack> Cluster<T> {
                     self.others.insert(path, dv);
                 }
             }
+            {
+                let mut election = self.election.lock();
+                election.members.retain(|p, _| self.others.contains_key(p));
+                for path in self.others.keys() {
+                    election.member(path);
+                }
+            }
             let mut paths =
                 iter::once(&self.our_path).chain(self.others.keys()).collect::<Vec<_>>();
             paths.sort();
@@ -172,4 +209,415 @@ impl<T: Pack> Cluster<T> {
             other.write(cmd);
         }
     }
+
+    /// Campaign to become the leader of the cluster, and wait until
+    /// we are elected. Unlike `primary`, at most one member can be the
+    /// leader at a time, even during a partition, because the leader
+    /// must hold a lease granted by a majority of the cluster. The
+    /// cluster size used to compute the majority is the larger of the
+    /// number of known members and `shards + 1`.
+    ///
+    /// The lease lasts for `lease`, and is renewed automatically while
+    /// the returned `LeaderGuard` is alive. If it can't be renewed in
+    /// time then leadership is lost. Dropping the guard resigns. A
+    /// member doesn't remember the leases it granted if it restarts,
+    /// so it refuses to vote until `lease` has passed since it
+    /// joined. For this to be safe every member must campaign with
+    /// the same `lease`.
+    ///
+    /// Each election produces a fencing token that is larger than the
+    /// token of any previous leader. Pass it along with any
+    /// operations the leader performs on external resources so they
+    /// can reject operations from a deposed leader.
+    pub async fn campaign(&self, lease: Duration) -> Result<LeaderGuard> {
+        let mut backoff = lease / 10;
+        loop {
+            let start = Instant::now();
+            let (token, round, rx) = {
+                let mut election = self.election.lock();
+                let token = max(election.promised, election.seen) + 1;
+                let (round, rx) = election.start_round(token, lease);
+                (token, round, rx)
+            };
+            let res = time::timeout(lease / 3, rx).await;
+            self.election.lock().rounds.remove(&round);
+            if let Ok(Ok(true)) = res {
+                info!("elected leader with token {}", token);
+                return Ok(LeaderGuard::new(&self.election, token, lease, start + lease));
+            }
+            // randomize the backoff so competing candidates don't
+            // keep splitting the vote
+            let jitter = Uuid::new_v4().as_u128() as u32 % 1000;
+            time::sleep(backoff + backoff * jitter / 1000).await;
+            backoff = min(backoff * 2, lease);
+        }
+    }
+
+    #[cfg(test)]
+    fn partition(&self, partitioned: bool) {
+        self.election.lock().partitioned = partitioned
+    }
+}
+
+#[derive(Debug, Clone, Pack)]
+enum ElectionMsg {
+    Vote { candidate: Path, token: u64, round: u64, lease: Duration },
+    Reply { round: u64, granted: bool, promised: u64 },
+    Release { candidate: Path, token: u64 },
+}
+
+impl ElectionMsg {
+    fn to_value(&self) -> Value {
+        Value::Bytes(Bytes::from(utils::pack(self).unwrap()))
+    }
+}
+
+struct Round {
+    granted: usize,
+    denied: usize,
+    result: Option<oneshot::Sender<bool>>,
+}
+
+struct Election {
+    subscriber: Subscriber,
+    us: Path,
+    shards: usize,
+    // the election values of the other members
+    members: HashMap<Path, Dval>,
+    // the largest token we have granted
+    promised: u64,
+    // the largest token we have heard of
+    seen: u64,
+    // who we have granted a lease to, the token, and when it expires
+    lease: Option<(Path, u64, Instant)>,
+    // when we joined, we don't vote until any lease we granted
+    // before a restart has expired
+    started: Instant,
+    next_round: u64,
+    rounds: HashMap<u64, Round>,
+    partitioned: bool,
+}
+
+impl Election {
+    fn new(subscriber: Subscriber, us: Path, shards: usize) -> Self {
+        Election {
+            subscriber,
+            us,
+            shards,
+            members: HashMap::new(),
+            promised: 0,
+            seen: 0,
+            lease: None,
+            started: Instant::now(),
+            next_round: 0,
+            rounds: HashMap::new(),
+            partitioned: false,
+        }
+    }
+
+    fn member(&mut self, path: &Path) -> &Dval {
+        let subscriber = &self.subscriber;
+        self.members
+            .entry(path.clone())
+            .or_insert_with(|| subscriber.subscribe(path.append("election")))
+    }
+
+    fn quorum(&self) -> usize {
+        max(self.members.len() + 1, self.shards + 1) / 2 + 1
+    }
+
+    // decide whether to grant a lease to candidate
+    fn vote(&mut self, candidate: &Path, token: u64, lease: Duration) -> bool {
+        let now = Instant::now();
+        self.seen = max(self.seen, token);
+        if now < self.started + lease {
+            return false;
+        }
+        let granted = match &self.lease {
+            // only the holder of an active lease may renew it
+            Some((holder, t, expires)) if *expires > now => {
+                holder == candidate && *t == token
+            }
+            Some((holder, t, _)) if *t == token => holder == candidate,
+            Some(_) | None => token > self.promised,
+        };
+        if granted {
+            self.promised = max(self.promised, token);
+            self.lease = Some((candidate.clone(), token, now + lease));
+        }
+        granted
+    }
+
+    fn release(&mut self, candidate: &Path, token: u64) {
+        if let Some((holder, t, expires)) = &mut self.lease {
+            if holder == candidate && *t == token {
+                *expires = Instant::now();
+            }
+        }
+    }
+
+    fn check_round(&mut self, id: u64) {
+        let quorum = self.quorum();
+        let voters = max(self.members.len() + 1, self.shards + 1);
+        if let Some(round) = self.rounds.get_mut(&id) {
+            let result = if round.granted >= quorum {
+                Some(true)
+            } else if voters.saturating_sub(round.denied) < quorum {
+                Some(false)
+            } else {
+                None
+            };
+            if let (Some(r), Some(tx)) = (result, round.result.take()) {
+                let _ = tx.send(r);
+            }
+        }
+    }
+
+    // ask every member, including us, for a lease
+    fn start_round(
+        &mut self,
+        token: u64,
+        lease: Duration,
+    ) -> (u64, oneshot::Receiver<bool>) {
+        let id = self.next_round;
+        self.next_round += 1;
+        let (tx, rx) = oneshot::channel();
+        let us = self.us.clone();
+        let granted = self.vote(&us, token, lease);
+        let round = Round {
+            granted: if granted { 1 } else { 0 },
+            denied: if granted { 0 } else { 1 },
+            result: Some(tx),
+        };
+        self.rounds.insert(id, round);
+        if !self.partitioned {
+            let msg = ElectionMsg::Vote { candidate: us, token, round: id, lease };
+            let msg = msg.to_value();
+            for member in self.members.values() {
+                member.write(msg.clone());
+            }
+        }
+        self.check_round(id);
+        (id, rx)
+    }
+
+    fn broadcast_release(&mut self, token: u64) {
+        let us = self.us.clone();
+        self.release(&us, token);
+        if !self.partitioned {
+            let msg = ElectionMsg::Release { candidate: us, token }.to_value();
+            for member in self.members.values() {
+                member.write(msg.clone());
+            }
+        }
+    }
+
+    fn process(&mut self, msg: ElectionMsg) {
+        match msg {
+            ElectionMsg::Vote { candidate, token, round, lease } => {
+                let granted = self.vote(&candidate, token, lease);
+                let promised = self.promised;
+                let reply = ElectionMsg::Reply { round, granted, promised };
+                self.member(&candidate).write(reply.to_value());
+            }
+            ElectionMsg::Reply { round: id, granted, promised } => {
+                self.seen = max(self.seen, promised);
+                if let Some(round) = self.rounds.get_mut(&id) {
+                    if granted {
+                        round.granted += 1
+                    } else {
+                        round.denied += 1
+                    }
+                }
+                self.check_round(id)
+            }
+            ElectionMsg::Release { candidate, token } => self.release(&candidate, token),
+        }
+    }
+
+    async fn run(
+        election: Weak<Mutex<Election>>,
+        mut msgs: mpsc::Receiver<Pooled<Vec<WriteRequest>>>,
+    ) {
+        while let Some(mut batch) = msgs.next().await {
+            let inner = match election.upgrade() {
+                None => break,
+                Some(inner) => inner,
+            };
+            let mut inner = inner.lock();
+            for req in batch.drain(..) {
+                if inner.partitioned {
+                    continue;
+                }
+                match &req.value {
+                    Value::Bytes(b) => match ElectionMsg::decode(&mut &**b) {
+                        Ok(msg) => inner.process(msg),
+                        Err(_) => warn!("ignoring invalid election msg"),
+                    },
+                    v => warn!("ignoring invalid election msg: {:?}", v),
+                }
+            }
+        }
+    }
+}
+
+/// Proof that we are the leader of the cluster, see `Cluster::campaign`.
+pub struct LeaderGuard {
+    token: u64,
+    leader: watch::Receiver<bool>,
+    _stop: oneshot::Sender<()>,
+}
+
+impl LeaderGuard {
+    fn new(
+        election: &Arc<Mutex<Election>>,
+        token: u64,
+        lease: Duration,
+        mut valid_until: Instant,
+    ) -> Self {
+        let (tx_leader, leader) = watch::channel(true);
+        let (tx_stop, rx_stop) = oneshot::channel::<()>();
+        let election = Arc::downgrade(election);
+        task::spawn(async move {
+            let mut stop = rx_stop.fuse();
+            let mut wait = lease / 3;
+            loop {
+                let now = Instant::now();
+                let sleep = wait.min(valid_until.saturating_duration_since(now));
+                select_biased! {
+                    _ = stop => break,
+                    _ = time::sleep(sleep).fuse() => (),
+                }
+                let start = Instant::now();
+                if start >= valid_until {
+                    break;
+                }
+                let (round, rx) = match election.upgrade() {
+                    None => break,
+                    Some(election) => {
+                        let mut election = election.lock();
+                        election.start_round(token, lease)
+                    }
+                };
+                let res = time::timeout(valid_until - start, rx).await;
+                if let Some(election) = election.upgrade() {
+                    election.lock().rounds.remove(&round);
+                }
+                match res {
+                    Ok(Ok(true)) => {
+                        valid_until = start + lease;
+                        wait = lease / 3;
+                    }
+                    Ok(Ok(false)) | Ok(Err(_)) | Err(_) => wait = lease / 10,
+                }
+            }
+            if Instant::now() >= valid_until {
+                warn!("lost leadership with token {}", token);
+            }
+            let _ = tx_leader.send(false);
+            if let Some(election) = election.upgrade() {
+                election.lock().broadcast_release(token);
+            }
+        });
+        LeaderGuard { token, leader, _stop: tx_stop }
+    }
+
+    /// The fencing token of this term of leadership. It is larger
+    /// than the token of every previous leader.
+    pub fn token(&self) -> u64 {
+        self.token
+    }
+
+    /// Returns true if we are still the leader
+    pub fn is_leader(&self) -> bool {
+        *self.leader.borrow()
+    }
+
+    /// Wait until we are no longer the leader, because the lease
+    /// could not be renewed.
+    pub async fn lost_leadership(&mut self) {
+        while *self.leader.borrow_and_update() {
+            if self.leader.changed().await.is_err() {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::channel::test::Ctx;
+    use tokio::runtime::Runtime;
+
+    #[test]
+    fn leader_election() {
+        Runtime::new().unwrap().block_on(async move {
+            let ctx = Ctx::new().await;
+            let base = Path::from("/cluster");
+            let lease = Duration::from_millis(500);
+            let (a, b, c) = future::join3(
+                Cluster::<u64>::new(
+                    &ctx.publisher,
+                    ctx.subscriber.clone(),
+                    base.clone(),
+                    2,
+                ),
+                Cluster::<u64>::new(
+                    &ctx.publisher,
+                    ctx.subscriber.clone(),
+                    base.clone(),
+                    2,
+                ),
+                Cluster::<u64>::new(
+                    &ctx.publisher,
+                    ctx.subscriber.clone(),
+                    base.clone(),
+                    2,
+                ),
+            )
+            .await;
+            let (a, b, _c) = (a.unwrap(), b.unwrap(), c.unwrap());
+            let mut ga = a.campaign(lease).await.unwrap();
+            assert!(ga.is_leader());
+            // b can't be elected while a holds the lease
+            assert!(time::timeout(lease * 3, b.campaign(lease)).await.is_err());
+            assert!(ga.is_leader());
+            // cut a off from the rest of the cluster
+            a.partition(true);
+            time::timeout(lease * 2, ga.lost_leadership()).await.unwrap();
+            assert!(!ga.is_leader());
+            let gb = time::timeout(lease * 10, b.campaign(lease)).await.unwrap().unwrap();
+            assert!(gb.is_leader());
+            assert!(gb.token() > ga.token());
+            // a can't get elected from inside the partition
+            assert!(time::timeout(lease * 3, a.campaign(lease)).await.is_err());
+            // after the partition heals a can be elected once b resigns
+            a.partition(false);
+            let tb = gb.token();
+            drop(gb);
+            let ga = time::timeout(lease * 10, a.campaign(lease)).await.unwrap().unwrap();
+            assert!(ga.token() > tb);
+        })
+    }
+
+    #[test]
+    fn restart() {
+        Runtime::new().unwrap().block_on(async move {
+            let ctx = Ctx::new().await;
+            let lease = Duration::from_millis(500);
+            let (a, b) = (Path::from("/cluster/a"), Path::from("/cluster/b"));
+            let mut e = Election::new(ctx.subscriber.clone(), a.clone(), 2);
+            time::sleep(lease).await;
+            assert!(e.vote(&a, 1, lease));
+            // a member that restarts forgets the lease it granted to
+            // a, so it must not vote until that lease has expired
+            let mut e = Election::new(ctx.subscriber.clone(), a.clone(), 2);
+            assert!(!e.vote(&b, 1, lease));
+            assert!(!e.vote(&b, 2, lease));
+            time::sleep(lease).await;
+            assert!(e.vote(&b, 2, lease));
+            assert!(!e.vote(&a, 1, lease));
+        })
+    }
 }
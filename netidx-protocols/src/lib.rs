@@ -7,8 +7,14 @@ extern crate anyhow;
 #[macro_use]
 extern crate netidx_core;
 
+// so macros that refer to netidx_protocols work in this crate
+extern crate self as netidx_protocols;
+
 pub mod cluster;
 pub mod rpc;
 pub mod view;
 pub mod channel;
 pub mod pack_channel;
+pub mod queue;
+pub mod lock;
+pub mod replicated_map;
@@ -0,0 +1,557 @@
+//! Distributed locks. A lock server publishes three procedures under
+//! a base path, `acquire`, `renew`, and `release`, and publishes each
+//! lock that is held or waited on at `base/locks/name`. The value of a
+//! lock is the fencing token of it's current holder, or null if it
+//! is free.
+//!
+//! Locks are held for a lease, which the holder must renew before it
+//! expires. Each time a lock is granted the server also publishes the
+//! grant at `base/grants/token`, and the holder subscribes to it. If
+//! the holder unsubscribes from it's grant (e.g. because it
+//! disconnected) then the lock is released immediately. Since every
+//! grant has it's own path, a previous holder unsubscribing can't
+//! release the lock of the next, even if they share a subscriber.
+//! Clients waiting for a lock are queued, and are granted the lock in
+//! the order they asked for it.
+pub mod server {
+    use crate::{
+        define_rpc,
+        rpc::server::{ArgSpec, Proc, RpcCall, RpcReply},
+        rpc_err,
+    };
+    use anyhow::Result;
+    use arcstr::ArcStr;
+    use futures::{
+        channel::{mpsc, oneshot},
+        prelude::*,
+        select_biased,
+    };
+    use fxhash::FxHashMap;
+    use log::info;
+    use netidx::{
+        chars::Chars,
+        path::Path,
+        publisher::{ClId, Event, Id, Publisher, Val, Value},
+    };
+    use std::{
+        collections::VecDeque,
+        time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+    };
+    use tokio::{task, time};
+
+    enum Req {
+        Acquire { call: RpcCall, name: Chars, lease: Duration, wait: bool },
+        Renew { reply: RpcReply, name: Chars, token: u64 },
+        Release { reply: RpcReply, name: Chars, token: u64 },
+    }
+
+    struct Holder {
+        client: ClId,
+        token: u64,
+        lease: Duration,
+        expires: Instant,
+        // published at base/grants/token until the lock is released
+        grant: Val,
+    }
+
+    struct Lock {
+        val: Val,
+        holder: Option<Holder>,
+        waiters: VecDeque<(RpcCall, Duration)>,
+    }
+
+    struct Locks {
+        publisher: Publisher,
+        base: Path,
+        next_token: u64,
+        locks: FxHashMap<Chars, Lock>,
+        // grant id -> lock name
+        by_id: FxHashMap<Id, Chars>,
+    }
+
+    impl Locks {
+        fn lock(&mut self, name: &Chars) -> Result<&mut Lock> {
+            if !self.locks.contains_key(name) {
+                let path = self.base.append("locks").append(name);
+                let val = self.publisher.publish(path, Value::Null)?;
+                let lock = Lock { val, holder: None, waiters: VecDeque::new() };
+                self.locks.insert(name.clone(), lock);
+            }
+            Ok(self.locks.get_mut(name).unwrap())
+        }
+
+        // grant the lock to the next waiter who still wants it. Remove
+        // the lock if no one holds it or is waiting for it.
+        fn grant(&mut self, name: &Chars) {
+            let now = Instant::now();
+            let mut batch = self.publisher.start_batch();
+            if let Some(lock) = self.locks.get_mut(name) {
+                if lock.holder.is_none() {
+                    while let Some((mut call, lease)) = lock.waiters.pop_front() {
+                        if !call.is_cancelled() {
+                            let token = self.next_token;
+                            self.next_token += 1;
+                            let path =
+                                self.base.append("grants").append(&token.to_string());
+                            let grant =
+                                match self.publisher.publish(path, Value::U64(token)) {
+                                    Ok(grant) => grant,
+                                    Err(e) => {
+                                        let e = Chars::from(e.to_string());
+                                        call.reply.send(Value::Error(e));
+                                        continue;
+                                    }
+                                };
+                            self.by_id.insert(grant.id(), name.clone());
+                            lock.holder = Some(Holder {
+                                client: call.client,
+                                token,
+                                lease,
+                                expires: now + lease,
+                                grant,
+                            });
+                            lock.val.update(&mut batch, Value::U64(token));
+                            // the holder must be able to resolve the
+                            // grant when it gets the reply
+                            let publisher = self.publisher.clone();
+                            let mut reply = call.reply;
+                            task::spawn(async move {
+                                publisher.flushed().await;
+                                reply.send(Value::U64(token))
+                            });
+                            break;
+                        }
+                    }
+                }
+                if lock.holder.is_none() {
+                    lock.val.update(&mut batch, Value::Null);
+                    if lock.waiters.is_empty() {
+                        self.locks.remove(name);
+                    }
+                }
+            }
+            task::spawn(batch.commit(None));
+        }
+
+        fn release(&mut self, name: &Chars) {
+            if let Some(lock) = self.locks.get_mut(name) {
+                if let Some(holder) = lock.holder.take() {
+                    self.by_id.remove(&holder.grant.id());
+                    info!("lock {} token {} released", name, holder.token);
+                }
+            }
+            self.grant(name)
+        }
+
+        fn process(&mut self, req: Req) {
+            match req {
+                Req::Acquire { mut call, name, lease, wait } => {
+                    let lock = match self.lock(&name) {
+                        Ok(lock) => lock,
+                        Err(e) => {
+                            call.reply.send(Value::Error(Chars::from(e.to_string())));
+                            return;
+                        }
+                    };
+                    if lock.holder.is_some() && !wait {
+                        call.reply.send(Value::Null)
+                    } else {
+                        lock.waiters.push_back((call, lease));
+                        self.grant(&name)
+                    }
+                }
+                Req::Renew { mut reply, name, token } => {
+                    let now = Instant::now();
+                    match self.locks.get_mut(&name).and_then(|l| l.holder.as_mut()) {
+                        Some(h) if h.token == token => {
+                            h.expires = now + h.lease;
+                            reply.send(Value::Ok)
+                        }
+                        Some(_) | None => {
+                            reply.send(Value::Error(Chars::from("lock is not held")))
+                        }
+                    }
+                }
+                Req::Release { mut reply, name, token } => {
+                    match self.locks.get(&name).and_then(|l| l.holder.as_ref()) {
+                        Some(h) if h.token == token => {
+                            self.release(&name);
+                            reply.send(Value::Ok)
+                        }
+                        Some(_) | None => {
+                            reply.send(Value::Error(Chars::from("lock is not held")))
+                        }
+                    }
+                }
+            }
+        }
+
+        fn holder(&mut self, id: &Id) -> Option<(Chars, &mut Holder)> {
+            let name = self.by_id.get(id)?;
+            let holder = self.locks.get_mut(name)?.holder.as_mut()?;
+            Some((name.clone(), holder))
+        }
+
+        // the holder of a lock unsubscribed from it's grant
+        fn unsubscribed(&mut self, id: Id, client: ClId) {
+            match self.holder(&id) {
+                Some((name, h)) if h.client == client && h.grant.id() == id => {
+                    self.release(&name)
+                }
+                Some(_) | None => (),
+            }
+        }
+
+        // release expired leases and forget waiters who gave up
+        fn expire(&mut self) {
+            let now = Instant::now();
+            let mut names = Vec::new();
+            for (name, lock) in self.locks.iter_mut() {
+                lock.waiters.retain(|(call, _)| !call.is_cancelled());
+                match &lock.holder {
+                    Some(h) if h.expires <= now => {
+                        info!("lock {} token {} lease expired", name, h.token);
+                        self.by_id.remove(&h.grant.id());
+                        lock.holder = None;
+                        names.push(name.clone())
+                    }
+                    Some(_) => (),
+                    None => names.push(name.clone()),
+                }
+            }
+            for name in names {
+                self.grant(&name)
+            }
+        }
+    }
+
+    /// A lock server, the locks are released and the procedures are
+    /// removed when it is dropped.
+    pub struct LockServer {
+        _acquire: Proc,
+        _renew: Proc,
+        _release: Proc,
+        _stop: oneshot::Sender<()>,
+    }
+
+    impl LockServer {
+        /// Publish a new lock server at `base`
+        pub fn new(publisher: &Publisher, base: Path) -> Result<LockServer> {
+            let (tx, mut rx) = mpsc::channel(100);
+            let _acquire = define_rpc!(
+                publisher,
+                base.append("acquire"),
+                "acquire a lock, return it's fencing token",
+                |call: RpcCall, name: Chars, lease: Duration, wait: bool| {
+                    Some(Req::Acquire { call, name, lease, wait })
+                },
+                Some(tx.clone()),
+                name: Chars = Value::Null; "the name of the lock",
+                lease: Duration = Duration::from_secs(30); "the lease of the lock",
+                wait: bool = true; "wait for the lock, if false return null if it is held"
+            )?;
+            let _renew = define_rpc!(
+                publisher,
+                base.append("renew"),
+                "renew the lease of a lock we hold",
+                |c: RpcCall, name: Chars, token: u64| {
+                    Some(Req::Renew { reply: c.reply, name, token })
+                },
+                Some(tx.clone()),
+                name: Chars = Value::Null; "the name of the lock",
+                token: u64 = Value::Null; "the fencing token returned by acquire"
+            )?;
+            let _release = define_rpc!(
+                publisher,
+                base.append("release"),
+                "release a lock we hold",
+                |c: RpcCall, name: Chars, token: u64| {
+                    Some(Req::Release { reply: c.reply, name, token })
+                },
+                Some(tx),
+                name: Chars = Value::Null; "the name of the lock",
+                token: u64 = Value::Null; "the fencing token returned by acquire"
+            )?;
+            let (tx_events, mut events) = mpsc::unbounded();
+            publisher.events(tx_events);
+            // seed tokens with the time so they keep increasing if
+            // the server restarts
+            let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+            let mut locks = Locks {
+                publisher: publisher.clone(),
+                base,
+                next_token: now.as_micros() as u64,
+                locks: FxHashMap::default(),
+                by_id: FxHashMap::default(),
+            };
+            let (tx_stop, rx_stop) = oneshot::channel();
+            task::spawn(async move {
+                let mut stop = rx_stop.fuse();
+                let mut expire = time::interval(Duration::from_millis(100));
+                loop {
+                    select_biased! {
+                        _ = stop => break,
+                        _ = expire.tick().fuse() => locks.expire(),
+                        req = rx.select_next_some() => locks.process(req),
+                        ev = events.select_next_some() => match ev {
+                            Event::Unsubscribe(id, client) => {
+                                locks.unsubscribed(id, client)
+                            }
+                            Event::Subscribe(_, _) | Event::Destroyed(_) => (),
+                        },
+                    }
+                }
+            });
+            Ok(LockServer { _acquire, _renew, _release, _stop: tx_stop })
+        }
+    }
+}
+
+pub mod client {
+    use crate::rpc::client::Proc;
+    use anyhow::Result;
+    use futures::{channel::oneshot, prelude::*, select_biased};
+    use log::warn;
+    use netidx::{
+        path::Path,
+        subscriber::{Subscriber, Val, Value},
+    };
+    use std::{
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+    use tokio::{sync::watch, task, time};
+
+    struct Inner {
+        subscriber: Subscriber,
+        base: Path,
+        acquire: Proc,
+        renew: Proc,
+        release: Proc,
+    }
+
+    /// A client of a lock server
+    #[derive(Clone)]
+    pub struct LockClient(Arc<Inner>);
+
+    impl LockClient {
+        /// Connect to the lock server at `base`
+        pub async fn new(subscriber: &Subscriber, base: Path) -> Result<LockClient> {
+            let (acquire, renew, release) = future::try_join3(
+                Proc::new(subscriber, base.append("acquire")),
+                Proc::new(subscriber, base.append("renew")),
+                Proc::new(subscriber, base.append("release")),
+            )
+            .await?;
+            let subscriber = subscriber.clone();
+            Ok(LockClient(Arc::new(Inner { subscriber, base, acquire, renew, release })))
+        }
+
+        async fn acquire_inner(
+            &self,
+            name: &str,
+            lease: Duration,
+            wait: bool,
+        ) -> Result<Option<LockGuard>> {
+            let start = Instant::now();
+            let args = [
+                ("name", Value::from(String::from(name))),
+                ("lease", Value::from(lease)),
+                ("wait", Value::from(wait)),
+            ];
+            match self.0.acquire.call(args).await? {
+                Value::Null => Ok(None),
+                Value::U64(token) => {
+                    let path = self.0.base.append("grants").append(&token.to_string());
+                    let val =
+                        self.0.subscriber.subscribe_nondurable_one(path, None).await;
+                    let guard = LockGuard::new(self, name, token, lease, start, val?);
+                    Ok(Some(guard))
+                }
+                Value::Error(e) => bail!("acquire failed {}", e),
+                v => bail!("unexpected reply from acquire {}", v),
+            }
+        }
+
+        /// Wait until we hold the lock `name`, and return a guard
+        /// that releases it when dropped. The lock is held for
+        /// `lease`, and the guard renews the lease automatically
+        /// until it is dropped. Dropping the future before the lock
+        /// is granted leaves the queue.
+        pub async fn acquire(&self, name: &str, lease: Duration) -> Result<LockGuard> {
+            match self.acquire_inner(name, lease, true).await? {
+                Some(guard) => Ok(guard),
+                None => bail!("acquire failed"),
+            }
+        }
+
+        /// Acquire the lock `name` if it is free, return None if it
+        /// is held by someone else.
+        pub async fn try_acquire(
+            &self,
+            name: &str,
+            lease: Duration,
+        ) -> Result<Option<LockGuard>> {
+            self.acquire_inner(name, lease, false).await
+        }
+    }
+
+    /// Proof that we hold a lock. The lock is released when the guard
+    /// is dropped.
+    pub struct LockGuard {
+        token: u64,
+        held: watch::Receiver<bool>,
+        stop: Option<oneshot::Sender<()>>,
+        done: Option<oneshot::Receiver<()>>,
+    }
+
+    impl LockGuard {
+        fn new(
+            client: &LockClient,
+            name: &str,
+            token: u64,
+            lease: Duration,
+            start: Instant,
+            // the server releases the lock if we unsubscribe from it
+            grant: Val,
+        ) -> Self {
+            let (tx_held, held) = watch::channel(true);
+            let (tx_stop, rx_stop) = oneshot::channel::<()>();
+            let (tx_done, done) = oneshot::channel();
+            let client = client.clone();
+            let name = Value::from(String::from(name));
+            task::spawn(async move {
+                let mut stop = rx_stop.fuse();
+                let mut valid_until = start + lease;
+                loop {
+                    let now = Instant::now();
+                    let sleep =
+                        (lease / 3).min(valid_until.saturating_duration_since(now));
+                    select_biased! {
+                        _ = stop => break,
+                        _ = time::sleep(sleep).fuse() => (),
+                    }
+                    let start = Instant::now();
+                    if start >= valid_until {
+                        break;
+                    }
+                    let args = [("name", name.clone()), ("token", Value::U64(token))];
+                    let timeout = valid_until - start;
+                    match client.0.renew.call_with_timeout(timeout, args).await {
+                        Ok(Value::Ok) => valid_until = start + lease,
+                        Ok(Value::Error(e)) => {
+                            warn!("lost lock {} token {}: {}", name, token, e);
+                            break;
+                        }
+                        Ok(_) | Err(_) => (),
+                    }
+                }
+                let _ = tx_held.send(false);
+                // unsubscribing releases the lock, the release call
+                // makes sure it's done before we return
+                drop(grant);
+                if Instant::now() < valid_until {
+                    let args = [("name", name.clone()), ("token", Value::U64(token))];
+                    let _ = client.0.release.call(args).await;
+                }
+                let _ = tx_done.send(());
+            });
+            LockGuard { token, held, stop: Some(tx_stop), done: Some(done) }
+        }
+
+        /// The fencing token of this lock. Every time a lock is
+        /// granted it gets a larger token than the last time.
+        pub fn token(&self) -> u64 {
+            self.token
+        }
+
+        /// Returns true if we still hold the lock
+        pub fn is_held(&self) -> bool {
+            *self.held.borrow()
+        }
+
+        /// Wait until we no longer hold the lock, because the lease
+        /// could not be renewed.
+        pub async fn lost(&mut self) {
+            while *self.held.borrow_and_update() {
+                if self.held.changed().await.is_err() {
+                    break;
+                }
+            }
+        }
+
+        /// Release the lock, and wait for the server to acknowledge
+        pub async fn release(mut self) {
+            drop(self.stop.take());
+            if let Some(done) = self.done.take() {
+                let _ = done.await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{client::LockClient, server::LockServer};
+    use crate::channel::test::Ctx;
+    use netidx::path::Path;
+    use std::time::Duration;
+    use tokio::{runtime::Runtime, time};
+
+    #[test]
+    fn lock() {
+        Runtime::new().unwrap().block_on(async move {
+            let ctx = Ctx::new().await;
+            let base = Path::from("/locks");
+            let lease = Duration::from_secs(1);
+            let _server = LockServer::new(&ctx.publisher, base.clone()).unwrap();
+            let c0 = LockClient::new(&ctx.subscriber, base.clone()).await.unwrap();
+            let c1 = LockClient::new(&ctx.subscriber, base.clone()).await.unwrap();
+            let g0 = c0.acquire("compactor", lease).await.unwrap();
+            assert!(c1.try_acquire("compactor", lease).await.unwrap().is_none());
+            // the lease is renewed, so the lock is still held later
+            let waiting = c1.acquire("compactor", lease);
+            assert!(time::timeout(lease * 3, waiting).await.is_err());
+            assert!(g0.is_held());
+            let waiting = tokio::task::spawn({
+                let c1 = c1.clone();
+                async move { c1.acquire("compactor", lease).await }
+            });
+            time::sleep(Duration::from_millis(100)).await;
+            let t0 = g0.token();
+            g0.release().await;
+            let g1 = time::timeout(lease, waiting).await.unwrap().unwrap().unwrap();
+            assert!(g1.token() > t0);
+            // other locks are independent
+            let g2 = c0.acquire("other", lease).await.unwrap();
+            assert!(g2.is_held());
+            drop(g1);
+            time::sleep(Duration::from_millis(100)).await;
+            assert!(c0.try_acquire("compactor", lease).await.unwrap().is_some());
+        })
+    }
+
+    #[test]
+    fn shared_subscriber() {
+        Runtime::new().unwrap().block_on(async move {
+            let ctx = Ctx::new().await;
+            let base = Path::from("/locks");
+            let lease = Duration::from_secs(1);
+            let _server = LockServer::new(&ctx.publisher, base.clone()).unwrap();
+            let c0 = LockClient::new(&ctx.subscriber, base.clone()).await.unwrap();
+            let c1 = LockClient::new(&ctx.subscriber, base.clone()).await.unwrap();
+            let g0 = c0.acquire("compactor", lease).await.unwrap();
+            let waiting = tokio::task::spawn({
+                let c1 = c1.clone();
+                async move { c1.acquire("compactor", lease).await }
+            });
+            time::sleep(Duration::from_millis(100)).await;
+            drop(g0);
+            let g1 = time::timeout(lease, waiting).await.unwrap().unwrap().unwrap();
+            // the first guard going away must not release the second
+            time::sleep(lease * 2).await;
+            assert!(g1.is_held());
+            assert!(c0.try_acquire("compactor", lease).await.unwrap().is_none());
+        })
+    }
+}
@@ -190,6 +190,28 @@ pub async fn singleton_with_flags(
     Ok(Singleton(server::singleton_with_flags(publisher, flags, timeout, path).await?))
 }
 
+/// Create a resumable single connection, see
+/// `channel::server::singleton_resumable`
+pub async fn singleton_resumable(
+    publisher: &Publisher,
+    flags: PublishFlags,
+    timeout: Option<Duration>,
+    replay: usize,
+    resume_timeout: Duration,
+    path: Path,
+) -> Result<Singleton> {
+    let inner = server::singleton_resumable(
+        publisher,
+        flags,
+        timeout,
+        replay,
+        resume_timeout,
+        path,
+    )
+    .await?;
+    Ok(Singleton(inner))
+}
+
 /// A listener can accept connections from muliple clients and produce
 /// a channel to talk to each one.
 pub struct Listener(server::Listener);
@@ -215,6 +237,28 @@ impl Listener {
         Ok(Self(inner))
     }
 
+    /// Every connection accepted by this listener will be resumable,
+    /// see `channel::server::Listener::new_resumable`
+    pub async fn new_resumable(
+        publisher: &Publisher,
+        flags: PublishFlags,
+        timeout: Option<Duration>,
+        replay: usize,
+        resume_timeout: Duration,
+        path: Path,
+    ) -> Result<Self> {
+        let inner = server::Listener::new_resumable(
+            publisher,
+            flags,
+            timeout,
+            replay,
+            resume_timeout,
+            path,
+        )
+        .await?;
+        Ok(Self(inner))
+    }
+
     /// Wait for a client to connect, and return a singleton
     /// connection to the new client.
     pub async fn accept(&mut self) -> Result<Singleton> {
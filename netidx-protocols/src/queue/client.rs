@@ -0,0 +1,108 @@
+use crate::channel::client::Connection;
+use anyhow::Result;
+use netidx::{
+    pack::Pack,
+    path::Path,
+    subscriber::{Subscriber, Value},
+    utils,
+};
+
+/// Enqueues messages on a queue
+pub struct Producer(Connection);
+
+impl Producer {
+    /// Connect to the queue at `path`
+    pub async fn new(subscriber: &Subscriber, path: Path) -> Result<Producer> {
+        Ok(Producer(Connection::connect(subscriber, path).await?))
+    }
+
+    /// Add `v` to the back of the queue. The message starts it's
+    /// journey immediately, call flush to know when it's in the
+    /// queue.
+    pub fn enqueue(&self, v: Value) -> Result<()> {
+        self.0.send(Value::from(("enqueue", v)))
+    }
+
+    /// Pack `t` and add it to the back of the queue, consumers can
+    /// use `Delivery::unpack` to get it back.
+    pub fn enqueue_pack<T: Pack>(&self, t: &T) -> Result<()> {
+        self.enqueue(Value::Bytes(utils::pack(t)?.freeze()))
+    }
+
+    /// Wait until every message enqueued so far is in the queue, and
+    /// if the queue is durable, on disk.
+    pub async fn flush(&mut self) -> Result<()> {
+        self.0.send(Value::from("sync"))?;
+        self.0.flush().await?;
+        loop {
+            match self.0.recv_one().await? {
+                Value::String(s) if &*s == "synced" => break Ok(()),
+                _ => (),
+            }
+        }
+    }
+}
+
+/// A message delivered to a consumer
+#[derive(Debug, Clone)]
+pub struct Delivery {
+    /// The id of the message in the queue
+    pub id: u64,
+    /// The number of times the message has been delivered, including
+    /// this one.
+    pub attempts: u32,
+    pub value: Value,
+}
+
+impl Delivery {
+    /// Unpack a message enqueued with `Producer::enqueue_pack`
+    pub fn unpack<T: Pack>(&self) -> Result<T> {
+        match &self.value {
+            Value::Bytes(b) => Ok(T::decode(&mut &**b)?),
+            v => bail!("expected a packed message, got {}", v),
+        }
+    }
+}
+
+/// Receives messages from a queue. Every message must be acked once
+/// it has been processed, or nacked if it could not be processed.
+pub struct Consumer(Connection);
+
+impl Consumer {
+    /// Connect to the queue at `path`. At most `prefetch` messages
+    /// will be delivered to this consumer without being acked or
+    /// nacked.
+    pub async fn new(
+        subscriber: &Subscriber,
+        path: Path,
+        prefetch: usize,
+    ) -> Result<Consumer> {
+        let con = Connection::connect(subscriber, path).await?;
+        con.send(Value::from(("consume", prefetch)))?;
+        Ok(Consumer(con))
+    }
+
+    /// Wait for the next message
+    pub async fn recv(&self) -> Result<Delivery> {
+        let v = self.0.recv_one().await?;
+        match v.clone().cast_to::<(u64, u32, Value)>() {
+            Ok((id, attempts, value)) => Ok(Delivery { id, attempts, value }),
+            Err(_) => bail!("unexpected message from the queue {}", v),
+        }
+    }
+
+    /// The message was processed, remove it from the queue
+    pub fn ack(&self, d: &Delivery) -> Result<()> {
+        self.0.send(Value::from(("ack", d.id)))
+    }
+
+    /// The message could not be processed, deliver it again
+    pub fn nack(&self, d: &Delivery) -> Result<()> {
+        self.0.send(Value::from(("nack", d.id)))
+    }
+
+    /// Wait for acks and nacks to be flushed out to os buffers
+    pub async fn flush(&self) -> Result<()> {
+        self.0.flush().await
+    }
+}
@@ -0,0 +1,198 @@
+// The on disk log of a durable queue. Each record is a big endian u32
+// length followed by a packed Entry. On open the log is replayed, and
+// every message that was enqueued but never acked goes back in the
+// queue. A torn record at the end of the log (e.g. from a crash) is
+// discarded.
+//
+// The log is written by a dedicated thread, so the queue never waits
+// for the disk while it holds it's lock. Records are written in the
+// order they are sent to the thread.
+use anyhow::Result;
+use bytes::Buf;
+use futures::channel::oneshot;
+use log::warn;
+use netidx::{pack::Pack, publisher::Value, utils};
+use netidx_derive::Pack;
+use std::{
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions},
+    io::{BufWriter, Seek, SeekFrom, Write},
+    path::PathBuf,
+    sync::mpsc,
+    thread,
+};
+
+#[derive(Debug, Clone, PartialEq, Pack)]
+enum Entry {
+    Enqueue(u64, Value),
+    Ack(u64),
+}
+
+enum Op {
+    Write(Entry),
+    Flush,
+    Sync(oneshot::Sender<Result<()>>),
+    Compact(Vec<(u64, Value)>),
+}
+
+// the log file, owned by the journal thread
+struct Log {
+    path: PathBuf,
+    file: BufWriter<File>,
+}
+
+impl Log {
+    fn write(file: &mut BufWriter<File>, e: &Entry) -> Result<()> {
+        let rec = utils::pack(e)?;
+        file.write_all(&(rec.len() as u32).to_be_bytes())?;
+        Ok(file.write_all(&rec)?)
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.file.flush()?;
+        Ok(self.file.get_ref().sync_data()?)
+    }
+
+    fn compact(&mut self, live: Vec<(u64, Value)>) -> Result<()> {
+        let tmp = self.path.with_extension("compact");
+        let mut file = BufWriter::new(File::create(&tmp)?);
+        for (id, v) in live {
+            Self::write(&mut file, &Entry::Enqueue(id, v))?
+        }
+        file.flush()?;
+        file.get_ref().sync_all()?;
+        fs::rename(&tmp, &self.path)?;
+        self.file = file;
+        Ok(())
+    }
+
+    fn process(&mut self, op: Op) -> Result<()> {
+        match op {
+            Op::Write(e) => Self::write(&mut self.file, &e),
+            Op::Flush => Ok(self.file.flush()?),
+            Op::Sync(reply) => match self.sync() {
+                Ok(()) => Ok(drop(reply.send(Ok(())))),
+                Err(e) => {
+                    let _ = reply.send(Err(anyhow!("journal failed {}", e)));
+                    Err(e)
+                }
+            },
+            Op::Compact(live) => self.compact(live),
+        }
+    }
+
+    // once a write fails the log is no longer trustworthy, so every
+    // later sync fails
+    fn run(mut self, ops: mpsc::Receiver<Op>) {
+        let mut failed: Option<String> = None;
+        while let Ok(op) = ops.recv() {
+            let r = match &failed {
+                None => self.process(op),
+                Some(e) => {
+                    if let Op::Sync(reply) = op {
+                        let _ = reply.send(Err(anyhow!("journal failed {}", e)));
+                    }
+                    Ok(())
+                }
+            };
+            if let Err(e) = r {
+                warn!("queue journal {:?} failed {}", self.path, e);
+                failed = Some(e.to_string())
+            }
+        }
+    }
+}
+
+pub(super) struct Journal {
+    ops: mpsc::Sender<Op>,
+    // the number of records that describe acked messages
+    garbage: usize,
+}
+
+impl Journal {
+    /// Open the journal at path, creating it if it doesn't exist, and
+    /// return the messages that are still in the queue in order.
+    pub(super) fn open(path: PathBuf) -> Result<(Self, Vec<(u64, Value)>)> {
+        let mut live = BTreeMap::new();
+        let mut garbage = 0;
+        let mut valid = 0;
+        if path.exists() {
+            let data = fs::read(&path)?;
+            let mut buf = &data[..];
+            while buf.remaining() >= 4 {
+                let len = (&buf[..4]).get_u32() as usize;
+                if buf.remaining() < len + 4 {
+                    break;
+                }
+                buf.advance(4);
+                let mut rec = &buf[..len];
+                match Entry::decode(&mut rec) {
+                    Ok(Entry::Enqueue(id, v)) => {
+                        live.insert(id, v);
+                    }
+                    Ok(Entry::Ack(id)) => {
+                        if live.remove(&id).is_some() {
+                            garbage += 2
+                        }
+                    }
+                    Err(_) => break,
+                }
+                buf.advance(len);
+                valid += len + 4;
+            }
+        }
+        let file = OpenOptions::new().create(true).write(true).open(&path)?;
+        // discard anything we couldn't read
+        file.set_len(valid as u64)?;
+        file.sync_all()?;
+        let mut file = BufWriter::new(file);
+        file.seek(SeekFrom::End(0))?;
+        let (ops, rx) = mpsc::channel();
+        let log = Log { path, file };
+        thread::Builder::new()
+            .name("netidx-queue-journal".into())
+            .spawn(move || log.run(rx))?;
+        let t = Journal { ops, garbage };
+        Ok((t, live.into_iter().collect()))
+    }
+
+    fn send(&self, op: Op) {
+        // the thread only stops when we are dropped
+        let _ = self.ops.send(op);
+    }
+
+    pub(super) fn enqueue(&mut self, id: u64, v: &Value) {
+        self.send(Op::Write(Entry::Enqueue(id, v.clone())))
+    }
+
+    pub(super) fn ack(&mut self, id: u64) {
+        self.garbage += 2;
+        self.send(Op::Write(Entry::Ack(id)))
+    }
+
+    /// Write buffered records to the os
+    pub(super) fn flush(&mut self) {
+        self.send(Op::Flush)
+    }
+
+    /// Write buffered records to disk, the returned channel is
+    /// resolved when they are.
+    pub(super) fn sync(&mut self) -> oneshot::Receiver<Result<()>> {
+        let (tx, rx) = oneshot::channel();
+        self.send(Op::Sync(tx));
+        rx
+    }
+
+    /// true if the journal is mostly acked messages, and should be
+    /// rewritten.
+    pub(super) fn should_compact(&self, live: usize) -> bool {
+        self.garbage > 10_000 && self.garbage > live
+    }
+
+    /// Rewrite the journal so it contains only the specified live
+    /// messages.
+    pub(super) fn compact(&mut self, live: Vec<(u64, Value)>) {
+        self.garbage = 0;
+        self.send(Op::Compact(live))
+    }
+}
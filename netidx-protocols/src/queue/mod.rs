@@ -0,0 +1,23 @@
+// A work queue with competing consumers.
+//
+// The queue is published as a channel listener, producers and
+// consumers are channel connections to it. Clients send,
+//
+// ("enqueue", v): add v to the back of the queue
+// "sync": the server will reply "synced" once every message enqueued
+// before it is in the queue, and on disk if the queue is durable
+// ("consume", n): deliver messages to this connection, with at most n
+// unacknowledged at once
+// ("ack", id): message id was processed, remove it from the queue
+// ("nack", id): message id was not processed, deliver it again
+//
+// The server delivers messages to consumers as (id, attempts, v),
+// where attempts is the number of times the message has been
+// delivered, including this one.
+
+pub mod client;
+mod journal;
+pub mod server;
+
+#[cfg(test)]
+mod test;
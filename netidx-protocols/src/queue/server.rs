@@ -0,0 +1,448 @@
+use super::journal::Journal;
+use crate::channel::server::{Connection, Listener};
+use anyhow::Result;
+use futures::{
+    channel::{mpsc, oneshot},
+    prelude::*,
+    select_biased,
+};
+use fxhash::{FxHashMap, FxHashSet};
+use log::{info, warn};
+use netidx::{
+    path::Path,
+    publisher::{Publisher, Value},
+};
+use parking_lot::Mutex;
+use std::{
+    collections::VecDeque,
+    path::PathBuf,
+    sync::{Arc, Weak},
+    time::{Duration, Instant},
+};
+use tokio::{task, time};
+
+struct Message {
+    id: u64,
+    attempts: u32,
+    value: Value,
+}
+
+struct InFlight {
+    msg: Message,
+    consumer: u64,
+    deadline: Instant,
+}
+
+struct Consumer {
+    credit: usize,
+    deliver: mpsc::UnboundedSender<Value>,
+    // messages delivered to this consumer that it hasn't acked or
+    // nacked yet, it may only ack or nack these
+    outstanding: FxHashSet<u64>,
+}
+
+struct State {
+    visibility_timeout: Duration,
+    next_id: u64,
+    next_consumer: u64,
+    ready: VecDeque<Message>,
+    in_flight: FxHashMap<u64, InFlight>,
+    consumers: FxHashMap<u64, Consumer>,
+    // consumers in round robin order
+    order: VecDeque<u64>,
+    journal: Option<Journal>,
+}
+
+impl State {
+    fn enqueue(&mut self, value: Value) {
+        let id = self.next_id;
+        self.next_id += 1;
+        if let Some(journal) = &mut self.journal {
+            journal.enqueue(id, &value)
+        }
+        self.ready.push_back(Message { id, attempts: 0, value })
+    }
+
+    fn requeue(&mut self, mut msgs: Vec<Message>) {
+        msgs.sort_by_key(|m| m.id);
+        for m in msgs.into_iter().rev() {
+            self.ready.push_front(m)
+        }
+    }
+
+    fn dispatch(&mut self) {
+        let now = Instant::now();
+        let mut idle = 0;
+        while self.ready.len() > 0 && idle < self.order.len() {
+            let cid = self.order.pop_front().unwrap();
+            self.order.push_back(cid);
+            match self.consumers.get_mut(&cid) {
+                Some(c) if c.credit > 0 => {
+                    let mut msg = self.ready.pop_front().unwrap();
+                    msg.attempts += 1;
+                    let v = Value::from((msg.id, msg.attempts, msg.value.clone()));
+                    if c.deliver.unbounded_send(v).is_err() {
+                        // the consumer is gone, it will be cleaned up
+                        // by it's connection task
+                        self.ready.push_front(msg);
+                        idle += 1;
+                    } else {
+                        c.credit -= 1;
+                        c.outstanding.insert(msg.id);
+                        idle = 0;
+                        let deadline = now + self.visibility_timeout;
+                        self.in_flight
+                            .insert(msg.id, InFlight { msg, consumer: cid, deadline });
+                    }
+                }
+                Some(_) | None => idle += 1,
+            }
+        }
+    }
+
+    fn connect(&mut self) -> (u64, mpsc::UnboundedReceiver<Value>) {
+        let (deliver, rx) = mpsc::unbounded();
+        self.next_consumer += 1;
+        let cid = self.next_consumer;
+        let outstanding = FxHashSet::default();
+        self.consumers.insert(cid, Consumer { credit: 0, deliver, outstanding });
+        self.order.push_back(cid);
+        (cid, rx)
+    }
+
+    fn consume(&mut self, cid: u64, n: usize) {
+        if let Some(c) = self.consumers.get_mut(&cid) {
+            c.credit += n;
+        }
+    }
+
+    // if message id was delivered to cid, and cid hasn't acked or
+    // nacked it yet, give cid back the credit for it and return true
+    fn answer(&mut self, cid: u64, id: u64) -> bool {
+        match self.consumers.get_mut(&cid) {
+            Some(c) if c.outstanding.remove(&id) => {
+                c.credit += 1;
+                true
+            }
+            Some(_) | None => false,
+        }
+    }
+
+    fn ack(&mut self, cid: u64, id: u64) {
+        if !self.answer(cid, id) {
+            return warn!("queue consumer {} acked message {} it doesn't hold", cid, id);
+        }
+        // even if the message timed out it was processed by someone,
+        // so it doesn't need to be delivered again
+        let removed = self.in_flight.remove(&id).is_some() || {
+            let len = self.ready.len();
+            self.ready.retain(|m| m.id != id);
+            self.ready.len() < len
+        };
+        if removed {
+            if let Some(journal) = &mut self.journal {
+                journal.ack(id);
+                let live = self.ready.len() + self.in_flight.len();
+                if journal.should_compact(live) {
+                    let mut msgs = self
+                        .ready
+                        .iter()
+                        .chain(self.in_flight.values().map(|f| &f.msg))
+                        .map(|m| (m.id, m.value.clone()))
+                        .collect::<Vec<_>>();
+                    msgs.sort_by_key(|(id, _)| *id);
+                    journal.compact(msgs)
+                }
+            }
+        }
+    }
+
+    fn nack(&mut self, cid: u64, id: u64) {
+        if !self.answer(cid, id) {
+            return warn!("queue consumer {} nacked message {} it doesn't hold", cid, id);
+        }
+        match self.in_flight.get(&id) {
+            Some(f) if f.consumer == cid => {
+                let f = self.in_flight.remove(&id).unwrap();
+                self.requeue(vec![f.msg])
+            }
+            Some(_) | None => (),
+        }
+    }
+
+    // redeliver messages whose consumer has been quiet for too long
+    fn expire(&mut self, now: Instant) {
+        let expired = self
+            .in_flight
+            .iter()
+            .filter(|(_, f)| f.deadline <= now)
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        if expired.len() > 0 {
+            info!("redelivering {} messages after visibility timeout", expired.len());
+            let msgs =
+                expired.iter().filter_map(|id| self.in_flight.remove(id)).map(|f| f.msg);
+            let msgs = msgs.collect();
+            self.requeue(msgs);
+            self.dispatch()
+        }
+    }
+
+    fn disconnect(&mut self, cid: u64) {
+        self.consumers.remove(&cid);
+        self.order.retain(|c| *c != cid);
+        let lost = self
+            .in_flight
+            .iter()
+            .filter(|(_, f)| f.consumer == cid)
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        let msgs = lost.iter().filter_map(|id| self.in_flight.remove(id)).map(|f| f.msg);
+        let msgs = msgs.collect();
+        self.requeue(msgs);
+        self.dispatch()
+    }
+
+    fn flush(&mut self) {
+        if let Some(journal) = &mut self.journal {
+            journal.flush()
+        }
+    }
+
+    // the returned channel is resolved when the journal is on disk
+    fn sync(&mut self) -> Option<oneshot::Receiver<Result<()>>> {
+        self.journal.as_mut().map(|journal| journal.sync())
+    }
+}
+
+enum Req {
+    Enqueue(Value),
+    Sync,
+    Consume(usize),
+    Ack(u64),
+    Nack(u64),
+}
+
+impl Req {
+    fn parse(v: Value) -> Result<Req> {
+        match v {
+            Value::String(s) if &*s == "sync" => Ok(Req::Sync),
+            Value::Array(a) if a.len() == 2 => match (&a[0], &a[1]) {
+                (Value::String(s), v) if &**s == "enqueue" => Ok(Req::Enqueue(v.clone())),
+                (Value::String(s), v) if &**s == "consume" => {
+                    Ok(Req::Consume(v.clone().cast_to::<u64>()? as usize))
+                }
+                (Value::String(s), v) if &**s == "ack" => {
+                    Ok(Req::Ack(v.clone().cast_to::<u64>()?))
+                }
+                (Value::String(s), v) if &**s == "nack" => {
+                    Ok(Req::Nack(v.clone().cast_to::<u64>()?))
+                }
+                _ => bail!("unexpected request {}", Value::Array(a.clone())),
+            },
+            v => bail!("unexpected request {}", v),
+        }
+    }
+}
+
+async fn client_loop(
+    state: Weak<Mutex<State>>,
+    cid: u64,
+    mut deliver: mpsc::UnboundedReceiver<Value>,
+    con: Connection,
+) -> Result<()> {
+    let mut reqs: Vec<Value> = Vec::new();
+    let mut deliveries: Vec<Value> = Vec::new();
+    loop {
+        select_biased! {
+            v = deliver.next() => match v {
+                None => bail!("queue stopped"),
+                Some(v) => deliveries.push(v),
+            },
+            r = con.recv(&mut reqs).fuse() => r?,
+        }
+        if deliveries.len() > 0 {
+            while let Ok(Some(v)) = deliver.try_next() {
+                deliveries.push(v)
+            }
+            let mut batch = con.start_batch();
+            for v in deliveries.drain(..) {
+                batch.queue(v)
+            }
+            con.send(batch).await?
+        }
+        if reqs.len() > 0 {
+            let mut synced = 0;
+            let on_disk = {
+                let state = state.upgrade().ok_or_else(|| anyhow!("queue stopped"))?;
+                let mut state = state.lock();
+                for v in reqs.drain(..) {
+                    match Req::parse(v)? {
+                        Req::Enqueue(v) => state.enqueue(v),
+                        Req::Sync => synced += 1,
+                        Req::Consume(n) => state.consume(cid, n),
+                        Req::Ack(id) => state.ack(cid, id),
+                        Req::Nack(id) => state.nack(cid, id),
+                    }
+                }
+                let on_disk = if synced > 0 {
+                    state.sync()
+                } else {
+                    state.flush();
+                    None
+                };
+                state.dispatch();
+                on_disk
+            };
+            // wait for the journal without holding the lock
+            if let Some(on_disk) = on_disk {
+                on_disk.await??
+            }
+            for _ in 0..synced {
+                con.send_one(Value::from("synced")).await?
+            }
+        }
+    }
+}
+
+/// A work queue published at a path. Any number of producers may
+/// enqueue messages, and any number of consumers may receive them,
+/// each message is delivered to only one consumer at a time. Messages
+/// are removed from the queue when the consumer acknowledges them, if
+/// the consumer disconnects, nacks the message, or doesn't
+/// acknowledge it within the visibility timeout, then it will be
+/// delivered again, possibly to a different consumer.
+///
+/// Delivery is at least once, consumers should be prepared to see a
+/// message more than once.
+///
+/// The queue, and all connections to it, stop when it is dropped.
+pub struct Queue {
+    state: Arc<Mutex<State>>,
+    _stop: oneshot::Sender<()>,
+}
+
+impl Queue {
+    /// Publish a new queue at `path`. The contents of the queue are
+    /// only kept in memory.
+    pub async fn new(
+        publisher: &Publisher,
+        visibility_timeout: Duration,
+        path: Path,
+    ) -> Result<Queue> {
+        Self::new_inner(publisher, visibility_timeout, None, path).await
+    }
+
+    /// Publish a new durable queue at `path`. Every message is
+    /// written to the log at `log` before it is added to the queue,
+    /// and acks are written to the log as well, so the queue will
+    /// survive a restart. If the log already exists then the
+    /// unacknowledged messages in it are put back in the queue.
+    ///
+    /// The log is only guaranteed to be on disk when a producer
+    /// flushes.
+    pub async fn new_durable(
+        publisher: &Publisher,
+        visibility_timeout: Duration,
+        log: PathBuf,
+        path: Path,
+    ) -> Result<Queue> {
+        Self::new_inner(publisher, visibility_timeout, Some(log), path).await
+    }
+
+    async fn new_inner(
+        publisher: &Publisher,
+        visibility_timeout: Duration,
+        log: Option<PathBuf>,
+        path: Path,
+    ) -> Result<Queue> {
+        let (journal, ready, next_id) = match log {
+            None => (None, VecDeque::new(), 0),
+            Some(log) => {
+                let (journal, msgs) = Journal::open(log)?;
+                let next_id = msgs.last().map(|(id, _)| *id + 1).unwrap_or(0);
+                let ready = msgs
+                    .into_iter()
+                    .map(|(id, value)| Message { id, attempts: 0, value })
+                    .collect::<VecDeque<_>>();
+                (Some(journal), ready, next_id)
+            }
+        };
+        let state = Arc::new(Mutex::new(State {
+            visibility_timeout,
+            next_id,
+            next_consumer: 0,
+            ready,
+            in_flight: FxHashMap::default(),
+            consumers: FxHashMap::default(),
+            order: VecDeque::new(),
+            journal,
+        }));
+        let mut listener = Listener::new(publisher, None, path.clone()).await?;
+        let (tx_stop, rx_stop) = oneshot::channel();
+        let weak = Arc::downgrade(&state);
+        task::spawn(async move {
+            let tick = (visibility_timeout / 4).max(Duration::from_millis(100));
+            loop {
+                time::sleep(tick).await;
+                match weak.upgrade() {
+                    None => break,
+                    Some(state) => {
+                        state.lock().expire(Instant::now());
+                    }
+                }
+            }
+        });
+        let weak = Arc::downgrade(&state);
+        task::spawn(async move {
+            let mut stop = rx_stop.fuse();
+            loop {
+                select_biased! {
+                    _ = stop => break,
+                    r = listener.accept().fuse() => match r {
+                        Err(e) => {
+                            warn!("queue {} listener failed {}", path, e);
+                            break
+                        }
+                        Ok(singleton) => {
+                            let weak = weak.clone();
+                            task::spawn(async move {
+                                let con = match singleton.wait_connected().await {
+                                    Ok(con) => con,
+                                    Err(e) => return info!("queue handshake failed {}", e),
+                                };
+                                let (cid, deliver) = match weak.upgrade() {
+                                    None => return,
+                                    Some(state) => {
+                                        let mut state = state.lock();
+                                        state.connect()
+                                    }
+                                };
+                                let r = client_loop(weak.clone(), cid, deliver, con).await;
+                                if let Err(e) = r {
+                                    info!("queue client {} disconnected {}", cid, e)
+                                }
+                                if let Some(state) = weak.upgrade() {
+                                    state.lock().disconnect(cid);
+                                }
+                            });
+                        }
+                    },
+                }
+            }
+        });
+        Ok(Queue { state, _stop: tx_stop })
+    }
+
+    /// The number of messages waiting to be delivered
+    pub fn ready(&self) -> usize {
+        let state = self.state.lock();
+        state.ready.len()
+    }
+
+    /// The number of messages that have been delivered but not yet
+    /// acknowledged
+    pub fn in_flight(&self) -> usize {
+        self.state.lock().in_flight.len()
+    }
+}
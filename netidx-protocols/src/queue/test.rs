@@ -0,0 +1,118 @@
+use super::*;
+use crate::channel::test::Ctx;
+use futures::{prelude::*, select_biased};
+use netidx::subscriber::Value;
+use std::{collections::HashSet, time::Duration};
+use tokio::{runtime::Runtime, time};
+
+#[test]
+fn competing_consumers() {
+    Runtime::new().unwrap().block_on(async move {
+        let ctx = Ctx::new().await;
+        let to = Duration::from_secs(30);
+        let _queue =
+            server::Queue::new(&ctx.publisher, to, ctx.base.clone()).await.unwrap();
+        let mut producer =
+            client::Producer::new(&ctx.subscriber, ctx.base.clone()).await.unwrap();
+        let c0 =
+            client::Consumer::new(&ctx.subscriber, ctx.base.clone(), 10).await.unwrap();
+        let c1 =
+            client::Consumer::new(&ctx.subscriber, ctx.base.clone(), 10).await.unwrap();
+        for i in 0..100u64 {
+            producer.enqueue_pack(&i).unwrap();
+        }
+        producer.flush().await.unwrap();
+        let mut seen = HashSet::new();
+        let (mut n0, mut n1) = (0, 0);
+        while seen.len() < 100 {
+            let (d, c) = select_biased! {
+                d = c0.recv().fuse() => { n0 += 1; (d.unwrap(), &c0) },
+                d = c1.recv().fuse() => { n1 += 1; (d.unwrap(), &c1) },
+            };
+            assert_eq!(d.attempts, 1);
+            assert!(seen.insert(d.unpack::<u64>().unwrap()));
+            c.ack(&d).unwrap();
+        }
+        assert!(n0 > 0 && n1 > 0);
+        assert!(time::timeout(Duration::from_millis(250), c0.recv()).await.is_err());
+    })
+}
+
+#[test]
+fn redelivery() {
+    Runtime::new().unwrap().block_on(async move {
+        let ctx = Ctx::new().await;
+        let to = Duration::from_secs(1);
+        let queue =
+            server::Queue::new(&ctx.publisher, to, ctx.base.clone()).await.unwrap();
+        let mut producer =
+            client::Producer::new(&ctx.subscriber, ctx.base.clone()).await.unwrap();
+        producer.enqueue(Value::from("hello")).unwrap();
+        producer.flush().await.unwrap();
+        let consumer =
+            client::Consumer::new(&ctx.subscriber, ctx.base.clone(), 1).await.unwrap();
+        let d = consumer.recv().await.unwrap();
+        assert_eq!(d.attempts, 1);
+        consumer.nack(&d).unwrap();
+        let d = consumer.recv().await.unwrap();
+        assert_eq!(d.attempts, 2);
+        // don't ack, the visibility timeout should deliver it again to
+        // the other consumer
+        let other =
+            client::Consumer::new(&ctx.subscriber, ctx.base.clone(), 1).await.unwrap();
+        let d = time::timeout(to * 5, other.recv()).await.unwrap().unwrap();
+        assert_eq!(d.attempts, 3);
+        assert_eq!(d.value, Value::from("hello"));
+        other.ack(&d).unwrap();
+        time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(queue.ready() + queue.in_flight(), 0);
+    })
+}
+
+#[test]
+fn durable() {
+    Runtime::new().unwrap().block_on(async move {
+        let ctx = Ctx::new().await;
+        let to = Duration::from_secs(30);
+        let log = std::env::temp_dir()
+            .join(format!("netidx-queue-test-{}.log", uuid::Uuid::new_v4()));
+        let queue =
+            server::Queue::new_durable(&ctx.publisher, to, log.clone(), ctx.base.clone())
+                .await
+                .unwrap();
+        let mut producer =
+            client::Producer::new(&ctx.subscriber, ctx.base.clone()).await.unwrap();
+        for i in 0..10u64 {
+            producer.enqueue_pack(&i).unwrap();
+        }
+        producer.flush().await.unwrap();
+        let consumer =
+            client::Consumer::new(&ctx.subscriber, ctx.base.clone(), 5).await.unwrap();
+        for i in 0..5u64 {
+            let d = consumer.recv().await.unwrap();
+            assert_eq!(d.unpack::<u64>().unwrap(), i);
+            consumer.ack(&d).unwrap();
+        }
+        consumer.flush().await.unwrap();
+        while queue.in_flight() > 0 {
+            time::sleep(Duration::from_millis(10)).await
+        }
+        assert_eq!(queue.ready(), 5);
+        drop(consumer);
+        drop(producer);
+        drop(queue);
+        time::sleep(Duration::from_millis(100)).await;
+        let base = ctx.base.append("restarted");
+        let _queue =
+            server::Queue::new_durable(&ctx.publisher, to, log.clone(), base.clone())
+                .await
+                .unwrap();
+        let consumer = client::Consumer::new(&ctx.subscriber, base, 5).await.unwrap();
+        for i in 5..10u64 {
+            let d = consumer.recv().await.unwrap();
+            assert_eq!(d.unpack::<u64>().unwrap(), i);
+            consumer.ack(&d).unwrap();
+        }
+        let _ = std::fs::remove_file(&log);
+    })
+}
@@ -0,0 +1,321 @@
+use crate::cluster::Cluster;
+use anyhow::Result;
+use netidx::{pack::Pack, path::Path, publisher::Publisher, subscriber::Subscriber};
+use netidx_derive::Pack;
+use std::{
+    cmp::max,
+    collections::{hash_map::Entry as HEntry, HashMap},
+    hash::Hash,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+// the maximum number of entries in one snapshot message
+const SNAPSHOT_CHUNK: usize = 1000;
+
+// how long to wait for a member that asked for a snapshot to show up
+// in the resolver
+const SNAPSHOT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The version of an entry. Stamps are totally ordered by time, and
+/// then by the member that made the change, so every member picks
+/// the same winner for concurrent changes.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Pack)]
+struct Stamp {
+    time: u64,
+    member: Path,
+}
+
+#[derive(Debug, Clone, Pack)]
+struct Entry<V> {
+    stamp: Stamp,
+    // None if the key was removed
+    value: Option<V>,
+}
+
+#[derive(Debug, Clone, Pack)]
+enum Msg<K, V> {
+    Set { key: K, entry: Entry<V> },
+    SnapshotReq { member: Path },
+    Snapshot { entries: Vec<(K, Entry<V>)> },
+}
+
+/// A change to the map made by another member
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change<K, V> {
+    Insert(K, V),
+    Remove(K),
+}
+
+/// A map replicated among the members of a `Cluster`. Every member
+/// may change the map, and changes are broadcast to all other
+/// members. Conflicting changes are resolved by last writer wins,
+/// using a hybrid clock stamp with the member's path as a tie
+/// breaker, so once every change has been delivered all members have
+/// the same map.
+///
+/// A new member asks the existing members for a snapshot when it
+/// joins. Every member replies, since merging is idempotent it
+/// doesn't matter that the new member gets more than one.
+///
+/// Removed keys are kept as tombstones so that a removal can't be
+/// undone by an older insert that arrives late. Tombstones are never
+/// collected, so memory use, and the size of snapshots, grows with
+/// the number of distinct keys ever inserted, not the number
+/// currently in the map. Maps with many short lived keys should
+/// reuse keys or be recreated periodically.
+///
+/// Like `Cluster` the map does nothing on it's own, you must call
+/// `wait_changes` in a loop to apply changes from other members, and
+/// `poll_members` periodically to discover new members.
+pub struct ReplicatedMap<K: Pack, V: Pack> {
+    cluster: Cluster<Msg<K, V>>,
+    map: HashMap<K, Entry<V>>,
+    clock: u64,
+    // members waiting for a snapshot
+    snapshot_reqs: Vec<(Path, Instant)>,
+}
+
+impl<K, V> ReplicatedMap<K, V>
+where
+    K: Pack + Hash + Eq + Clone,
+    V: Pack + Clone,
+{
+    /// Join the map replicated under `base`, waiting for at least
+    /// `shards` other members, as `Cluster::new`, and request a
+    /// snapshot from them.
+    pub async fn new(
+        publisher: &Publisher,
+        subscriber: Subscriber,
+        base: Path,
+        shards: usize,
+    ) -> Result<Self> {
+        let cluster = Cluster::new(publisher, subscriber, base, shards).await?;
+        cluster.send_cmd(&Msg::SnapshotReq { member: cluster.path() });
+        Ok(ReplicatedMap {
+            cluster,
+            map: HashMap::new(),
+            clock: 0,
+            snapshot_reqs: vec![],
+        })
+    }
+
+    /// Return our path in the cluster
+    pub fn path(&self) -> Path {
+        self.cluster.path()
+    }
+
+    /// Returns true if we are the primary member of the cluster, see
+    /// `Cluster::primary`
+    pub fn primary(&self) -> bool {
+        self.cluster.primary()
+    }
+
+    /// Poll for new members, see `Cluster::poll_members`
+    pub async fn poll_members(&mut self) -> Result<bool> {
+        let res = self.cluster.poll_members().await?;
+        self.send_snapshots();
+        Ok(res)
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.map.get(key).and_then(|e| e.value.as_ref())
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.map.iter().filter_map(|(k, e)| e.value.as_ref().map(|v| (k, v)))
+    }
+
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+
+    fn stamp(&mut self) -> Stamp {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_micros() as u64)
+            .unwrap_or(0);
+        self.clock = max(self.clock + 1, now);
+        Stamp { time: self.clock, member: self.cluster.path() }
+    }
+
+    fn set(&mut self, key: K, value: Option<V>) {
+        let entry = Entry { stamp: self.stamp(), value };
+        self.cluster.send_cmd(&Msg::Set { key: key.clone(), entry: entry.clone() });
+        self.map.insert(key, entry);
+    }
+
+    /// Insert `value` at `key`, and send the change to the other
+    /// members
+    pub fn insert(&mut self, key: K, value: V) {
+        self.set(key, Some(value))
+    }
+
+    /// Remove `key`, and send the change to the other members
+    pub fn remove(&mut self, key: K) {
+        if self.contains_key(&key) {
+            self.set(key, None)
+        }
+    }
+
+    // merge an entry from another member, return the change if it
+    // changed the map
+    fn merge(&mut self, key: K, entry: Entry<V>) -> Option<Change<K, V>> {
+        self.clock = max(self.clock, entry.stamp.time);
+        let change = entry.value.as_ref().map(|v| Change::Insert(key.clone(), v.clone()));
+        match self.map.entry(key) {
+            HEntry::Vacant(e) => {
+                e.insert(entry);
+                change
+            }
+            HEntry::Occupied(mut e) => {
+                if e.get().stamp >= entry.stamp {
+                    None
+                } else {
+                    let old = e.insert(entry);
+                    match (change, old.value) {
+                        (Some(change), _) => Some(change),
+                        (None, Some(_)) => Some(Change::Remove(e.key().clone())),
+                        (None, None) => None,
+                    }
+                }
+            }
+        }
+    }
+
+    // send snapshots to the members that asked for one that we know
+    // about, keep the rest until we discover them, or give up
+    fn send_snapshots(&mut self) {
+        if !self.snapshot_reqs.is_empty() {
+            let now = Instant::now();
+            let entries =
+                self.map.iter().map(|(k, e)| (k.clone(), e.clone())).collect::<Vec<_>>();
+            let cluster = &self.cluster;
+            self.snapshot_reqs.retain(|(member, asked)| {
+                if cluster.is_member(member) {
+                    for chunk in entries.chunks(SNAPSHOT_CHUNK) {
+                        let entries = chunk.to_vec();
+                        cluster.send_cmd_to_one(member, &Msg::Snapshot { entries });
+                    }
+                    false
+                } else {
+                    now - *asked < SNAPSHOT_TIMEOUT
+                }
+            });
+        }
+    }
+
+    /// Wait for messages from other members and apply them to the
+    /// map, returning the changes they caused. The changes may be
+    /// empty, for example if a new member asked for a snapshot. This
+    /// is cancel safe, nothing is awaited after messages are applied.
+    pub async fn wait_changes(&mut self) -> Result<Vec<Change<K, V>>> {
+        // a new member may have joined after we last polled, so we
+        // must poll before we can send it a snapshot. Snapshots we
+        // can't send yet are retried the next time we are called.
+        if !self.snapshot_reqs.is_empty() {
+            self.poll_members().await?;
+        }
+        let mut changes = Vec::new();
+        for msg in self.cluster.wait_cmds().await? {
+            match msg {
+                Msg::Set { key, entry } => changes.extend(self.merge(key, entry)),
+                Msg::Snapshot { entries } => {
+                    for (key, entry) in entries {
+                        changes.extend(self.merge(key, entry))
+                    }
+                }
+                Msg::SnapshotReq { member } => {
+                    self.snapshot_reqs.push((member, Instant::now()))
+                }
+            }
+        }
+        self.send_snapshots();
+        Ok(changes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::channel::test::Ctx;
+    use futures::{future, prelude::*, select_biased};
+    use tokio::{runtime::Runtime, time};
+
+    #[test]
+    fn replicated_map() {
+        Runtime::new().unwrap().block_on(async move {
+            let ctx = Ctx::new().await;
+            let base = Path::from("/replicated");
+            let to = Duration::from_secs(10);
+            let (a, b) = future::join(
+                ReplicatedMap::<u64, String>::new(
+                    &ctx.publisher,
+                    ctx.subscriber.clone(),
+                    base.clone(),
+                    1,
+                ),
+                ReplicatedMap::<u64, String>::new(
+                    &ctx.publisher,
+                    ctx.subscriber.clone(),
+                    base.clone(),
+                    1,
+                ),
+            )
+            .await;
+            let (mut a, mut b) = (a.unwrap(), b.unwrap());
+            a.insert(0, "zero".into());
+            a.insert(1, "one".into());
+            let mut changes = Vec::new();
+            while changes.len() < 2 {
+                changes
+                    .extend(time::timeout(to, b.wait_changes()).await.unwrap().unwrap());
+            }
+            assert_eq!(changes[0], Change::Insert(0, "zero".into()));
+            assert_eq!(changes[1], Change::Insert(1, "one".into()));
+            // concurrent changes to the same key converge
+            a.insert(2, "a".into());
+            b.insert(2, "b".into());
+            b.remove(0);
+            let converge = async {
+                while a.get(&2) != b.get(&2) || a.contains_key(&0) {
+                    select_biased! {
+                        r = a.wait_changes().fuse() => { r.unwrap(); },
+                        r = b.wait_changes().fuse() => { r.unwrap(); },
+                    }
+                }
+            };
+            time::timeout(to, converge).await.unwrap();
+            assert_eq!(a.len(), 2);
+            // a late joiner gets a snapshot
+            let mut c = ReplicatedMap::<u64, String>::new(
+                &ctx.publisher,
+                ctx.subscriber.clone(),
+                base.clone(),
+                2,
+            )
+            .await
+            .unwrap();
+            let snapshot = async {
+                while c.len() < 2 {
+                    select_biased! {
+                        r = a.wait_changes().fuse() => { r.unwrap(); },
+                        r = b.wait_changes().fuse() => { r.unwrap(); },
+                        r = c.wait_changes().fuse() => { r.unwrap(); },
+                    }
+                }
+            };
+            time::timeout(to, snapshot).await.unwrap();
+            assert!(!c.contains_key(&0));
+            assert_eq!(c.get(&1), a.get(&1));
+            assert_eq!(c.get(&2), a.get(&2));
+        })
+    }
+}
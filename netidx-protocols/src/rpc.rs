@@ -2,7 +2,7 @@ use anyhow::Result;
 use arcstr::ArcStr;
 use futures::{
     channel::{mpsc, oneshot},
-    future,
+    future, pin_mut,
     prelude::*,
     select_biased, stream,
 };
@@ -16,24 +16,116 @@ use netidx::{
     publisher::{
         ClId, Id, PublishFlags, Publisher, SendResult, Val, Value, WriteRequest,
     },
-    subscriber::{Dval, Subscriber, SubscriberId},
+    subscriber::{Dval, Event, Subscriber, SubscriberId},
 };
 use parking_lot::Mutex;
 use std::{
     borrow::Borrow,
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     iter,
     ops::Drop,
     sync::{Arc, Weak},
     time::{Duration, Instant},
 };
-use tokio::{sync::Mutex as AsyncMutex, task};
+use tokio::{sync::Mutex as AsyncMutex, task, time};
+
+/**
+Define a typed rpc service from a trait. Each method of the trait
+becomes an rpc procedure, and two types are generated, for a trait
+named `Calc` they would be,
+
+* `CalcServer`, which publishes an implementation of `Calc` as a set
+  of `server::Proc`s under a base path, one for each method.
+* `CalcClient`, which wraps a `client::Proc` for each method, and has
+  a typed async method for each one.
+
+Methods must be async, take `&self`, and return `Result<T>`. Their
+arguments must implement `FromValue` and `Into<Value>`, and `T` must
+implement `Into<Value>` and `FromValue`. The method doc comment is
+published as the procedure doc, and arguments may be annotated with
+`#[rpc(doc = "...", default = expr)]` to set their doc and default
+value. If an error is returned it is sent to the caller as a
+`Value::Error`, which the client turns back into an error. If the
+client cancels the call, or it's deadline passes, before the method
+returns then the method's future is dropped.
+
+# Example
+```no_run
+use anyhow::Result;
+use netidx::{path::Path, publisher::Publisher, subscriber::Subscriber};
+
+#[netidx_protocols::rpc::service]
+pub trait Calc {
+    /// add two numbers
+    async fn add(&self, #[rpc(doc = "the first number")] a: i64, b: i64) -> Result<i64>;
+}
+
+struct Impl;
+
+impl Calc for Impl {
+    async fn add(&self, a: i64, b: i64) -> Result<i64> {
+        Ok(a + b)
+    }
+}
+
+# async fn z(publisher: Publisher, subscriber: Subscriber) -> Result<()> {
+let _server = CalcServer::new(&publisher, Path::from("/examples/calc"), Impl)?;
+let calc = CalcClient::new(&subscriber, Path::from("/examples/calc")).await?;
+assert_eq!(calc.add(1, 2).await?, 3);
+# Ok(())
+# }
+```
+**/
+pub use netidx_derive::rpc_service as service;
+
+#[doc(hidden)]
+pub mod __private {
+    pub use super::{
+        client::Proc as ClientProc,
+        server::{ArgSpec, Proc as ServerProc, RpcCall},
+    };
+    pub use anyhow::{anyhow, Result};
+    pub use arcstr::ArcStr;
+    pub use netidx::{
+        chars::Chars,
+        path::Path,
+        publisher::{Publisher, Value},
+        subscriber::{FromValue, Subscriber},
+    };
+    pub use tokio::task::spawn;
+}
+
+/// The version of the rpc protocol. It is published at
+/// `procedure/doc/version`, where it can't be confused with an
+/// argument or a procedure. Clients that see it send the arguments
+/// of a call along with the call, in a single write tagged with a
+/// call id, so one client may have many calls in flight. Otherwise
+/// (old servers) the arguments are written to their values before
+/// the call, and calls from the same subscriber are serialized.
+///
+/// The reply to a versioned call is either `(call_id, value)`, or
+/// `(call_id, "stream", path)` if the server streams the reply, in
+/// which case the client connects to the channel at path and
+/// receives `("partial", value)` messages followed by exactly one
+/// `("final", value)`.
+///
+/// A versioned call may also be `(call_id, args, timeout)`, in which
+/// case the server will consider the call cancelled after timeout,
+/// and a client may cancel an outstanding call by writing `("cancel",
+/// call_id)`.
+const VERSION: u32 = 1;
+
+fn version_path(name: &Path) -> Path {
+    name.append("doc/version")
+}
 
 #[macro_use]
 pub mod server {
     use std::panic::{catch_unwind, AssertUnwindSafe};
 
     use super::*;
+    use crate::channel::server::{session, singleton_with_flags, Connection};
+    use tokio::sync::watch;
 
     atomic_id!(ProcId);
 
@@ -102,20 +194,94 @@ pub mod server {
         static ref ARGS: Pool<HashMap<ArcStr, Value>> = Pool::new(10000, 50);
     }
 
-    pub struct RpcReply(Option<SendResult>);
+    pub struct RpcReply {
+        result: Option<SendResult>,
+        // replies to clients that implement VERSION are tagged with
+        // the call id
+        call_id: Option<u64>,
+        stream: StreamCtx,
+    }
+
+    // what is needed to publish a streaming reply
+    #[derive(Clone)]
+    struct StreamCtx {
+        publisher: Publisher,
+        flags: PublishFlags,
+        name: Path,
+    }
 
     impl Drop for RpcReply {
         fn drop(&mut self) {
-            if let Some(reply) = self.0.take() {
-                let _ = reply.send(Value::Error(Chars::from("rpc call failed")));
-            }
+            self.send(Value::Error(Chars::from("rpc call failed")))
         }
     }
 
     impl RpcReply {
         pub fn send<T: Into<Value>>(&mut self, m: T) {
-            if let Some(res) = self.0.take() {
-                res.send(m.into());
+            if let Some(res) = self.result.take() {
+                match self.call_id {
+                    None => res.send(m.into()),
+                    Some(id) => res.send(Value::from((id, m.into()))),
+                }
+            }
+        }
+
+        /// Reply with a stream instead of a single value. The
+        /// returned `RpcStream` may send any number of partial
+        /// results before it is finished with a final value. This
+        /// will fail if the client doesn't support streaming replies,
+        /// or if it doesn't connect to the stream within 15 seconds.
+        pub async fn stream(&mut self) -> Result<RpcStream> {
+            let call_id = match self.call_id {
+                Some(call_id) => call_id,
+                None => bail!("the client does not support streaming replies"),
+            };
+            if self.result.is_none() {
+                bail!("the call has already been replied to")
+            }
+            let StreamCtx { publisher, flags, name } = &self.stream;
+            let path = session(&name.append("stream"));
+            let singleton =
+                singleton_with_flags(publisher, *flags, None, path.clone()).await?;
+            if let Some(res) = self.result.take() {
+                res.send(Value::from((call_id, "stream", path)));
+            }
+            let to = Duration::from_secs(15);
+            let con = time::timeout(to, singleton.wait_connected()).await??;
+            Ok(RpcStream(Some(con)))
+        }
+    }
+
+    /// A streaming reply to an rpc call. If it is dropped without
+    /// being finished the client will receive an error as the final
+    /// value.
+    pub struct RpcStream(Option<Connection>);
+
+    impl Drop for RpcStream {
+        fn drop(&mut self) {
+            if let Some(con) = self.0.take() {
+                task::spawn(async move {
+                    let m = Value::Error(Chars::from("rpc call failed"));
+                    let _ = con.send_one(Value::from(("final", m))).await;
+                });
+            }
+        }
+    }
+
+    impl RpcStream {
+        /// Send a partial result, or a progress update, to the client
+        pub async fn send<T: Into<Value>>(&self, m: T) -> Result<()> {
+            match &self.0 {
+                None => bail!("the stream is finished"),
+                Some(con) => con.send_one(Value::from(("partial", m.into()))).await,
+            }
+        }
+
+        /// Send the final value and close the stream
+        pub async fn finish<T: Into<Value>>(mut self, m: T) -> Result<()> {
+            match self.0.take() {
+                None => bail!("the stream is finished"),
+                Some(con) => con.send_one(Value::from(("final", m.into()))).await,
             }
         }
     }
@@ -132,6 +298,105 @@ pub mod server {
         pub id: ProcId,
         pub args: Pooled<HashMap<ArcStr, Value>>,
         pub reply: RpcReply,
+        deadline: Option<Instant>,
+        cancel: watch::Receiver<bool>,
+    }
+
+    impl RpcCall {
+        /// The time after which the client will no longer wait for
+        /// the reply, if the client or the procedure set a timeout.
+        pub fn deadline(&self) -> Option<Instant> {
+            self.deadline
+        }
+
+        /// Return true if the call has been cancelled by the client,
+        /// or it's deadline has passed.
+        pub fn is_cancelled(&self) -> bool {
+            *self.cancel.borrow()
+                || self.deadline.map(|d| Instant::now() >= d).unwrap_or(false)
+        }
+
+        /// Wait until the call is cancelled by the client, or it's
+        /// deadline passes. Handlers doing long running work may
+        /// select on this in order to stop working on calls no one is
+        /// waiting for. Calls from clients that don't implement the
+        /// current protocol version can't be cancelled, but they may
+        /// still have a deadline set by the procedure's `Config`.
+        pub async fn cancelled(&self) {
+            self.cancellation().await
+        }
+
+        // like cancelled, but doesn't borrow the call
+        fn cancellation(&self) -> impl Future<Output = ()> + Send + 'static {
+            let mut cancel = self.cancel.clone();
+            let deadline = self.deadline;
+            async move {
+                let cancel = async move {
+                    if cancel.wait_for(|c| *c).await.is_err() {
+                        // the call can no longer be cancelled
+                        future::pending::<()>().await
+                    }
+                };
+                match deadline {
+                    None => cancel.await,
+                    Some(deadline) => {
+                        let _: std::result::Result<_, _> =
+                            time::timeout_at(deadline.into(), cancel).await;
+                    }
+                }
+            }
+        }
+
+        /// Reply to the call with the result of `f`, an error is
+        /// sent as a `Value::Error`. If the call is cancelled, or
+        /// it's deadline passes, before `f` is done then `f` is
+        /// dropped.
+        pub async fn reply_with<T, E, F>(mut self, f: F)
+        where
+            T: Into<Value>,
+            E: std::fmt::Display,
+            F: Future<Output = std::result::Result<T, E>>,
+        {
+            let cancelled = self.cancellation().fuse();
+            let f = f.fuse();
+            pin_mut!(cancelled, f);
+            let res = select_biased! {
+                () = cancelled => return,
+                r = f => r,
+            };
+            match res {
+                Ok(v) => self.reply.send(v),
+                Err(e) => self.reply.send(Value::Error(Chars::from(e.to_string()))),
+            }
+        }
+    }
+
+    /// Configuration of a procedure.
+    #[derive(Debug, Clone, Copy)]
+    pub struct Config {
+        /// The timeout of calls that don't specify one, and the
+        /// maximum timeout of calls that do. Default None.
+        pub timeout: Option<Duration>,
+        /// Arguments written by clients that never called the
+        /// procedure are discarded after this long. Default 60s.
+        pub stale: Duration,
+        /// Don't collect stale arguments or finished calls unless
+        /// there are more than this many. Default 128.
+        pub gc_threshold: usize,
+        /// Don't collect stale arguments or finished calls more often
+        /// than this. Default 1s.
+        pub gc_freq: Duration,
+    }
+
+    impl Default for Config {
+        fn default() -> Self {
+            Config {
+                timeout: None,
+                stale: Duration::from_secs(60),
+                gc_threshold: 128,
+                gc_freq: Duration::from_secs(1),
+            }
+        }
     }
 
     struct Arg {
@@ -145,12 +410,57 @@ pub mod server {
         initiated: Instant,
     }
 
+    // decode a call made by a client that implements VERSION, return
+    // the call id (if it could be decoded), the arguments, and the
+    // timeout
+    fn decode_call(
+        v: Value,
+    ) -> (Option<u64>, Result<(Pooled<HashMap<ArcStr, Value>>, Option<Duration>)>) {
+        let (call_id, args, timeout) = match v {
+            Value::Array(a) if a.len() == 2 || a.len() == 3 => {
+                match a[0].clone().cast_to::<u64>() {
+                    Err(e) => return (None, Err(e)),
+                    Ok(call_id) => (call_id, a[1].clone(), a.get(2).cloned()),
+                }
+            }
+            v => return (None, Err(anyhow!("invalid call {}", v))),
+        };
+        let timeout = match timeout.map(|t| t.cast_to::<Duration>()).transpose() {
+            Err(e) => return (Some(call_id), Err(e)),
+            Ok(timeout) => timeout,
+        };
+        let args = args.cast_to::<Vec<(Chars, Value)>>().map(|args| {
+            let mut res = ARGS.take();
+            res.extend(args.into_iter().map(|(k, v)| (ArcStr::from(&*k), v)));
+            (res, timeout)
+        });
+        (Some(call_id), args)
+    }
+
+    // decode a cancellation of a call made by a client that
+    // implements VERSION
+    fn decode_cancel(v: &Value) -> Option<u64> {
+        match v {
+            Value::Array(a) if a.len() == 2 => match &a[0] {
+                Value::String(s) if &**s == "cancel" => {
+                    a[1].clone().cast_to::<u64>().ok()
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
     struct ProcInner<M: FnMut(RpcCall) -> Option<T> + Send + 'static, T: Send + 'static> {
         id: ProcId,
+        stream: StreamCtx,
         call: Arc<Val>,
         _doc: Val,
+        _version: Val,
         args: HashMap<Id, Arg, FxBuildHasher>,
         pending: HashMap<ClId, PendingCall, FxBuildHasher>,
+        calls: HashMap<(ClId, u64), watch::Sender<bool>, FxBuildHasher>,
+        config: Config,
         handler: Option<mpsc::Sender<T>>,
         map: M,
         events: stream::Fuse<mpsc::Receiver<Pooled<Vec<WriteRequest>>>>,
@@ -164,15 +474,17 @@ pub mod server {
         T: Send + 'static,
     {
         async fn run(mut self) {
-            static GC_FREQ: Duration = Duration::from_secs(1);
-            static GC_THRESHOLD: usize = 128;
             fn gc_pending(
+                config: &Config,
                 pending: &mut HashMap<ClId, PendingCall, FxBuildHasher>,
+                calls: &mut HashMap<(ClId, u64), watch::Sender<bool>, FxBuildHasher>,
                 now: Instant,
             ) {
-                static STALE: Duration = Duration::from_secs(60);
-                pending.retain(|_, pc| now - pc.initiated < STALE);
+                pending.retain(|_, pc| now - pc.initiated < config.stale);
                 pending.shrink_to_fit();
+                // calls are finished once the handler drops the RpcCall
+                calls.retain(|_, cancel| !cancel.is_closed());
+                calls.shrink_to_fit();
             }
             let mut stop = self.stop;
             loop {
@@ -180,13 +492,65 @@ pub mod server {
                     _ = stop => break,
                     mut batch = self.events.select_next_some() => for req in batch.drain(..) {
                         if req.id == self.call.id() {
-                            let args = self.pending.remove(&req.client).map(|pc| pc.args)
-                                .unwrap_or_else(|| ARGS.take());
+                            if let Some(call_id) = decode_cancel(&req.value) {
+                                if let Some(cancel) = self.calls.remove(&(req.client, call_id)) {
+                                    let _ = cancel.send(true);
+                                }
+                                continue
+                            }
+                            let (call_id, args, timeout) = match req.value {
+                                Value::Null => {
+                                    let args = self.pending.remove(&req.client)
+                                        .map(|pc| pc.args)
+                                        .unwrap_or_else(|| ARGS.take());
+                                    (None, args, None)
+                                }
+                                v => match decode_call(v) {
+                                    (call_id, Ok((args, timeout))) => (call_id, args, timeout),
+                                    (call_id, Err(e)) => {
+                                        let e = Chars::from(format!("invalid call {}", e));
+                                        let mut reply = RpcReply {
+                                            result: req.send_result,
+                                            call_id,
+                                            stream: self.stream.clone(),
+                                        };
+                                        reply.send(Value::Error(e));
+                                        continue
+                                    }
+                                }
+                            };
+                            let now = Instant::now();
+                            let timeout = match (timeout, self.config.timeout) {
+                                (None, None) => None,
+                                (Some(t), None) | (None, Some(t)) => Some(t),
+                                (Some(t0), Some(t1)) => Some(t0.min(t1)),
+                            };
+                            let (tx_cancel, rx_cancel) = watch::channel(false);
+                            if let Some(call_id) = call_id {
+                                self.calls.insert((req.client, call_id), tx_cancel);
+                                if self.calls.len() > self.config.gc_threshold
+                                    && now - self.last_gc > self.config.gc_freq
+                                {
+                                    self.last_gc = now;
+                                    gc_pending(
+                                        &self.config,
+                                        &mut self.pending,
+                                        &mut self.calls,
+                                        now,
+                                    );
+                                }
+                            }
                             let call = RpcCall {
                                 client: req.client,
                                 id: self.id,
                                 args,
-                                reply: RpcReply(req.send_result),
+                                reply: RpcReply {
+                                    result: req.send_result,
+                                    call_id,
+                                    stream: self.stream.clone(),
+                                },
+                                deadline: timeout.map(|t| now + t),
+                                cancel: rx_cancel,
                             };
                             let t = match catch_unwind(AssertUnwindSafe(|| (self.map)(call))) {
                                 Ok(t) => t,
@@ -213,11 +577,16 @@ pub mod server {
                             if let Some(Arg {name, ..}) = self.args.get(&req.id) {
                                 pending.args.insert(name.clone(), req.value);
                             }
-                            if gc && self.pending.len() > GC_THRESHOLD {
+                            if gc && self.pending.len() > self.config.gc_threshold {
                                 let now = Instant::now();
-                                if now - self.last_gc > GC_FREQ {
+                                if now - self.last_gc > self.config.gc_freq {
                                     self.last_gc = now;
-                                    gc_pending(&mut self.pending, now);
+                                    gc_pending(
+                                        &self.config,
+                                        &mut self.pending,
+                                        &mut self.calls,
+                                        now,
+                                    );
                                 }
                             }
                         }
@@ -324,6 +693,33 @@ pub mod server {
             args: impl IntoIterator<Item = ArgSpec>,
             map: F,
             handler: Option<mpsc::Sender<T>>,
+        ) -> Result<Proc> {
+            Self::new_with_config(
+                publisher,
+                flags,
+                Config::default(),
+                name,
+                doc,
+                args,
+                map,
+                handler,
+            )
+        }
+
+        /// Just like `new_with_flags`, but with the specified `Config`
+        /// instead of the default one.
+        pub fn new_with_config<
+            T: Send + 'static,
+            F: FnMut(RpcCall) -> Option<T> + Send + 'static,
+        >(
+            publisher: &Publisher,
+            flags: PublishFlags,
+            config: Config,
+            name: Path,
+            doc: Value,
+            args: impl IntoIterator<Item = ArgSpec>,
+            map: F,
+            handler: Option<mpsc::Sender<T>>,
         ) -> Result<Proc> {
             let id = ProcId::new();
             let (tx_ev, rx_ev) = mpsc::channel(3);
@@ -338,6 +734,11 @@ pub mod server {
                 name.append("doc"),
                 doc,
             )?;
+            let _version = publisher.publish_with_flags(
+                flags | PublishFlags::USE_EXISTING,
+                version_path(&name),
+                Value::U32(VERSION),
+            )?;
             publisher.writes(call.id(), tx_ev.clone());
             let args = args
                 .into_iter()
@@ -363,10 +764,18 @@ pub mod server {
                 .collect::<Result<HashMap<Id, Arg, FxBuildHasher>>>()?;
             let inner = ProcInner {
                 id,
+                stream: StreamCtx {
+                    publisher: publisher.clone(),
+                    flags,
+                    name: name.clone(),
+                },
                 call,
                 _doc,
+                _version,
                 args,
                 pending: HashMap::with_hasher(FxBuildHasher::default()),
+                calls: HashMap::with_hasher(FxBuildHasher::default()),
+                config,
                 map,
                 handler,
                 events: rx_ev.fuse(),
@@ -390,12 +799,17 @@ pub mod server {
 #[macro_use]
 pub mod client {
     use super::*;
+    use crate::{channel::client::Connection, rpc::server::ArgSpec};
+    use futures::stream::BoxStream;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    static CALL_ID: AtomicU64 = AtomicU64::new(0);
 
     lazy_static! {
-        // The same procedure can't be called concurrently from the
-        // same subscriber. If it is, the arguments of the two calls
-        // could be permuted. This structure ensures that this does
-        // not happen.
+        // If the server doesn't implement VERSION the same procedure
+        // can't be called concurrently from the same subscriber. If
+        // it is, the arguments of the two calls could be
+        // permuted. This structure ensures that this does not happen.
         static ref PROCS: Mutex<FxHashMap<SubscriberId, FxHashMap<Path, Weak<AsyncMutex<()>>>>> =
             Mutex::new(HashMap::with_hasher(FxBuildHasher::default()));
     }
@@ -413,10 +827,35 @@ pub mod client {
         }
     }
 
+    // cancels the call on the server if the call future is dropped
+    // before the reply is received
+    struct CancelGuard<'a> {
+        call: &'a Dval,
+        call_id: u64,
+        armed: bool,
+    }
+
+    impl<'a> Drop for CancelGuard<'a> {
+        fn drop(&mut self) {
+            if self.armed {
+                self.call.write(Value::from(("cancel", self.call_id)));
+            }
+        }
+    }
+
+    // a reply to a call, either a single value, or the path of the
+    // channel the reply will be streamed over
+    enum Reply {
+        Value(Value),
+        Stream(Path),
+    }
+
     #[derive(Debug)]
     struct ProcInner {
+        subscriber: Subscriber,
         name: Path,
         sid: SubscriberId,
+        // None if the server implements VERSION
         lock: Option<Arc<AsyncMutex<()>>>,
         call: Dval,
         args: HashMap<String, Dval>,
@@ -450,7 +889,35 @@ pub mod client {
         /// resources.
         pub async fn new(subscriber: &Subscriber, name: Path) -> Result<Proc> {
             let sid = subscriber.id();
-            let lock = {
+            let call = subscriber.subscribe(name.clone());
+            let version = version_path(&name);
+            let pat = GlobSet::new(
+                true,
+                [
+                    Glob::new(Chars::from(format!("{}/*/val", name.clone())))?,
+                    Glob::new(Chars::from(String::from(&*version)))?,
+                ],
+            )?;
+            let mut args = HashMap::new();
+            let mut versioned = false;
+            let mut batches = subscriber.resolver().list_matching(&pat).await?;
+            for mut batch in batches.drain(..) {
+                for arg_path in batch.drain(..) {
+                    if arg_path == version {
+                        versioned = true;
+                    } else {
+                        let arg_name =
+                            Path::basename(Path::dirname(&*arg_path).unwrap()).unwrap();
+                        args.insert(
+                            String::from(arg_name),
+                            subscriber.subscribe(arg_path),
+                        );
+                    }
+                }
+            }
+            let lock = if versioned {
+                None
+            } else {
                 let mut locks = PROCS.lock();
                 let lock = locks
                     .entry(sid)
@@ -466,21 +933,8 @@ pub mod client {
                     }
                 }
             };
-            let call = subscriber.subscribe(name.clone());
-            let pat = GlobSet::new(
-                true,
-                iter::once(Glob::new(Chars::from(format!("{}/*/val", name.clone())))?),
-            )?;
-            let mut args = HashMap::new();
-            let mut batches = subscriber.resolver().list_matching(&pat).await?;
-            for mut batch in batches.drain(..) {
-                for arg_path in batch.drain(..) {
-                    let arg_name =
-                        Path::basename(Path::dirname(&*arg_path).unwrap()).unwrap();
-                    args.insert(String::from(arg_name), subscriber.subscribe(arg_path));
-                }
-            }
-            Ok(Proc(Arc::new(ProcInner { name, sid, lock, call, args })))
+            let subscriber = subscriber.clone();
+            Ok(Proc(Arc::new(ProcInner { subscriber, name, sid, lock, call, args })))
         }
 
         /**
@@ -503,8 +957,11 @@ pub mod client {
 
         # Notes
 
-        `call` may safely be called concurrently on multiple
-        instances of `Proc` that call the same procedure
+        `call` may safely be called concurrently, on the same `Proc`
+        or on multiple instances of `Proc` that call the same
+        procedure. If the server implements the current protocol
+        version the calls will be in flight at the same time,
+        otherwise calls from the same subscriber are serialized
         (there is internal syncronization).
         **/
         pub async fn call<I, K>(&self, args: I) -> Result<Value>
@@ -512,8 +969,158 @@ pub mod client {
             I: IntoIterator<Item = (K, Value)>,
             K: Borrow<str>,
         {
+            self.call_inner(None, args).await
+        }
+
+        /**
+        Call the procedure, and give up if it doesn't reply within
+        `timeout`. The timeout is sent to the server along with the
+        call, so it may stop working on the call once it expires.
+
+        Dropping the future returned by `call` or `call_with_timeout`
+        before the reply is received cancels the call on the server
+        (if the server implements the current protocol version).
+        **/
+        pub async fn call_with_timeout<I, K>(
+            &self,
+            timeout: Duration,
+            args: I,
+        ) -> Result<Value>
+        where
+            I: IntoIterator<Item = (K, Value)>,
+            K: Borrow<str>,
+        {
+            match time::timeout(timeout, self.call_inner(Some(timeout), args)).await {
+                Ok(r) => r,
+                Err(_) => bail!("rpc call timed out"),
+            }
+        }
+
+        async fn call_inner<I, K>(
+            &self,
+            timeout: Option<Duration>,
+            args: I,
+        ) -> Result<Value>
+        where
+            I: IntoIterator<Item = (K, Value)>,
+            K: Borrow<str>,
+        {
+            match self.call_reply(timeout, args).await? {
+                Reply::Value(v) => Ok(v),
+                Reply::Stream(path) => {
+                    let mut stream = self.connect_stream(path).await?;
+                    let mut last = Value::Null;
+                    while let Some(v) = stream.next().await {
+                        last = v;
+                    }
+                    Ok(last)
+                }
+            }
+        }
+
+        /**
+        Call the procedure, and return a stream of it's results. If
+        the server streams the reply the stream will yield every
+        partial result, and then the final value, otherwise it will
+        yield just the single reply. If the stream fails before the
+        final value is received then the last item will be a
+        `Value::Error`.
+
+        `call` may be used with procedures that stream their replies,
+        in which case it will discard the partial results and return
+        the final value.
+        **/
+        pub async fn call_stream<I, K>(
+            &self,
+            args: I,
+        ) -> Result<BoxStream<'static, Value>>
+        where
+            I: IntoIterator<Item = (K, Value)>,
+            K: Borrow<str>,
+        {
+            match self.call_reply(None, args).await? {
+                Reply::Value(v) => Ok(stream::once(future::ready(v)).boxed()),
+                Reply::Stream(path) => self.connect_stream(path).await,
+            }
+        }
+
+        async fn connect_stream(&self, path: Path) -> Result<BoxStream<'static, Value>> {
+            let con = Connection::connect(&self.0.subscriber, path).await?;
+            let stream = stream::unfold(Some(con), |con| async move {
+                let con = con?;
+                match con.recv_one().await {
+                    Err(e) => {
+                        let e = Chars::from(format!("rpc stream failed {}", e));
+                        Some((Value::Error(e), None))
+                    }
+                    Ok(m) => match m.cast_to::<(Chars, Value)>() {
+                        Ok((kind, v)) if &*kind == "partial" => Some((v, Some(con))),
+                        Ok((kind, v)) if &*kind == "final" => Some((v, None)),
+                        _ => {
+                            let e = Chars::from("invalid rpc stream message");
+                            Some((Value::Error(e), None))
+                        }
+                    },
+                }
+            });
+            Ok(stream.boxed())
+        }
+
+        async fn call_reply<I, K>(
+            &self,
+            timeout: Option<Duration>,
+            args: I,
+        ) -> Result<Reply>
+        where
+            I: IntoIterator<Item = (K, Value)>,
+            K: Borrow<str>,
+        {
+            let lock = match &self.0.lock {
+                Some(lock) => lock,
+                None => {
+                    let mut call = Vec::new();
+                    for (name, val) in args {
+                        let name = name.borrow();
+                        if !self.0.args.contains_key(name) {
+                            bail!("no such argument {}", name)
+                        }
+                        call.push(Value::from((Chars::from(String::from(name)), val)));
+                    }
+                    let call_id = CALL_ID.fetch_add(1, Ordering::Relaxed);
+                    let call = match timeout {
+                        None => Value::from((call_id, call)),
+                        Some(timeout) => Value::from((call_id, call, timeout)),
+                    };
+                    let mut guard =
+                        CancelGuard { call: &self.0.call, call_id, armed: true };
+                    let reply =
+                        self.0.call.write_with_recipt(call).await.map_err(|_| {
+                            anyhow!("call cancelled before a reply was received")
+                        })?;
+                    guard.armed = false;
+                    // replies are tagged with the call id, unless the
+                    // server couldn't decode the call at all
+                    return match reply {
+                        Value::Array(a) if a.len() == 2 || a.len() == 3 => {
+                            let id = a[0].clone().cast_to::<u64>()?;
+                            if id != call_id {
+                                bail!("reply to the wrong call {}", id)
+                            }
+                            match &a[1..] {
+                                [v] => Ok(Reply::Value(v.clone())),
+                                [Value::String(s), path] if &**s == "stream" => {
+                                    Ok(Reply::Stream(path.clone().cast_to::<Path>()?))
+                                }
+                                _ => bail!("invalid rpc reply"),
+                            }
+                        }
+                        e @ Value::Error(_) => Ok(Reply::Value(e)),
+                        v => bail!("invalid rpc reply {}", v),
+                    };
+                }
+            };
             let result = {
-                let _guard = self.0.lock.as_ref().unwrap().lock().await;
+                let _guard = lock.lock().await;
                 for (name, val) in args {
                     match self.0.args.get(name.borrow()) {
                         None => bail!("no such argument {}", name.borrow()),
@@ -525,9 +1132,11 @@ pub mod client {
                 }
                 self.0.call.write_with_recipt(Value::Null)
             };
-            Ok(result
-                .await
-                .map_err(|_| anyhow!("call cancelled before a reply was received"))?)
+            Ok(Reply::Value(
+                result
+                    .await
+                    .map_err(|_| anyhow!("call cancelled before a reply was received"))?,
+            ))
         }
 
         /// List the procedures' arguments
@@ -535,6 +1144,82 @@ pub mod client {
             self.0.args.keys().map(|s| s.as_str())
         }
     }
+
+    /// The published description of a procedure, see `describe`.
+    #[derive(Debug, Clone)]
+    pub struct ProcInfo {
+        pub name: Path,
+        pub doc: Value,
+        pub args: Vec<ArgSpec>,
+    }
+
+    /// List the procedures published under `base`. A procedure is
+    /// any published path that has a published `doc` child, which
+    /// is what `server::Proc` publishes.
+    pub async fn list(subscriber: &Subscriber, base: &Path) -> Result<Vec<Path>> {
+        let pat = GlobSet::new(
+            true,
+            iter::once(Glob::new(Chars::from(format!("{}/**", base)))?),
+        )?;
+        let mut published = HashSet::new();
+        let mut batches = subscriber.resolver().list_matching(&pat).await?;
+        for mut batch in batches.drain(..) {
+            published.extend(batch.drain(..));
+        }
+        let mut procs = published
+            .iter()
+            .filter(|p| published.contains(&p.append("doc")))
+            .cloned()
+            .collect::<Vec<_>>();
+        procs.sort();
+        Ok(procs)
+    }
+
+    /// Fetch the published doc of the procedure `name`, and the doc
+    /// and default value of each of it's arguments. Subscriptions
+    /// that don't succeed within `timeout` are an error.
+    pub async fn describe(
+        subscriber: &Subscriber,
+        name: Path,
+        timeout: Option<Duration>,
+    ) -> Result<ProcInfo> {
+        let pat = GlobSet::new(
+            true,
+            iter::once(Glob::new(Chars::from(format!("{}/*/val", name.clone())))?),
+        )?;
+        let mut args = Vec::new();
+        let mut batches = subscriber.resolver().list_matching(&pat).await?;
+        for mut batch in batches.drain(..) {
+            for arg_path in batch.drain(..) {
+                let arg = Path::dirname(&*arg_path).unwrap();
+                let arg_name = ArcStr::from(Path::basename(arg).unwrap());
+                args.push((arg_name, Path::from(ArcStr::from(arg))));
+            }
+        }
+        args.sort_by(|(n0, _), (n1, _)| n0.cmp(n1));
+        let paths = iter::once(name.append("doc"))
+            .chain(args.iter().flat_map(|(_, p)| [p.append("val"), p.append("doc")]));
+        let mut values = HashMap::new();
+        let mut subs = subscriber.subscribe_nondurable(paths, timeout).await;
+        while let Some((path, val)) = subs.next().await {
+            let v = match val?.last() {
+                Event::Update(v) => v,
+                Event::Unsubscribed => Value::Null,
+            };
+            values.insert(path, v);
+        }
+        let mut get = |p: Path| values.remove(&p).unwrap_or(Value::Null);
+        let doc = get(name.append("doc"));
+        let args = args
+            .into_iter()
+            .map(|(arg_name, p)| ArgSpec {
+                name: arg_name,
+                doc: get(p.append("doc")),
+                default_value: get(p.append("val")),
+            })
+            .collect();
+        Ok(ProcInfo { name, doc, args })
+    }
 }
 
 #[cfg(test)]
@@ -586,4 +1271,342 @@ mod test {
             })
             .unwrap()
     }
+
+    #[test]
+    fn list_and_describe() {
+        let _ = env_logger::try_init();
+        Runtime::new()
+            .unwrap()
+            .block_on(async move {
+                let ctx = Ctx::new().await;
+                let base = Path::from("/rpc/discover");
+                let echo = |mut c: RpcCall, a: Value| -> Option<()> {
+                    c.reply.send(a);
+                    None
+                };
+                let _p0 = define_rpc!(
+                    &ctx.publisher,
+                    base.append("echo"),
+                    "echos it's argument",
+                    echo,
+                    None,
+                    arg: Value = 42u64; "the argument",
+                    version: Value = "foo"; "another argument"
+                )?;
+                let _p1 = define_rpc!(
+                    &ctx.publisher,
+                    base.append("sub/echo"),
+                    "also echos it's argument",
+                    echo,
+                    None,
+                    arg: Value = Value::Null; "the argument"
+                )?;
+                ctx.publisher.flushed().await;
+                let procs = client::list(&ctx.subscriber, &base).await?;
+                assert_eq!(procs, vec![base.append("echo"), base.append("sub/echo")]);
+                let info =
+                    client::describe(&ctx.subscriber, base.append("echo"), None).await?;
+                assert_eq!(info.doc, Value::from("echos it's argument"));
+                let args = info
+                    .args
+                    .iter()
+                    .map(|a| (&*a.name, a.default_value.clone(), a.doc.clone()))
+                    .collect::<Vec<_>>();
+                assert_eq!(
+                    args,
+                    vec![
+                        ("arg", Value::U64(42), Value::from("the argument")),
+                        ("version", Value::from("foo"), Value::from("another argument")),
+                    ]
+                );
+                Ok::<(), anyhow::Error>(())
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn concurrent_calls() {
+        let _ = env_logger::try_init();
+        Runtime::new()
+            .unwrap()
+            .block_on(async move {
+                let ctx = Ctx::new().await;
+                let proc_name = Path::from("/rpc/concurrent");
+                let (tx, mut rx) = mpsc::channel(10);
+                let _server_proc = define_rpc!(
+                    &ctx.publisher,
+                    proc_name.clone(),
+                    "sleeps then echos it's argument",
+                    |c, a| Some((c, a)),
+                    Some(tx),
+                    arg: u64 = Value::Null; "arg doc"
+                )
+                .unwrap();
+                task::spawn(async move {
+                    while let Some((c, a)) = rx.next().await {
+                        let mut c: RpcCall = c;
+                        task::spawn(async move {
+                            // later calls return first
+                            time::sleep(Duration::from_millis(200 - a * 10)).await;
+                            c.reply.send(a)
+                        });
+                    }
+                });
+                time::sleep(Duration::from_millis(100)).await;
+                let proc = client::Proc::new(&ctx.subscriber, proc_name.clone()).await?;
+                let start = Instant::now();
+                let calls = (0..10u64).map(|i| {
+                    let proc = proc.clone();
+                    async move { (i, proc.call([("arg", Value::U64(i))]).await) }
+                });
+                for (i, res) in future::join_all(calls).await {
+                    assert_eq!(res?, Value::U64(i));
+                }
+                // the calls were in flight at the same time
+                assert!(start.elapsed() < Duration::from_millis(1000));
+                Ok::<(), anyhow::Error>(())
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn old_client() {
+        let _ = env_logger::try_init();
+        Runtime::new()
+            .unwrap()
+            .block_on(async move {
+                let ctx = Ctx::new().await;
+                let proc_name = Path::from("/rpc/old");
+                let _server_proc = define_rpc!(
+                    &ctx.publisher,
+                    proc_name.clone(),
+                    "echos it's argument",
+                    |mut c: RpcCall, a: Value| -> Option<()> {
+                        c.reply.send(a);
+                        None
+                    },
+                    None,
+                    arg: Value = Value::Null; "arg doc"
+                )
+                .unwrap();
+                time::sleep(Duration::from_millis(100)).await;
+                // old clients write the arguments, then null to the procedure
+                let arg = ctx.subscriber.subscribe(proc_name.append("arg/val"));
+                let call = ctx.subscriber.subscribe(proc_name.clone());
+                arg.wait_subscribed().await?;
+                call.wait_subscribed().await?;
+                arg.write(Value::from("hello old rpc"));
+                let res = call.write_with_recipt(Value::Null).await?;
+                assert_eq!(res, Value::from("hello old rpc"));
+                Ok::<(), anyhow::Error>(())
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn stream_reply() {
+        let _ = env_logger::try_init();
+        Runtime::new()
+            .unwrap()
+            .block_on(async move {
+                let ctx = Ctx::new().await;
+                let proc_name = Path::from("/rpc/stream");
+                let (tx, mut rx) = mpsc::channel(10);
+                let _server_proc = define_rpc!(
+                    &ctx.publisher,
+                    proc_name.clone(),
+                    "counts to n",
+                    |c, n| Some((c, n)),
+                    Some(tx),
+                    n: u64 = Value::Null; "the number to count to"
+                )
+                .unwrap();
+                task::spawn(async move {
+                    while let Some((c, n)) = rx.next().await {
+                        let mut c: RpcCall = c;
+                        task::spawn(async move {
+                            let stream = c.reply.stream().await?;
+                            for i in 0..n {
+                                stream.send(i).await?;
+                            }
+                            stream.finish(n).await
+                        });
+                    }
+                });
+                time::sleep(Duration::from_millis(100)).await;
+                let proc = client::Proc::new(&ctx.subscriber, proc_name.clone()).await?;
+                let res = proc.call_stream([("n", Value::U64(5))]).await?;
+                let res = res.collect::<Vec<_>>().await;
+                assert_eq!(res, (0..=5u64).map(Value::U64).collect::<Vec<_>>());
+                // call returns only the final value
+                assert_eq!(proc.call([("n", Value::U64(3))]).await?, Value::U64(3));
+                Ok::<(), anyhow::Error>(())
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn cancel_call() {
+        let _ = env_logger::try_init();
+        Runtime::new()
+            .unwrap()
+            .block_on(async move {
+                let ctx = Ctx::new().await;
+                let proc_name = Path::from("/rpc/cancel");
+                let (tx, mut rx) = mpsc::channel(10);
+                let _server_proc = define_rpc!(
+                    &ctx.publisher,
+                    proc_name.clone(),
+                    "never replies",
+                    |c, a| Some((c, a)),
+                    Some(tx),
+                    arg: Value = Value::Null; "arg doc"
+                )
+                .unwrap();
+                let (tx_cancelled, mut rx_cancelled) = mpsc::unbounded();
+                task::spawn(async move {
+                    while let Some((c, _)) = rx.next().await {
+                        let c: RpcCall = c;
+                        let tx_cancelled = tx_cancelled.clone();
+                        task::spawn(async move {
+                            c.cancelled().await;
+                            let _ = tx_cancelled.unbounded_send(c.deadline());
+                        });
+                    }
+                });
+                time::sleep(Duration::from_millis(100)).await;
+                let proc = client::Proc::new(&ctx.subscriber, proc_name.clone()).await?;
+                // dropping the call future cancels the call
+                let to = Duration::from_millis(100);
+                assert!(time::timeout(to, proc.call([("arg", Value::Null)]))
+                    .await
+                    .is_err());
+                let deadline = time::timeout(Duration::from_secs(5), rx_cancelled.next())
+                    .await?
+                    .unwrap();
+                assert_eq!(deadline, None);
+                // the timeout is propagated to the server
+                assert!(proc
+                    .call_with_timeout(to, [("arg", Value::Null)])
+                    .await
+                    .is_err());
+                let deadline = time::timeout(Duration::from_secs(5), rx_cancelled.next())
+                    .await?
+                    .unwrap();
+                assert!(deadline.is_some());
+                Ok::<(), anyhow::Error>(())
+            })
+            .unwrap()
+    }
+
+    #[service]
+    trait Calc {
+        /// add two numbers
+        async fn add(
+            &self,
+            #[rpc(doc = "the first number")] a: i64,
+            #[rpc(doc = "the second number", default = 1)] b: i64,
+        ) -> Result<i64>;
+
+        /// divide two numbers
+        async fn div(&self, a: f64, b: f64) -> Result<f64>;
+
+        /// the name of the calculator
+        async fn name(&self) -> Result<String>;
+    }
+
+    struct CalcImpl;
+
+    impl Calc for CalcImpl {
+        async fn add(&self, a: i64, b: i64) -> Result<i64> {
+            Ok(a + b)
+        }
+
+        async fn div(&self, a: f64, b: f64) -> Result<f64> {
+            if b == 0. {
+                bail!("division by zero")
+            }
+            Ok(a / b)
+        }
+
+        async fn name(&self) -> Result<String> {
+            Ok(String::from("calc"))
+        }
+    }
+
+    #[service]
+    trait Sleeper {
+        /// never returns
+        async fn sleep(&self) -> Result<()>;
+    }
+
+    // tells the test when the handler future is dropped
+    struct Dropped(mpsc::UnboundedSender<()>);
+
+    impl Drop for Dropped {
+        fn drop(&mut self) {
+            let _ = self.0.unbounded_send(());
+        }
+    }
+
+    struct SleeperImpl(mpsc::UnboundedSender<()>);
+
+    impl Sleeper for SleeperImpl {
+        async fn sleep(&self) -> Result<()> {
+            let _dropped = Dropped(self.0.clone());
+            future::pending().await
+        }
+    }
+
+    #[test]
+    fn typed_service_cancel() {
+        let _ = env_logger::try_init();
+        Runtime::new()
+            .unwrap()
+            .block_on(async move {
+                let ctx = Ctx::new().await;
+                let base = Path::from("/rpc/sleeper");
+                let (tx, mut rx) = mpsc::unbounded();
+                let _server =
+                    SleeperServer::new(&ctx.publisher, base.clone(), SleeperImpl(tx))?;
+                ctx.publisher.flushed().await;
+                let sleeper = SleeperClient::new(&ctx.subscriber, base).await?;
+                // dropping the call cancels it, which drops the handler
+                let to = Duration::from_millis(100);
+                assert!(time::timeout(to, sleeper.sleep()).await.is_err());
+                time::timeout(Duration::from_secs(5), rx.next()).await?.unwrap();
+                Ok::<(), anyhow::Error>(())
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn typed_service() {
+        let _ = env_logger::try_init();
+        Runtime::new()
+            .unwrap()
+            .block_on(async move {
+                let ctx = Ctx::new().await;
+                let base = Path::from("/rpc/calc");
+                let _server = CalcServer::new(&ctx.publisher, base.clone(), CalcImpl)?;
+                ctx.publisher.flushed().await;
+                let calc = CalcClient::new(&ctx.subscriber, base.clone()).await?;
+                assert_eq!(calc.add(1, 2).await?, 3);
+                assert_eq!(calc.div(1., 2.).await?, 0.5);
+                assert!(calc.div(1., 0.).await.is_err());
+                assert_eq!(calc.name().await?, "calc");
+                let add = client::Proc::new(&ctx.subscriber, base.append("add")).await?;
+                let mut args = add.args().collect::<Vec<_>>();
+                args.sort();
+                assert_eq!(args, vec!["a", "b"]);
+                let res = call_rpc!(add, a: 41).await?;
+                assert_eq!(res, Value::I64(42));
+                match call_rpc!(add, a: "foo").await? {
+                    Value::Error(_) => (),
+                    v => panic!("expected an error, got {}", v),
+                }
+                Ok::<(), anyhow::Error>(())
+            })
+            .unwrap()
+    }
 }
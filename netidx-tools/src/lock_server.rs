@@ -0,0 +1,32 @@
+use anyhow::{Context, Result};
+use futures::future;
+use netidx::{
+    config::Config,
+    path::Path,
+    publisher::{BindCfg, DesiredAuth, PublisherBuilder},
+};
+use netidx_protocols::lock::server::LockServer;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub(super) struct Params {
+    #[structopt(
+        short = "b",
+        long = "bind",
+        help = "configure the bind address e.g. 192.168.0.0/16, 127.0.0.1:5000"
+    )]
+    bind: Option<BindCfg>,
+    #[structopt(long = "base", help = "base path", default_value = "/local/lock")]
+    base: Path,
+}
+
+pub(crate) async fn run(config: Config, auth: DesiredAuth, p: Params) -> Result<()> {
+    let publisher = PublisherBuilder::new(config)
+        .desired_auth(auth)
+        .bind_cfg(p.bind)
+        .build()
+        .await
+        .context("create publisher")?;
+    let _server = LockServer::new(&publisher, p.base).context("create lock server")?;
+    future::pending().await
+}
@@ -1,7 +1,9 @@
 #![recursion_limit = "2048"]
+mod lock_server;
 mod publisher;
 mod record_client;
 mod resolver;
+mod rpc;
 mod stress_channel_publisher;
 mod stress_channel_subscriber;
 mod stress_publisher;
@@ -72,6 +74,20 @@ enum Opt {
         #[structopt(subcommand)]
         cmd: resolver::ResolverCmd,
     },
+    #[structopt(name = "rpc", about = "list, describe and call procedures")]
+    Rpc {
+        #[structopt(flatten)]
+        common: ClientParams,
+        #[structopt(subcommand)]
+        cmd: rpc::RpcCmd,
+    },
+    #[structopt(name = "lock-server", about = "run a distributed lock server")]
+    LockServer {
+        #[structopt(flatten)]
+        common: ClientParams,
+        #[structopt(flatten)]
+        params: lock_server::Params,
+    },
     #[structopt(name = "publisher", about = "publish data")]
     Publisher {
         #[structopt(flatten)]
@@ -150,6 +166,14 @@ async fn tokio_main() -> Result<()> {
             let (cfg, auth) = common.load();
             resolver::run(cfg, auth, cmd).await
         }
+        Opt::Rpc { common, cmd } => {
+            let (cfg, auth) = common.load();
+            rpc::run(cfg, auth, cmd).await
+        }
+        Opt::LockServer { common, params } => {
+            let (cfg, auth) = common.load();
+            lock_server::run(cfg, auth, params).await
+        }
         Opt::Publisher { common, params } => {
             let (cfg, auth) = common.load();
             publisher::run(cfg, auth, params).await
@@ -9,6 +9,7 @@ use netidx::{
     config::Config,
     path::Path,
     pool::Pooled,
+    protocol::json,
     publisher::{
         BindCfg, DesiredAuth, Id, Publisher, PublisherBuilder, Typ, Val, Value,
         WriteRequest,
@@ -145,18 +146,15 @@ pub(super) async fn run(config: Config, auth: DesiredAuth, params: Params) -> Re
             let mut m = utils::splitn_escaped(buf.as_str().trim(), 3, '\\', '|');
             let path =
                 tryc!("missing path", m.next().ok_or_else(|| anyhow!("missing path")));
-            let typ = {
-                let v = tryc!(
-                    "missing type",
-                    m.next().ok_or_else(|| anyhow!("malformed line"))
-                );
-                tryc!("parse type", v.parse::<Typ>())
-            };
-            let val = {
-                let v = tryc!(
-                    "missing value",
-                    m.next().ok_or_else(|| anyhow!("malformed data"))
-                );
+            let typ =
+                tryc!("missing type", m.next().ok_or_else(|| anyhow!("malformed line")));
+            let v =
+                tryc!("missing value", m.next().ok_or_else(|| anyhow!("malformed data")));
+            let val = if typ == "json" {
+                let v = tryc!("parse json", serde_json::from_str(v));
+                tryc!("parse val", json::from_json(&v))
+            } else {
+                let typ = tryc!("parse type", typ.parse::<Typ>());
                 tryc!("parse val", typ.parse(v))
             };
             match by_path.get(path) {
@@ -118,7 +118,8 @@ async fn oneshot(subscriber: Subscriber, params: OneshotParams) -> Result<()> {
     let mut res = client.oneshot(&start, &end, &filter).await?;
     for OneshotReplyShard { pathmap, image, .. } in res.0.iter_mut() {
         for (id, value) in image.drain() {
-            Out { raw: false, path: &pathmap[&id], value }.write(&mut buf)?;
+            Out { raw: false, json: false, path: &pathmap[&id], value }
+                .write(&mut buf)?;
         }
     }
     stdout.write_all_buf(&mut buf).await?;
@@ -140,13 +141,19 @@ async fn oneshot(subscriber: Subscriber, params: OneshotParams) -> Result<()> {
                             let (ts, mut batch) = deltas.pop_front().unwrap();
                             Out {
                                 raw: false,
+                                json: false,
                                 path: "timestamp",
                                 value: Event::Update(Value::DateTime(ts)),
                             }
                             .write(&mut buf)?;
                             for BatchItem(id, value) in batch.drain(..) {
-                                Out { raw: false, path: &pathmap[&id], value }
-                                    .write(&mut buf)?;
+                                Out {
+                                    raw: false,
+                                    json: false,
+                                    path: &pathmap[&id],
+                                    value,
+                                }
+                                .write(&mut buf)?;
                             }
                             stdout.write_all_buf(&mut buf).await?;
                         }
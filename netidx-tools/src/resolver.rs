@@ -1,16 +1,23 @@
 use anyhow::{Context, Result};
 use arcstr::ArcStr;
+use futures::{pin_mut, StreamExt};
 use netidx::{
     chars::Chars,
     config::Config,
     path::Path,
+    pool::Pooled,
     protocol::glob::{Glob, GlobSet},
-    resolver_client::{ChangeTracker, DesiredAuth, ResolverRead, ResolverWrite},
+    resolver_client::{
+        ChangeTracker, DesiredAuth, MetadataMatch, PathMatch, ResolverRead,
+        ResolverWrite, Search,
+    },
 };
 use std::{collections::HashSet, iter, net::SocketAddr, time::Duration};
 use structopt::StructOpt;
 use tokio::time;
 
+const PAGE_SIZE: u32 = 10_000;
+
 #[derive(StructOpt, Debug)]
 pub(super) enum ResolverCmd {
     #[structopt(name = "resolve", about = "resolve an in the resolver server")]
@@ -37,6 +44,32 @@ pub(super) enum ResolverCmd {
         #[structopt(name = "path")]
         path: Option<Path>,
     },
+    #[structopt(name = "metadata", about = "metadata attached to path")]
+    Metadata {
+        #[structopt(name = "path")]
+        path: Path,
+    },
+    #[structopt(name = "search", about = "search for paths by name and metadata")]
+    Search {
+        #[structopt(long = "base", default_value = "/", help = "search under base")]
+        base: Path,
+        #[structopt(long = "regex", short = "r", help = "pattern is a regex")]
+        regex: bool,
+        #[structopt(
+            long = "meta",
+            short = "m",
+            help = "metadata predicate, key or key=value, may be repeated"
+        )]
+        metadata: Vec<String>,
+        #[structopt(
+            long = "page-size",
+            default_value = "1000",
+            help = "number of results to fetch at a time"
+        )]
+        page_size: u32,
+        #[structopt(name = "pattern", help = "match any path component")]
+        pattern: Option<String>,
+    },
     #[structopt(name = "add", about = "add a new entry")]
     Add {
         #[structopt(name = "path")]
@@ -96,13 +129,33 @@ pub(super) async fn run(
             let mut paths = HashSet::new();
             loop {
                 if resolver.check_changed(&mut ct).await.context("check changed")? {
-                    for b in resolver.list_matching(&globs).await.unwrap().iter() {
-                        for p in b.iter() {
-                            if !paths.contains(p) {
-                                paths.insert(p.clone());
-                                println!("{}", p);
+                    let mut print = |p: &Path| {
+                        if !paths.contains(p) {
+                            paths.insert(p.clone());
+                            println!("{}", p);
+                        }
+                    };
+                    let pages = resolver.list_matching_stream(globs.clone(), PAGE_SIZE);
+                    pin_mut!(pages);
+                    let mut first = true;
+                    while let Some(b) = pages.next().await {
+                        match b {
+                            Ok(b) => b.iter().for_each(&mut print),
+                            // resolvers that don't support paging
+                            Err(_) if first => {
+                                let batches = resolver
+                                    .list_matching(&globs)
+                                    .await
+                                    .context("list matching")?;
+                                batches
+                                    .iter()
+                                    .flat_map(|b| b.iter())
+                                    .for_each(&mut print);
+                                break;
                             }
+                            Err(e) => return Err(e).context("list matching"),
                         }
+                        first = false;
                     }
                 }
                 if watch {
@@ -125,6 +178,53 @@ pub(super) async fn run(
                 println!("{}", row);
             }
         }
+        ResolverCmd::Metadata { path } => {
+            let resolver = ResolverRead::new(config, auth);
+            let md = resolver.metadata(path).await.context("resolve metadata")?;
+            for (k, v) in md.iter() {
+                println!("{}: {}", k, v)
+            }
+        }
+        ResolverCmd::Search { base, regex, metadata, page_size, pattern } => {
+            let resolver = ResolverRead::new(config, auth);
+            let path = pattern.map(|p| {
+                if regex {
+                    PathMatch::Regex(Chars::from(p))
+                } else {
+                    PathMatch::Substring(Chars::from(p))
+                }
+            });
+            let metadata = metadata
+                .into_iter()
+                .map(|m| match m.split_once('=') {
+                    None => MetadataMatch::Has(Chars::from(m)),
+                    Some((k, v)) => MetadataMatch::Eq(
+                        Chars::from(String::from(k)),
+                        Chars::from(String::from(v)),
+                    ),
+                })
+                .collect::<Vec<_>>();
+            let mut after = None;
+            loop {
+                let res = resolver
+                    .search(Search {
+                        base: base.clone(),
+                        path: path.clone(),
+                        metadata: Pooled::orphan(metadata.clone()),
+                        after,
+                        limit: page_size,
+                    })
+                    .await
+                    .context("search")?;
+                for p in res.paths.iter() {
+                    println!("{}", p);
+                }
+                after = res.paths.last().cloned();
+                if !res.more || after.is_none() {
+                    break;
+                }
+            }
+        }
         ResolverCmd::Add { path, socketaddr } => {
             let resolver = ResolverWrite::new(config, auth, socketaddr)
                 .context("create resolver write")?;
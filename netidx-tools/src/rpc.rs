@@ -0,0 +1,157 @@
+use anyhow::{Context, Result};
+use futures::StreamExt;
+use netidx::{
+    config::Config,
+    path::Path,
+    resolver_client::DesiredAuth,
+    subscriber::{Subscriber, Typ, Value},
+};
+use netidx_protocols::rpc::{
+    client::{self, Proc, ProcInfo},
+    server::ArgSpec,
+};
+use std::time::Duration;
+use structopt::StructOpt;
+use tokio::time;
+
+#[derive(StructOpt, Debug)]
+pub(super) enum RpcCmd {
+    #[structopt(name = "list", about = "list the procedures under a path")]
+    List {
+        #[structopt(name = "base", default_value = "/")]
+        base: Path,
+    },
+    #[structopt(name = "describe", about = "print the usage of a procedure")]
+    Describe {
+        #[structopt(name = "path")]
+        path: Path,
+    },
+    #[structopt(name = "call", about = "call a procedure")]
+    Call {
+        #[structopt(
+            short = "t",
+            long = "timeout",
+            default_value = "30",
+            help = "give up if the call doesn't complete within timeout seconds"
+        )]
+        timeout: u64,
+        #[structopt(
+            short = "s",
+            long = "stream",
+            help = "print partial results as they arrive"
+        )]
+        stream: bool,
+        #[structopt(name = "path")]
+        path: Path,
+        #[structopt(name = "args", help = "arguments of the form name=value")]
+        args: Vec<String>,
+    },
+}
+
+fn naked(v: &Value) -> String {
+    match v {
+        Value::String(s) => String::from(&**s),
+        v => v.to_string(),
+    }
+}
+
+fn print_usage(info: &ProcInfo) {
+    println!("{}: {}", info.name, naked(&info.doc));
+    for ArgSpec { name, doc, default_value } in info.args.iter() {
+        println!(
+            "    {}={} ({}): {}",
+            name,
+            default_value,
+            typ(default_value),
+            naked(doc)
+        );
+    }
+}
+
+fn typ(v: &Value) -> &'static str {
+    match v {
+        Value::Null => "any",
+        v => Typ::get(v).name(),
+    }
+}
+
+// parse an argument, if the default value of the argument has a type
+// then the argument must be convertible to that type.
+fn parse_arg(info: &ProcInfo, arg: &str) -> Result<(String, Value)> {
+    let (name, val) =
+        arg.split_once('=').ok_or_else(|| anyhow!("expected name=value, got {}", arg))?;
+    let spec = info
+        .args
+        .iter()
+        .find(|spec| &*spec.name == name)
+        .ok_or_else(|| anyhow!("{} has no argument {}", info.name, name))?;
+    let val = match Typ::get(&spec.default_value) {
+        Typ::Null => val.parse::<Value>().with_context(|| {
+            format!("invalid value for {}, strings must be quoted", name)
+        })?,
+        typ => match val.parse::<Value>() {
+            Ok(v) => v.cast(typ).ok_or_else(|| {
+                anyhow!("{} must be a {}, got {}", name, typ.name(), val)
+            })?,
+            Err(_) => typ
+                .parse(val)
+                .with_context(|| format!("{} must be a {}", name, typ.name()))?,
+        },
+    };
+    Ok((String::from(name), val))
+}
+
+pub(super) async fn run(config: Config, auth: DesiredAuth, cmd: RpcCmd) -> Result<()> {
+    let subscriber = Subscriber::new(config, auth).context("create subscriber")?;
+    let to = Some(Duration::from_secs(10));
+    match cmd {
+        RpcCmd::List { base } => {
+            for path in client::list(&subscriber, &base).await.context("list")? {
+                println!("{}", path)
+            }
+        }
+        RpcCmd::Describe { path } => {
+            let info =
+                client::describe(&subscriber, path, to).await.context("describe")?;
+            print_usage(&info)
+        }
+        RpcCmd::Call { timeout, stream, path, args } => {
+            let info = client::describe(&subscriber, path.clone(), to)
+                .await
+                .context("describe")?;
+            let args = match args
+                .iter()
+                .map(|a| parse_arg(&info, a))
+                .collect::<Result<Vec<_>>>()
+            {
+                Ok(args) => args,
+                Err(e) => {
+                    print_usage(&info);
+                    return Err(e);
+                }
+            };
+            let proc = Proc::new(&subscriber, path).await.context("subscribe")?;
+            let timeout = Duration::from_secs(timeout);
+            let res = if stream {
+                let call = async {
+                    let mut results = proc.call_stream(args).await?;
+                    let mut last = None;
+                    while let Some(v) = results.next().await {
+                        if let Some(partial) = last.replace(v) {
+                            println!("{}", partial)
+                        }
+                    }
+                    Ok::<_, anyhow::Error>(last.unwrap_or(Value::Null))
+                };
+                time::timeout(timeout, call).await.context("rpc call timed out")??
+            } else {
+                proc.call_with_timeout(timeout, args).await?
+            };
+            match res {
+                Value::Error(e) => bail!("{}", e),
+                v => println!("{}", v),
+            }
+        }
+    }
+    Ok(())
+}
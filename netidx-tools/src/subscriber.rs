@@ -17,7 +17,10 @@ use netidx::{
     config::Config,
     path::Path,
     pool::Pooled,
-    protocol::value_parser::{escaped_string, value, VAL_ESC},
+    protocol::{
+        json,
+        value_parser::{escaped_string, value, VAL_ESC},
+    },
     resolver_client::DesiredAuth,
     subscriber::{Dval, Event, SubId, Subscriber, Typ, UpdatesFlags, Value},
     utils::{splitn_escaped, BatchItem, Batched},
@@ -48,6 +51,8 @@ pub(super) struct Params {
     no_stdin: bool,
     #[structopt(short = "r", long = "raw", help = "don't print the path or the type")]
     raw: bool,
+    #[structopt(short = "j", long = "json", help = "print values as canonical json")]
+    json: bool,
     #[structopt(
         short = "t",
         long = "subscribe-timeout",
@@ -148,6 +153,7 @@ impl<'a> fmt::Display for WVal<'a> {
 #[derive(Debug, Clone)]
 pub(crate) struct Out<'a> {
     pub(crate) raw: bool,
+    pub(crate) json: bool,
     pub(crate) path: &'a str,
     pub(crate) value: Event,
 }
@@ -164,7 +170,16 @@ impl<'a> Out<'a> {
                 }
             }
             Event::Update(v) => {
-                if self.raw {
+                if self.json {
+                    if !self.raw {
+                        to_stdout.extend_from_slice(self.path.as_bytes());
+                        to_stdout.extend_from_slice(b"|json|");
+                    }
+                    let w = &mut BytesWriter(to_stdout);
+                    serde_json::to_writer(&mut *w, &json::to_json(v))
+                        .context("write json")?;
+                    writeln!(w).context("finish write line")?
+                } else if self.raw {
                     let w = &mut BytesWriter(to_stdout);
                     writeln!(w, "{}", WVal(v)).context("write raw line")?
                 } else {
@@ -197,6 +212,7 @@ struct Ctx {
     oneshot: bool,
     requests_finished: bool,
     raw: bool,
+    json: bool,
     subscribe_timeout: Option<Duration>,
 }
 
@@ -242,6 +258,7 @@ impl Ctx {
             oneshot: p.oneshot,
             requests_finished: false,
             raw: p.raw,
+            json: p.json,
         }
     }
 
@@ -382,7 +399,7 @@ impl Ctx {
                         if self.subscribe_timeout.is_some() {
                             self.subscribe_ts.remove(path);
                         }
-                        Out { raw: self.raw, path: &**path, value }
+                        Out { raw: self.raw, json: self.json, path: &**path, value }
                             .write(&mut self.to_stdout)?;
                         if self.oneshot {
                             if let Some(path) = self.paths.get(&id).cloned() {
@@ -1,15 +1,62 @@
+//! The websocket protocol. Values are encoded using the canonical
+//! JSON encoding in `netidx::protocol::json`.
 use netidx::{
     path::Path,
     pool::Pooled,
-    protocol::value::Value,
+    protocol::{json, value::Value},
     publisher::Id as PubId,
     subscriber::{Event, SubId},
 };
 use serde_derive::{Deserialize, Serialize};
 
+mod json_args {
+    use super::*;
+    use serde::{de::Error, Deserialize, Deserializer};
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        d: D,
+    ) -> Result<Pooled<Vec<(Pooled<String>, Value)>>, D::Error> {
+        let args = Vec::<(String, serde_json::Value)>::deserialize(d)?;
+        let mut res = Pooled::orphan(Vec::with_capacity(args.len()));
+        for (name, v) in args {
+            let v = json::from_json(&v).map_err(D::Error::custom)?;
+            res.push((Pooled::orphan(name), v));
+        }
+        Ok(res)
+    }
+}
+
+mod json_event {
+    use super::*;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    enum JsonEvent {
+        Update(#[serde(with = "json")] Value),
+        Unsubscribed,
+    }
+
+    pub(super) fn serialize<S: Serializer>(e: &Event, s: S) -> Result<S::Ok, S::Error> {
+        match e {
+            Event::Update(v) => JsonEvent::Update(v.clone()).serialize(s),
+            Event::Unsubscribed => JsonEvent::Unsubscribed.serialize(s),
+        }
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        d: D,
+    ) -> Result<Event, D::Error> {
+        Ok(match JsonEvent::deserialize(d)? {
+            JsonEvent::Update(v) => Event::Update(v),
+            JsonEvent::Unsubscribed => Event::Unsubscribed,
+        })
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BatchItem {
     pub id: PubId,
+    #[serde(with = "json")]
     pub data: Value,
 }
 
@@ -24,10 +71,12 @@ pub enum Request {
     },
     Write {
         id: SubId,
+        #[serde(with = "json")]
         val: Value,
     },
     Publish {
         path: Path,
+        #[serde(with = "json")]
         init: Value,
     },
     Update {
@@ -39,6 +88,7 @@ pub enum Request {
     Call {
         id: u64,
         path: Path,
+        #[serde(deserialize_with = "json_args::deserialize")]
         args: Pooled<Vec<(Pooled<String>, Value)>>,
     },
     #[serde(other)]
@@ -48,20 +98,36 @@ pub enum Request {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Update {
     pub id: SubId,
+    #[serde(with = "json_event")]
     pub event: Event,
 }
 
 #[derive(Debug, Clone, Serialize)]
 #[serde(tag = "type")]
 pub enum Response {
-    Subscribed { id: SubId },
-    Update { updates: Pooled<Vec<Update>> },
+    Subscribed {
+        id: SubId,
+    },
+    Update {
+        updates: Pooled<Vec<Update>>,
+    },
     Unsubscribed,
     Wrote,
-    Published { id: PubId },
+    Published {
+        id: PubId,
+    },
     Updated,
     Unpublished,
-    CallSuccess { id: u64, result: Value },
-    CallFailed { id: u64, error: String },
-    Error { error: String },
+    CallSuccess {
+        id: u64,
+        #[serde(with = "json")]
+        result: Value,
+    },
+    CallFailed {
+        id: u64,
+        error: String,
+    },
+    Error {
+        error: String,
+    },
 }
@@ -0,0 +1,397 @@
+//! A blocking api for programs that don't want to use async.
+//!
+//! The types in this module wrap their async counterparts, and run
+//! them on a background tokio runtime that is shared by every
+//! blocking object in the process. The runtime is started the first
+//! time it is needed and runs until the process exits.
+//!
+//! The blocking methods must not be called from inside an async
+//! context (e.g. from a tokio task), doing so will panic. Async
+//! programs should use the async api directly.
+//!
+//! ```no_run
+//! use netidx::{
+//!     blocking::{Publisher, Subscriber},
+//!     config::Config,
+//!     path::Path,
+//!     publisher::{DesiredAuth, Value},
+//! };
+//!
+//! let cfg = Config::load_default().unwrap();
+//! let publisher = Publisher::new(cfg.clone(), DesiredAuth::Anonymous, None).unwrap();
+//! let val = publisher.publish(Path::from("/test/foo"), 42).unwrap();
+//! let subscriber = Subscriber::new(cfg, DesiredAuth::Anonymous).unwrap();
+//! let mut dv = subscriber.subscribe_one(Path::from("/test/foo"), None).unwrap();
+//! publisher.update(&val, 43);
+//! println!("{:?}", dv.recv_update().unwrap());
+//! ```
+use crate::{
+    config::Config,
+    path::Path,
+    pool::Pooled,
+    protocol::{
+        glob::GlobSet,
+        resolver::{Publisher as PublisherRef, PublisherId},
+    },
+    publisher::{self, BindCfg, Id, PublisherBuilder, UpdateBatch, Val, WriteRequest},
+    resolver_client::{self, ChangeTracker, DesiredAuth, Resolved, Table},
+    subscriber::{self, Event, SubId, UpdatesFlags, Value},
+};
+use anyhow::Result;
+use futures::{channel::mpsc, prelude::*};
+use fxhash::FxHashMap;
+use std::{collections::VecDeque, future::Future, time::Duration};
+use tokio::{
+    runtime::{self, Runtime},
+    time,
+};
+
+lazy_static! {
+    static ref RUNTIME: Runtime = runtime::Builder::new_multi_thread()
+        .worker_threads(2)
+        .thread_name("netidx-blocking")
+        .enable_all()
+        .build()
+        .expect("failed to start the netidx blocking runtime");
+}
+
+fn block_on<F: Future>(f: F) -> F::Output {
+    RUNTIME.block_on(f)
+}
+
+async fn with_timeout<T, F>(timeout: Option<Duration>, f: F) -> Result<T>
+where
+    F: Future<Output = Result<T>>,
+{
+    match timeout {
+        None => f.await,
+        Some(d) => time::timeout(d, f).await?,
+    }
+}
+
+/// A blocking `Publisher`. Like the async `Publisher` it is
+/// internally reference counted, and cloning it is cheap.
+#[derive(Debug, Clone)]
+pub struct Publisher(Option<publisher::Publisher>);
+
+// the last reference to a publisher spawns a task to clean up
+impl Drop for Publisher {
+    fn drop(&mut self) {
+        let _g = RUNTIME.enter();
+        drop(self.0.take())
+    }
+}
+
+impl Publisher {
+    /// Create a new publisher. If `bind_cfg` is `None` the default
+    /// from the config will be used.
+    pub fn new(
+        cfg: Config,
+        auth: DesiredAuth,
+        bind_cfg: Option<BindCfg>,
+    ) -> Result<Self> {
+        let publisher = block_on(
+            PublisherBuilder::new(cfg).desired_auth(auth).bind_cfg(bind_cfg).build(),
+        )?;
+        Ok(Publisher(Some(publisher)))
+    }
+
+    /// Wrap an existing async publisher
+    pub fn from_async(publisher: publisher::Publisher) -> Self {
+        Publisher(Some(publisher))
+    }
+
+    /// Return the underlying async publisher
+    pub fn inner(&self) -> &publisher::Publisher {
+        self.0.as_ref().unwrap()
+    }
+
+    /// Publish `path` with initial value `init`, see
+    /// `publisher::Publisher::publish`.
+    pub fn publish<T>(&self, path: Path, init: T) -> Result<Val>
+    where
+        T: TryInto<Value>,
+        <T as TryInto<Value>>::Error: std::error::Error + Send + Sync + 'static,
+    {
+        let _g = RUNTIME.enter();
+        self.inner().publish(path, init)
+    }
+
+    /// Update a single value and wait for the update to be sent. Use
+    /// `start_batch` and `commit` to update many values at once.
+    pub fn update<T: Into<Value>>(&self, val: &Val, v: T) {
+        let mut batch = self.start_batch();
+        val.update(&mut batch, v);
+        self.commit(batch, None)
+    }
+
+    /// Start a new update batch
+    pub fn start_batch(&self) -> UpdateBatch {
+        self.inner().start_batch()
+    }
+
+    /// Commit a batch of updates, see `UpdateBatch::commit`.
+    pub fn commit(&self, batch: UpdateBatch, timeout: Option<Duration>) {
+        block_on(batch.commit(timeout))
+    }
+
+    /// Wait until all previously committed batches have been flushed
+    /// to the os.
+    pub fn flushed(&self) {
+        block_on(self.inner().flushed())
+    }
+
+    /// Wait until at least one client is subscribed to `id`
+    pub fn wait_client(&self, id: Id) {
+        block_on(self.inner().wait_client(id))
+    }
+
+    /// Receive writes to `id`. Each call replaces any previous
+    /// receiver for `id`.
+    pub fn writes(&self, id: Id) -> Writes {
+        let (tx, rx) = mpsc::channel(3);
+        self.inner().writes(id, tx);
+        Writes { rx, queued: VecDeque::new() }
+    }
+
+    /// Unpublish everything and wait for the resolver to acknowledge
+    pub fn shutdown(mut self) {
+        if let Some(publisher) = self.0.take() {
+            block_on(publisher.shutdown())
+        }
+    }
+}
+
+/// Writes to a published value, see `Publisher::writes`
+#[derive(Debug)]
+pub struct Writes {
+    rx: mpsc::Receiver<Pooled<Vec<WriteRequest>>>,
+    queued: VecDeque<WriteRequest>,
+}
+
+impl Writes {
+    /// Wait for the next write request, or return an error if the
+    /// value has been unpublished.
+    pub fn recv(&mut self) -> Result<WriteRequest> {
+        match self.recv_timeout(None)? {
+            Some(req) => Ok(req),
+            None => bail!("timed out"),
+        }
+    }
+
+    /// Wait at most `timeout` for the next write request, returning
+    /// `None` if it expires.
+    pub fn recv_timeout(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> Result<Option<WriteRequest>> {
+        if let Some(req) = self.queued.pop_front() {
+            return Ok(Some(req));
+        }
+        let rx = &mut self.rx;
+        match block_on(with_timeout(timeout, async { Ok(rx.next().await) })) {
+            Err(_) => Ok(None),
+            Ok(None) => bail!("the value is no longer published"),
+            Ok(Some(mut batch)) => {
+                self.queued.extend(batch.drain(..));
+                Ok(self.queued.pop_front())
+            }
+        }
+    }
+}
+
+/// A blocking `Subscriber`. Like the async `Subscriber` it is
+/// internally reference counted, and cloning it is cheap.
+#[derive(Debug, Clone)]
+pub struct Subscriber(subscriber::Subscriber);
+
+impl Subscriber {
+    /// Create a new subscriber
+    pub fn new(cfg: Config, auth: DesiredAuth) -> Result<Self> {
+        let _g = RUNTIME.enter();
+        Ok(Subscriber(subscriber::Subscriber::new(cfg, auth)?))
+    }
+
+    /// Wrap an existing async subscriber
+    pub fn from_async(subscriber: subscriber::Subscriber) -> Self {
+        Subscriber(subscriber)
+    }
+
+    /// Return the underlying async subscriber
+    pub fn inner(&self) -> &subscriber::Subscriber {
+        &self.0
+    }
+
+    /// Return a resolver that shares this subscriber's connections
+    pub fn resolver(&self) -> ResolverRead {
+        ResolverRead(self.0.resolver())
+    }
+
+    /// Create a durable subscription to `path`, see
+    /// `subscriber::Subscriber::subscribe`. This does not wait for
+    /// the subscription to succeed.
+    pub fn subscribe(&self, path: Path) -> Dval {
+        let _g = RUNTIME.enter();
+        Dval::from_async(self.0.subscribe(path))
+    }
+
+    /// Create a durable subscription to `path` and wait at most
+    /// `timeout` for it to succeed.
+    pub fn subscribe_one(&self, path: Path, timeout: Option<Duration>) -> Result<Dval> {
+        let dv = self.subscribe(path);
+        dv.wait_subscribed(timeout)?;
+        Ok(dv)
+    }
+}
+
+type Updates = mpsc::Receiver<Pooled<Vec<(SubId, Event)>>>;
+
+/// A blocking durable subscription, see `subscriber::Dval`.
+#[derive(Debug)]
+pub struct Dval {
+    dval: subscriber::Dval,
+    updates: Option<Updates>,
+    queued: VecDeque<Event>,
+}
+
+impl Clone for Dval {
+    fn clone(&self) -> Self {
+        Dval::from_async(self.dval.clone())
+    }
+}
+
+impl Dval {
+    /// Wrap an existing async `Dval`
+    pub fn from_async(dval: subscriber::Dval) -> Self {
+        Dval { dval, updates: None, queued: VecDeque::new() }
+    }
+
+    /// Return the underlying async `Dval`
+    pub fn inner(&self) -> &subscriber::Dval {
+        &self.dval
+    }
+
+    /// return the unique id of this `Dval`
+    pub fn id(&self) -> SubId {
+        self.dval.id()
+    }
+
+    /// Get the last value published, or `Unsubscribed` if the
+    /// subscription is currently dead.
+    pub fn last(&self) -> Event {
+        self.dval.last()
+    }
+
+    /// Wait at most `timeout` for the `Dval` to be subscribed
+    pub fn wait_subscribed(&self, timeout: Option<Duration>) -> Result<()> {
+        block_on(with_timeout(timeout, self.dval.wait_subscribed()))
+    }
+
+    /// Wait for the next update. The first call registers for
+    /// updates, and returns the current value if the `Dval` is
+    /// subscribed. Updates are queued from then on, so none are
+    /// missed between calls.
+    pub fn recv_update(&mut self) -> Result<Event> {
+        match self.recv_update_timeout(None)? {
+            Some(ev) => Ok(ev),
+            None => bail!("timed out"),
+        }
+    }
+
+    /// Like `recv_update`, but wait at most `timeout`, returning
+    /// `None` if it expires.
+    pub fn recv_update_timeout(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> Result<Option<Event>> {
+        if let Some(ev) = self.queued.pop_front() {
+            return Ok(Some(ev));
+        }
+        let dval = &self.dval;
+        let rx = self.updates.get_or_insert_with(|| {
+            let (tx, rx) = mpsc::channel(3);
+            dval.updates(UpdatesFlags::BEGIN_WITH_LAST, tx);
+            rx
+        });
+        match block_on(with_timeout(timeout, async { Ok(rx.next().await) })) {
+            Err(_) => Ok(None),
+            Ok(None) => bail!("the subscriber is gone"),
+            Ok(Some(mut batch)) => {
+                self.queued.extend(batch.drain(..).map(|(_, ev)| ev));
+                Ok(self.queued.pop_front())
+            }
+        }
+    }
+
+    /// Write a value to the publisher, see `subscriber::Dval::write`
+    pub fn write(&self, v: Value) -> bool {
+        self.dval.write(v)
+    }
+
+    /// Write a value to the publisher and wait at most `timeout` for
+    /// it's reply.
+    pub fn write_with_recipt(
+        &self,
+        v: Value,
+        timeout: Option<Duration>,
+    ) -> Result<Value> {
+        let rx = self.dval.write_with_recipt(v);
+        block_on(with_timeout(timeout, async { Ok(rx.await?) }))
+    }
+}
+
+type Publishers = Pooled<FxHashMap<PublisherId, PublisherRef>>;
+
+/// A blocking resolver client, see `resolver_client::ResolverRead`
+#[derive(Debug, Clone)]
+pub struct ResolverRead(resolver_client::ResolverRead);
+
+impl ResolverRead {
+    pub fn new(cfg: Config, auth: DesiredAuth) -> Self {
+        let _g = RUNTIME.enter();
+        ResolverRead(resolver_client::ResolverRead::new(cfg, auth))
+    }
+
+    /// Wrap an existing async resolver
+    pub fn from_async(resolver: resolver_client::ResolverRead) -> Self {
+        ResolverRead(resolver)
+    }
+
+    /// Return the underlying async resolver
+    pub fn inner(&self) -> &resolver_client::ResolverRead {
+        &self.0
+    }
+
+    /// resolve the specified paths, results are in send order
+    pub fn resolve<I>(&self, batch: I) -> Result<(Publishers, Pooled<Vec<Resolved>>)>
+    where
+        I: IntoIterator<Item = Path>,
+    {
+        block_on(self.0.resolve(batch))
+    }
+
+    /// list children of the specified path
+    pub fn list(&self, path: Path) -> Result<Pooled<Vec<Path>>> {
+        block_on(self.0.list(path))
+    }
+
+    /// list paths matching the specified globset
+    pub fn list_matching(
+        &self,
+        globset: &GlobSet,
+    ) -> Result<Pooled<Vec<Pooled<Vec<Path>>>>> {
+        block_on(self.0.list_matching(globset))
+    }
+
+    /// return the table descriptor of `path`
+    pub fn table(&self, path: Path) -> Result<Table> {
+        block_on(self.0.table(path))
+    }
+
+    /// return true if anything under the tracked path may have
+    /// changed since the last call, see
+    /// `resolver_client::ResolverRead::check_changed`.
+    pub fn check_changed(&self, tracker: &mut ChangeTracker) -> Result<bool> {
+        block_on(self.0.check_changed(tracker))
+    }
+}
@@ -74,6 +74,8 @@ pub mod file {
         pub default_auth: super::DefaultAuthMech,
         #[serde(default)]
         pub default_bind_config: Option<String>,
+        #[serde(default)]
+        pub hedge_percentile: Option<f64>,
     }
 
     impl Config {
@@ -236,6 +238,11 @@ pub struct Config {
     pub tls: Option<Tls>,
     pub default_auth: DefaultAuthMech,
     pub default_bind_config: publisher::BindCfg,
+    /// Reads are sent to the fastest resolver server. If this is set,
+    /// and a read takes longer than this percentile (0 - 100) of that
+    /// server's recent latency, then a duplicate is sent to the next
+    /// fastest server and whichever answers first is used.
+    pub hedge_percentile: Option<f64>,
 }
 
 impl Config {
@@ -283,6 +290,11 @@ impl Config {
                 }
             }
         }
+        if let Some(p) = cfg.hedge_percentile {
+            if !(p > 0. && p < 100.) {
+                bail!("hedge_percentile must be between 0 and 100")
+            }
+        }
         if !cfg.addrs.iter().all(|(a, _)| a.ip().is_loopback())
             && !cfg.addrs.iter().all(|(a, _)| !a.ip().is_loopback())
         {
@@ -297,6 +309,7 @@ impl Config {
                 None => publisher::BindCfg::default(),
                 Some(s) => s.parse()?,
             },
+            hedge_percentile: cfg.hedge_percentile,
         })
     }
 
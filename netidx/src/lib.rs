@@ -100,6 +100,7 @@
 //!
 //! * Publish with a [`Publisher`](publisher/struct.Publisher.html)
 //! * Subscribe with a [`Subscriber`](subscriber/struct.Subscriber.html)
+//! * Programs that don't use async can use the [`blocking`](blocking/index.html) api
 #![recursion_limit = "1024"]
 #[macro_use] extern crate lazy_static;
 #[macro_use] extern crate serde_derive;
@@ -112,6 +113,7 @@ pub use netidx_netproto as protocol;
 
 pub(crate) mod tls;
 mod batch_channel;
+pub mod blocking;
 mod channel;
 pub mod config;
 mod os;
@@ -1,4 +1,5 @@
 mod server;
+mod tree;
 pub use crate::protocol::{
     publisher::Id,
     value::{FromValue, Typ, Value},
@@ -8,7 +9,10 @@ use crate::{
     config::Config,
     path::Path,
     pool::{Pool, Pooled},
-    protocol::{publisher, resolver::UserInfo},
+    protocol::{
+        publisher,
+        resolver::{Metadata, UserInfo},
+    },
     resolver_client::ResolverWrite,
     resolver_server::auth::Permissions,
     tls,
@@ -28,6 +32,7 @@ use if_addrs::get_if_addrs;
 use log::{error, info};
 use parking_lot::Mutex;
 use rand::{self, Rng};
+use serde::Serialize;
 use std::{
     boxed::Box,
     collections::{hash_map::Entry, BTreeMap, BTreeSet, HashMap, HashSet},
@@ -42,6 +47,7 @@ use std::{
     time::Duration,
 };
 use tokio::{net::TcpListener, task};
+pub use tree::PublishedTree;
 
 /// Control how the publisher picks a bind address. The address we
 /// give to the resolver server must be uniquely routable back to us,
@@ -296,6 +302,7 @@ lazy_static! {
     static ref BATCHES: Pool<Vec<WriteRequest>> = Pool::new(100, 10_000);
     static ref TOPUB: Pool<HashMap<Path, Option<u32>>> = Pool::new(10, 10_000);
     static ref TOUPUB: Pool<HashSet<Path>> = Pool::new(5, 10_000);
+    static ref TOMETA: Pool<HashMap<Path, Metadata>> = Pool::new(5, 10_000);
     static ref TOUSUB: Pool<HashMap<Id, Subscribed>> = Pool::new(5, 10_000);
     static ref RAWBATCH: Pool<Vec<BatchMsg>> = Pool::new(100, 100_000);
     static ref UPDATES: Pool<Vec<publisher::From>> = Pool::new(100, 100_000);
@@ -676,6 +683,7 @@ impl Drop for DefaultHandle {
         if let Some(t) = self.publisher.upgrade() {
             let mut pb = t.0.lock();
             pb.default.remove(self.path.as_ref());
+            pb.to_set_metadata.remove(self.path.as_ref());
             pb.to_unpublish_default.insert(self.path.clone());
             if let Some(paths) = pb.advertised.remove(&self.path) {
                 for path in paths {
@@ -853,6 +861,7 @@ struct PublisherInner {
     to_publish_default: Pooled<HashMap<Path, Option<u32>>>,
     to_unpublish: Pooled<HashSet<Path>>,
     to_unpublish_default: Pooled<HashSet<Path>>,
+    to_set_metadata: Pooled<HashMap<Path, Metadata>>,
     to_unsubscribe: Pooled<HashMap<Id, Subscribed>>,
     publish_triggered: bool,
     trigger_publish: UnboundedSender<Option<oneshot::Sender<()>>>,
@@ -907,6 +916,7 @@ impl PublisherInner {
         self.by_path.remove(path);
         if !self.is_advertised(path) {
             self.to_publish.remove(path);
+            self.to_set_metadata.remove(path);
             self.to_unpublish.insert(path.clone());
             self.trigger_publish();
         }
@@ -1138,6 +1148,7 @@ impl Publisher {
             to_publish_default: TOPUB.take(),
             to_unpublish: TOUPUB.take(),
             to_unpublish_default: TOUPUB.take(),
+            to_set_metadata: TOMETA.take(),
             to_unsubscribe: TOUSUB.take(),
             publish_triggered: false,
             trigger_publish: tx_trigger,
@@ -1418,6 +1429,37 @@ impl Publisher {
         self.publish_default_with_flags(PublishFlags::empty(), base)
     }
 
+    /// Publish `t` as a tree of paths under `base`, one path per leaf
+    /// field. Call `PublishedTree::update` to publish changes to
+    /// `t`. Subscribers can reassemble `t` using
+    /// `subscriber::TreeSubscription`.
+    pub fn publish_tree<T: Serialize>(&self, base: Path, t: &T) -> Result<PublishedTree> {
+        let mut tree = PublishedTree::new(self.clone(), base);
+        // no leaves exist yet, so there is nothing to update
+        let mut batch = self.start_batch();
+        tree.update(&mut batch, t)?;
+        Ok(tree)
+    }
+
+    /// Attach metadata (e.g. description, units, owner) to `path`,
+    /// replacing any metadata previously attached. `path` must be
+    /// published by this publisher, either directly or as a default
+    /// publisher base. The metadata is sent to the resolver along
+    /// with the next publish batch, and is removed when the path is
+    /// unpublished. Setting empty metadata removes it.
+    pub fn set_metadata(&self, path: Path, metadata: Metadata) -> Result<()> {
+        let mut pb = self.0.lock();
+        if pb.stop.is_none() {
+            bail!("publisher is dead")
+        }
+        if !pb.by_path.contains_key(&path) && !pb.default.contains_key(&path) {
+            bail!("path is not published")
+        }
+        pb.to_set_metadata.insert(path, metadata);
+        pb.trigger_publish();
+        Ok(())
+    }
+
     /// Start a new update batch. Updates are queued in the batch (see
     /// `Val::update`), and then the batch can be either discarded, or
     /// committed. If discarded then none of the updates will have any
@@ -1607,6 +1649,7 @@ async fn publish_loop(
             let mut to_publish_default;
             let mut to_unpublish;
             let mut to_unpublish_default;
+            let mut to_set_metadata;
             let mut to_unsubscribe;
             let resolver = {
                 let mut pb = publisher.0.lock();
@@ -1616,6 +1659,7 @@ async fn publish_loop(
                 to_unpublish = mem::replace(&mut pb.to_unpublish, TOUPUB.take());
                 to_unpublish_default =
                     mem::replace(&mut pb.to_unpublish_default, TOUPUB.take());
+                to_set_metadata = mem::replace(&mut pb.to_set_metadata, TOMETA.take());
                 to_unsubscribe = mem::replace(&mut pb.to_unsubscribe, TOUSUB.take());
                 pb.publish_triggered = false;
                 pb.resolver.clone()
@@ -1632,6 +1676,11 @@ async fn publish_loop(
                     error!("failed to publish_default some paths {} will retry", e)
                 }
             }
+            if to_set_metadata.len() > 0 {
+                if let Err(e) = resolver.set_metadata(to_set_metadata.drain()).await {
+                    error!("failed to set metadata on some paths {}", e)
+                }
+            }
             if to_unpublish.len() > 0 {
                 if let Err(e) = resolver.unpublish(to_unpublish.drain()).await {
                     error!("failed to unpublish some paths {} will retry", e)
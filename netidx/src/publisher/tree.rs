@@ -0,0 +1,79 @@
+use super::{Publisher, UpdateBatch, Val, Value};
+use crate::{path::Path, protocol::json::from_json};
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::Value as JValue;
+use std::collections::BTreeMap;
+
+// non empty objects become subtrees, everything else is a leaf
+fn flatten(base: &Path, v: JValue, leaves: &mut BTreeMap<Path, Value>) -> Result<()> {
+    match v {
+        JValue::Object(m) if !m.is_empty() => {
+            for (k, v) in m {
+                if k.is_empty() {
+                    bail!("empty keys can't be published under {}", base)
+                }
+                flatten(&base.append(&*Path::escape(&k)), v, leaves)?
+            }
+            Ok(())
+        }
+        v => {
+            leaves.insert(base.clone(), from_json(&v)?);
+            Ok(())
+        }
+    }
+}
+
+/// A struct or map published as a tree of paths, created by
+/// `Publisher::publish_tree`. Each field of a struct (or key of a
+/// map) becomes a path under the base, nested structs and maps
+/// become nested subtrees, and every other field is published as a
+/// leaf value, converted from JSON as by
+/// `netidx_netproto::json::from_json`.
+///
+/// Dropping the `PublishedTree` unpublishes all it's leaves.
+pub struct PublishedTree {
+    publisher: Publisher,
+    base: Path,
+    leaves: BTreeMap<Path, Val>,
+}
+
+impl PublishedTree {
+    pub(super) fn new(publisher: Publisher, base: Path) -> Self {
+        Self { publisher, base, leaves: BTreeMap::new() }
+    }
+
+    /// The base path of the tree
+    pub fn base(&self) -> &Path {
+        &self.base
+    }
+
+    /// The currently published leaves
+    pub fn leaves(&self) -> impl Iterator<Item = &Path> {
+        self.leaves.keys()
+    }
+
+    /// Update the tree to reflect `t`. Leaves that have changed are
+    /// updated in `batch` with `update_changed`, leaves that no
+    /// longer exist are unpublished, and new leaves are published
+    /// immediately.
+    pub fn update<T: Serialize>(&mut self, batch: &mut UpdateBatch, t: &T) -> Result<()> {
+        let mut new = BTreeMap::new();
+        match serde_json::to_value(t)? {
+            JValue::Object(m) if m.is_empty() => (),
+            v @ JValue::Object(_) => flatten(&self.base, v, &mut new)?,
+            _ => bail!("only structs and maps can be published as trees"),
+        }
+        self.leaves.retain(|path, _| new.contains_key(path));
+        for (path, v) in new {
+            match self.leaves.get(&path) {
+                Some(val) => val.update_changed(batch, v),
+                None => {
+                    let val = self.publisher.publish(path.clone(), v)?;
+                    self.leaves.insert(path, val);
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,108 @@
+//! An opt in cache of resolve results, so that resolving the same
+//! paths over and over again (e.g. mass resubscription after a
+//! publisher restarts) doesn't have to go to the resolver server
+//! every time.
+use crate::{
+    path::Path,
+    protocol::resolver::{Publisher, PublisherId, Resolved},
+};
+use fxhash::FxHashMap;
+use std::{cmp::min, time::Duration};
+use tokio::time::Instant;
+
+/// Publishers reject resolver tokens older than 5 minutes, make sure
+/// we never hand out a token that is close to that.
+pub(super) const MAX_TTL: Duration = Duration::from_secs(240);
+
+#[derive(Debug)]
+struct Entry {
+    expires: Instant,
+    resolved: Resolved,
+    publishers: Vec<Publisher>,
+}
+
+#[derive(Debug)]
+pub(super) struct ResolveCache {
+    ttl: Option<Duration>,
+    entries: FxHashMap<Path, Entry>,
+    next_gc: Instant,
+}
+
+impl ResolveCache {
+    pub(super) fn new() -> Self {
+        ResolveCache { ttl: None, entries: FxHashMap::default(), next_gc: Instant::now() }
+    }
+
+    pub(super) fn ttl(&self) -> Option<Duration> {
+        self.ttl
+    }
+
+    /// set the ttl, or disable the cache if `ttl` is `None`. Changing
+    /// the ttl drops everything in the cache.
+    pub(super) fn set_ttl(&mut self, ttl: Option<Duration>) {
+        self.ttl = ttl.map(|ttl| min(ttl, MAX_TTL));
+        self.entries.clear();
+    }
+
+    /// Look up `path`, if it's cached then add the publishers it
+    /// refers to to `publishers` and return it.
+    pub(super) fn get(
+        &self,
+        path: &Path,
+        now: Instant,
+        publishers: &mut FxHashMap<PublisherId, Publisher>,
+    ) -> Option<Resolved> {
+        match self.entries.get(path) {
+            Some(e) if now < e.expires => {
+                for p in &e.publishers {
+                    publishers.entry(p.id).or_insert_with(|| p.clone());
+                }
+                Some(e.resolved.clone())
+            }
+            None | Some(_) => None,
+        }
+    }
+
+    pub(super) fn insert(
+        &mut self,
+        path: Path,
+        resolved: &Resolved,
+        publishers: &FxHashMap<PublisherId, Publisher>,
+        now: Instant,
+    ) {
+        let ttl = match self.ttl {
+            None => return,
+            Some(ttl) => ttl,
+        };
+        // not published yet, we don't want to delay finding out
+        // when it is
+        if resolved.publishers.is_empty() {
+            return;
+        }
+        let mut refs = Vec::with_capacity(resolved.publishers.len());
+        for r in resolved.publishers.iter() {
+            match publishers.get(&r.id) {
+                Some(p) => refs.push(p.clone()),
+                None => return,
+            }
+        }
+        if now >= self.next_gc {
+            self.next_gc = now + ttl;
+            self.entries.retain(|_, e| now < e.expires);
+        }
+        let e =
+            Entry { expires: now + ttl, resolved: resolved.clone(), publishers: refs };
+        self.entries.insert(path, e);
+    }
+
+    pub(super) fn invalidate(&mut self, path: &Path) {
+        self.entries.remove(path);
+    }
+
+    /// invalidate `path` and everything under it
+    pub(super) fn invalidate_under(&mut self, path: &Path) {
+        if !self.entries.is_empty() {
+            self.entries.retain(|p, _| !Path::is_parent(path, p));
+        }
+    }
+}
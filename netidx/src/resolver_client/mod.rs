@@ -1,10 +1,14 @@
+mod cache;
 pub(crate) mod common;
 mod read_client;
 mod write_client;
 
 pub use crate::protocol::{
     glob::{Glob, GlobSet},
-    resolver::{Resolved, Table},
+    resolver::{
+        Cursor, ListPage, Metadata, MetadataMatch, PathMatch, Resolved, Search,
+        SearchResult, Table,
+    },
 };
 use crate::{
     config::Config,
@@ -18,13 +22,17 @@ use crate::{
 };
 use anyhow::Result;
 use arcstr::ArcStr;
+use cache::ResolveCache;
 pub use common::DesiredAuth;
 use common::{
     ResponseChan, FROMREADPOOL, FROMWRITEPOOL, LISTPOOL, PATHPOOL, PUBLISHERPOOL,
     RAWFROMREADPOOL, RAWFROMWRITEPOOL, RAWTOREADPOOL, RAWTOWRITEPOOL, RESOLVEDPOOL,
     TOREADPOOL, TOWRITEPOOL,
 };
-use futures::future;
+use futures::{
+    future,
+    stream::{self, Stream},
+};
 use fxhash::FxHashMap;
 use parking_lot::{Mutex, RwLock};
 use read_client::ReadClient;
@@ -54,8 +62,16 @@ trait ToPath {
 impl ToPath for ToRead {
     fn path(&self) -> Option<&Path> {
         match self {
-            ToRead::List(p) | ToRead::Table(p) | ToRead::Resolve(p) => Some(p),
-            ToRead::ListMatching(_) | ToRead::GetChangeNr(_) => None,
+            ToRead::List(p)
+            | ToRead::Table(p)
+            | ToRead::Resolve(p)
+            | ToRead::Metadata(p)
+            | ToRead::ListPage(p, _)
+            | ToRead::Columns(p)
+            | ToRead::Search(Search { base: p, .. }) => Some(p),
+            ToRead::ListMatching(_)
+            | ToRead::ListMatchingPage(_, _)
+            | ToRead::GetChangeNr(_) => None,
         }
     }
 }
@@ -63,13 +79,15 @@ impl ToPath for ToRead {
 impl ToPath for ToWrite {
     fn path(&self) -> Option<&Path> {
         match self {
-            ToWrite::Clear | ToWrite::Heartbeat => None,
+            // registrations always go to the cluster we're configured for
+            ToWrite::Clear | ToWrite::Heartbeat | ToWrite::RegisterReferral(_) => None,
             ToWrite::Publish(p)
             | ToWrite::Unpublish(p)
             | ToWrite::UnpublishDefault(p)
             | ToWrite::PublishDefault(p)
             | ToWrite::PublishWithFlags(p, _)
-            | ToWrite::PublishDefaultWithFlags(p, _) => Some(p),
+            | ToWrite::PublishDefaultWithFlags(p, _)
+            | ToWrite::SetMetadata(p, _) => Some(p),
         }
     }
 }
@@ -189,6 +207,7 @@ where
         writer_addr: SocketAddr,
         secrets: Arc<RwLock<FxHashMap<SocketAddr, u128>>>,
         tls: Option<tls::CachedConnector>,
+        hedge_percentile: Option<f64>,
     ) -> Self;
     fn send(&mut self, batch: Pooled<Vec<(usize, T)>>) -> ResponseChan<F>;
 }
@@ -200,8 +219,9 @@ impl Connection<ToRead, FromRead> for ReadClient {
         _writer_addr: SocketAddr,
         _secrets: Arc<RwLock<FxHashMap<SocketAddr, u128>>>,
         tls: Option<tls::CachedConnector>,
+        hedge_percentile: Option<f64>,
     ) -> Self {
-        ReadClient::new(resolver, desired_auth, tls)
+        ReadClient::new(resolver, desired_auth, tls, hedge_percentile)
     }
 
     fn send(&mut self, batch: Pooled<Vec<(usize, ToRead)>>) -> ResponseChan<FromRead> {
@@ -216,6 +236,7 @@ impl Connection<ToWrite, FromWrite> for WriteClient {
         writer_addr: SocketAddr,
         secrets: Arc<RwLock<FxHashMap<SocketAddr, u128>>>,
         tls: Option<tls::CachedConnector>,
+        _hedge_percentile: Option<f64>,
     ) -> Self {
         WriteClient::new(resolver, desired_auth, writer_addr, secrets, tls)
     }
@@ -238,6 +259,7 @@ where
     writer_addr: SocketAddr,
     secrets: Arc<RwLock<FxHashMap<SocketAddr, u128>>>,
     tls: Option<tls::CachedConnector>,
+    hedge_percentile: Option<f64>,
     phantom: PhantomData<(T, F)>,
     f_pool: Pool<Vec<F>>,
     fi_pool: Pool<Vec<(usize, F)>>,
@@ -265,6 +287,7 @@ where
                     self.writer_addr,
                     self.secrets.clone(),
                     self.tls.clone(),
+                    self.hedge_percentile,
                 );
                 self.by_server.insert(r, con.clone());
                 con.send(batch)
@@ -295,6 +318,7 @@ where
     ) -> ResolverWrap<C, T, F> {
         let secrets = Arc::new(RwLock::new(HashMap::default()));
         let tls = default.tls.clone().map(tls::CachedConnector::new);
+        let hedge_percentile = default.hedge_percentile;
         let mut router = Router::new();
         let default: Arc<Referral> = Arc::new(default.to_referral());
         router.add_referral(default.clone());
@@ -306,6 +330,7 @@ where
             writer_addr,
             secrets,
             tls,
+            hedge_percentile,
             f_pool,
             fi_pool,
             ti_pool,
@@ -388,18 +413,49 @@ impl ChangeTracker {
 }
 
 #[derive(Debug, Clone)]
-pub struct ResolverRead(ResolverWrap<ReadClient, ToRead, FromRead>);
+pub struct ResolverRead(
+    ResolverWrap<ReadClient, ToRead, FromRead>,
+    Arc<Mutex<ResolveCache>>,
+);
 
 impl ResolverRead {
     pub fn new(default: Config, desired_auth: DesiredAuth) -> Self {
-        ResolverRead(ResolverWrap::new(
-            default,
-            desired_auth,
-            SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 0),
-            RAWFROMREADPOOL.clone(),
-            FROMREADPOOL.clone(),
-            TOREADPOOL.clone(),
-        ))
+        ResolverRead(
+            ResolverWrap::new(
+                default,
+                desired_auth,
+                SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 0),
+                RAWFROMREADPOOL.clone(),
+                FROMREADPOOL.clone(),
+                TOREADPOOL.clone(),
+            ),
+            Arc::new(Mutex::new(ResolveCache::new())),
+        )
+    }
+
+    /// Enable caching of resolve results for `ttl`, or disable it if
+    /// `ttl` is `None`. The cache is disabled by default. `ttl` is
+    /// capped at 4 minutes, because publishers won't accept resolver
+    /// tokens older than 5 minutes. Changing the ttl empties the
+    /// cache.
+    ///
+    /// Cached results are shared by all clones of this
+    /// `ResolverRead`, including the one used by a `Subscriber`. A
+    /// cached path is dropped when a subscription to it fails, and
+    /// cached paths under the tracked path are dropped when
+    /// `check_changed` reports a change.
+    pub fn set_cache_ttl(&self, ttl: Option<Duration>) {
+        self.1.lock().set_ttl(ttl)
+    }
+
+    /// Return the resolve cache ttl, `None` if the cache is disabled
+    pub fn cache_ttl(&self) -> Option<Duration> {
+        self.1.lock().ttl()
+    }
+
+    /// Remove `path` from the resolve cache, if it's there
+    pub fn invalidate(&self, path: &Path) {
+        self.1.lock().invalidate(path)
     }
 
     /// send the specified messages to the resolver, and return the answers (in send order)
@@ -410,7 +466,9 @@ impl ResolverRead {
         self.0.send(batch).await
     }
 
-    /// resolve the specified paths, results are in send order
+    /// resolve the specified paths, results are in send order. If the
+    /// cache is enabled (see `set_cache_ttl`) then cached paths won't
+    /// be sent to the resolver.
     pub async fn resolve<I>(
         &self,
         batch: I,
@@ -420,7 +478,51 @@ impl ResolverRead {
     {
         let mut to = RAWTOREADPOOL.take();
         to.extend(batch.into_iter().map(ToRead::Resolve));
-        let (publishers, mut result) = self.send(&to).await?;
+        if self.1.lock().ttl().is_none() {
+            return self.resolve_uncached(&to).await;
+        }
+        let now = Instant::now();
+        let mut publishers = PUBLISHERPOOL.take();
+        let mut cached = Vec::new();
+        let mut uncached = RAWTOREADPOOL.take();
+        {
+            let cache = self.1.lock();
+            for (i, m) in to.drain(..).enumerate() {
+                let hit = match &m {
+                    ToRead::Resolve(path) => cache.get(path, now, &mut publishers),
+                    _ => None,
+                };
+                match hit {
+                    Some(r) => cached.push((i, r)),
+                    None => uncached.push(m),
+                }
+            }
+        }
+        let mut resolved = if uncached.is_empty() {
+            RESOLVEDPOOL.take()
+        } else {
+            let (mut p, resolved) = self.resolve_uncached(&uncached).await?;
+            let mut cache = self.1.lock();
+            for (m, r) in uncached.iter().zip(resolved.iter()) {
+                if let ToRead::Resolve(path) = m {
+                    cache.insert(path.clone(), r, &p, now);
+                }
+            }
+            publishers.extend(p.drain());
+            resolved
+        };
+        // put the cached results back in send order
+        for (i, r) in cached {
+            resolved.insert(i, r);
+        }
+        Ok((publishers, resolved))
+    }
+
+    async fn resolve_uncached(
+        &self,
+        to: &Pooled<Vec<ToRead>>,
+    ) -> Result<(Pooled<FxHashMap<PublisherId, Publisher>>, Pooled<Vec<Resolved>>)> {
+        let (publishers, mut result) = self.send(to).await?;
         if result.len() != to.len() {
             bail!(
                 "unexpected number of resolve results {} expected {}",
@@ -581,9 +683,95 @@ impl ResolverRead {
             m => bail!("unexpected response to GetChangeNr, {:?}", m),
         })
         .await?;
+        if res {
+            self.1.lock().invalidate_under(&tracker.path);
+        }
         Ok(res)
     }
 
+    /// Get one page of the children of the specified path. Paths
+    /// are sorted. See `Cursor` for how to get the next page.
+    pub async fn list_page(&self, path: Path, cursor: Cursor) -> Result<ListPage> {
+        let mut to = RAWTOREADPOOL.take();
+        to.push(ToRead::ListPage(path, cursor));
+        let (_, mut result) = self.send(&to).await?;
+        if result.len() != 1 {
+            bail!("expected 1 result from list_page got {}", result.len());
+        } else {
+            match result.pop().unwrap() {
+                FromRead::ListPage(lp) => Ok(lp),
+                m => bail!("unexpected result from list_page {:?}", m),
+            }
+        }
+    }
+
+    /// Like `list`, but fetch the children lazily in sorted pages of
+    /// at most `page_size` paths. Use this instead of `list` when
+    /// `path` might have a huge number of children.
+    pub fn list_stream(
+        &self,
+        path: Path,
+        page_size: u32,
+    ) -> impl Stream<Item = Result<Pooled<Vec<Path>>>> + Send + 'static {
+        let resolver = self.clone();
+        stream::unfold(Some(None), move |after: Option<Option<Path>>| {
+            let resolver = resolver.clone();
+            let path = path.clone();
+            async move {
+                let cursor = Cursor { after: after?, limit: page_size };
+                match resolver.list_page(path, cursor).await {
+                    Err(e) => Some((Err(e), None)),
+                    Ok(lp) => {
+                        let next = match lp.paths.last() {
+                            Some(last) if lp.more => Some(Some(last.clone())),
+                            Some(_) | None => None,
+                        };
+                        Some((Ok(lp.paths), next))
+                    }
+                }
+            }
+        })
+    }
+
+    /// Like `list_matching`, but fetch the results lazily in pages
+    /// of at most `page_size` paths. Each server in the cluster is
+    /// listed in turn, and within a server paths are sorted by level
+    /// and then by name. Use this instead of `list_matching` when the
+    /// globset might match a huge number of paths.
+    pub fn list_matching_stream(
+        &self,
+        globset: GlobSet,
+        page_size: u32,
+    ) -> impl Stream<Item = Result<Pooled<Vec<Path>>>> + Send + 'static {
+        let st = ListMatchingStream {
+            resolver: self.clone(),
+            globset,
+            page_size,
+            pending: vec![None],
+            done: HashSet::new(),
+            current: None,
+            finished: false,
+        };
+        stream::unfold(st, |mut st| async move { st.next().await.map(|r| (r, st)) })
+    }
+
+    /// Get the columns of the table rooted at the specified path,
+    /// without it's rows. Use this along with `list_stream` instead
+    /// of `table` when the table might have a huge number of rows.
+    pub async fn columns(&self, path: Path) -> Result<Pooled<Vec<(Path, Z64)>>> {
+        let mut to = RAWTOREADPOOL.take();
+        to.push(ToRead::Columns(path));
+        let (_, mut result) = self.send(&to).await?;
+        if result.len() != 1 {
+            bail!("expected 1 result from columns got {}", result.len());
+        } else {
+            match result.pop().unwrap() {
+                FromRead::Columns(cols) => Ok(cols),
+                m => bail!("unexpected result from columns {:?}", m),
+            }
+        }
+    }
+
     pub async fn table(&self, path: Path) -> Result<Table> {
         let mut to = RAWTOREADPOOL.take();
         to.push(ToRead::Table(path.clone()));
@@ -611,6 +799,130 @@ impl ResolverRead {
             }
         }
     }
+
+    /// Get the metadata attributes attached to the specified path by
+    /// it's publisher. If no metadata is attached the result will be
+    /// empty.
+    pub async fn metadata(&self, path: Path) -> Result<Metadata> {
+        let mut to = RAWTOREADPOOL.take();
+        to.push(ToRead::Metadata(path));
+        let (_, mut result) = self.send(&to).await?;
+        if result.len() != 1 {
+            bail!("expected 1 result from metadata got {}", result.len());
+        } else {
+            match result.pop().unwrap() {
+                FromRead::Metadata(md) => Ok(md),
+                m => bail!("unexpected result from metadata {:?}", m),
+            }
+        }
+    }
+
+    /// Search for published paths under `search.base` by name and
+    /// metadata. The search is handled by the cluster that owns
+    /// `search.base`, child clusters mounted below it are not
+    /// searched. See `Search` for how to page through large results.
+    pub async fn search(&self, search: Search) -> Result<SearchResult> {
+        let mut to = RAWTOREADPOOL.take();
+        to.push(ToRead::Search(search));
+        let (_, mut result) = self.send(&to).await?;
+        if result.len() != 1 {
+            bail!("expected 1 result from search got {}", result.len());
+        } else {
+            match result.pop().unwrap() {
+                FromRead::Search(r) => Ok(r),
+                m => bail!("unexpected result from search {:?}", m),
+            }
+        }
+    }
+}
+
+struct ListMatchingStream {
+    resolver: ResolverRead,
+    globset: GlobSet,
+    page_size: u32,
+    pending: Vec<Option<Arc<Referral>>>,
+    done: HashSet<Arc<Referral>>,
+    current: Option<(Arc<Referral>, Option<Path>)>,
+    finished: bool,
+}
+
+impl ListMatchingStream {
+    async fn next(&mut self) -> Option<Result<Pooled<Vec<Path>>>> {
+        while !self.finished {
+            let (referral, after) = match self.current.take() {
+                Some(current) => current,
+                None => {
+                    let mut inner = self.resolver.0 .0.lock();
+                    match self.pending.pop() {
+                        Some(r) => {
+                            let r = r.unwrap_or_else(|| inner.default.clone());
+                            if self.done.contains(&r) {
+                                continue;
+                            }
+                            if self.done.len() > MAX_REFERRALS {
+                                self.finished = true;
+                                return Some(Err(anyhow!("max referrals reached")));
+                            }
+                            self.done.insert(r.clone());
+                            (inner.router.add_referral(r), None)
+                        }
+                        None => {
+                            self.finished = true;
+                            if self.globset.published_only() {
+                                break;
+                            }
+                            let mut refs = PATHPOOL.take();
+                            for p in inner.router.cached.keys() {
+                                if self.globset.is_match(p) {
+                                    refs.push(p.clone());
+                                }
+                            }
+                            if refs.len() > 0 {
+                                return Some(Ok(refs));
+                            }
+                            break;
+                        }
+                    }
+                }
+            };
+            let cursor = Cursor { after, limit: self.page_size };
+            let mut to = TOREADPOOL.take();
+            to.push((0, ToRead::ListMatchingPage(self.globset.clone(), cursor)));
+            let reply =
+                self.resolver.0 .0.lock().send_to_server(Some(referral.clone()), to);
+            let mut replies = match reply.await {
+                Ok((_, replies)) => replies,
+                Err(e) => {
+                    self.finished = true;
+                    return Some(Err(e.into()));
+                }
+            };
+            for (_, reply) in replies.drain(..) {
+                match reply {
+                    FromRead::ListPage(mut lp) => {
+                        for r in lp.referrals.drain(..) {
+                            self.pending.push(Some(Arc::new(r)));
+                        }
+                        if let Some(last) = lp.paths.last() {
+                            if lp.more {
+                                self.current =
+                                    Some((referral.clone(), Some(last.clone())));
+                            }
+                            return Some(Ok(lp.paths));
+                        }
+                    }
+                    m => {
+                        self.finished = true;
+                        return Some(Err(anyhow!(
+                            "unexpected list_matching_page response {:?}",
+                            m
+                        )));
+                    }
+                }
+            }
+        }
+        None
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -725,6 +1037,30 @@ impl ResolverWrite {
         self.send_expect(batch, FromWrite::Unpublished, ToWrite::UnpublishDefault).await
     }
 
+    /// Attach metadata to paths you have already published, replacing
+    /// any metadata previously attached to them. Passing empty
+    /// metadata removes it. Metadata is removed automatically when a
+    /// path is no longer published by anyone.
+    pub async fn set_metadata<I: IntoIterator<Item = (Path, Metadata)>>(
+        &self,
+        batch: I,
+    ) -> Result<()> {
+        self.send_expect(batch, FromWrite::Published, |(path, md)| {
+            ToWrite::SetMetadata(path, md)
+        })
+        .await
+    }
+
+    /// Register a child cluster with the cluster this client is
+    /// configured for, or renew an existing registration. The
+    /// registration expires unless it is renewed within the
+    /// referral's ttl. Requires the `r` permission at the referral's
+    /// path.
+    pub async fn register_referral(&self, referral: Referral) -> Result<()> {
+        self.send_expect([referral], FromWrite::Published, ToWrite::RegisterReferral)
+            .await
+    }
+
     // CR estokes: this is broken on complex clusters, but it's also
     // redundant, consider removing it.
     pub async fn clear(&self) -> Result<()> {
@@ -12,158 +12,137 @@ use crate::{
     tls,
     utils::Either,
 };
-use anyhow::{Error, Result};
+use anyhow::Result;
 use cross_krb5::ClientCtx;
 use futures::{
     channel::{mpsc, oneshot},
+    future,
     prelude::*,
+    select_biased,
+    stream::FuturesUnordered,
 };
 use fxhash::FxHashSet;
-use log::{info, warn};
+use log::{debug, info, warn};
+use parking_lot::Mutex;
 use rand::{seq::SliceRandom, thread_rng, Rng};
 use std::{
-    cmp::max, collections::HashSet, fmt::Debug, net::SocketAddr, sync::Arc,
+    cmp::{max, min},
+    collections::VecDeque,
+    net::SocketAddr,
+    sync::Arc,
     time::Duration,
 };
-use tokio::{net::TcpStream, task, time};
+use tokio::{net::TcpStream, task, time, time::Instant};
 
-// continue with timeout
-macro_rules! cwt {
+// bail with timeout
+macro_rules! bwt {
     ($msg:expr, $e:expr) => {
-        try_cf!(
-            $msg,
-            continue,
-            try_cf!($msg, continue, time::timeout(HELLO_TO, $e).await)
-        )
+        match time::timeout(HELLO_TO, $e).await {
+            Ok(Ok(x)) => x,
+            Ok(Err(e)) => bail!("{}: {}", $msg, e),
+            Err(_) => bail!("{}: timed out", $msg),
+        }
     };
 }
 
 async fn connect(
-    bad_addrs: &mut FxHashSet<SocketAddr>,
-    resolver: &Referral,
+    addr: SocketAddr,
+    auth: &Auth,
     desired_auth: &DesiredAuth,
     tls: &Option<tls::CachedConnector>,
 ) -> Result<Channel> {
-    let mut addrs = resolver.addrs.clone();
-    addrs.as_mut_slice().shuffle(&mut thread_rng());
-    let mut n = 0;
-    loop {
-        let (addr, auth) = &addrs[n % addrs.len()];
-        let tries = n / addrs.len();
-        if tries >= 3 {
-            bail!("can't connect to any resolver servers");
-        }
-        if tries == 0 && bad_addrs.contains(&addr) {
-            n += 1;
-            continue;
-        } else {
-            bad_addrs.clear()
-        }
-        if n % addrs.len() == 0 && tries > 0 {
-            let wait = thread_rng().gen_range(1..12);
-            time::sleep(Duration::from_secs(wait)).await;
-        }
-        n += 1;
-        let mut con = match time::timeout(HELLO_TO, TcpStream::connect(&addr)).await {
-            Ok(Ok(con)) => con,
-            Err(_) => {
-                warn!(
-                    "failed to connect to resolver server {} connection timed out",
-                    addr
-                );
-                bad_addrs.insert(*addr);
-                continue;
-            }
-            Ok(Err(e)) => {
-                warn!("failed to connect to resolver server {} error: {}", addr, e);
-                bad_addrs.insert(*addr);
-                continue;
-            }
-        };
-        try_cf!("no delay", con.set_nodelay(true));
-        cwt!("send version", channel::write_raw(&mut con, &3u64));
-        if cwt!("recv version", channel::read_raw::<u64, _>(&mut con)) != 3 {
-            continue;
-        }
-        let con = match (desired_auth, auth) {
-            (DesiredAuth::Anonymous, _) => {
-                let mut con = Channel::new::<ClientCtx, TcpStream>(None, con);
-                cwt!("hello", con.send_one(&ClientHello::ReadOnly(AuthRead::Anonymous)));
-                match cwt!("reply", con.receive::<AuthRead>()) {
-                    AuthRead::Anonymous => (),
-                    AuthRead::Local | AuthRead::Krb5 | AuthRead::Tls => {
-                        bail!("protocol error")
-                    }
+    let mut con = bwt!("connect", TcpStream::connect(&addr));
+    con.set_nodelay(true)?;
+    bwt!("send version", channel::write_raw(&mut con, &3u64));
+    if bwt!("recv version", channel::read_raw::<u64, _>(&mut con)) != 3 {
+        bail!("incompatible protocol version")
+    }
+    Ok(match (desired_auth, auth) {
+        (DesiredAuth::Anonymous, _) => {
+            let mut con = Channel::new::<ClientCtx, TcpStream>(None, con);
+            bwt!("hello", con.send_one(&ClientHello::ReadOnly(AuthRead::Anonymous)));
+            match bwt!("reply", con.receive::<AuthRead>()) {
+                AuthRead::Anonymous => (),
+                AuthRead::Local | AuthRead::Krb5 | AuthRead::Tls => {
+                    bail!("protocol error")
                 }
-                con
-            }
-            (
-                DesiredAuth::Krb5 { .. } | DesiredAuth::Local | DesiredAuth::Tls { .. },
-                Auth::Anonymous,
-            ) => {
-                bail!("requested authentication mechanism not supported")
             }
-            (
-                DesiredAuth::Local | DesiredAuth::Krb5 { .. } | DesiredAuth::Tls { .. },
-                Auth::Local { path },
-            ) => {
-                let mut con = Channel::new::<ClientCtx, TcpStream>(None, con);
-                let tok = cwt!("local token", AuthClient::token(&*path));
-                cwt!("hello", con.send_one(&ClientHello::ReadOnly(AuthRead::Local)));
-                cwt!("token", con.send_one(&tok));
-                match cwt!("reply", con.receive::<AuthRead>()) {
-                    AuthRead::Local => (),
-                    AuthRead::Krb5 | AuthRead::Anonymous | AuthRead::Tls => {
-                        bail!("protocol error")
-                    }
+            con
+        }
+        (
+            DesiredAuth::Krb5 { .. } | DesiredAuth::Local | DesiredAuth::Tls { .. },
+            Auth::Anonymous,
+        ) => {
+            bail!("requested authentication mechanism not supported")
+        }
+        (
+            DesiredAuth::Local | DesiredAuth::Krb5 { .. } | DesiredAuth::Tls { .. },
+            Auth::Local { path },
+        ) => {
+            let mut con = Channel::new::<ClientCtx, TcpStream>(None, con);
+            let tok = bwt!("local token", AuthClient::token(path));
+            bwt!("hello", con.send_one(&ClientHello::ReadOnly(AuthRead::Local)));
+            bwt!("token", con.send_one(&tok));
+            match bwt!("reply", con.receive::<AuthRead>()) {
+                AuthRead::Local => (),
+                AuthRead::Krb5 | AuthRead::Anonymous | AuthRead::Tls => {
+                    bail!("protocol error")
                 }
-                con
             }
-            (DesiredAuth::Local, Auth::Krb5 { .. } | Auth::Tls { .. }) => {
-                bail!("local auth not supported")
-            }
-            (DesiredAuth::Krb5 { .. }, Auth::Tls { .. }) => {
-                bail!("krb5 authentication is not supported")
-            }
-            (DesiredAuth::Krb5 { upn, .. }, Auth::Krb5 { spn }) => {
-                let upn = upn.as_ref().map(|s| s.as_str());
-                let hello = ClientHello::ReadOnly(AuthRead::Krb5);
-                cwt!("hello", channel::write_raw(&mut con, &hello));
-                let ctx = cwt!("k5auth", krb5_authentication(upn, &*spn, &mut con));
-                match cwt!("reply", channel::read_raw::<AuthRead, _>(&mut con)) {
-                    AuthRead::Krb5 => Channel::new(Some(K5CtxWrap::new(ctx)), con),
-                    AuthRead::Local | AuthRead::Anonymous | AuthRead::Tls => {
-                        bail!("protocol error")
-                    }
+            con
+        }
+        (DesiredAuth::Local, Auth::Krb5 { .. } | Auth::Tls { .. }) => {
+            bail!("local auth not supported")
+        }
+        (DesiredAuth::Krb5 { .. }, Auth::Tls { .. }) => {
+            bail!("krb5 authentication is not supported")
+        }
+        (DesiredAuth::Krb5 { upn, .. }, Auth::Krb5 { spn }) => {
+            let upn = upn.as_ref().map(|s| s.as_str());
+            let hello = ClientHello::ReadOnly(AuthRead::Krb5);
+            bwt!("hello", channel::write_raw(&mut con, &hello));
+            let ctx = bwt!("k5auth", krb5_authentication(upn, spn, &mut con));
+            match bwt!("reply", channel::read_raw::<AuthRead, _>(&mut con)) {
+                AuthRead::Krb5 => Channel::new(Some(K5CtxWrap::new(ctx)), con),
+                AuthRead::Local | AuthRead::Anonymous | AuthRead::Tls => {
+                    bail!("protocol error")
                 }
             }
-            (DesiredAuth::Tls { .. }, Auth::Krb5 { .. }) => {
-                bail!("tls authentication is not supported")
-            }
-            (DesiredAuth::Tls { .. }, Auth::Tls { name }) => {
-                let tls = tls.as_ref().ok_or_else(|| anyhow!("no tls cache"))?;
-                let ctx = task::block_in_place(|| tls.load(name))?;
-                let hello = ClientHello::ReadOnly(AuthRead::Tls);
-                cwt!("hello", channel::write_raw(&mut con, &hello));
-                let name = rustls::ServerName::try_from(&**name)?;
-                let tls = ctx.connect(name, con).await?;
-                let mut con = Channel::new::<
-                    ClientCtx,
-                    tokio_rustls::client::TlsStream<TcpStream>,
-                >(None, tls);
-                match cwt!("reply", con.receive::<AuthRead>()) {
-                    AuthRead::Tls => con,
-                    AuthRead::Local | AuthRead::Anonymous | AuthRead::Krb5 { .. } => {
-                        bail!("protocol error")
-                    }
+        }
+        (DesiredAuth::Tls { .. }, Auth::Krb5 { .. }) => {
+            bail!("tls authentication is not supported")
+        }
+        (DesiredAuth::Tls { .. }, Auth::Tls { name }) => {
+            let tls = tls.as_ref().ok_or_else(|| anyhow!("no tls cache"))?;
+            let ctx = task::block_in_place(|| tls.load(name))?;
+            let hello = ClientHello::ReadOnly(AuthRead::Tls);
+            bwt!("hello", channel::write_raw(&mut con, &hello));
+            let name = rustls::ServerName::try_from(&**name)?;
+            let tls = ctx.connect(name, con).await?;
+            let mut con = Channel::new::<
+                ClientCtx,
+                tokio_rustls::client::TlsStream<TcpStream>,
+            >(None, tls);
+            match bwt!("reply", con.receive::<AuthRead>()) {
+                AuthRead::Tls => con,
+                AuthRead::Local | AuthRead::Anonymous | AuthRead::Krb5 { .. } => {
+                    bail!("protocol error")
                 }
             }
-        };
-        break Ok(con);
-    }
+        }
+    })
 }
 
-type Batch = (Pooled<Vec<(usize, ToRead)>>, oneshot::Sender<Response<FromRead>>);
+/// The number of latency samples to keep for each server
+const SAMPLES: usize = 100;
+
+/// Don't hedge until we have at least this many samples
+const MIN_SAMPLES: usize = 10;
+
+/// How long to avoid a server after it fails
+const BACKOFF: Duration = Duration::from_secs(10);
+
+type Batch = (Arc<Pooled<Vec<(usize, ToRead)>>>, oneshot::Sender<Response<FromRead>>);
 
 fn partition_publishers(m: FromRead) -> Either<FromRead, Publisher> {
     match m {
@@ -173,134 +152,313 @@ fn partition_publishers(m: FromRead) -> Either<FromRead, Publisher> {
         | FromRead::GetChangeNr(_)
         | FromRead::List(_)
         | FromRead::ListMatching(_)
+        | FromRead::Metadata(_)
         | FromRead::Referral(_)
         | FromRead::Resolved(_)
+        | FromRead::Search(_)
+        | FromRead::ListPage(_)
+        | FromRead::Columns(_)
         | FromRead::Table(_) => Either::Left(m),
     }
 }
 
+// operations that can take a long time depending on how much data
+// they return
+fn is_slow(m: &ToRead) -> bool {
+    match m {
+        ToRead::List(_)
+        | ToRead::ListMatching(_)
+        | ToRead::ListPage(_, _)
+        | ToRead::ListMatchingPage(_, _)
+        | ToRead::Search(_) => true,
+        ToRead::Resolve(_)
+        | ToRead::Table(_)
+        | ToRead::Metadata(_)
+        | ToRead::Columns(_)
+        | ToRead::GetChangeNr(_) => false,
+    }
+}
+
+async fn send_batch(
+    c: &mut Channel,
+    tx_batch: &[(usize, ToRead)],
+) -> Result<Response<FromRead>> {
+    let mut timeout = max(HELLO_TO, Duration::from_micros(tx_batch.len() as u64 * 50));
+    for (_, m) in tx_batch {
+        if is_slow(m) {
+            timeout += HELLO_TO;
+        }
+        if let Err(e) = c.queue_send(m) {
+            c.clear();
+            bail!("failed to encode {:?}", e)
+        }
+    }
+    c.flush_timeout(timeout).await?;
+    let mut rx_batch = RAWFROMREADPOOL.take();
+    let mut publishers = PUBLISHERPOOL.take();
+    while rx_batch.len() < tx_batch.len() {
+        let f = c.receive_batch_fn(|m| match partition_publishers(m) {
+            Either::Left(m) => rx_batch.push(m),
+            Either::Right(p) => {
+                publishers.insert(p.id, p);
+            }
+        });
+        time::timeout(timeout, f).await??
+    }
+    let mut result = FROMREADPOOL.take();
+    result.extend(rx_batch.drain(..).enumerate().map(|(i, m)| (tx_batch[i].0, m)));
+    Ok((publishers, result))
+}
+
+// one connection to one member server. Failed batches are dropped,
+// and it's up to the dispatcher to try them somewhere else.
 async fn connection(
     mut receiver: mpsc::UnboundedReceiver<Batch>,
-    resolver: Arc<Referral>,
+    addr: SocketAddr,
+    auth: Auth,
     desired_auth: DesiredAuth,
     tls: Option<tls::CachedConnector>,
 ) {
     let mut con: Option<Channel> = None;
-    let mut bad_addrs: FxHashSet<SocketAddr> = HashSet::default();
-    'main: loop {
-        match receiver.next().await {
-            None => break,
-            Some((tx_batch, reply)) => {
-                let mut tries: usize = 0;
-                'batch: loop {
-                    if tries > 3 {
+    while let Some((tx_batch, reply)) = receiver.next().await {
+        // someone else already answered it
+        if reply.is_canceled() {
+            continue;
+        }
+        // the server may have closed an idle connection, so try a
+        // new connection once before giving up
+        let mut tries = 0;
+        while tries < 2 {
+            tries += 1;
+            let c = match con {
+                Some(ref mut c) => c,
+                None => match connect(addr, &auth, &desired_auth, &tls).await {
+                    Ok(c) => con.insert(c),
+                    Err(e) => {
+                        warn!("failed to connect to resolver server {} {}", addr, e);
                         break;
                     }
-                    if tries > 1 {
-                        let wait = thread_rng().gen_range(1..12);
-                        time::sleep(Duration::from_secs(wait)).await
+                },
+            };
+            match send_batch(c, &tx_batch).await {
+                Ok(r) => {
+                    let _ = reply.send(r);
+                    break;
+                }
+                Err(e) => {
+                    warn!("read connection to {} failed {}", addr, e);
+                    con = None;
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Server {
+    addr: SocketAddr,
+    to: mpsc::UnboundedSender<Batch>,
+    samples: VecDeque<Duration>,
+    mean: Option<Duration>,
+    failed: Option<Instant>,
+}
+
+impl Server {
+    fn healthy(&self, now: Instant) -> bool {
+        self.failed.map(|t| now - t > BACKOFF).unwrap_or(true)
+    }
+
+    fn record(&mut self, latency: Duration) {
+        if self.samples.len() >= SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(latency);
+        self.mean =
+            Some(self.samples.iter().sum::<Duration>() / self.samples.len() as u32);
+    }
+
+    fn percentile(&self, p: f64) -> Option<Duration> {
+        if self.samples.len() < MIN_SAMPLES {
+            return None;
+        }
+        let mut sorted = self.samples.iter().copied().collect::<Vec<_>>();
+        sorted.sort();
+        let i = ((p / 100.) * sorted.len() as f64).ceil() as usize;
+        Some(sorted[min(sorted.len(), max(1, i)) - 1])
+    }
+}
+
+#[derive(Debug)]
+struct Servers {
+    servers: Vec<Server>,
+    hedge_percentile: Option<f64>,
+}
+
+impl Servers {
+    // healthy servers first, fastest first. Servers we haven't heard
+    // from yet come first so we find out how fast they are, ties are
+    // broken randomly so load is spread out.
+    fn ranked(&self, exclude: &FxHashSet<usize>) -> Vec<usize> {
+        let now = Instant::now();
+        let mut ranked =
+            (0..self.servers.len()).filter(|i| !exclude.contains(i)).collect::<Vec<_>>();
+        ranked.shuffle(&mut thread_rng());
+        ranked.sort_by_key(|i| {
+            let s = &self.servers[*i];
+            (!s.healthy(now), s.mean.unwrap_or(Duration::ZERO))
+        });
+        ranked
+    }
+}
+
+fn record(servers: &Mutex<Servers>, i: usize, start: Instant, sample: bool, ok: bool) {
+    let mut servers = servers.lock();
+    let s = &mut servers.servers[i];
+    // a failure took at least this long, which should count against
+    // the server once it's healthy again
+    if sample {
+        s.record(start.elapsed());
+    }
+    s.failed = if ok { None } else { Some(Instant::now()) };
+}
+
+// send the batch to the fastest server, hedging to the next fastest
+// if it's slow, and falling back to the others if it fails.
+async fn dispatch(
+    servers: Arc<Mutex<Servers>>,
+    batch: Arc<Pooled<Vec<(usize, ToRead)>>>,
+    reply: oneshot::Sender<Response<FromRead>>,
+) {
+    // slow operations would throw off the latency stats, and we don't
+    // want to duplicate them
+    let sample = !batch.iter().any(|(_, m)| is_slow(m));
+    let mut failed = FxHashSet::default();
+    let mut tries = 0;
+    while tries < 3 {
+        let (first, second, hedge_after) = {
+            let servers = servers.lock();
+            let ranked = servers.ranked(&failed);
+            let hedge_after = match servers.hedge_percentile {
+                Some(p) if sample && ranked.len() > 1 => {
+                    servers.servers[ranked[0]].percentile(p)
+                }
+                None | Some(_) => None,
+            };
+            match ranked.first() {
+                None => (None, None, None),
+                Some(i) => (Some(*i), ranked.get(1).copied(), hedge_after),
+            }
+        };
+        let first = match first {
+            Some(i) => i,
+            None => {
+                // every server failed, wait a bit and try them all again
+                tries += 1;
+                failed.clear();
+                let wait = thread_rng().gen_range(1..12);
+                time::sleep(Duration::from_secs(wait)).await;
+                continue;
+            }
+        };
+        let send = |i: usize| {
+            let (tx, rx) = oneshot::channel();
+            let _ = servers.lock().servers[i].to.unbounded_send((batch.clone(), tx));
+            (Instant::now(), rx)
+        };
+        let (start, mut rx) = send(first);
+        let hedge = async {
+            match (second, hedge_after) {
+                (Some(_), Some(d)) => time::sleep(d).await,
+                (None, _) | (_, None) => future::pending().await,
+            }
+        };
+        select_biased! {
+            r = (&mut rx).fuse() => {
+                record(&servers, first, start, sample, r.is_ok());
+                match r {
+                    Ok(r) => {
+                        let _ = reply.send(r);
+                        return;
                     }
-                    tries += 1;
-                    let c = match con {
-                        Some(ref mut c) => c,
-                        None => {
-                            match connect(&mut bad_addrs, &resolver, &desired_auth, &tls)
-                                .await
-                            {
-                                Ok(c) => {
-                                    con = Some(c);
-                                    con.as_mut().unwrap()
-                                }
-                                Err(e) => {
-                                    con = None;
-                                    warn!("connect_read failed: {}", e);
-                                    continue;
-                                }
-                            }
-                        }
-                    };
-                    let mut timeout =
-                        max(HELLO_TO, Duration::from_micros(tx_batch.len() as u64 * 50));
-                    for (_, m) in &*tx_batch {
-                        match m {
-                            ToRead::List(_) | ToRead::ListMatching(_) => {
-                                timeout += HELLO_TO;
-                            }
-                            _ => (),
-                        }
-                        match c.queue_send(m) {
-                            Ok(()) => (),
-                            Err(e) => {
-                                warn!("failed to encode {:?}", e);
-                                c.clear();
-                                continue 'main;
-                            }
-                        }
+                    Err(_) => {
+                        failed.insert(first);
                     }
-                    match c.flush_timeout(timeout).await {
-                        Err(e) => {
-                            warn!("read connection send error: {}", e);
-                            con = None;
+                }
+            }
+            () = hedge.fuse() => {
+                // second is always Some if the hedge fires
+                let second = second.unwrap();
+                {
+                    let servers = servers.lock();
+                    let from = servers.servers[first].addr;
+                    let to = servers.servers[second].addr;
+                    debug!("hedging read from {} to {}", from, to);
+                }
+                let (hstart, hrx) = send(second);
+                let mut rxs = FuturesUnordered::new();
+                rxs.push(rx.map(move |r| (first, start, r)).boxed());
+                rxs.push(hrx.map(move |r| (second, hstart, r)).boxed());
+                while let Some((i, t, r)) = rxs.next().await {
+                    record(&servers, i, t, sample, r.is_ok());
+                    match r {
+                        Err(_) => {
+                            failed.insert(i);
                         }
-                        Ok(()) => {
-                            let mut rx_batch = RAWFROMREADPOOL.take();
-                            let mut publishers = PUBLISHERPOOL.take();
-                            while rx_batch.len() < tx_batch.len() {
-                                let f =
-                                    c.receive_batch_fn(|m| {
-                                        match partition_publishers(m) {
-                                            Either::Left(m) => rx_batch.push(m),
-                                            Either::Right(p) => {
-                                                publishers.insert(p.id, p);
-                                            }
-                                        }
-                                    });
-                                match time::timeout(timeout, f).await {
-                                    Ok(Ok(())) => (),
-                                    Ok(Err(e)) => {
-                                        warn!("read connection failed {}", e);
-                                        con = None;
-                                        continue 'batch;
-                                    }
-                                    Err(e) => {
-                                        warn!("read connection timeout: {}", e);
-                                        con = None;
-                                        continue 'batch;
-                                    }
-                                }
+                        Ok(r) => {
+                            let _ = reply.send(r);
+                            // the loser took at least this long. Dropping
+                            // it's reply tells it not to bother if it
+                            // hasn't started yet.
+                            if sample && !rxs.is_empty() {
+                                let (loser, t) = if i == first {
+                                    (second, hstart)
+                                } else {
+                                    (first, start)
+                                };
+                                servers.lock().servers[loser].record(t.elapsed());
                             }
-                            let mut result = FROMREADPOOL.take();
-                            result.extend(
-                                rx_batch
-                                    .drain(..)
-                                    .enumerate()
-                                    .map(|(i, m)| (tx_batch[i].0, m)),
-                            );
-                            let _ = reply.send((publishers, result));
-                            break;
+                            return;
                         }
                     }
                 }
             }
         }
     }
+    warn!("can't reach any resolver servers");
 }
 
 #[derive(Debug, Clone)]
-pub(super) struct ReadClient(mpsc::UnboundedSender<Batch>);
+pub(super) struct ReadClient(Arc<Mutex<Servers>>);
 
 impl ReadClient {
     pub(super) fn new(
         resolver: Arc<Referral>,
         desired_auth: DesiredAuth,
         tls: Option<tls::CachedConnector>,
+        hedge_percentile: Option<f64>,
     ) -> Self {
-        let (to_tx, to_rx) = mpsc::unbounded();
-        task::spawn(async move {
-            connection(to_rx, resolver, desired_auth, tls).await;
-            info!("read task shutting down")
-        });
-        Self(to_tx)
+        let servers = resolver
+            .addrs
+            .iter()
+            .map(|(addr, auth)| {
+                let (to_tx, to_rx) = mpsc::unbounded();
+                let (addr, auth) = (*addr, auth.clone());
+                let desired_auth = desired_auth.clone();
+                let tls = tls.clone();
+                task::spawn(async move {
+                    connection(to_rx, addr, auth, desired_auth, tls).await;
+                    info!("read task for {} shutting down", addr)
+                });
+                Server {
+                    addr,
+                    to: to_tx,
+                    samples: VecDeque::new(),
+                    mean: None,
+                    failed: None,
+                }
+            })
+            .collect();
+        Self(Arc::new(Mutex::new(Servers { servers, hedge_percentile })))
     }
 
     pub(crate) fn send(
@@ -308,7 +466,7 @@ impl ReadClient {
         batch: Pooled<Vec<(usize, ToRead)>>,
     ) -> ResponseChan<FromRead> {
         let (tx, rx) = oneshot::channel();
-        let _ = self.0.unbounded_send((batch, tx));
+        task::spawn(dispatch(self.0.clone(), Arc::new(batch), tx));
         rx
     }
 }
@@ -29,7 +29,14 @@ use netidx_core::pool::Pool;
 use parking_lot::RwLock;
 use rand::{thread_rng, Rng};
 use std::{
-    cmp::max, collections::HashMap, fmt::Debug, net::SocketAddr, sync::Arc,
+    cmp::max,
+    collections::HashMap,
+    fmt::Debug,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
     time::Duration,
 };
 use tokio::{
@@ -64,12 +71,63 @@ macro_rules! wt {
 const HB: Duration = Duration::from_secs(TTL / 2);
 const LINGER: Duration = Duration::from_secs(TTL / 10);
 
+// What we've told the resolver, so it can be replayed if it forgets
+#[derive(Debug, Default)]
+struct Published {
+    paths: HashMap<Path, ToWrite>,
+    metadata: HashMap<Path, ToWrite>,
+}
+
+// How writes are distributed among the members of the cluster. If
+// the cluster replicates writes we only write to one member, the
+// primary, otherwise we write to all of them.
+#[derive(Debug)]
+struct Mode {
+    known: AtomicBool,
+    replicated: AtomicBool,
+    primary: AtomicUsize,
+    members: usize,
+}
+
+impl Mode {
+    fn new(members: usize) -> Self {
+        Self {
+            known: AtomicBool::new(false),
+            replicated: AtomicBool::new(false),
+            primary: AtomicUsize::new(thread_rng().gen_range(0..max(1, members))),
+            members,
+        }
+    }
+
+    fn set(&self, replicated: bool) {
+        self.replicated.store(replicated, Ordering::Relaxed);
+        self.known.store(true, Ordering::Relaxed);
+    }
+
+    // true if we write to all the members
+    fn fan_out(&self) -> bool {
+        self.known.load(Ordering::Relaxed) && !self.replicated.load(Ordering::Relaxed)
+    }
+
+    fn primary(&self) -> usize {
+        self.primary.load(Ordering::Relaxed)
+    }
+
+    fn failover(&self, from: usize) {
+        let to = (from + 1) % self.members;
+        let _ =
+            self.primary.compare_exchange(from, to, Ordering::Relaxed, Ordering::Relaxed);
+    }
+}
+
 struct Connection {
     con: Option<Channel>,
+    idx: usize,
+    mode: Arc<Mode>,
     resolver_addr: SocketAddr,
     resolver_auth: Auth,
     write_addr: SocketAddr,
-    published: Arc<RwLock<HashMap<Path, ToWrite>>>,
+    published: Arc<RwLock<Published>>,
     secrets: Arc<RwLock<FxHashMap<SocketAddr, u128>>>,
     security_context: Option<K5CtxWrap<ClientCtx>>,
     tls: Option<tls::CachedConnector>,
@@ -91,7 +149,11 @@ impl Connection {
 
     async fn republish(&mut self, con: &mut Channel, ttl_expired: bool) -> Result<()> {
         let mut names = REPUB.take();
-        names.extend(self.published.read().values().cloned());
+        {
+            let published = self.published.read();
+            names.extend(published.paths.values().cloned());
+            names.extend(published.metadata.values().cloned());
+        }
         let len = names.len();
         if len == 0 {
             info!("connected to resolver {:?} for write", self.resolver_addr);
@@ -330,6 +392,7 @@ impl Connection {
                 }
             };
         debug!("write_con resolver hello {:?}", r);
+        self.mode.set(r.replicated);
         if ownership_check {
             let secret: Secret = wt!("recv secret", con.receive())??;
             {
@@ -360,7 +423,13 @@ impl Connection {
     }
 
     async fn send_heartbeat(&mut self) {
-        for _ in 0..3 {
+        if !self.mode.fan_out() && self.mode.primary() != self.idx {
+            // the primary keeps our session alive
+            self.con = None;
+            return;
+        }
+        let tries = if self.mode.fan_out() { 3 } else { 1 };
+        for _ in 0..tries {
             match self.con {
                 Some(ref mut c) => match c.send_one(&ToWrite::Heartbeat).await {
                     Ok(()) => break,
@@ -373,6 +442,10 @@ impl Connection {
                     Ok(()) => break,
                     Err(e) => {
                         self.handle_failed_connect(e);
+                        if !self.mode.fan_out() {
+                            self.mode.failover(self.idx);
+                            break;
+                        }
                         let wait = thread_rng().gen_range(1..12);
                         time::sleep(Duration::from_secs(wait)).await;
                     }
@@ -384,12 +457,15 @@ impl Connection {
     async fn process_batch(&mut self, (tx_batch, reply): ArcBatch) -> Result<()> {
         self.active = true;
         let mut tries: usize = 0;
+        // when writing to one member it's better to fail over quickly
+        let fan_out = self.mode.fan_out();
+        let max_tries = if fan_out { 3 } else { 1 };
         'batch: loop {
-            if tries > 3 {
+            if tries > max_tries {
                 self.degraded = true;
                 bail!("abandoning batch");
             }
-            if tries > 0 {
+            if tries > 0 && fan_out {
                 let wait = thread_rng().gen_range(1..12);
                 time::sleep(Duration::from_secs(wait)).await;
             }
@@ -440,6 +516,18 @@ impl Connection {
                                     self.degraded = true;
                                 }
                             },
+                            ToWrite::SetMetadata(p, md)
+                                if matches!(rx, FromWrite::Published) =>
+                            {
+                                let mut published = self.published.write();
+                                // the path may have been unpublished
+                                // while the request was in flight
+                                if md.is_empty() || !published.paths.contains_key(p) {
+                                    published.metadata.remove(p);
+                                } else {
+                                    published.metadata.insert(p.clone(), tx.clone());
+                                }
+                            }
                             _ => (),
                         }
                     }
@@ -459,10 +547,12 @@ impl Connection {
 
     async fn start(
         receiver: mpsc::Receiver<ArcBatch>,
+        idx: usize,
+        mode: Arc<Mode>,
         resolver_addr: SocketAddr,
         resolver_auth: Auth,
         write_addr: SocketAddr,
-        published: Arc<RwLock<HashMap<Path, ToWrite>>>,
+        published: Arc<RwLock<Published>>,
         desired_auth: DesiredAuth,
         secrets: Arc<RwLock<FxHashMap<SocketAddr, u128>>>,
         tls: Option<tls::CachedConnector>,
@@ -478,6 +568,8 @@ impl Connection {
             security_context: None,
             tls,
             con: None,
+            idx,
+            mode,
             degraded: false,
             active: false,
             heartbeat: time::interval_at(now + HB, HB),
@@ -521,11 +613,11 @@ async fn write_mgr(
     write_addr: SocketAddr,
     tls: Option<tls::CachedConnector>,
 ) -> Result<()> {
-    let published: Arc<RwLock<HashMap<Path, ToWrite>>> =
-        Arc::new(RwLock::new(HashMap::new()));
+    let published = Arc::new(RwLock::new(Published::default()));
+    let mode = Arc::new(Mode::new(resolver.addrs.len()));
     let mut senders = {
         let mut senders = Vec::new();
-        for (addr, auth) in resolver.addrs.iter() {
+        for (idx, (addr, auth)) in resolver.addrs.iter().enumerate() {
             let (sender, receiver) = mpsc::channel(100);
             let addr = *addr;
             let auth = auth.clone();
@@ -533,10 +625,13 @@ async fn write_mgr(
             let desired_auth = desired_auth.clone();
             let secrets = secrets.clone();
             let tls = tls.clone();
+            let mode = mode.clone();
             senders.push(sender);
             task::spawn(async move {
                 Connection::start(
                     receiver,
+                    idx,
+                    mode,
                     addr,
                     auth,
                     write_addr,
@@ -562,24 +657,75 @@ async fn write_mgr(
                     | ToWrite::PublishDefault(p)
                     | ToWrite::PublishWithFlags(p, _)
                     | ToWrite::PublishDefaultWithFlags(p, _) => {
-                        published.insert(p.clone(), tx.clone());
+                        published.paths.insert(p.clone(), tx.clone());
                     }
-                    ToWrite::Unpublish(_)
-                    | ToWrite::UnpublishDefault(_)
+                    ToWrite::Unpublish(p) | ToWrite::UnpublishDefault(p) => {
+                        // the resolver drops metadata along with the path
+                        published.metadata.remove(p);
+                    }
+                    // metadata is recorded once the resolver accepts it,
+                    // so a rejected change isn't replayed forever
+                    ToWrite::SetMetadata(_, _)
                     | ToWrite::Clear
-                    | ToWrite::Heartbeat => (),
+                    | ToWrite::Heartbeat
+                    | ToWrite::RegisterReferral(_) => (),
                 }
             }
         }
-        for s in senders.iter_mut() {
-            let (tx, rx) = oneshot::channel();
-            let _ = s.send((Arc::clone(&tx_batch), tx)).await;
-            waiters.push(rx);
-        }
-        match select_ok(waiters).await {
-            Err(e) => warn!("write_mgr: write failed on all writers {}", e),
-            Ok((rx_batch, _)) => {
-                let _ = reply.send(rx_batch);
+        if mode.fan_out() {
+            for s in senders.iter_mut() {
+                let (tx, rx) = oneshot::channel();
+                let _ = s.send((Arc::clone(&tx_batch), tx)).await;
+                waiters.push(rx);
+            }
+            match select_ok(waiters).await {
+                Err(e) => warn!("write_mgr: write failed on all writers {}", e),
+                Ok((rx_batch, _)) => {
+                    let _ = reply.send(rx_batch);
+                }
+            }
+        } else {
+            // until we know the cluster isn't replicated write to one
+            // member at a time, failing over to the next on error
+            let mut sent = Vec::new();
+            let mut result = None;
+            for _ in 0..senders.len() {
+                let i = mode.primary();
+                let (tx, rx) = oneshot::channel();
+                let _ = senders[i].send((Arc::clone(&tx_batch), tx)).await;
+                sent.push(i);
+                match rx.await {
+                    Ok(rx_batch) => {
+                        result = Some(rx_batch);
+                        break;
+                    }
+                    Err(_) if mode.fan_out() => break,
+                    Err(_) => mode.failover(i),
+                }
+            }
+            if mode.fan_out() {
+                // not replicated, so the others need the batch too
+                for (i, s) in senders.iter_mut().enumerate() {
+                    if !sent.contains(&i) {
+                        let (tx, rx) = oneshot::channel();
+                        let _ = s.send((Arc::clone(&tx_batch), tx)).await;
+                        waiters.push(rx);
+                    }
+                }
+            }
+            match result {
+                Some(rx_batch) => {
+                    let _ = reply.send(rx_batch);
+                }
+                None if waiters.is_empty() => {
+                    warn!("write_mgr: write failed on all writers")
+                }
+                None => match select_ok(waiters).await {
+                    Err(e) => warn!("write_mgr: write failed on all writers {}", e),
+                    Ok((rx_batch, _)) => {
+                        let _ = reply.send(rx_batch);
+                    }
+                },
             }
         }
     }
@@ -27,6 +27,7 @@ bitflags! {
         const LIST             = 0x08;
         const PUBLISH          = 0x10;
         const PUBLISH_DEFAULT  = 0x20;
+        const REFERRAL         = 0x40;
     }
 }
 
@@ -59,9 +60,12 @@ impl TryFrom<&str> for Permissions {
                 'd' => {
                     p |= Permissions::PUBLISH_DEFAULT;
                 }
+                'r' => {
+                    p |= Permissions::REFERRAL;
+                }
                 c => {
                     return Err(anyhow!(
-                        "unrecognized permission bit {}, valid bits are !swlpd",
+                        "unrecognized permission bit {}, valid bits are !swlpdr",
                         c
                     ))
                 }
@@ -2,6 +2,7 @@ use crate::{
     chars::Chars,
     path::Path,
     protocol::resolver::{self, Referral},
+    resolver_client::DesiredAuth,
     tls, utils,
 };
 use anyhow::Result;
@@ -102,7 +103,7 @@ impl Default for PMap {
 
 /// The on disk format, encoded as JSON
 pub(crate) mod file {
-    use super::{super::config::check_addrs, resolver, Chars, PMap};
+    use super::{super::config::check_addrs, resolver, Chars, DesiredAuth, PMap};
     use crate::{path::Path, pool::Pooled};
     use anyhow::Result;
     use std::net::{SocketAddr, IpAddr, Ipv4Addr};
@@ -205,6 +206,15 @@ pub(crate) mod file {
         pub(super) reader_ttl: u64,
         pub(super) writer_ttl: u64,
         pub(super) id_map_command: Option<String>,
+        #[serde(default)]
+        pub(super) replica_addr: Option<SocketAddr>,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    pub(super) struct Register {
+        pub(super) ttl: u16,
+        pub(super) auth: DesiredAuth,
     }
 
     #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -212,6 +222,8 @@ pub(crate) mod file {
     pub(super) struct Config {
         pub(super) children: Vec<Referral>,
         pub(super) parent: Option<Referral>,
+        #[serde(default)]
+        pub(super) register: Option<Register>,
         pub(super) member_servers: Vec<MemberServer>,
         pub(super) perms: PMap,
     }
@@ -229,11 +241,24 @@ pub struct MemberServer {
     pub(super) writer_ttl: Duration,
     #[allow(dead_code)]
     pub(crate) id_map_command: Option<String>, // default /usr/bin/id
+    pub(super) replica_addr: Option<SocketAddr>,
+}
+
+/// How this cluster registers itself as a child of it's parent
+#[derive(Debug, Clone)]
+pub(super) struct Register {
+    /// the registration expires if it isn't renewed within ttl seconds
+    pub(super) ttl: u16,
+    /// the authentication mechanism to use with the parent. The
+    /// parent only accepts registrations from authenticated users
+    /// that have the referral permission for the path.
+    pub(super) auth: DesiredAuth,
 }
 
 #[derive(Debug, Clone)]
 pub struct Config {
     pub(super) parent: Option<Referral>,
+    pub(super) register: Option<Register>,
     pub(super) children: BTreeMap<Path, Referral>,
     pub(super) perms: PMap,
     pub member_servers: Vec<MemberServer>,
@@ -249,6 +274,27 @@ impl Config {
             .collect::<Vec<_>>();
         check_addrs(&addrs)?;
         let parent = cfg.parent.map(|r| r.check(Some(&addrs))).transpose()?;
+        let register = match cfg.register {
+            None => None,
+            Some(r) => {
+                if parent.is_none() {
+                    bail!("a parent is required to register with")
+                }
+                if r.ttl == 0 {
+                    bail!("register ttl must be non zero")
+                }
+                match r.auth {
+                    DesiredAuth::Tls { .. } => {
+                        bail!("tls auth is not supported for registration")
+                    }
+                    DesiredAuth::Anonymous => {
+                        bail!("the parent won't accept anonymous registrations")
+                    }
+                    DesiredAuth::Krb5 { .. } | DesiredAuth::Local => (),
+                }
+                Some(Register { ttl: r.ttl, auth: r.auth })
+            }
+        };
         let children = {
             let root = parent.as_ref().map(|r| r.path.as_ref()).unwrap_or("/");
             let children = cfg
@@ -356,10 +402,19 @@ impl Config {
                     reader_ttl: Duration::from_secs(m.reader_ttl),
                     writer_ttl: Duration::from_secs(m.writer_ttl),
                     id_map_command: m.id_map_command,
+                    replica_addr: m.replica_addr,
                 })
             })
             .collect::<Result<Vec<_>>>()?;
-        Ok(Config { parent, children, perms: cfg.perms, member_servers })
+        if member_servers.iter().any(|m| m.replica_addr.is_some()) {
+            if !member_servers.iter().all(|m| m.replica_addr.is_some()) {
+                bail!("either all member servers or none must have a replica_addr")
+            }
+            if !member_servers.iter().all(|m| matches!(m.auth, Auth::Anonymous)) {
+                bail!("replication is only supported with anonymous authentication")
+            }
+        }
+        Ok(Config { parent, register, children, perms: cfg.perms, member_servers })
     }
 
     /// Load the cluster config from the specified file.
@@ -367,6 +422,11 @@ impl Config {
         Config::parse(&read_to_string(file)?)
     }
 
+    /// True if the member servers replicate writes among themselves
+    pub(super) fn replicated(&self) -> bool {
+        self.member_servers.iter().all(|m| m.replica_addr.is_some())
+    }
+
     pub(super) fn root(&self) -> &str {
         self.parent.as_ref().map(|r| r.path.as_ref()).unwrap_or("/")
     }
@@ -1,5 +1,7 @@
 pub(crate) mod auth;
 pub mod config;
+mod register;
+mod replica;
 pub(crate) mod secctx;
 mod shard_store;
 mod store;
@@ -31,12 +33,13 @@ use log::{debug, error, info, warn};
 use netidx_core::{pack::BoundedBytes, utils::make_sha3_token};
 use parking_lot::{Mutex, RwLock};
 use rand::{thread_rng, Rng};
+use replica::Replica;
 use secctx::{K5SecData, LocalSecData, SecCtx, TlsSecData};
 use shard_store::Store;
 use std::{
     collections::{hash_map::Entry, HashMap, HashSet},
     fmt::Debug,
-    mem,
+    iter, mem,
     net::SocketAddr,
     sync::Arc,
     time::Duration,
@@ -127,7 +130,7 @@ impl Clinfos {
             })
             .await;
         if cleanup {
-            ctx.store.handle_clear(uifo.clone(), publisher.clone()).await?;
+            ctx.clear_client(uifo, publisher).await?;
             self.0.lock().remove(&publisher.addr);
         }
         Ok(())
@@ -192,7 +195,7 @@ impl Clinfos {
             match r {
                 R::Finished(publisher, t, rx) => break Ok((publisher, t, rx)),
                 R::ClearClient(publisher) => {
-                    ctx.store.handle_clear(uifo.clone(), publisher).await?;
+                    ctx.clear_client(uifo, &publisher).await?;
                     self.0.lock().remove(&hello.write_addr);
                 }
             }
@@ -214,9 +217,28 @@ struct Ctx {
     cfg: MemberServer,
     id: SocketAddr,
     store: Store,
+    replica: Option<Replica>,
     delay_reads: Option<Instant>,
 }
 
+impl Ctx {
+    async fn clear_client(
+        &self,
+        uifo: &Arc<UserInfo>,
+        publisher: &Arc<Publisher>,
+    ) -> Result<()> {
+        match &self.replica {
+            None => self.store.handle_clear(uifo.clone(), publisher.clone()).await,
+            Some(replica) => {
+                if let Err(e) = replica.close(publisher).await {
+                    warn!("failed to close replicated session {}", e)
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
 async fn client_loop_write(
     ctx: Arc<Ctx>,
     connection_id: CId,
@@ -280,17 +302,38 @@ async fn client_loop_write(
                                 ToWrite::Publish(_)
                                     | ToWrite::PublishDefault(_)
                                     | ToWrite::PublishWithFlags(_, _)
-                                    | ToWrite::PublishDefaultWithFlags(_, _) =>
+                                    | ToWrite::PublishDefaultWithFlags(_, _)
+                                    | ToWrite::SetMetadata(_, _) =>
                                     c.queue_send(&FromWrite::Published)?,
                                 ToWrite::Unpublish(_) =>
                                     c.queue_send(&FromWrite::Unpublished)?,
                                 ToWrite::UnpublishDefault(_) =>
                                     c.queue_send(&FromWrite::Unpublished)?,
+                                // clear doesn't remove referrals
+                                m @ ToWrite::RegisterReferral(_) => {
+                                    let m = iter::once(m);
+                                    match &ctx.replica {
+                                        Some(replica) => {
+                                            for r in replica.write(&publisher, m).await? {
+                                                c.queue_send(&r)?
+                                            }
+                                        }
+                                        None => ctx.store.handle_batch_write(
+                                            Some(&mut *c),
+                                            uifo.clone(),
+                                            publisher.clone(),
+                                            m
+                                        ).await?,
+                                    }
+                                }
                                 ToWrite::Clear => {
-                                    ctx.store.handle_clear(
-                                        uifo.clone(),
-                                        publisher.clone()
-                                    ).await?;
+                                    match &ctx.replica {
+                                        Some(replica) => replica.clear(&publisher).await?,
+                                        None => ctx.store.handle_clear(
+                                            uifo.clone(),
+                                            publisher.clone()
+                                        ).await?,
+                                    }
                                     c.queue_send(&FromWrite::Unpublished)?
                                 }
                             }
@@ -298,7 +341,22 @@ async fn client_loop_write(
                         c.flush().await?;
                         batch = Pooled::orphan(rest);
                     }
-                    if let Err(e) = ctx.store.handle_batch_write(
+                    if let Some(replica) = &ctx.replica {
+                        match replica.write(&publisher, batch.drain(..)).await {
+                            Ok(replies) => {
+                                for m in replies.iter() {
+                                    c.queue_send(m)?
+                                }
+                                c.flush().await?
+                            }
+                            Err(e) => {
+                                drop(con);
+                                ctx.ctracker.close(connection_id);
+                                ctx.clinfos.remove(&ctx, &publisher, &uifo).await?;
+                                bail!("replicated write failed {}", e)
+                            }
+                        }
+                    } else if let Err(e) = ctx.store.handle_batch_write(
                         Some(c),
                         uifo.clone(),
                         publisher.clone(),
@@ -411,6 +469,7 @@ async fn write_client_anonymous_auth(
         ttl: ctx.cfg.writer_ttl.as_secs(),
         ttl_expired,
         resolver_id: ctx.id,
+        replicated: ctx.replica.is_some(),
         auth: AuthWrite::Anonymous,
     };
     info!("hello_write accepting Anonymous authentication");
@@ -441,6 +500,7 @@ async fn write_client_local_auth(
         ttl: ctx.cfg.writer_ttl.as_secs(),
         ttl_expired: true, // re auth always clears
         resolver_id: ctx.id,
+        replicated: ctx.replica.is_some(),
         auth: AuthWrite::Local,
     };
     debug!("hello_write sending {:?}", h);
@@ -471,6 +531,7 @@ async fn write_client_reuse_local(
         ttl: ctx.cfg.writer_ttl.as_secs(),
         ttl_expired,
         resolver_id: ctx.id,
+        replicated: ctx.replica.is_some(),
         auth: AuthWrite::Reuse,
     };
     match time::timeout(ctx.cfg.hello_timeout, con.send_one(&h)).await {
@@ -502,6 +563,7 @@ async fn write_client_krb5_auth(
         ttl: ctx.cfg.writer_ttl.as_secs(),
         ttl_expired: true, // re auth always clears
         resolver_id: ctx.id,
+        replicated: ctx.replica.is_some(),
         auth: AuthWrite::Krb5 { spn: Chars::from("") },
     };
     debug!("hello_write sending {:?}", h);
@@ -538,6 +600,7 @@ async fn write_client_reuse_krb5(
         ttl: ctx.cfg.writer_ttl.as_secs(),
         ttl_expired,
         resolver_id: ctx.id,
+        replicated: ctx.replica.is_some(),
         auth: AuthWrite::Reuse,
     };
     info!("hello_write reusing krb5 context");
@@ -586,6 +649,7 @@ async fn write_client_tls_auth(
         ttl: ctx.cfg.writer_ttl.as_secs(),
         ttl_expired: true,
         resolver_id: ctx.id,
+        replicated: ctx.replica.is_some(),
         auth: AuthWrite::Tls { name: Chars::from("") },
     };
     debug!("hello_write sending {:?}", h);
@@ -619,6 +683,7 @@ async fn write_client_reuse_tls(
         ttl: ctx.cfg.writer_ttl.as_secs(),
         ttl_expired,
         resolver_id: ctx.id,
+        replicated: ctx.replica.is_some(),
         auth: AuthWrite::Reuse,
     };
     info!("hello_write reusing tls context");
@@ -669,6 +734,12 @@ async fn hello_client_write(
             SecCtx::Anonymous => bail!(NO),
         },
     };
+    if let Some(replica) = &ctx.replica {
+        if let Err(e) = replica.open(&publisher).await {
+            ctx.clinfos.remove(&ctx, &publisher, &uifo).await?;
+            bail!("failed to open replicated session {}", e)
+        }
+    }
     Ok(client_loop_write(ctx, connection_id, con, server_stop, rx_stop, uifo, publisher)
         .await?)
 }
@@ -784,6 +855,11 @@ async fn hello_client(
                     bail!("no read clients allowed yet");
                 }
             }
+            if let Some(replica) = &ctx.replica {
+                if !replica.synced() {
+                    bail!("not yet synced with the cluster");
+                }
+            }
             Ok(hello_client_read(ctx, s, server_stop, hello).await?)
         }
         ClientHello::WriteOnly(hello) => {
@@ -800,6 +876,7 @@ async fn server_loop(
     id: usize,
 ) -> Result<()> {
     debug!("server task start I am id: {}", id);
+    let member_id = id;
     let member = cfg.member_servers[id].clone();
     debug!("my member config {:?}", member);
     let delay_reads =
@@ -814,6 +891,12 @@ async fn server_loop(
         secctx.clone(),
         id,
     );
+    let replica = if cfg.replicated() {
+        debug!("starting replica");
+        Some(Replica::start(&cfg, member_id, store.clone()).await?)
+    } else {
+        None
+    };
     let listen_addr = SocketAddr::new(member.bind_addr, id.port());
     debug!("creating tcp listener on {:?}", listen_addr);
     let listener = TcpListener::bind(listen_addr).await?;
@@ -825,6 +908,7 @@ async fn server_loop(
         id,
         delay_reads,
         store,
+        replica,
     });
     let mut stop = stop.fuse();
     let mut client_stops: Vec<oneshot::Sender<()>> = Vec::new();
@@ -832,6 +916,7 @@ async fn server_loop(
     debug!("signaling ready");
     let mut listen_addr = listener.local_addr()?;
     listen_addr.set_ip(id.ip());
+    let _register = register::start(&cfg, &ctx.cfg, listen_addr).await?;
     let _ = ready.send(listen_addr);
     loop {
         select_biased! {
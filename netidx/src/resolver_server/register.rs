@@ -0,0 +1,145 @@
+//! Registration of a cluster as a child of it's parent, so the parent
+//! doesn't need to be configured with it, and restarted when it's
+//! added.
+use super::config::{Config, MemberServer};
+use crate::{
+    channel::{self, Channel},
+    config::Config as ClientConfig,
+    pack::BoundedBytes,
+    path::Path,
+    pool::Pooled,
+    protocol::{
+        publisher::Hello,
+        resolver::{AuthChallenge, HashMethod, Referral},
+    },
+    publisher::BindCfg,
+    resolver_client::ResolverWrite,
+    utils,
+};
+use anyhow::Result;
+use cross_krb5::ServerCtx;
+use futures::{channel::oneshot, prelude::*, select_biased};
+use fxhash::FxHashMap;
+use log::{debug, info, warn};
+use parking_lot::RwLock;
+use std::{cmp::max, net::SocketAddr, sync::Arc, time::Duration};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    task, time,
+};
+
+const HELLO_TIMEOUT: Duration = Duration::from_secs(10);
+
+// the parent checks that we own our write address by connecting to
+// it and asking us to prove we know the secret it gave us
+async fn ownership_check(
+    mut con: TcpStream,
+    secrets: Arc<RwLock<FxHashMap<SocketAddr, u128>>>,
+) -> Result<()> {
+    channel::write_raw(&mut con, &3u64).await?;
+    if channel::read_raw::<u64, _>(&mut con).await? != 3 {
+        bail!("incompatible protocol version")
+    }
+    let id = match channel::read_raw(&mut con).await? {
+        Hello::ResolverAuthenticate(id) => id,
+        _ => bail!("expected an ownership check"),
+    };
+    let mut con = Channel::new::<ServerCtx, TcpStream>(None, con);
+    let secret = secrets.read().get(&id).copied().ok_or_else(|| anyhow!("no secret"))?;
+    let challenge: AuthChallenge = con.receive().await?;
+    if challenge.hash_method != HashMethod::Sha3_512 {
+        bail!("requested hash method not supported")
+    }
+    let reply = utils::make_sha3_token([
+        &challenge.challenge.to_be_bytes()[..],
+        &secret.to_be_bytes()[..],
+    ]);
+    con.send_one(&BoundedBytes::<4096>(reply)).await?;
+    // prevent fishing for the key
+    time::sleep(Duration::from_secs(1)).await;
+    Ok(())
+}
+
+async fn run(
+    listener: TcpListener,
+    writer: ResolverWrite,
+    referral: Referral,
+    stop: oneshot::Receiver<()>,
+) {
+    let secrets = writer.secrets();
+    let accept = async {
+        loop {
+            match listener.accept().await {
+                Err(e) => warn!("register accept failed {}", e),
+                Ok((s, _)) => {
+                    let secrets = secrets.clone();
+                    task::spawn(async move {
+                        let r = time::timeout(HELLO_TIMEOUT, ownership_check(s, secrets));
+                        debug!("ownership check finished {:?}", r.await)
+                    });
+                }
+            }
+        }
+    };
+    // renew often enough that losing a renewal won't expire us
+    let ttl = referral.ttl.unwrap_or(1) as u64;
+    let mut renew = time::interval(Duration::from_secs(max(1, ttl / 3)));
+    let register = async {
+        loop {
+            renew.tick().await;
+            match writer.register_referral(referral.clone()).await {
+                Ok(()) => debug!("registered {} with the parent", referral.path),
+                Err(e) => {
+                    warn!("failed to register {} with the parent {}", referral.path, e)
+                }
+            }
+        }
+    };
+    select_biased! {
+        _ = stop.fuse() => (),
+        () = accept.fuse() => (),
+        () = register.fuse() => (),
+    }
+    info!("stopped registering {} with the parent", referral.path)
+}
+
+/// If this cluster is configured to register with it's parent, then
+/// start registering this member server, which is listening on
+/// `us`. Registration stops when the returned sender is dropped.
+pub(super) async fn start(
+    cfg: &Config,
+    member: &MemberServer,
+    us: SocketAddr,
+) -> Result<Option<oneshot::Sender<()>>> {
+    let (parent, register) = match (&cfg.parent, &cfg.register) {
+        (Some(parent), Some(register)) => (parent, register),
+        (None, _) | (_, None) => return Ok(None),
+    };
+    let listener = TcpListener::bind(SocketAddr::new(member.bind_addr, 0)).await?;
+    let write_addr = SocketAddr::new(us.ip(), listener.local_addr()?.port());
+    let addrs = cfg
+        .member_servers
+        .iter()
+        .map(|m| {
+            let addr = if m.addr == member.addr { us } else { m.addr };
+            (addr, m.auth.clone().into())
+        })
+        .collect::<Vec<_>>();
+    let referral = Referral {
+        path: parent.path.clone(),
+        ttl: Some(register.ttl),
+        addrs: Pooled::orphan(addrs),
+    };
+    let client_cfg = ClientConfig {
+        base: Path::from("/"),
+        addrs: parent.addrs.to_vec(),
+        tls: None,
+        default_auth: Default::default(),
+        default_bind_config: BindCfg::default(),
+        hedge_percentile: None,
+    };
+    let writer = ResolverWrite::new(client_cfg, register.auth.clone(), write_addr)?;
+    let (tx, rx) = oneshot::channel();
+    task::spawn(run(listener, writer, referral, rx));
+    Ok(Some(tx))
+}
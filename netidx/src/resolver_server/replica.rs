@@ -0,0 +1,1200 @@
+//! Replication of the resolver store between the member servers of a
+//! cluster.
+//!
+//! Every write a publisher makes to a member server is appended to a
+//! log that the member servers agree on using the raft consensus
+//! algorithm. Each member applies the committed log in order to its
+//! own store, so publishers need only write to one member, and reads
+//! can be served by any member. The log is kept in memory. A member
+//! that restarts rejoins with an empty store, the leader brings it up
+//! to date by sending it a snapshot followed by the rest of the log,
+//! and it won't accept read clients until it has caught up.
+//!
+//! Since the log and the votes a member has granted are forgotten
+//! when it restarts, a member that hasn't caught up with a leader
+//! since it started won't vote, because the candidate may be missing
+//! entries the member had committed before it restarted. Once it has
+//! caught up it still won't vote in any term it saw before then,
+//! because it may have voted in that term before it restarted. The
+//! one exception is when the cluster is forming for the first time,
+//! a member that has never seen a leader or a member with a non
+//! empty log votes for candidates with an empty log. So if a
+//! majority of the cluster restarts before the restarted members
+//! catch up, the cluster is unavailable until enough of the members
+//! that kept their logs are back.
+//!
+//! Publishers are tracked by session. A session begins when a
+//! publisher connects to a member server, and ends when it times out,
+//! or when it begins a new session with another member. The sessions
+//! of a member that the leader hasn't heard from for a writer ttl are
+//! expired.
+use super::{auth::ANONYMOUS, config::Config, shard_store::Store};
+use crate::{
+    channel::Channel,
+    path::Path,
+    pool::{Pool, Pooled},
+    protocol::resolver::{
+        FromWrite, Publisher, PublisherId, ReplicaCmd, ReplicaEntry, ReplicaMsg,
+        ReplicaSession, SessionId, ToWrite,
+    },
+};
+use anyhow::Result;
+use cross_krb5::ServerCtx;
+use futures::{
+    channel::{
+        mpsc::{unbounded, UnboundedReceiver, UnboundedSender},
+        oneshot,
+    },
+    prelude::*,
+    select_biased,
+};
+use fxhash::{FxHashMap, FxHashSet};
+use log::{debug, info, warn};
+use parking_lot::Mutex;
+use rand::{thread_rng, Rng};
+use std::{
+    collections::{HashMap, VecDeque},
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    task,
+    time::{self, Instant},
+};
+
+const HEARTBEAT: Duration = Duration::from_millis(100);
+const ELECTION_TIMEOUT: Duration = Duration::from_millis(1000);
+const PROPOSE_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_APPEND: u64 = 10_000;
+const COMPACT_AFTER: usize = 100_000;
+const SNAPSHOT_CHUNK: usize = 100_000;
+
+lazy_static! {
+    static ref WRITES: Pool<Vec<ToWrite>> = Pool::new(1000, 100_000);
+    static ref ENTRIES: Pool<Vec<ReplicaEntry>> = Pool::new(100, 100_000);
+    static ref SESSIONS: Pool<Vec<ReplicaSession>> = Pool::new(10, 100_000);
+}
+
+type Reply = oneshot::Sender<Result<Vec<FromWrite>>>;
+
+struct Shared {
+    member: u64,
+    incarnation: u64,
+    // proposals waiting to be applied by log index, with the term
+    // they were appended in
+    waiters: Mutex<FxHashMap<u64, (u64, Reply)>>,
+    // the commit index of the leader when we first heard from it
+    sync_target: AtomicU64,
+    applied: AtomicU64,
+    synced: AtomicBool,
+}
+
+impl Shared {
+    fn check_synced(&self) {
+        let applied = self.applied.load(Ordering::Relaxed);
+        if applied >= self.sync_target.load(Ordering::Relaxed) {
+            self.synced.store(true, Ordering::Relaxed)
+        }
+    }
+}
+
+struct Snapshot {
+    index: u64,
+    term: u64,
+    sessions: Vec<ReplicaSession>,
+}
+
+enum ToApply {
+    Entries(Vec<(u64, ReplicaEntry)>),
+    Install(Snapshot),
+    Snapshot(oneshot::Sender<Snapshot>),
+}
+
+enum ToCore {
+    Propose(ReplicaCmd, Reply),
+    Recv(u64, ReplicaMsg),
+    Snapshot(u64, Snapshot),
+}
+
+struct Session {
+    // the publisher as recorded by the member the session is with
+    publisher: Publisher,
+    // the publisher as recorded in our store
+    local: Arc<Publisher>,
+    paths: FxHashMap<Path, ToWrite>,
+    defaults: FxHashMap<Path, ToWrite>,
+    metadata: FxHashMap<Path, ToWrite>,
+    referrals: FxHashMap<Path, ToWrite>,
+}
+
+impl Session {
+    fn record(&mut self, w: &ToWrite) {
+        match w {
+            ToWrite::Publish(p) | ToWrite::PublishWithFlags(p, _) => {
+                self.paths.insert(p.clone(), w.clone());
+            }
+            ToWrite::PublishDefault(p) | ToWrite::PublishDefaultWithFlags(p, _) => {
+                self.defaults.insert(p.clone(), w.clone());
+            }
+            ToWrite::Unpublish(p) => {
+                self.paths.remove(p);
+                self.metadata.remove(p);
+            }
+            ToWrite::UnpublishDefault(p) => {
+                self.defaults.remove(p);
+                self.metadata.remove(p);
+            }
+            ToWrite::SetMetadata(p, md) => {
+                if md.is_empty() {
+                    self.metadata.remove(p);
+                } else {
+                    self.metadata.insert(p.clone(), w.clone());
+                }
+            }
+            ToWrite::Clear => {
+                self.paths.clear();
+                self.defaults.clear();
+                self.metadata.clear();
+            }
+            ToWrite::RegisterReferral(r) => {
+                self.referrals.insert(r.path.clone(), w.clone());
+            }
+            ToWrite::Heartbeat => (),
+        }
+    }
+
+    // the writes that recreate this session, metadata must come last
+    fn writes(&self) -> impl Iterator<Item = &ToWrite> {
+        self.referrals
+            .values()
+            .chain(self.paths.values())
+            .chain(self.defaults.values())
+            .chain(self.metadata.values())
+    }
+}
+
+// applies the committed log to the store
+struct Applier {
+    shared: Arc<Shared>,
+    store: Store,
+    sessions: FxHashMap<SessionId, Session>,
+    by_addr: FxHashMap<SocketAddr, SessionId>,
+    index: u64,
+    term: u64,
+}
+
+impl Applier {
+    async fn close(&mut self, id: &SessionId) -> Result<()> {
+        if let Some(s) = self.sessions.remove(id) {
+            self.by_addr.remove(&s.publisher.addr);
+            self.store.handle_clear(ANONYMOUS.clone(), s.local).await?
+        }
+        Ok(())
+    }
+
+    async fn open(&mut self, id: SessionId, publisher: Publisher) -> Result<()> {
+        if let Some(cur) = self.by_addr.get(&publisher.addr).copied() {
+            if cur == id {
+                return Ok(());
+            }
+            self.close(&cur).await?
+        }
+        let local = Arc::new(Publisher { id: PublisherId::new(), ..publisher.clone() });
+        self.by_addr.insert(publisher.addr, id);
+        self.sessions.insert(
+            id,
+            Session {
+                publisher,
+                local,
+                paths: HashMap::default(),
+                defaults: HashMap::default(),
+                metadata: HashMap::default(),
+                referrals: HashMap::default(),
+            },
+        );
+        Ok(())
+    }
+
+    async fn write(
+        &mut self,
+        id: &SessionId,
+        mut batch: Pooled<Vec<ToWrite>>,
+    ) -> Result<Vec<FromWrite>> {
+        let mut replies = Vec::new();
+        match self.sessions.get_mut(id) {
+            None => bail!("session closed"),
+            Some(s) => {
+                for w in batch.iter() {
+                    s.record(w)
+                }
+                let publisher = s.local.clone();
+                let batch = batch.drain(..);
+                let uifo = ANONYMOUS.clone();
+                self.store
+                    .handle_batch_write(Some(&mut replies), uifo, publisher, batch)
+                    .await?;
+            }
+        }
+        Ok(replies)
+    }
+
+    async fn apply(&mut self, cmd: ReplicaCmd) -> Result<Vec<FromWrite>> {
+        match cmd {
+            ReplicaCmd::Noop => (),
+            ReplicaCmd::Open(id, publisher) => self.open(id, *publisher).await?,
+            ReplicaCmd::Write(id, batch) => return self.write(&id, batch).await,
+            ReplicaCmd::Clear(id) => match self.sessions.get_mut(&id) {
+                None => bail!("session closed"),
+                Some(s) => {
+                    s.record(&ToWrite::Clear);
+                    let publisher = s.local.clone();
+                    self.store.handle_clear(ANONYMOUS.clone(), publisher).await?
+                }
+            },
+            ReplicaCmd::Close(id) => self.close(&id).await?,
+            ReplicaCmd::Expire { member, keep } => {
+                let expired = self
+                    .sessions
+                    .keys()
+                    .filter(|id| id.member == member && id.incarnation != keep)
+                    .copied()
+                    .collect::<Vec<_>>();
+                for id in expired {
+                    self.close(&id).await?
+                }
+            }
+        }
+        Ok(vec![])
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        let sessions = self
+            .sessions
+            .iter()
+            .map(|(id, s)| {
+                let mut writes = WRITES.take();
+                writes.extend(s.writes().cloned());
+                ReplicaSession { session: *id, publisher: s.publisher.clone(), writes }
+            })
+            .collect();
+        Snapshot { index: self.index, term: self.term, sessions }
+    }
+
+    async fn install(&mut self, snap: Snapshot) -> Result<()> {
+        let ids = self.sessions.keys().copied().collect::<Vec<_>>();
+        for id in ids {
+            self.close(&id).await?
+        }
+        {
+            let mut waiters = self.shared.waiters.lock();
+            waiters.retain(|i, _| *i > snap.index);
+        }
+        for s in snap.sessions {
+            self.open(s.session, s.publisher).await?;
+            self.write(&s.session, s.writes).await?;
+        }
+        self.index = snap.index;
+        self.term = snap.term;
+        self.shared.applied.store(snap.index, Ordering::Relaxed);
+        self.shared.check_synced();
+        Ok(())
+    }
+
+    async fn run(mut self, mut rx: UnboundedReceiver<ToApply>) {
+        while let Some(m) = rx.next().await {
+            match m {
+                ToApply::Snapshot(reply) => {
+                    let _ = reply.send(self.snapshot());
+                }
+                ToApply::Install(snap) => {
+                    info!("installing snapshot at {}", snap.index);
+                    if let Err(e) = self.install(snap).await {
+                        warn!("failed to install snapshot {}", e)
+                    }
+                }
+                ToApply::Entries(entries) => {
+                    for (index, e) in entries {
+                        let res = self.apply(e.cmd).await;
+                        self.index = index;
+                        self.term = e.term;
+                        let waiter = self.shared.waiters.lock().remove(&index);
+                        match waiter {
+                            Some((term, reply)) if term == e.term => {
+                                let _ = reply.send(res);
+                            }
+                            Some((_, reply)) => {
+                                let _ = reply.send(Err(anyhow!("proposal lost")));
+                            }
+                            None => {
+                                if let Err(e) = res {
+                                    debug!("replicated write failed {}", e)
+                                }
+                            }
+                        }
+                        self.shared.applied.store(index, Ordering::Relaxed);
+                    }
+                    self.shared.check_synced();
+                }
+            }
+        }
+    }
+}
+
+// the log, entries[i] has index base + i + 1
+struct Log {
+    base: u64,
+    base_term: u64,
+    entries: VecDeque<ReplicaEntry>,
+}
+
+impl Log {
+    fn last_index(&self) -> u64 {
+        self.base + self.entries.len() as u64
+    }
+
+    fn last_term(&self) -> u64 {
+        self.entries.back().map(|e| e.term).unwrap_or(self.base_term)
+    }
+
+    fn get(&self, index: u64) -> Option<&ReplicaEntry> {
+        if index <= self.base {
+            None
+        } else {
+            self.entries.get((index - self.base - 1) as usize)
+        }
+    }
+
+    fn term(&self, index: u64) -> Option<u64> {
+        if index == self.base {
+            Some(self.base_term)
+        } else {
+            self.get(index).map(|e| e.term)
+        }
+    }
+
+    // remove index and everything after it
+    fn truncate(&mut self, index: u64) {
+        if index > self.base {
+            self.entries.truncate((index - self.base - 1) as usize)
+        }
+    }
+
+    // remove index and everything before it
+    fn compact(&mut self, index: u64) {
+        if index > self.base && index <= self.last_index() {
+            self.base_term = self.term(index).unwrap();
+            self.entries.drain(..(index - self.base) as usize);
+            self.base = index;
+        }
+    }
+
+    fn reset(&mut self, index: u64, term: u64) {
+        self.entries.clear();
+        self.base = index;
+        self.base_term = term;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Role {
+    Follower,
+    Candidate,
+    Leader,
+}
+
+struct Peer {
+    to: UnboundedSender<ReplicaMsg>,
+    next_index: u64,
+    match_index: u64,
+    last_ack: Instant,
+    expired: bool,
+    snapshot_pending: bool,
+}
+
+struct Core {
+    shared: Arc<Shared>,
+    members: u64,
+    peers: FxHashMap<u64, Peer>,
+    writer_ttl: Duration,
+    role: Role,
+    term: u64,
+    voted_for: Option<u64>,
+    // we don't vote in this term or earlier ones, see the module docs
+    fence: u64,
+    // we have seen a leader, or a member with a non empty log
+    formed: bool,
+    votes: FxHashSet<u64>,
+    leader: Option<u64>,
+    heard_from_leader: Option<Instant>,
+    election_deadline: Instant,
+    log: Log,
+    commit: u64,
+    // the last index sent to the applier
+    sent: u64,
+    // proposals forwarded to the leader
+    forwarded: FxHashMap<u64, Reply>,
+    next_id: u64,
+    incoming: Option<Snapshot>,
+    announce: Option<oneshot::Receiver<Result<Vec<FromWrite>>>>,
+    announced: bool,
+    to_apply: UnboundedSender<ToApply>,
+    to_core: UnboundedSender<ToCore>,
+}
+
+impl Core {
+    fn me(&self) -> u64 {
+        self.shared.member
+    }
+
+    fn reset_election_deadline(&mut self) {
+        let jitter = thread_rng().gen_range(0..ELECTION_TIMEOUT.as_millis() as u64);
+        self.election_deadline =
+            Instant::now() + ELECTION_TIMEOUT + Duration::from_millis(jitter);
+    }
+
+    fn send(&self, to: u64, m: ReplicaMsg) {
+        if let Some(p) = self.peers.get(&to) {
+            let _ = p.to.unbounded_send(m);
+        }
+    }
+
+    fn fail_forwarded(&mut self) {
+        for (_, reply) in self.forwarded.drain() {
+            let _ = reply.send(Err(anyhow!("leader changed")));
+        }
+    }
+
+    fn become_follower(&mut self, term: u64, leader: Option<u64>) {
+        if term > self.term {
+            self.term = term;
+            self.voted_for = None;
+        }
+        if !self.shared.synced.load(Ordering::Relaxed) {
+            self.fence = self.term;
+        }
+        if self.leader != leader {
+            if let Some(l) = leader {
+                info!("member {} following {} in term {}", self.me(), l, term);
+            }
+            self.fail_forwarded();
+        }
+        self.role = Role::Follower;
+        self.leader = leader;
+    }
+
+    fn campaign(&mut self) {
+        self.term += 1;
+        self.role = Role::Candidate;
+        self.leader = None;
+        self.voted_for = Some(self.me());
+        self.votes.clear();
+        self.votes.insert(self.me());
+        self.fail_forwarded();
+        self.reset_election_deadline();
+        debug!("member {} campaigning in term {}", self.me(), self.term);
+        if self.votes.len() as u64 * 2 > self.members {
+            self.become_leader()
+        } else {
+            let (last_index, last_term) = (self.log.last_index(), self.log.last_term());
+            for p in self.peers.values() {
+                let term = self.term;
+                let m = ReplicaMsg::RequestVote { term, last_index, last_term };
+                let _ = p.to.unbounded_send(m);
+            }
+        }
+    }
+
+    fn become_leader(&mut self) {
+        info!("member {} is the leader in term {}", self.me(), self.term);
+        self.role = Role::Leader;
+        self.leader = Some(self.me());
+        let next_index = self.log.last_index() + 1;
+        let now = Instant::now();
+        for p in self.peers.values_mut() {
+            p.next_index = next_index;
+            p.match_index = 0;
+            p.last_ack = now;
+            p.expired = false;
+            p.snapshot_pending = false;
+        }
+        let (index, _) = self.append(ReplicaCmd::Noop);
+        if self.shared.sync_target.load(Ordering::Relaxed) == u64::MAX {
+            self.shared.sync_target.store(index, Ordering::Relaxed)
+        }
+        self.broadcast_append();
+        self.maybe_commit();
+    }
+
+    fn append(&mut self, cmd: ReplicaCmd) -> (u64, u64) {
+        self.log.entries.push_back(ReplicaEntry { term: self.term, cmd });
+        (self.log.last_index(), self.term)
+    }
+
+    fn send_append(&mut self, to: u64) {
+        let p = match self.peers.get_mut(&to) {
+            None => return,
+            Some(p) => p,
+        };
+        if p.next_index <= self.log.base {
+            if !p.snapshot_pending {
+                p.snapshot_pending = true;
+                let (tx, rx) = oneshot::channel();
+                let _ = self.to_apply.unbounded_send(ToApply::Snapshot(tx));
+                let to_core = self.to_core.clone();
+                task::spawn(async move {
+                    if let Ok(snap) = rx.await {
+                        let _ = to_core.unbounded_send(ToCore::Snapshot(to, snap));
+                    }
+                });
+            }
+            return;
+        }
+        let prev_index = p.next_index - 1;
+        let prev_term = self.log.term(prev_index).unwrap();
+        let last = self.log.last_index().min(prev_index + MAX_APPEND);
+        let mut entries = ENTRIES.take();
+        for i in p.next_index..=last {
+            entries.push(self.log.get(i).unwrap().clone())
+        }
+        p.next_index = last + 1;
+        let m = ReplicaMsg::Append {
+            term: self.term,
+            prev_index,
+            prev_term,
+            commit: self.commit,
+            entries,
+        };
+        let _ = p.to.unbounded_send(m);
+    }
+
+    fn broadcast_append(&mut self) {
+        let peers = self.peers.keys().copied().collect::<Vec<_>>();
+        for p in peers {
+            self.send_append(p)
+        }
+    }
+
+    fn send_snapshot(&mut self, to: u64, snap: Snapshot) {
+        if self.role != Role::Leader {
+            return;
+        }
+        let p = match self.peers.get_mut(&to) {
+            None => return,
+            Some(p) => p,
+        };
+        info!("sending snapshot at {} to member {}", snap.index, to);
+        p.snapshot_pending = false;
+        p.next_index = snap.index + 1;
+        let (term, index, index_term) = (self.term, snap.index, snap.term);
+        let mut sessions = SESSIONS.take();
+        let mut n = 0;
+        for mut s in snap.sessions {
+            while n + s.writes.len() > SNAPSHOT_CHUNK {
+                let mut writes = WRITES.take();
+                writes.extend(s.writes.drain(..SNAPSHOT_CHUNK - n));
+                let session = s.session;
+                let publisher = s.publisher.clone();
+                sessions.push(ReplicaSession { session, publisher, writes });
+                let sessions = std::mem::replace(&mut sessions, SESSIONS.take());
+                let m = ReplicaMsg::Snapshot {
+                    term,
+                    index,
+                    index_term,
+                    sessions,
+                    done: false,
+                };
+                let _ = p.to.unbounded_send(m);
+                n = 0;
+            }
+            n += s.writes.len();
+            sessions.push(s);
+        }
+        let m = ReplicaMsg::Snapshot { term, index, index_term, sessions, done: true };
+        let _ = p.to.unbounded_send(m);
+    }
+
+    fn dispatch_committed(&mut self) {
+        if self.commit > self.sent {
+            let entries = (self.sent + 1..=self.commit)
+                .map(|i| (i, self.log.get(i).unwrap().clone()))
+                .collect::<Vec<_>>();
+            self.sent = self.commit;
+            let _ = self.to_apply.unbounded_send(ToApply::Entries(entries));
+        }
+    }
+
+    fn maybe_commit(&mut self) {
+        let mut matched = self.peers.values().map(|p| p.match_index).collect::<Vec<_>>();
+        matched.push(self.log.last_index());
+        matched.sort_unstable_by(|a, b| b.cmp(a));
+        let n = matched[(self.members / 2) as usize];
+        if n > self.commit && self.log.term(n) == Some(self.term) {
+            self.commit = n;
+            self.dispatch_committed();
+            self.broadcast_append();
+        }
+    }
+
+    fn propose(&mut self, cmd: ReplicaCmd, reply: Reply) {
+        match (self.role, self.leader) {
+            (Role::Leader, _) => {
+                let (index, term) = self.append(cmd);
+                self.shared.waiters.lock().insert(index, (term, reply));
+                self.broadcast_append();
+                self.maybe_commit();
+            }
+            (Role::Follower, Some(leader)) => {
+                let id = self.next_id;
+                self.next_id += 1;
+                self.forwarded.insert(id, reply);
+                self.send(leader, ReplicaMsg::Propose { id, cmd });
+            }
+            (Role::Follower | Role::Candidate, _) => {
+                let _ = reply.send(Err(anyhow!("no leader")));
+            }
+        }
+    }
+
+    fn handle_request_vote(&mut self, from: u64, term: u64, last: (u64, u64)) {
+        // a member that has heard from a leader recently won't vote,
+        // so a member rejoining the cluster can't disrupt it
+        let recent = self
+            .heard_from_leader
+            .map(|t| t.elapsed() < ELECTION_TIMEOUT)
+            .unwrap_or(false);
+        if term > self.term && recent {
+            return;
+        }
+        if term > self.term {
+            self.become_follower(term, None);
+        }
+        if last != (0, 0) {
+            self.formed = true;
+        }
+        let up_to_date = last >= (self.log.last_term(), self.log.last_index());
+        // until we catch up after a restart we might be missing
+        // entries we committed before, and we might have voted in
+        // any term we saw before we caught up, see the module docs
+        let may_vote = if self.shared.synced.load(Ordering::Relaxed) {
+            term > self.fence
+        } else {
+            !self.formed && last == (0, 0)
+        };
+        let granted = term == self.term
+            && up_to_date
+            && may_vote
+            && self.voted_for.map(|v| v == from).unwrap_or(true);
+        if granted {
+            self.voted_for = Some(from);
+            self.reset_election_deadline();
+        }
+        self.send(from, ReplicaMsg::Vote { term: self.term, granted });
+    }
+
+    fn handle_vote(&mut self, from: u64, term: u64, granted: bool) {
+        if term > self.term {
+            self.become_follower(term, None);
+        } else if self.role == Role::Candidate && term == self.term && granted {
+            self.votes.insert(from);
+            if self.votes.len() as u64 * 2 > self.members {
+                self.become_leader()
+            }
+        }
+    }
+
+    fn heard_from(&mut self, from: u64, term: u64) {
+        self.formed = true;
+        self.become_follower(term, Some(from));
+        self.heard_from_leader = Some(Instant::now());
+        self.reset_election_deadline();
+    }
+
+    fn handle_append(
+        &mut self,
+        from: u64,
+        term: u64,
+        mut prev_index: u64,
+        mut prev_term: u64,
+        commit: u64,
+        mut entries: Pooled<Vec<ReplicaEntry>>,
+    ) {
+        let fail = |t: &Self| ReplicaMsg::AppendReply {
+            term: t.term,
+            success: false,
+            last_index: t.log.last_index(),
+        };
+        if term < self.term {
+            return self.send(from, fail(self));
+        }
+        self.heard_from(from, term);
+        if self.shared.sync_target.load(Ordering::Relaxed) == u64::MAX {
+            self.shared.sync_target.store(commit, Ordering::Relaxed);
+        }
+        if prev_index > self.log.last_index() {
+            return self.send(from, fail(self));
+        }
+        if prev_index < self.log.base {
+            // everything up to base is committed, and so must match
+            let skip = ((self.log.base - prev_index) as usize).min(entries.len());
+            entries.drain(..skip);
+            prev_index = self.log.base;
+            prev_term = self.log.base_term;
+        }
+        if self.log.term(prev_index) != Some(prev_term) {
+            self.log.truncate(prev_index);
+            return self.send(from, fail(self));
+        }
+        let last_index = prev_index + entries.len() as u64;
+        for (i, e) in entries.drain(..).enumerate() {
+            let index = prev_index + 1 + i as u64;
+            match self.log.term(index) {
+                Some(t) if t == e.term => (),
+                Some(_) => {
+                    self.log.truncate(index);
+                    self.log.entries.push_back(e)
+                }
+                None => self.log.entries.push_back(e),
+            }
+        }
+        if commit > self.commit {
+            self.commit = commit.min(last_index);
+            self.dispatch_committed();
+        }
+        let m = ReplicaMsg::AppendReply { term: self.term, success: true, last_index };
+        self.send(from, m)
+    }
+
+    fn handle_append_reply(&mut self, from: u64, term: u64, success: bool, last: u64) {
+        if term > self.term {
+            return self.become_follower(term, None);
+        }
+        if self.role != Role::Leader || term != self.term {
+            return;
+        }
+        if let Some(p) = self.peers.get_mut(&from) {
+            p.last_ack = Instant::now();
+            p.expired = false;
+            if success {
+                p.match_index = p.match_index.max(last);
+                p.next_index = p.next_index.max(last + 1);
+                self.maybe_commit();
+            } else {
+                p.next_index = p.next_index.min(last + 1).max(1);
+                self.send_append(from);
+            }
+        }
+    }
+
+    fn handle_snapshot(
+        &mut self,
+        from: u64,
+        term: u64,
+        index: u64,
+        index_term: u64,
+        mut sessions: Pooled<Vec<ReplicaSession>>,
+        done: bool,
+    ) {
+        if term < self.term {
+            let last_index = self.log.last_index();
+            let m =
+                ReplicaMsg::AppendReply { term: self.term, success: false, last_index };
+            return self.send(from, m);
+        }
+        self.heard_from(from, term);
+        let snap = match &mut self.incoming {
+            Some(s) if s.index == index && s.term == index_term => s,
+            Some(_) | None => self.incoming.insert(Snapshot {
+                index,
+                term: index_term,
+                sessions: vec![],
+            }),
+        };
+        snap.sessions.extend(sessions.drain(..));
+        if done {
+            let snap = self.incoming.take().unwrap();
+            if snap.index > self.commit {
+                let mut merged: FxHashMap<SessionId, ReplicaSession> = HashMap::default();
+                for mut s in snap.sessions {
+                    match merged.get_mut(&s.session) {
+                        None => {
+                            merged.insert(s.session, s);
+                        }
+                        Some(m) => m.writes.extend(s.writes.drain(..)),
+                    }
+                }
+                self.log.reset(snap.index, snap.term);
+                self.commit = snap.index;
+                self.sent = snap.index;
+                let sessions = merged.into_values().collect();
+                let snap = Snapshot { index: snap.index, term: snap.term, sessions };
+                let _ = self.to_apply.unbounded_send(ToApply::Install(snap));
+            }
+            let last_index = index.max(self.commit);
+            let m =
+                ReplicaMsg::AppendReply { term: self.term, success: true, last_index };
+            self.send(from, m)
+        }
+    }
+
+    fn handle_propose(&mut self, from: u64, id: u64, cmd: ReplicaCmd) {
+        if self.role == Role::Leader {
+            let (index, term) = self.append(cmd);
+            self.send(from, ReplicaMsg::Proposed { id, index, term });
+            self.broadcast_append();
+            self.maybe_commit();
+        } else {
+            self.send(from, ReplicaMsg::Proposed { id, index: 0, term: 0 })
+        }
+    }
+
+    fn handle_proposed(&mut self, id: u64, index: u64, term: u64) {
+        if let Some(reply) = self.forwarded.remove(&id) {
+            if index == 0 {
+                let _ = reply.send(Err(anyhow!("proposal rejected")));
+            } else if index <= self.sent {
+                let _ = reply.send(Err(anyhow!("proposal lost")));
+            } else {
+                self.shared.waiters.lock().insert(index, (term, reply));
+            }
+        }
+    }
+
+    fn handle(&mut self, m: ToCore) {
+        match m {
+            ToCore::Propose(cmd, reply) => self.propose(cmd, reply),
+            ToCore::Snapshot(to, snap) => self.send_snapshot(to, snap),
+            ToCore::Recv(from, m) => match m {
+                ReplicaMsg::Hello { .. } => (),
+                ReplicaMsg::RequestVote { term, last_index, last_term } => {
+                    self.handle_request_vote(from, term, (last_term, last_index))
+                }
+                ReplicaMsg::Vote { term, granted } => {
+                    self.handle_vote(from, term, granted)
+                }
+                ReplicaMsg::Append { term, prev_index, prev_term, commit, entries } => {
+                    self.handle_append(from, term, prev_index, prev_term, commit, entries)
+                }
+                ReplicaMsg::AppendReply { term, success, last_index } => {
+                    self.handle_append_reply(from, term, success, last_index)
+                }
+                ReplicaMsg::Snapshot { term, index, index_term, sessions, done } => {
+                    self.handle_snapshot(from, term, index, index_term, sessions, done)
+                }
+                ReplicaMsg::Propose { id, cmd } => self.handle_propose(from, id, cmd),
+                ReplicaMsg::Proposed { id, index, term } => {
+                    self.handle_proposed(id, index, term)
+                }
+            },
+        }
+    }
+
+    fn tick(&mut self) {
+        let now = Instant::now();
+        match self.role {
+            Role::Follower | Role::Candidate => {
+                if now >= self.election_deadline {
+                    self.campaign()
+                }
+            }
+            Role::Leader => {
+                let mut expired = vec![];
+                for (id, p) in self.peers.iter_mut() {
+                    if !p.expired && now - p.last_ack > self.writer_ttl {
+                        p.expired = true;
+                        expired.push(*id);
+                    }
+                }
+                for member in expired {
+                    info!("expiring the sessions of member {}", member);
+                    self.append(ReplicaCmd::Expire { member, keep: 0 });
+                }
+                self.broadcast_append();
+                self.maybe_commit();
+            }
+        }
+        // end the sessions of our previous incarnation
+        if let Some(rx) = &mut self.announce {
+            match rx.try_recv() {
+                Ok(None) => (),
+                Ok(Some(Ok(_))) => self.announce = None,
+                Ok(Some(Err(_))) | Err(_) => {
+                    self.announce = None;
+                    self.announced = false;
+                }
+            }
+        }
+        if !self.announced && self.leader.is_some() {
+            let (tx, rx) = oneshot::channel();
+            let member = self.me();
+            let keep = self.shared.incarnation;
+            self.announced = true;
+            self.announce = Some(rx);
+            self.propose(ReplicaCmd::Expire { member, keep }, tx);
+        }
+        if self.log.entries.len() > COMPACT_AFTER {
+            let applied = self.shared.applied.load(Ordering::Relaxed);
+            self.log.compact(applied.min(self.commit));
+        }
+    }
+
+    async fn run(
+        mut self,
+        mut rx: UnboundedReceiver<ToCore>,
+        stop: oneshot::Receiver<()>,
+    ) {
+        let mut stop = stop.fuse();
+        let mut tick = time::interval(HEARTBEAT);
+        loop {
+            select_biased! {
+                _ = stop => break,
+                m = rx.next() => match m {
+                    None => break,
+                    Some(m) => self.handle(m),
+                },
+                _ = tick.tick().fuse() => self.tick(),
+            }
+        }
+        info!("replica core shutting down")
+    }
+}
+
+// send messages to one peer, reconnecting as necessary
+async fn send_loop(member: u64, addr: SocketAddr, mut rx: UnboundedReceiver<ReplicaMsg>) {
+    let mut con: Option<Channel> = None;
+    while let Some(m) = rx.next().await {
+        if con.is_none() {
+            let r = time::timeout(ELECTION_TIMEOUT, TcpStream::connect(addr)).await;
+            match r {
+                Ok(Ok(s)) => {
+                    let _ = s.set_nodelay(true);
+                    let mut c = Channel::new::<ServerCtx, TcpStream>(None, s);
+                    match c.send_one(&ReplicaMsg::Hello { member }).await {
+                        Ok(()) => con = Some(c),
+                        Err(e) => debug!("failed to send hello to {} {}", addr, e),
+                    }
+                }
+                Ok(Err(e)) => debug!("failed to connect to {} {}", addr, e),
+                Err(_) => debug!("timeout connecting to {}", addr),
+            }
+        }
+        match &mut con {
+            None => {
+                // raft tolerates lost messages, and queuing them
+                // for an unreachable member would waste memory
+                while rx.try_recv().is_ok() {}
+            }
+            Some(c) => {
+                let mut r = c.queue_send(&m);
+                while let Ok(m) = rx.try_recv() {
+                    if r.is_err() {
+                        break;
+                    }
+                    r = c.queue_send(&m);
+                }
+                if let Err(e) = r {
+                    warn!("failed to queue replica message for {} {}", addr, e);
+                    c.clear();
+                } else if let Err(e) = c.flush().await {
+                    debug!("connection to {} failed {}", addr, e);
+                    con = None;
+                }
+            }
+        }
+    }
+}
+
+async fn recv_loop(
+    members: u64,
+    s: TcpStream,
+    to_core: UnboundedSender<ToCore>,
+) -> Result<()> {
+    s.set_nodelay(true)?;
+    let mut con = Channel::new::<ServerCtx, TcpStream>(None, s);
+    let from = match time::timeout(ELECTION_TIMEOUT, con.receive()).await?? {
+        ReplicaMsg::Hello { member } if member < members => member,
+        m => bail!("unexpected hello {:?}", m),
+    };
+    let mut batch = Vec::new();
+    loop {
+        con.receive_batch(&mut batch).await?;
+        for m in batch.drain(..) {
+            to_core.unbounded_send(ToCore::Recv(from, m))?
+        }
+    }
+}
+
+async fn accept_loop(
+    members: u64,
+    listener: TcpListener,
+    to_core: UnboundedSender<ToCore>,
+    stop: oneshot::Receiver<()>,
+) {
+    let mut stop = stop.fuse();
+    loop {
+        select_biased! {
+            _ = stop => break,
+            r = listener.accept().fuse() => match r {
+                Err(e) => warn!("replica accept failed {}", e),
+                Ok((s, _)) => {
+                    let to_core = to_core.clone();
+                    task::spawn(async move {
+                        let r = recv_loop(members, s, to_core).await;
+                        debug!("replica connection closed {:?}", r)
+                    });
+                }
+            },
+        }
+    }
+}
+
+struct ReplicaInner {
+    shared: Arc<Shared>,
+    to_core: UnboundedSender<ToCore>,
+    _stop: (oneshot::Sender<()>, oneshot::Sender<()>),
+}
+
+/// A member server's replica of the cluster's store
+#[derive(Clone)]
+pub(super) struct Replica(Arc<ReplicaInner>);
+
+impl Replica {
+    pub(super) async fn start(cfg: &Config, member: usize, store: Store) -> Result<Self> {
+        let us = &cfg.member_servers[member];
+        let addr = us.replica_addr.ok_or_else(|| anyhow!("no replica_addr"))?;
+        let listener =
+            TcpListener::bind(SocketAddr::new(us.bind_addr, addr.port())).await?;
+        let members = cfg.member_servers.len() as u64;
+        let shared = Arc::new(Shared {
+            member: member as u64,
+            incarnation: thread_rng().gen_range(1..u64::MAX),
+            waiters: Mutex::new(HashMap::default()),
+            sync_target: AtomicU64::new(u64::MAX),
+            applied: AtomicU64::new(0),
+            synced: AtomicBool::new(false),
+        });
+        let (to_core, core_rx) = unbounded();
+        let (to_apply, apply_rx) = unbounded();
+        let peers = cfg
+            .member_servers
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != member)
+            .filter_map(|(i, m)| m.replica_addr.map(|a| (i as u64, a)))
+            .map(|(i, addr)| {
+                let (to, rx) = unbounded();
+                task::spawn(send_loop(member as u64, addr, rx));
+                let now = Instant::now();
+                let p = Peer {
+                    to,
+                    next_index: 1,
+                    match_index: 0,
+                    last_ack: now,
+                    expired: false,
+                    snapshot_pending: false,
+                };
+                (i, p)
+            })
+            .collect();
+        let applier = Applier {
+            shared: shared.clone(),
+            store,
+            sessions: HashMap::default(),
+            by_addr: HashMap::default(),
+            index: 0,
+            term: 0,
+        };
+        task::spawn(applier.run(apply_rx));
+        let mut core = Core {
+            shared: shared.clone(),
+            members,
+            peers,
+            writer_ttl: us.writer_ttl,
+            role: Role::Follower,
+            term: 0,
+            voted_for: None,
+            fence: 0,
+            formed: false,
+            votes: FxHashSet::default(),
+            leader: None,
+            heard_from_leader: None,
+            election_deadline: Instant::now(),
+            log: Log { base: 0, base_term: 0, entries: VecDeque::new() },
+            commit: 0,
+            sent: 0,
+            forwarded: HashMap::default(),
+            next_id: 0,
+            incoming: None,
+            announce: None,
+            announced: false,
+            to_apply,
+            to_core: to_core.clone(),
+        };
+        if members > 1 {
+            core.reset_election_deadline();
+        }
+        let (stop_core, stop_core_rx) = oneshot::channel();
+        let (stop_accept, stop_accept_rx) = oneshot::channel();
+        task::spawn(core.run(core_rx, stop_core_rx));
+        task::spawn(accept_loop(members, listener, to_core.clone(), stop_accept_rx));
+        Ok(Replica(Arc::new(ReplicaInner {
+            shared,
+            to_core,
+            _stop: (stop_core, stop_accept),
+        })))
+    }
+
+    /// True once we have caught up with the leader
+    pub(super) fn synced(&self) -> bool {
+        self.0.shared.synced.load(Ordering::Relaxed)
+    }
+
+    fn session(&self, publisher: &Publisher) -> SessionId {
+        SessionId {
+            member: self.0.shared.member,
+            incarnation: self.0.shared.incarnation,
+            id: publisher.id,
+        }
+    }
+
+    async fn propose(&self, cmd: ReplicaCmd) -> Result<Vec<FromWrite>> {
+        let (tx, rx) = oneshot::channel();
+        self.0.to_core.unbounded_send(ToCore::Propose(cmd, tx))?;
+        time::timeout(PROPOSE_TIMEOUT, rx).await??
+    }
+
+    /// Begin a session for publisher, or continue it if it's current
+    pub(super) async fn open(&self, publisher: &Publisher) -> Result<()> {
+        let id = self.session(publisher);
+        self.propose(ReplicaCmd::Open(id, Box::new(publisher.clone()))).await?;
+        Ok(())
+    }
+
+    /// Apply a batch of writes from publisher. Fails if the session
+    /// has been closed.
+    pub(super) async fn write(
+        &self,
+        publisher: &Publisher,
+        batch: impl Iterator<Item = ToWrite>,
+    ) -> Result<Vec<FromWrite>> {
+        let mut writes = WRITES.take();
+        writes.extend(batch);
+        self.propose(ReplicaCmd::Write(self.session(publisher), writes)).await
+    }
+
+    /// Clear everything published by publisher
+    pub(super) async fn clear(&self, publisher: &Publisher) -> Result<()> {
+        self.propose(ReplicaCmd::Clear(self.session(publisher))).await?;
+        Ok(())
+    }
+
+    /// Clear everything published by publisher and end it's session
+    pub(super) async fn close(&self, publisher: &Publisher) -> Result<()> {
+        self.propose(ReplicaCmd::Close(self.session(publisher))).await?;
+        Ok(())
+    }
+}
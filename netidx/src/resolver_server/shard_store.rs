@@ -1,7 +1,10 @@
 use super::{
-    auth::{Permissions, UserInfo},
+    auth::{Permissions, UserInfo, ANONYMOUS},
     secctx::SecCtx,
-    store::{self, COLS_POOL, MAX_READ_BATCH, MAX_WRITE_BATCH, PATH_POOL, REF_POOL},
+    store::{
+        self, COLS_POOL, MAX_LIST_PAGE, MAX_READ_BATCH, MAX_SEARCH_RESULTS,
+        MAX_WRITE_BATCH, PATH_POOL, REF_POOL,
+    },
 };
 use crate::{
     channel::Channel,
@@ -11,8 +14,8 @@ use crate::{
     protocol::{
         glob::Scope,
         resolver::{
-            FromRead, FromWrite, GetChangeNr, ListMatching, Publisher, PublisherId,
-            Referral, Resolved, Table, ToRead, ToWrite,
+            FromRead, FromWrite, GetChangeNr, ListMatching, ListPage, Publisher,
+            PublisherId, Referral, Resolved, SearchResult, Table, ToRead, ToWrite,
         },
     },
 };
@@ -35,9 +38,11 @@ use std::{
     net::SocketAddr,
     result,
     sync::Arc,
-    time::SystemTime,
+    time::{Duration, Instant, SystemTime},
 };
-use tokio::task;
+use tokio::{task, time};
+
+const EXPIRE_REFERRALS: Duration = Duration::from_secs(1);
 
 type ReadB = Vec<(u64, ToRead)>;
 type ReadR = VecDeque<(u64, FromRead)>;
@@ -98,8 +103,10 @@ impl Shard {
         let t = Shard { read, write, internal };
         task::spawn(async move {
             let mut store = store::Store::new(parent, children);
+            let mut expire = time::interval(EXPIRE_REFERRALS);
             loop {
                 select! {
+                    _ = expire.tick().fuse() => store.expire_referrals(Instant::now()),
                     batch = read_rx.next() => match batch {
                         None => break,
                         Some((req, reply)) => {
@@ -215,7 +222,7 @@ impl Shard {
             ToRead::ListMatching(set) => {
                 let mut referrals = REF_POOL.take();
                 if shard == 0 {
-                    for glob in set.iter() {
+                    for glob in set.included() {
                         store.referrals_in_scope(
                             &mut *referrals,
                             glob.base(),
@@ -225,7 +232,7 @@ impl Shard {
                 }
                 let allowed = pmap
                     .map(|pmap| {
-                        set.iter().all(|g| {
+                        set.included().all(|g| {
                             pmap.allowed_in_scope(
                                 g.base(),
                                 g.scope(),
@@ -245,6 +252,69 @@ impl Shard {
                     (id, FromRead::ListMatching(lm))
                 }
             }
+            ToRead::ListPage(path, cursor) => {
+                if let Some(r) = store.check_referral(&path) {
+                    (id, FromRead::Referral(r))
+                } else {
+                    let allowed = pmap
+                        .map(|pmap| pmap.allowed(&*path, Permissions::LIST, &*uifo))
+                        .unwrap_or(true);
+                    if allowed {
+                        let limit = (cursor.limit as usize).min(MAX_LIST_PAGE);
+                        let (paths, more) =
+                            store.list_page(&path, cursor.after.as_ref(), limit);
+                        let referrals = REF_POOL.take();
+                        (id, FromRead::ListPage(ListPage { paths, referrals, more }))
+                    } else {
+                        (id, FromRead::Denied)
+                    }
+                }
+            }
+            ToRead::ListMatchingPage(set, cursor) => {
+                let mut referrals = REF_POOL.take();
+                if shard == 0 && cursor.after.is_none() {
+                    for glob in set.included() {
+                        store.referrals_in_scope(
+                            &mut *referrals,
+                            glob.base(),
+                            glob.scope(),
+                        )
+                    }
+                }
+                let allowed = pmap
+                    .map(|pmap| {
+                        set.included().all(|g| {
+                            pmap.allowed_in_scope(
+                                g.base(),
+                                g.scope(),
+                                Permissions::LIST,
+                                &*uifo,
+                            )
+                        })
+                    })
+                    .unwrap_or(true);
+                let (paths, more) = if !allowed {
+                    (PATH_POOL.take(), false)
+                } else {
+                    let limit = (cursor.limit as usize).min(MAX_LIST_PAGE);
+                    store.list_matching_page(&set, cursor.after.as_ref(), limit)
+                };
+                (id, FromRead::ListPage(ListPage { paths, referrals, more }))
+            }
+            ToRead::Columns(path) => {
+                if let Some(r) = store.check_referral(&path) {
+                    (id, FromRead::Referral(r))
+                } else {
+                    let allowed = pmap
+                        .map(|pmap| pmap.allowed(&*path, Permissions::LIST, &*uifo))
+                        .unwrap_or(true);
+                    if allowed {
+                        (id, FromRead::Columns(store.columns(&path)))
+                    } else {
+                        (id, FromRead::Denied)
+                    }
+                }
+            }
             ToRead::GetChangeNr(path) => {
                 let mut referrals = REF_POOL.take();
                 if shard == 0 {
@@ -263,6 +333,44 @@ impl Shard {
                     (id, FromRead::GetChangeNr(cn))
                 }
             }
+            ToRead::Metadata(path) => {
+                if let Some(r) = store.check_referral(&path) {
+                    (id, FromRead::Referral(r))
+                } else {
+                    let allowed = pmap
+                        .map(|pmap| pmap.allowed(&*path, Permissions::LIST, &*uifo))
+                        .unwrap_or(true);
+                    if allowed {
+                        (id, FromRead::Metadata(store.metadata(&path)))
+                    } else {
+                        (id, FromRead::Denied)
+                    }
+                }
+            }
+            ToRead::Search(search) => {
+                if let Some(r) = store.check_referral(&search.base) {
+                    (id, FromRead::Referral(r))
+                } else {
+                    let allowed = pmap
+                        .map(|pmap| {
+                            pmap.allowed_in_scope(
+                                &search.base,
+                                &Scope::Subtree,
+                                Permissions::LIST,
+                                &*uifo,
+                            )
+                        })
+                        .unwrap_or(true);
+                    if !allowed {
+                        (id, FromRead::Denied)
+                    } else {
+                        match store.search(&search) {
+                            Ok(r) => (id, FromRead::Search(r)),
+                            Err(e) => (id, FromRead::Error(e.to_string().into())),
+                        }
+                    }
+                }
+            }
             ToRead::Table(path) => {
                 if let Some(r) = store.check_referral(&path) {
                     (id, FromRead::Referral(r))
@@ -350,6 +458,41 @@ impl Shard {
                     (id, FromWrite::Unpublished)
                 }
             }
+            ToWrite::RegisterReferral(r) => {
+                // a referral redirects everyone below it's path, so
+                // only authenticated users explicitly granted the
+                // permission may register one
+                let allowed = uifo.id != ANONYMOUS.id
+                    && pmap
+                        .map(|p| p.allowed(&r.path, Permissions::REFERRAL, uifo))
+                        .unwrap_or(false);
+                if !Path::is_absolute(&*r.path) {
+                    (id, FromWrite::Error("absolute paths required".into()))
+                } else if !allowed {
+                    (id, FromWrite::Denied)
+                } else {
+                    match store.register_referral(r, Instant::now()) {
+                        Ok(()) => (id, FromWrite::Published),
+                        Err(e) => (id, FromWrite::Error(e.to_string().into())),
+                    }
+                }
+            }
+            ToWrite::SetMetadata(path, metadata) => {
+                let allowed = pmap
+                    .map(|p| p.allowed(&*path, Permissions::PUBLISH, uifo))
+                    .unwrap_or(true);
+                if !Path::is_absolute(&*path) {
+                    (id, FromWrite::Error("absolute paths required".into()))
+                } else if let Some(r) = store.check_referral(&path) {
+                    (id, FromWrite::Referral(r))
+                } else if !allowed {
+                    (id, FromWrite::Denied)
+                } else if store.set_metadata(&publisher, path, metadata) {
+                    (id, FromWrite::Published)
+                } else {
+                    (id, FromWrite::Error("path is not published".into()))
+                }
+            }
         }));
         resp
     }
@@ -367,6 +510,33 @@ macro_rules! same {
     };
 }
 
+/// Where the replies to a write batch go
+pub(super) trait WriteReplies {
+    fn queue_send(&mut self, m: &FromWrite) -> Result<()>;
+    async fn flush(&mut self) -> Result<()>;
+}
+
+impl WriteReplies for Channel {
+    fn queue_send(&mut self, m: &FromWrite) -> Result<()> {
+        Channel::queue_send(self, m)
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        Channel::flush(self).await
+    }
+}
+
+impl WriteReplies for Vec<FromWrite> {
+    fn queue_send(&mut self, m: &FromWrite) -> Result<()> {
+        self.push(m.clone());
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
 #[derive(Clone)]
 pub(super) struct Store {
     shards: Vec<Shard>,
@@ -419,6 +589,7 @@ impl Store {
         loop {
             let mut n = 0;
             let mut c = 0;
+            let mut limits: FxHashMap<u64, usize> = HashMap::default();
             let mut by_shard = self.read_shard_batch();
             while c < MAX_READ_BATCH {
                 match msgs.next() {
@@ -431,6 +602,11 @@ impl Store {
                         by_shard[s].push((n, ToRead::Resolve(path)));
                         c += 1;
                     }
+                    Some(ToRead::Metadata(path)) => {
+                        let s = self.shard(&path);
+                        by_shard[s].push((n, ToRead::Metadata(path)));
+                        c += 1;
+                    }
                     Some(ToRead::GetChangeNr(path)) => {
                         for b in by_shard.iter_mut() {
                             b.push((n, ToRead::GetChangeNr(path.clone())));
@@ -455,6 +631,35 @@ impl Store {
                         }
                         c += 100000;
                     }
+                    Some(ToRead::ListPage(path, cursor)) => {
+                        limits.insert(n, (cursor.limit as usize).min(MAX_LIST_PAGE));
+                        for b in by_shard.iter_mut() {
+                            b.push((n, ToRead::ListPage(path.clone(), cursor.clone())));
+                        }
+                        c += 1000;
+                    }
+                    Some(ToRead::ListMatchingPage(set, cursor)) => {
+                        limits.insert(n, (cursor.limit as usize).min(MAX_LIST_PAGE));
+                        for b in by_shard.iter_mut() {
+                            let m = ToRead::ListMatchingPage(set.clone(), cursor.clone());
+                            b.push((n, m));
+                        }
+                        c += 1000;
+                    }
+                    Some(ToRead::Columns(path)) => {
+                        for b in by_shard.iter_mut() {
+                            b.push((n, ToRead::Columns(path.clone())));
+                        }
+                        c += 1000;
+                    }
+                    Some(ToRead::Search(search)) => {
+                        let limit = (search.limit as usize).min(MAX_SEARCH_RESULTS);
+                        limits.insert(n, limit);
+                        for b in by_shard.iter_mut() {
+                            b.push((n, ToRead::Search(search.clone())));
+                        }
+                        c += 100000;
+                    }
                 }
                 n += 1;
             }
@@ -506,6 +711,7 @@ impl Store {
                     match replies[0].pop_front().unwrap() {
                         (_, FromRead::Publisher(_)) => unreachable!(),
                         (_, FromRead::Resolved(_)) => unreachable!(),
+                        (_, FromRead::Metadata(_)) => unreachable!(),
                         (_, m @ FromRead::Referral(_)) => {
                             same!(con, replies, &m, "desynced referral");
                         }
@@ -552,6 +758,77 @@ impl Store {
                                 referrals,
                             }))?;
                         }
+                        (_, FromRead::Search(mut sr)) => {
+                            let mut more = sr.more;
+                            let mut paths = PATH_POOL.take();
+                            paths.extend(sr.paths.drain(..));
+                            for i in 1..replies.len() {
+                                if let (_, FromRead::Search(mut sr)) =
+                                    replies[i].pop_front().unwrap()
+                                {
+                                    more |= sr.more;
+                                    paths.extend(sr.paths.drain(..));
+                                } else {
+                                    panic!("desynced search")
+                                }
+                            }
+                            paths.sort();
+                            let limit = limits[&i];
+                            if paths.len() > limit {
+                                paths.truncate(limit);
+                                more = true;
+                            }
+                            con.queue_send(&FromRead::Search(SearchResult {
+                                paths,
+                                more,
+                            }))?;
+                        }
+                        (_, FromRead::ListPage(mut lp)) => {
+                            let referrals = lp.referrals;
+                            let mut more = lp.more;
+                            let mut paths = PATH_POOL.take();
+                            paths.extend(lp.paths.drain(..));
+                            for i in 1..replies.len() {
+                                if let (_, FromRead::ListPage(mut lp)) =
+                                    replies[i].pop_front().unwrap()
+                                {
+                                    more |= lp.more;
+                                    paths.extend(lp.paths.drain(..));
+                                } else {
+                                    panic!("desynced listpage")
+                                }
+                            }
+                            // structural paths may be present in more than one shard
+                            paths.sort_by(|p0, p1| {
+                                (Path::levels(p0), p0).cmp(&(Path::levels(p1), p1))
+                            });
+                            paths.dedup();
+                            let limit = limits[&i];
+                            if paths.len() > limit {
+                                paths.truncate(limit);
+                                more = true;
+                            }
+                            let lp = ListPage { paths, referrals, more };
+                            con.queue_send(&FromRead::ListPage(lp))?;
+                        }
+                        (_, FromRead::Columns(mut cols)) => {
+                            let mut hcols = COLS_HPOOL.take();
+                            hcols.extend(cols.drain(..));
+                            for i in 1..replies.len() {
+                                if let (_, FromRead::Columns(mut cs)) =
+                                    replies[i].pop_front().unwrap()
+                                {
+                                    for (p, c) in cs.drain(..) {
+                                        hcols.entry(p).or_insert(Z64(0)).0 += c.0;
+                                    }
+                                } else {
+                                    panic!("desynced columns")
+                                }
+                            }
+                            let mut cols = COLS_POOL.take();
+                            cols.extend(hcols.drain());
+                            con.queue_send(&FromRead::Columns(cols))?;
+                        }
                         (_, FromRead::GetChangeNr(cn)) => {
                             let referrals = cn.referrals;
                             let resolver = cn.resolver;
@@ -606,9 +883,9 @@ impl Store {
         }
     }
 
-    pub(super) async fn handle_batch_write(
+    pub(super) async fn handle_batch_write<C: WriteReplies>(
         &self,
-        mut con: Option<&mut Channel>,
+        mut con: Option<&mut C>,
         uifo: Arc<UserInfo>,
         publisher: Arc<Publisher>,
         mut msgs: impl Iterator<Item = ToWrite>,
@@ -647,6 +924,15 @@ impl Store {
                             b.push((n, ToWrite::PublishDefault(path.clone())));
                         }
                     }
+                    Some(ToWrite::RegisterReferral(r)) => {
+                        for b in by_shard.iter_mut() {
+                            b.push((n, ToWrite::RegisterReferral(r.clone())));
+                        }
+                    }
+                    Some(ToWrite::SetMetadata(path, metadata)) => {
+                        let s = self.shard(&path);
+                        by_shard[s].push((n, ToWrite::SetMetadata(path, metadata)));
+                    }
                     Some(ToWrite::PublishWithFlags(path, flags)) => {
                         let s = self.shard(&path);
                         by_shard[s].push((n, ToWrite::PublishWithFlags(path, flags)));
@@ -741,11 +1027,17 @@ impl Store {
         published_paths.shuffle(&mut thread_rng());
         let iter = published_paths.into_iter();
         // clear the vast majority of published paths using resources fairly
-        self.handle_batch_write(None, uifo.clone(), publisher.clone(), iter).await?;
+        self.handle_batch_write(
+            None::<&mut Channel>,
+            uifo.clone(),
+            publisher.clone(),
+            iter,
+        )
+        .await?;
         // clear out anything left over that was sent to all shards,
         // e.g. default publishers.
-        self.handle_batch_write(None, uifo, publisher, iter::once(ToWrite::Clear))
-            .await?;
+        let clear = iter::once(ToWrite::Clear);
+        self.handle_batch_write(None::<&mut Channel>, uifo, publisher, clear).await?;
         Ok(())
     }
 }
@@ -1,26 +1,33 @@
 use super::{
     auth::{Permissions, UserInfo},
+    config::check_addrs,
     secctx::SecCtxDataReadGuard,
 };
 use crate::{
+    chars::Chars,
     pack::Z64,
     path::Path,
     pool::{Pool, Pooled},
     protocol::{
         glob::{GlobSet, Scope},
-        resolver::{Publisher, PublisherId, PublisherRef, Referral},
+        resolver::{
+            Metadata, MetadataMatch, PathMatch, Publisher, PublisherId, PublisherRef,
+            Referral, Search, SearchResult,
+        },
     },
     utils,
 };
+use anyhow::Result;
 use bytes::Bytes;
 use fxhash::FxHashMap;
 use immutable_chunkmap::set::Set as ISet;
 use log::debug;
+use regex::RegexBuilder;
 use std::{
     clone::Clone,
     collections::{
         hash_map::Entry,
-        BTreeMap, Bound,
+        BTreeMap, BTreeSet, Bound,
         Bound::{Excluded, Included, Unbounded},
         HashMap, HashSet,
     },
@@ -29,6 +36,7 @@ use std::{
     iter::{self, FromIterator},
     net::SocketAddr,
     sync::Arc,
+    time::{Duration, Instant},
 };
 
 lazy_static! {
@@ -38,12 +46,15 @@ lazy_static! {
     pub(super) static ref PATH_POOL: Pool<Vec<Path>> = Pool::new(256, 10000);
     pub(super) static ref COLS_POOL: Pool<Vec<(Path, Z64)>> = Pool::new(256, 10000);
     pub(super) static ref REF_POOL: Pool<Vec<Referral>> = Pool::new(256, 100);
+    pub(super) static ref METADATA_POOL: Pool<Vec<(Chars, Chars)>> = Pool::new(256, 100);
 }
 
 type Set<T> = ISet<T, 8>;
 pub(super) const MAX_WRITE_BATCH: usize = 100_000;
 pub(super) const MAX_READ_BATCH: usize = 1_000_000;
 pub(super) const GC_THRESHOLD: usize = 100_000;
+pub(super) const MAX_SEARCH_RESULTS: usize = 10_000;
+pub(super) const MAX_LIST_PAGE: usize = 100_000;
 
 fn with_trailing<R, F: FnOnce(&str) -> R>(p: &str, f: F) -> R {
     use std::{cell::RefCell, fmt::Write};
@@ -127,6 +138,11 @@ pub(super) struct Store {
     publishers_by_addr: FxHashMap<SocketAddr, PublisherId>,
     published_by_path: HashMap<Path, Set<PublisherId>>,
     flags_by_path: HashMap<Path, u32>,
+    metadata_by_path: HashMap<Path, Metadata>,
+    // search indexes, path component -> paths, and metadata key ->
+    // value -> paths
+    by_component: HashMap<String, BTreeSet<Path>>,
+    by_metadata: HashMap<Chars, HashMap<Chars, BTreeSet<Path>>>,
     published_by_id: FxHashMap<PublisherId, HashSet<Path>>,
     published_by_level: FxHashMap<usize, BTreeMap<Path, Z64>>,
     columns: HashMap<Path, HashMap<Path, Z64>>,
@@ -134,6 +150,8 @@ pub(super) struct Store {
     defaults_by_id: FxHashMap<PublisherId, HashSet<Path>>,
     parent: Option<Referral>,
     children: BTreeMap<Path, Referral>,
+    // children that registered themselves, and when they expire
+    dynamic: FxHashMap<Path, Instant>,
     sets: HCSet<PublisherId>,
 }
 
@@ -147,6 +165,9 @@ impl Store {
             publishers_by_addr: HashMap::default(),
             published_by_path: HashMap::default(),
             flags_by_path: HashMap::default(),
+            metadata_by_path: HashMap::default(),
+            by_component: HashMap::default(),
+            by_metadata: HashMap::default(),
             published_by_id: HashMap::default(),
             published_by_level: HashMap::default(),
             columns: HashMap::new(),
@@ -154,6 +175,7 @@ impl Store {
             defaults_by_id: HashMap::default(),
             parent,
             children,
+            dynamic: HashMap::default(),
             sets: HCSet::new(),
         };
         let children = t.children.keys().cloned().collect::<Vec<_>>();
@@ -252,6 +274,55 @@ impl Store {
         }
     }
 
+    /// Register a child cluster at `r.path`, or renew it's
+    /// registration. It will expire `r.ttl` seconds after `now` unless
+    /// it is renewed again.
+    pub(super) fn register_referral(&mut self, r: Referral, now: Instant) -> Result<()> {
+        let ttl = match r.ttl {
+            None | Some(0) => bail!("a ttl is required to register a referral"),
+            Some(ttl) => Duration::from_secs(ttl as u64),
+        };
+        let root = self.parent.as_ref().map(|r| r.path.as_ref()).unwrap_or("/");
+        if !Path::is_parent(root, &r.path) || Path::levels(&r.path) <= Path::levels(root)
+        {
+            bail!("child paths must be below the root path {}", root)
+        }
+        check_addrs(&*r.addrs)?;
+        if !self.dynamic.contains_key(&r.path) {
+            if self.children.contains_key(&r.path) {
+                bail!("{} is already a configured child", r.path)
+            }
+            let nested = self
+                .children
+                .keys()
+                .any(|p| Path::is_parent(p, &r.path) || Path::is_parent(&r.path, p));
+            if nested {
+                bail!("can't nest referral {} with another referral", r.path)
+            }
+            // see new
+            self.add_parents(r.path.append("z").as_ref());
+        }
+        self.dynamic.insert(r.path.clone(), now + ttl);
+        self.children.insert(r.path.clone(), r);
+        Ok(())
+    }
+
+    /// Remove registered children that were not renewed in time
+    pub(super) fn expire_referrals(&mut self, now: Instant) {
+        let expired = self
+            .dynamic
+            .iter()
+            .filter(|(_, exp)| **exp <= now)
+            .map(|(p, _)| p.clone())
+            .collect::<Vec<_>>();
+        for path in expired {
+            debug!("registered referral {} expired", path);
+            self.dynamic.remove(&path);
+            self.children.remove(&path);
+            self.remove_parents(path.append("z").as_ref());
+        }
+    }
+
     pub(super) fn referrals_in_scope<T: AsRef<str> + ?Sized>(
         &self,
         refs: &mut Vec<Referral>,
@@ -365,6 +436,7 @@ impl Store {
             self.flags_by_path.insert(path.clone(), flags);
         }
         if up {
+            self.index_path(&path);
             self.add_parents(path.as_ref());
             let n = Path::levels(path.as_ref());
             let cn = self
@@ -459,6 +531,10 @@ impl Store {
                 && !self.defaults.contains_key(&path)
             {
                 self.flags_by_path.remove(&path);
+                if let Some(md) = self.metadata_by_path.remove(&path) {
+                    self.unindex_metadata(&path, &md);
+                }
+                self.unindex_path(&path);
                 if let Some(s) = self.published_by_level.get_mut(&n) {
                     s.remove(&path);
                 };
@@ -489,6 +565,160 @@ impl Store {
         }
     }
 
+    /// Attach `metadata` to `path`, which must already be published
+    /// by `publisher`, either normally or as a default
+    /// publisher. Returns false if it isn't.
+    pub(super) fn set_metadata(
+        &mut self,
+        publisher: &Arc<Publisher>,
+        path: Path,
+        metadata: Metadata,
+    ) -> bool {
+        let published = |by_id: &FxHashMap<PublisherId, HashSet<Path>>| {
+            by_id.get(&publisher.id).map(|s| s.contains(&path)).unwrap_or(false)
+        };
+        if !published(&self.published_by_id) && !published(&self.defaults_by_id) {
+            false
+        } else {
+            if let Some(md) = self.metadata_by_path.remove(&path) {
+                self.unindex_metadata(&path, &md);
+            }
+            if !metadata.is_empty() {
+                self.index_metadata(&path, &metadata);
+                self.metadata_by_path.insert(path, metadata);
+            }
+            true
+        }
+    }
+
+    fn index_path(&mut self, path: &Path) {
+        for part in Path::parts(path) {
+            match self.by_component.get_mut(part) {
+                Some(paths) => {
+                    paths.insert(path.clone());
+                }
+                None => {
+                    let paths = iter::once(path.clone()).collect();
+                    self.by_component.insert(String::from(part), paths);
+                }
+            }
+        }
+    }
+
+    fn unindex_path(&mut self, path: &Path) {
+        for part in Path::parts(path) {
+            if let Some(paths) = self.by_component.get_mut(part) {
+                paths.remove(path);
+                if paths.is_empty() {
+                    self.by_component.remove(part);
+                }
+            }
+        }
+    }
+
+    fn index_metadata(&mut self, path: &Path, metadata: &Metadata) {
+        for (k, v) in metadata.iter() {
+            self.by_metadata
+                .entry(k.clone())
+                .or_insert_with(HashMap::new)
+                .entry(v.clone())
+                .or_insert_with(BTreeSet::new)
+                .insert(path.clone());
+        }
+    }
+
+    fn unindex_metadata(&mut self, path: &Path, metadata: &Metadata) {
+        for (k, v) in metadata.iter() {
+            if let Some(vals) = self.by_metadata.get_mut(k) {
+                if let Some(paths) = vals.get_mut(v) {
+                    paths.remove(path);
+                    if paths.is_empty() {
+                        vals.remove(v);
+                    }
+                }
+                if vals.is_empty() {
+                    self.by_metadata.remove(k);
+                }
+            }
+        }
+    }
+
+    pub(super) fn search(&self, search: &Search) -> Result<SearchResult> {
+        let limit = (search.limit as usize).min(MAX_SEARCH_RESULTS);
+        let regex = match &search.path {
+            Some(PathMatch::Regex(re)) => {
+                Some(RegexBuilder::new(re).size_limit(1024 * 1024).build()?)
+            }
+            Some(PathMatch::Substring(_)) | None => None,
+        };
+        let component_matches = |c: &str| match &search.path {
+            None => true,
+            Some(PathMatch::Substring(s)) => c.contains(&**s),
+            Some(PathMatch::Regex(_)) => regex.as_ref().unwrap().is_match(c),
+        };
+        let metadata_matches = |p: &Path| match self.metadata_by_path.get(p) {
+            None => search.metadata.is_empty(),
+            Some(md) => search.metadata.iter().all(|m| match m {
+                MetadataMatch::Has(k) => md.iter().any(|(mk, _)| mk == k),
+                MetadataMatch::Eq(k, v) => md.iter().any(|(mk, mv)| mk == k && mv == v),
+            }),
+        };
+        // the most selective index available gives the candidates,
+        // which are then checked against the full query. The
+        // component index turns a path search into a scan of every
+        // distinct component rather than every path, it can't prune
+        // substring and regex matches any further than that.
+        let candidates: Vec<&BTreeSet<Path>> = match search.metadata.first() {
+            Some(MetadataMatch::Has(k)) => {
+                self.by_metadata.get(k).into_iter().flat_map(|v| v.values()).collect()
+            }
+            Some(MetadataMatch::Eq(k, v)) => {
+                self.by_metadata.get(k).and_then(|vals| vals.get(v)).into_iter().collect()
+            }
+            None if search.path.is_some() => self
+                .by_component
+                .iter()
+                .filter(|(c, _)| component_matches(c))
+                .map(|(_, paths)| paths)
+                .collect(),
+            None => bail!("search requires a path or metadata predicate"),
+        };
+        let base = &*search.base;
+        let start = match &search.after {
+            Some(after) if &**after > base => Excluded(&**after),
+            Some(_) | None => Included(base),
+        };
+        let mut matched: BTreeSet<&Path> = BTreeSet::new();
+        for paths in candidates {
+            matched.extend(
+                paths
+                    .range::<str, (Bound<&str>, Bound<&str>)>((start, Unbounded))
+                    .take_while(|p| p.starts_with(base))
+                    .filter(|p| {
+                        // only the components below base are matched,
+                        // every path under base matches base itself.
+                        // Without a path pattern base itself can match.
+                        let path_matches = search.path.is_none()
+                            || Path::strip_prefix(base, p)
+                                .map(|rel| Path::parts(rel).any(&component_matches))
+                                .unwrap_or(false);
+                        path_matches && metadata_matches(p)
+                    })
+                    .take(limit + 1),
+            );
+        }
+        let mut paths = PATH_POOL.take();
+        paths.extend(matched.iter().take(limit).map(|p| (*p).clone()));
+        Ok(SearchResult { paths, more: matched.len() > limit })
+    }
+
+    pub(super) fn metadata(&self, path: &Path) -> Metadata {
+        match self.metadata_by_path.get(path) {
+            Some(md) => md.clone(),
+            None => METADATA_POOL.take(),
+        }
+    }
+
     fn get_flags(&self, path: &str) -> u32 {
         self.flags_by_path.get(path).copied().unwrap_or(0)
     }
@@ -661,7 +891,7 @@ impl Store {
     pub(super) fn list_matching(&self, pat: &GlobSet) -> Pooled<Vec<Path>> {
         let mut paths = PATH_POOL.take();
         let mut cur: Option<&str> = None;
-        for glob in pat.iter() {
+        for glob in pat.included() {
             if !cur.map(|p| Path::is_parent(p, glob.base())).unwrap_or(false) {
                 let base = glob.base();
                 let mut n = Path::levels(base) + 1;
@@ -698,6 +928,118 @@ impl Store {
         paths
     }
 
+    /// Like `list`, but only return paths after `after`, and at most
+    /// `limit` of them. Also returns true if there are more.
+    pub(super) fn list_page(
+        &self,
+        parent: &Path,
+        after: Option<&Path>,
+        limit: usize,
+    ) -> (Pooled<Vec<Path>>, bool) {
+        with_trailing(&*parent, |tmp| {
+            let n = Path::levels(parent);
+            let mut paths = PATH_POOL.take();
+            let start = match after {
+                Some(after) if &**after > tmp => &**after,
+                Some(_) | None => tmp,
+            };
+            if let Some(l) = self.published_by_level.get(&(n + 1)) {
+                paths.extend(
+                    l.range::<str, (Bound<&str>, Bound<&str>)>((
+                        Excluded(start),
+                        Unbounded,
+                    ))
+                    .map(|(p, _)| p)
+                    .take_while(|p| Path::is_parent(parent, p))
+                    .take(limit + 1)
+                    .cloned(),
+                )
+            }
+            let more = paths.len() > limit;
+            paths.truncate(limit);
+            (paths, more)
+        })
+    }
+
+    /// Like `list_matching`, but only return paths after `after`, and
+    /// at most `limit` of them. Paths are returned sorted by level,
+    /// and then by name, so a listing can be continued from any
+    /// path. Also returns true if there are more.
+    pub(super) fn list_matching_page(
+        &self,
+        pat: &GlobSet,
+        after: Option<&Path>,
+        limit: usize,
+    ) -> (Pooled<Vec<Path>>, bool) {
+        let mut paths = PATH_POOL.take();
+        // merge globs with overlapping bases, and order the bases so
+        // that their children at each level are in sorted order
+        let mut roots: Vec<(&str, Scope)> = Vec::new();
+        for glob in pat.included() {
+            match roots.iter_mut().find(|(b, _)| Path::is_parent(*b, glob.base())) {
+                None => roots.push((glob.base(), *glob.scope())),
+                Some((_, scope)) => {
+                    *scope = match (*scope, *glob.scope()) {
+                        (Scope::Subtree, _) | (_, Scope::Subtree) => Scope::Subtree,
+                        (Scope::Finite(n0), Scope::Finite(n1)) => {
+                            Scope::Finite(n0.max(n1))
+                        }
+                    }
+                }
+            }
+        }
+        roots.sort_by(|(b0, _), (b1, _)| {
+            let b0 = b0.bytes().chain(iter::once(b'/'));
+            b0.cmp(b1.bytes().chain(iter::once(b'/')))
+        });
+        let first = after.map(Path::levels).unwrap_or(0);
+        let last = self.published_by_level.keys().copied().max().unwrap_or(0);
+        'levels: for n in first..=last {
+            let l = match self.published_by_level.get(&n) {
+                None => continue,
+                Some(l) => l,
+            };
+            for (base, scope) in roots.iter() {
+                if n <= Path::levels(base) || !scope.contains(n) {
+                    continue;
+                }
+                let full = with_trailing(base, |tmp| {
+                    let start = match after {
+                        Some(after) if Path::levels(after) == n && &**after > tmp => {
+                            Excluded(&**after)
+                        }
+                        Some(_) | None if *base == "/" => Unbounded,
+                        Some(_) | None => Excluded(tmp),
+                    };
+                    let iter = l
+                        .range::<str, (Bound<&str>, Bound<&str>)>((start, Unbounded))
+                        .map(|(p, _)| p)
+                        .take_while(|p| Path::is_parent(base, p));
+                    for path in iter {
+                        let dn = Path::dirname(path).unwrap_or("/");
+                        if pat.is_match(path)
+                            && !self.children.contains_key(dn)
+                            && (!pat.published_only()
+                                || self.published_by_path.contains_key(path))
+                        {
+                            paths.push(path.clone());
+                            if paths.len() > limit {
+                                return true;
+                            }
+                        }
+                    }
+                    false
+                });
+                if full {
+                    break 'levels;
+                }
+            }
+        }
+        let more = paths.len() > limit;
+        paths.truncate(limit);
+        (paths, more)
+    }
+
     pub(super) fn get_change_nr(&self, path: &Path) -> Z64 {
         self.published_by_level
             .get(&Path::levels(path))
@@ -1,8 +1,16 @@
 use super::store::Store;
 use crate::{
+    chars::Chars,
     pack::Z64,
     path::Path,
-    protocol::resolver::{HashMethod, Publisher, PublisherId, PublisherRef, TargetAuth},
+    pool::Pooled,
+    protocol::{
+        glob::{Glob, GlobSet},
+        resolver::{
+            Auth, HashMethod, MetadataMatch, PathMatch, Publisher, PublisherId,
+            PublisherRef, Referral, Search, SearchResult, TargetAuth,
+        },
+    },
 };
 use bytes::Bytes;
 use fxhash::FxHashMap;
@@ -11,6 +19,7 @@ use std::{
     collections::{BTreeMap, HashMap},
     net::SocketAddr,
     sync::Arc,
+    time::{Duration, Instant},
 };
 
 #[test]
@@ -181,3 +190,203 @@ fn test_resolver_store() {
     let cols = store.columns(&Path::from("/app/test"));
     assert_eq!(cols.len(), 0);
 }
+
+#[test]
+fn test_resolver_store_search() {
+    let addr = "127.0.0.1:100".parse::<SocketAddr>().unwrap();
+    let publisher = Arc::new(Publisher {
+        id: PublisherId::new(),
+        addr,
+        hash_method: HashMethod::Sha3_512,
+        resolver: addr,
+        target_auth: TargetAuth::Anonymous,
+        user_info: None,
+    });
+    let mut store = Store::new(None, BTreeMap::new());
+    let paths = [
+        "/site/temp/s0",
+        "/site/temp/s1",
+        "/site/hvac/supply_temp",
+        "/site/hvac/fan",
+        "/site-b/temp/s0",
+    ];
+    for p in paths {
+        store.publish(Path::from(p), &publisher, false, None);
+    }
+    let md = |kvs: &[(&'static str, &'static str)]| {
+        Pooled::orphan(
+            kvs.iter()
+                .map(|(k, v)| (Chars::from(*k), Chars::from(*v)))
+                .collect::<Vec<_>>(),
+        )
+    };
+    store.set_metadata(&publisher, Path::from("/site/temp/s0"), md(&[("unit", "C")]));
+    store.set_metadata(&publisher, Path::from("/site/temp/s1"), md(&[("unit", "F")]));
+    store.set_metadata(
+        &publisher,
+        Path::from("/site/hvac/supply_temp"),
+        md(&[("unit", "C")]),
+    );
+    let search = |base: &'static str,
+                  path: Option<PathMatch>,
+                  metadata: Vec<MetadataMatch>,
+                  after: Option<&'static str>,
+                  limit: u32| Search {
+        base: Path::from(base),
+        path,
+        metadata: Pooled::orphan(metadata),
+        after: after.map(Path::from),
+        limit,
+    };
+    let names =
+        |r: SearchResult| r.paths.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+    let temp = Some(PathMatch::Substring(Chars::from("temp")));
+    let r = store.search(&search("/site", temp.clone(), vec![], None, 100)).unwrap();
+    assert!(!r.more);
+    assert_eq!(names(r), ["/site/hvac/supply_temp", "/site/temp/s0", "/site/temp/s1"]);
+    let r = store.search(&search("/site", temp.clone(), vec![], None, 2)).unwrap();
+    assert!(r.more);
+    assert_eq!(names(r), ["/site/hvac/supply_temp", "/site/temp/s0"]);
+    let after = Some("/site/temp/s0");
+    let r = store.search(&search("/site", temp.clone(), vec![], after, 2)).unwrap();
+    assert!(!r.more);
+    assert_eq!(names(r), ["/site/temp/s1"]);
+    let unit_c = MetadataMatch::Eq(Chars::from("unit"), Chars::from("C"));
+    let r = store.search(&search("/", None, vec![unit_c.clone()], None, 100)).unwrap();
+    assert_eq!(names(r), ["/site/hvac/supply_temp", "/site/temp/s0"]);
+    let has_unit = MetadataMatch::Has(Chars::from("unit"));
+    let re = Some(PathMatch::Regex(Chars::from("^s[0-9]$")));
+    let r = store.search(&search("/", re, vec![has_unit], None, 100)).unwrap();
+    assert_eq!(names(r), ["/site/temp/s0", "/site/temp/s1"]);
+    let bad_re = Some(PathMatch::Regex(Chars::from("(")));
+    assert!(store.search(&search("/", bad_re, vec![], None, 100)).is_err());
+    assert!(store.search(&search("/", None, vec![], None, 100)).is_err());
+    store.unpublish(&publisher, false, Path::from("/site/temp/s0"));
+    let r = store.search(&search("/", None, vec![unit_c], None, 100)).unwrap();
+    assert_eq!(names(r), ["/site/hvac/supply_temp"]);
+    let r = store.search(&search("/site", temp, vec![], None, 100)).unwrap();
+    assert_eq!(names(r), ["/site/hvac/supply_temp", "/site/temp/s1"]);
+}
+
+fn page_all<F>(f: F) -> Vec<Path>
+where
+    F: Fn(Option<&Path>) -> (Pooled<Vec<Path>>, bool),
+{
+    let mut all: Vec<Path> = vec![];
+    loop {
+        let (page, more) = f(all.last());
+        assert!(page.len() <= 3);
+        all.extend(page.iter().cloned());
+        if !more {
+            break all;
+        }
+    }
+}
+
+#[test]
+fn test_resolver_store_list_page() {
+    let addr = "127.0.0.1:100".parse::<SocketAddr>().unwrap();
+    let publisher = Arc::new(Publisher {
+        id: PublisherId::new(),
+        addr,
+        hash_method: HashMethod::Sha3_512,
+        resolver: addr,
+        target_auth: TargetAuth::Anonymous,
+        user_info: None,
+    });
+    let mut store = Store::new(None, BTreeMap::new());
+    for i in 0..20 {
+        for p in [format!("/a/{}/v", i), format!("/a-b/{}", i), format!("/c/{}", i)] {
+            store.publish(Path::from(p), &publisher, false, None);
+        }
+    }
+    let mut expected = store.list(&Path::from("/a"));
+    expected.sort();
+    let paged = page_all(|after| store.list_page(&Path::from("/a"), after, 3));
+    assert_eq!(&*expected, &paged);
+    let globs = vec![
+        Glob::new(Chars::from("/a/**")).unwrap(),
+        Glob::new(Chars::from("/a-b/*")).unwrap(),
+        Glob::new(Chars::from("/a/1*/*")).unwrap(),
+    ];
+    for published_only in [true, false] {
+        let set = GlobSet::new(published_only, globs.clone()).unwrap();
+        let mut expected = store.list_matching(&set).drain(..).collect::<Vec<_>>();
+        expected.sort_by(|p0, p1| (Path::levels(p0), p0).cmp(&(Path::levels(p1), p1)));
+        expected.dedup();
+        let paged = page_all(|after| store.list_matching_page(&set, after, 3));
+        assert_eq!(expected, paged);
+    }
+}
+
+#[test]
+fn test_resolver_store_list_matching_exclude() {
+    let addr = "127.0.0.1:100".parse::<SocketAddr>().unwrap();
+    let publisher = Arc::new(Publisher {
+        id: PublisherId::new(),
+        addr,
+        hash_method: HashMethod::Sha3_512,
+        resolver: addr,
+        target_auth: TargetAuth::Anonymous,
+        user_info: None,
+    });
+    let mut store = Store::new(None, BTreeMap::new());
+    for i in 0..20 {
+        for p in [format!("/a/{}/v", i), format!("/a/{}/debug", i), format!("/c/{}", i)] {
+            store.publish(Path::from(p), &publisher, false, None);
+        }
+    }
+    let globs = ["!/a/**/debug", "/a/**", "!/a/1*/**", "re:/c/1[0-9]", "/a/1/**"]
+        .into_iter()
+        .map(|g| Glob::new(Chars::from(g)).unwrap());
+    let set = GlobSet::new(true, globs).unwrap();
+    let mut paths = store.list_matching(&set).drain(..).collect::<Vec<_>>();
+    paths.sort();
+    paths.dedup();
+    let mut expected = (0..10)
+        .filter(|i| *i != 1)
+        .map(|i| Path::from(format!("/a/{}/v", i)))
+        .chain((10..20).map(|i| Path::from(format!("/c/{}", i))))
+        .collect::<Vec<_>>();
+    expected.sort();
+    assert_eq!(paths, expected);
+    expected.sort_by(|p0, p1| (Path::levels(p0), p0).cmp(&(Path::levels(p1), p1)));
+    let paged = page_all(|after| store.list_matching_page(&set, after, 3));
+    assert_eq!(expected, paged);
+}
+
+#[test]
+fn test_resolver_store_dynamic_referral() {
+    let referral = |path: &str, ttl: Option<u16>| Referral {
+        path: Path::from(String::from(path)),
+        ttl,
+        addrs: Pooled::orphan(vec![(
+            "127.0.0.1:4564".parse::<SocketAddr>().unwrap(),
+            Auth::Anonymous,
+        )]),
+    };
+    let static_child = referral("/static", Some(60));
+    let mut children = BTreeMap::new();
+    children.insert(static_child.path.clone(), static_child);
+    let mut store = Store::new(None, children);
+    let now = Instant::now();
+    assert!(store.register_referral(referral("/dyn/a", None), now).is_err());
+    assert!(store.register_referral(referral("/dyn/a", Some(0)), now).is_err());
+    assert!(store.register_referral(referral("/static", Some(10)), now).is_err());
+    assert!(store.register_referral(referral("/static/a", Some(10)), now).is_err());
+    store.register_referral(referral("/dyn/a", Some(10)), now).unwrap();
+    assert!(store.register_referral(referral("/dyn", Some(10)), now).is_err());
+    assert!(store.register_referral(referral("/dyn/a/b", Some(10)), now).is_err());
+    assert_eq!(&*store.list(&Path::from("/dyn")), &[Path::from("/dyn/a")]);
+    assert!(store.check_referral(&Path::from("/dyn/a/foo")).is_some());
+    // renewing moves the expiration forward
+    let later = now + Duration::from_secs(8);
+    store.register_referral(referral("/dyn/a", Some(10)), later).unwrap();
+    store.expire_referrals(now + Duration::from_secs(12));
+    assert!(store.check_referral(&Path::from("/dyn/a/foo")).is_some());
+    store.expire_referrals(later + Duration::from_secs(10));
+    assert!(store.check_referral(&Path::from("/dyn/a/foo")).is_none());
+    assert!(store.list(&Path::from("/dyn")).is_empty());
+    assert!(store.list(&Path::from("/")).iter().all(|p| &**p != "/dyn"));
+    assert!(store.check_referral(&Path::from("/static/foo")).is_some());
+}
@@ -1,4 +1,5 @@
 mod connection;
+mod tree;
 pub use crate::protocol::value::{FromValue, Typ, Value};
 pub use crate::resolver_client::DesiredAuth;
 use crate::{
@@ -49,6 +50,7 @@ use tokio::{
     task,
     time::{self, Instant},
 };
+pub use tree::TreeSubscription;
 use triomphe::Arc as TArc;
 
 type StreamsInner<T> = Arc<Vec<(T, ChanWrap<Pooled<Vec<(SubId, Event)>>>)>>;
@@ -1329,6 +1331,7 @@ impl Subscriber {
                 St::Subscribed(raw) => (path, Ok(raw)),
                 St::Error(e) => {
                     let mut t = sub.0.lock();
+                    t.resolver.invalidate(&path);
                     if let Some(sub) = t.subscribed.remove(path.as_ref()) {
                         match sub {
                             SubStatus::Subscribed(_) => unreachable!(),
@@ -1354,6 +1357,10 @@ impl Subscriber {
                         Ok(Ok(raw)) => Ok(raw),
                     };
                     let mut t = sub.0.lock();
+                    if res.is_err() {
+                        // the publisher may have gone away or moved
+                        t.resolver.invalidate(&path);
+                    }
                     match t.subscribed.entry(path.clone()) {
                         Entry::Vacant(_) => unreachable!(),
                         Entry::Occupied(mut e) => match res {
@@ -0,0 +1,195 @@
+use super::{Dval, Event, SubId, Subscriber, UpdatesFlags, Value};
+use crate::{
+    chars::Chars,
+    path::Path,
+    pool::Pooled,
+    protocol::{
+        glob::{Glob, GlobSet},
+        json::to_json,
+    },
+    resolver_client::ChangeTracker,
+};
+use anyhow::Result;
+use futures::{channel::mpsc, prelude::*, select_biased};
+use fxhash::FxHashMap;
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value as JValue};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    marker::PhantomData,
+    time::Duration,
+};
+use tokio::time;
+
+/// Subscribe to a tree published by `Publisher::publish_tree`, and
+/// reassemble it into a `T` whenever any leaf changes. Leaves added
+/// or removed by the publisher are discovered by polling the
+/// resolver every `poll_interval`.
+///
+/// Leaves are converted to JSON with `netidx_netproto::json::to_json`
+/// before deserializing, so leaves that aren't null, bools, strings,
+/// `I64`, `F64`, arrays, or maps must be deserialized from their
+/// tagged form. That includes integers larger than `i64::MAX`, which
+/// `publish_tree` publishes as `U64`.
+pub struct TreeSubscription<T> {
+    subscriber: Subscriber,
+    base: Path,
+    globs: GlobSet,
+    ctrack: ChangeTracker,
+    leaves: BTreeMap<Path, Dval>,
+    by_id: FxHashMap<SubId, Path>,
+    values: BTreeMap<Path, Value>,
+    tx: mpsc::Sender<Pooled<Vec<(SubId, Event)>>>,
+    rx: mpsc::Receiver<Pooled<Vec<(SubId, Event)>>>,
+    poll: time::Interval,
+    t: PhantomData<T>,
+}
+
+impl<T: DeserializeOwned> TreeSubscription<T> {
+    /// Subscribe to the tree under `base`
+    pub async fn new(subscriber: Subscriber, base: Path) -> Result<Self> {
+        Self::new_with_poll_interval(subscriber, base, Duration::from_secs(1)).await
+    }
+
+    /// Subscribe to the tree under `base`, checking for structural
+    /// changes every `poll_interval`.
+    pub async fn new_with_poll_interval(
+        subscriber: Subscriber,
+        base: Path,
+        poll_interval: Duration,
+    ) -> Result<Self> {
+        let glob = Glob::new(Chars::from(String::from(&*base.append("**"))))?;
+        let globs = GlobSet::new(true, [glob])?;
+        let (tx, rx) = mpsc::channel(10);
+        let mut poll = time::interval(poll_interval);
+        poll.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+        let mut t = TreeSubscription {
+            subscriber,
+            ctrack: ChangeTracker::new(base.clone()),
+            base,
+            globs,
+            leaves: BTreeMap::new(),
+            by_id: FxHashMap::default(),
+            values: BTreeMap::new(),
+            tx,
+            rx,
+            poll,
+            t: PhantomData,
+        };
+        t.poll_structure().await?;
+        Ok(t)
+    }
+
+    /// The base path of the tree
+    pub fn base(&self) -> &Path {
+        &self.base
+    }
+
+    /// Check the resolver for leaves that have been added or removed,
+    /// and adjust the subscriptions accordingly. Return true if the
+    /// set of leaves changed. This is called automatically by
+    /// `next`.
+    pub async fn poll_structure(&mut self) -> Result<bool> {
+        if !self.subscriber.resolver().check_changed(&mut self.ctrack).await? {
+            return Ok(false);
+        }
+        let mut batches = self.subscriber.resolver().list_matching(&self.globs).await?;
+        let mut all = BTreeSet::new();
+        for mut batch in batches.drain(..) {
+            all.extend(batch.drain(..));
+        }
+        let mut changed = false;
+        self.leaves.retain(|path, dv| {
+            let keep = all.contains(path);
+            if !keep {
+                changed = true;
+                self.by_id.remove(&dv.id());
+                self.values.remove(path);
+            }
+            keep
+        });
+        for path in all {
+            if !self.leaves.contains_key(&path) {
+                changed = true;
+                let dv = self.subscriber.subscribe(path.clone());
+                dv.updates(UpdatesFlags::BEGIN_WITH_LAST, self.tx.clone());
+                self.by_id.insert(dv.id(), path.clone());
+                self.leaves.insert(path, dv);
+            }
+        }
+        Ok(changed)
+    }
+
+    /// Reassemble the current state of the tree. This will fail if
+    /// any leaf doesn't have a value yet, or if the leaves don't
+    /// deserialize to a `T`.
+    pub fn last(&self) -> Result<T> {
+        if self.values.len() < self.leaves.len() {
+            bail!("not all leaves under {} are subscribed", self.base)
+        }
+        let mut root = Map::new();
+        for (path, v) in &self.values {
+            let rel = match Path::strip_prefix(&self.base, path) {
+                Some(rel) => rel,
+                None => bail!("{} is not under {}", path, self.base),
+            };
+            let mut parts = Path::parts(rel)
+                .map(|p| Path::unescape(p).into_owned())
+                .collect::<Vec<_>>();
+            let last = match parts.pop() {
+                Some(last) => last,
+                None => bail!("{} is not under {}", path, self.base),
+            };
+            let mut cur = &mut root;
+            for part in parts {
+                cur = match cur.entry(part).or_insert_with(|| JValue::Object(Map::new()))
+                {
+                    JValue::Object(m) => m,
+                    _ => bail!("{} is under a leaf", path),
+                };
+            }
+            if cur.insert(last, to_json(v)).is_some() {
+                bail!("{} is both a leaf and a subtree", path)
+            }
+        }
+        Ok(serde_json::from_value(JValue::Object(root))?)
+    }
+
+    /// Wait for the tree to change, and then return the reassembled
+    /// `T`. States of the tree that can't be reassembled, for example
+    /// because the publisher is part way through changing the
+    /// structure, are skipped.
+    pub async fn next(&mut self) -> Result<T> {
+        loop {
+            let batch = select_biased! {
+                b = self.rx.select_next_some() => Some(b),
+                _ = self.poll.tick().fuse() => None,
+            };
+            let changed = match batch {
+                None => self.poll_structure().await?,
+                Some(mut batch) => {
+                    let mut changed = false;
+                    for (id, ev) in batch.drain(..) {
+                        if let Some(path) = self.by_id.get(&id) {
+                            changed = true;
+                            match ev {
+                                Event::Update(v) => {
+                                    self.values.insert(path.clone(), v);
+                                }
+                                Event::Unsubscribed => {
+                                    self.values.remove(path);
+                                }
+                            }
+                        }
+                    }
+                    changed
+                }
+            };
+            if changed {
+                if let Ok(t) = self.last() {
+                    break Ok(t);
+                }
+            }
+        }
+    }
+}
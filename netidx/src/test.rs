@@ -3,15 +3,37 @@ mod resolver {
         chars::Chars,
         config::Config as ClientConfig,
         path::Path,
-        protocol::glob::{Glob, GlobSet},
+        pool::Pooled,
+        protocol::{
+            glob::{Glob, GlobSet},
+            resolver::{Auth, Publisher, PublisherId, Referral},
+        },
         publisher::PublishFlags,
-        resolver_client::{ChangeTracker, DesiredAuth, ResolverRead, ResolverWrite},
+        resolver_client::{
+            ChangeTracker, DesiredAuth, MetadataMatch, PathMatch, Resolved, ResolverRead,
+            ResolverWrite, Search,
+        },
         resolver_server::{config::Config as ServerConfig, Server},
     };
+    use futures::{future, Future, StreamExt};
+    use fxhash::FxHashMap;
     use netidx_netproto::resolver::TargetAuth;
     use rand::{thread_rng, Rng};
-    use std::{iter, net::SocketAddr, time::Duration};
-    use tokio::{runtime::Runtime, time};
+    use std::{
+        iter,
+        net::SocketAddr,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+    use tokio::{
+        io::{self, AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+        runtime::Runtime,
+        task, time,
+    };
 
     fn p(p: &'static str) -> Path {
         Path::from(p)
@@ -87,6 +109,164 @@ mod resolver {
         });
     }
 
+    #[test]
+    fn metadata() {
+        Runtime::new().unwrap().block_on(async {
+            let server_cfg = ServerConfig::load("../cfg/simple-server.json")
+                .expect("load simple server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            client_cfg.addrs[0].0 = *server.local_addr();
+            let paddr: SocketAddr = "127.0.0.1:1".parse().unwrap();
+            let w = ResolverWrite::new(client_cfg.clone(), DesiredAuth::Anonymous, paddr)
+                .unwrap();
+            let r = ResolverRead::new(client_cfg, DesiredAuth::Anonymous);
+            let md = |kvs: &[(&'static str, &'static str)]| {
+                Pooled::orphan(
+                    kvs.iter()
+                        .map(|(k, v)| (Chars::from(*k), Chars::from(*v)))
+                        .collect::<Vec<_>>(),
+                )
+            };
+            assert!(w.set_metadata(iter::once((p("/foo"), md(&[])))).await.is_err());
+            w.publish(iter::once(p("/foo"))).await.unwrap();
+            assert_eq!(r.metadata(p("/foo")).await.unwrap().len(), 0);
+            let kvs = [("description", "a foo"), ("units", "meters")];
+            w.set_metadata(iter::once((p("/foo"), md(&kvs)))).await.unwrap();
+            assert_eq!(&**r.metadata(p("/foo")).await.unwrap(), &**md(&kvs));
+            w.unpublish(iter::once(p("/foo"))).await.unwrap();
+            assert_eq!(r.metadata(p("/foo")).await.unwrap().len(), 0);
+            drop(server)
+        });
+    }
+
+    #[test]
+    fn search() {
+        Runtime::new().unwrap().block_on(async {
+            let server_cfg = ServerConfig::load("../cfg/simple-server.json")
+                .expect("load simple server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            client_cfg.addrs[0].0 = *server.local_addr();
+            let paddr: SocketAddr = "127.0.0.1:1".parse().unwrap();
+            let w = ResolverWrite::new(client_cfg.clone(), DesiredAuth::Anonymous, paddr)
+                .unwrap();
+            let r = ResolverRead::new(client_cfg, DesiredAuth::Anonymous);
+            let mut paths = (0..100)
+                .map(|i| Path::from(format!("/app/sensor{}/temp", i)))
+                .chain((0..100).map(|i| Path::from(format!("/app/sensor{}/rh", i))))
+                .collect::<Vec<_>>();
+            w.publish(paths.iter().cloned()).await.unwrap();
+            paths.retain(|p| p.ends_with("temp"));
+            paths.sort();
+            let mut found = vec![];
+            let mut after = None;
+            loop {
+                let res = r
+                    .search(Search {
+                        base: p("/app"),
+                        path: Some(PathMatch::Regex(Chars::from("^te"))),
+                        metadata: Pooled::orphan(vec![]),
+                        after: after.clone(),
+                        limit: 7,
+                    })
+                    .await
+                    .unwrap();
+                assert!(res.paths.len() <= 7);
+                found.extend(res.paths.iter().cloned());
+                after = found.last().cloned();
+                if !res.more {
+                    break;
+                }
+            }
+            assert_eq!(found, paths);
+            // the components of base itself don't match
+            let res = r
+                .search(Search {
+                    base: p("/app"),
+                    path: Some(PathMatch::Substring(Chars::from("app"))),
+                    metadata: Pooled::orphan(vec![]),
+                    after: None,
+                    limit: 7,
+                })
+                .await
+                .unwrap();
+            assert!(res.paths.is_empty());
+            // but a metadata only search can match base itself
+            let base = p("/app/sensor0/temp");
+            let md = Pooled::orphan(vec![(Chars::from("units"), Chars::from("C"))]);
+            w.set_metadata(iter::once((base.clone(), md))).await.unwrap();
+            let res = r
+                .search(Search {
+                    base: base.clone(),
+                    path: None,
+                    metadata: Pooled::orphan(vec![MetadataMatch::Has(Chars::from(
+                        "units",
+                    ))]),
+                    after: None,
+                    limit: 7,
+                })
+                .await
+                .unwrap();
+            assert_eq!(&*res.paths, &[base]);
+            drop(server)
+        });
+    }
+
+    #[test]
+    fn list_stream() {
+        Runtime::new().unwrap().block_on(async {
+            let server_cfg = ServerConfig::load("../cfg/simple-server.json")
+                .expect("load simple server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            client_cfg.addrs[0].0 = *server.local_addr();
+            let paddr: SocketAddr = "127.0.0.1:1".parse().unwrap();
+            let w = ResolverWrite::new(client_cfg.clone(), DesiredAuth::Anonymous, paddr)
+                .unwrap();
+            let r = ResolverRead::new(client_cfg, DesiredAuth::Anonymous);
+            let paths = (0..100)
+                .map(|i| Path::from(format!("/app/{}/v", i)))
+                .chain((0..100).map(|i| Path::from(format!("/app/{}", i))))
+                .collect::<Vec<_>>();
+            w.publish(paths.iter().cloned()).await.unwrap();
+            let mut expected = r.list(p("/app")).await.unwrap();
+            expected.sort();
+            let mut listed = vec![];
+            let pages = r.list_stream(p("/app"), 7);
+            futures::pin_mut!(pages);
+            while let Some(page) = pages.next().await {
+                let page = page.unwrap();
+                assert!(page.len() <= 7);
+                listed.extend(page.iter().cloned());
+            }
+            assert_eq!(&*expected, &listed);
+            let glob = Glob::new(Chars::from("/app/**")).unwrap();
+            let globs = GlobSet::new(true, iter::once(glob)).unwrap();
+            let mut expected = r
+                .list_matching(&globs)
+                .await
+                .unwrap()
+                .iter()
+                .flat_map(|b| b.iter().cloned())
+                .collect::<Vec<_>>();
+            expected
+                .sort_by(|p0, p1| (Path::levels(p0), p0).cmp(&(Path::levels(p1), p1)));
+            let mut listed = vec![];
+            let pages = r.list_matching_stream(globs, 7);
+            futures::pin_mut!(pages);
+            while let Some(page) = pages.next().await {
+                listed.extend(page.unwrap().iter().cloned());
+            }
+            assert_eq!(expected.len(), paths.len());
+            assert_eq!(expected, listed);
+            drop(server)
+        });
+    }
+
     struct Ctx {
         _local: Server,
         _root: (Server, Server),
@@ -229,6 +409,14 @@ mod resolver {
                 p("/app/huge1/z")
             ]
         );
+        let mut ls = Vec::new();
+        let pages = r.list_matching_stream(pset, 2);
+        futures::pin_mut!(pages);
+        while let Some(page) = pages.next().await {
+            ls.extend(page.unwrap().drain(..));
+        }
+        ls.sort();
+        assert_eq!(l, ls);
     }
 
     async fn check_resolve(
@@ -369,6 +557,535 @@ mod resolver {
         let _ = env_logger::try_init();
         Runtime::new().unwrap().block_on(run_publish_resolve_complex())
     }
+
+    fn free_addr() -> SocketAddr {
+        let l = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        l.local_addr().unwrap()
+    }
+
+    fn replicated_cfg(members: &[(SocketAddr, SocketAddr)]) -> ServerConfig {
+        let members = members
+            .iter()
+            .map(|(addr, replica_addr)| {
+                format!(
+                    r#"{{"pid_file": "", "addr": "{}", "replica_addr": "{}",
+                        "max_connections": 768, "hello_timeout": 10,
+                        "reader_ttl": 60, "writer_ttl": 120, "auth": "Anonymous"}}"#,
+                    addr, replica_addr
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        ServerConfig::parse(&format!(
+            r#"{{"parent": null, "children": [], "member_servers": [{}], "perms": {{}}}}"#,
+            members
+        ))
+        .expect("replicated server config")
+    }
+
+    fn client_cfg(addrs: &[SocketAddr]) -> ClientConfig {
+        let addrs = addrs
+            .iter()
+            .map(|a| format!(r#"["{}", "Anonymous"]"#, a))
+            .collect::<Vec<_>>()
+            .join(",");
+        ClientConfig::parse(&format!(r#"{{"addrs": [{}], "base": "/"}}"#, addrs))
+            .expect("client config")
+    }
+
+    async fn start_member(cfg: &ServerConfig, id: usize) -> Server {
+        // a stopped member may take a moment to release it's ports
+        for _ in 0..100 {
+            match Server::new(cfg.clone(), false, id).await {
+                Ok(server) => return server,
+                Err(_) => time::sleep(Duration::from_millis(100)).await,
+            }
+        }
+        panic!("member {} failed to start", id)
+    }
+
+    async fn retry_write<F: Future<Output = anyhow::Result<()>>>(f: impl Fn() -> F) {
+        // writes fail while the cluster elects a new leader
+        for _ in 0..100 {
+            if f().await.is_ok() {
+                return;
+            }
+            time::sleep(Duration::from_millis(100)).await
+        }
+        panic!("write failed")
+    }
+
+    async fn wait_converged(addr: SocketAddr, expected: &[Path], paddr: SocketAddr) {
+        let timeout = Duration::from_secs(1);
+        for _ in 0..300 {
+            let r = ResolverRead::new(client_cfg(&[addr]), DesiredAuth::Anonymous);
+            if let Ok(Ok(mut l)) = time::timeout(timeout, r.list(p("/app"))).await {
+                l.sort();
+                if &**l == expected {
+                    let (publishers, resolved) =
+                        r.resolve(expected.iter().cloned()).await.unwrap();
+                    for r in resolved.iter() {
+                        assert_eq!(r.publishers.len(), 1);
+                        assert_eq!(publishers[&r.publishers[0].id].addr, paddr);
+                    }
+                    return;
+                }
+            }
+            time::sleep(Duration::from_millis(100)).await
+        }
+        panic!("member {} did not converge", addr)
+    }
+
+    async fn run_replication() {
+        let members = (0..3).map(|_| (free_addr(), free_addr())).collect::<Vec<_>>();
+        let cfg = replicated_cfg(&members);
+        let addrs = members.iter().map(|(a, _)| *a).collect::<Vec<_>>();
+        let mut servers = Vec::new();
+        for i in 0..members.len() {
+            servers.push(Some(start_member(&cfg, i).await));
+        }
+        let paddr: SocketAddr = "127.0.0.1:1".parse().unwrap();
+        let w = ResolverWrite::new(client_cfg(&addrs), DesiredAuth::Anonymous, paddr)
+            .unwrap();
+        let paths = vec![p("/app/v0"), p("/app/v1"), p("/app/v2")];
+        retry_write(|| w.publish(paths.iter().cloned())).await;
+        for a in &addrs {
+            wait_converged(*a, &paths, paddr).await;
+        }
+        // the rest of the cluster carries on without member 2, and it
+        // catches up when it comes back
+        servers[2] = None;
+        retry_write(|| w.unpublish(iter::once(p("/app/v0")))).await;
+        retry_write(|| w.publish(iter::once(p("/app/v3")))).await;
+        let paths = vec![p("/app/v1"), p("/app/v2"), p("/app/v3")];
+        for a in &addrs[0..2] {
+            wait_converged(*a, &paths, paddr).await;
+        }
+        servers[2] = Some(start_member(&cfg, 2).await);
+        for a in &addrs {
+            wait_converged(*a, &paths, paddr).await;
+        }
+        drop(servers)
+    }
+
+    #[test]
+    fn replication() {
+        let _ = env_logger::try_init();
+        Runtime::new().unwrap().block_on(run_replication())
+    }
+
+    // replica_restart runs the members and the writer in child
+    // processes so it can stop and kill them. The children run the
+    // replica_process test, which does nothing unless the role is set.
+    #[cfg(unix)]
+    const ROLE: &str = "NETIDX_TEST_REPLICA_ROLE";
+    #[cfg(unix)]
+    const MEMBERS: &str = "NETIDX_TEST_REPLICA_MEMBERS";
+    #[cfg(unix)]
+    const ID: &str = "NETIDX_TEST_REPLICA_ID";
+    #[cfg(unix)]
+    const PADDR: &str = "NETIDX_TEST_REPLICA_PADDR";
+
+    #[cfg(unix)]
+    struct Proc {
+        child: std::process::Child,
+        stdin: std::process::ChildStdin,
+        stdout: std::io::BufReader<std::process::ChildStdout>,
+    }
+
+    #[cfg(unix)]
+    impl Proc {
+        fn spawn(role: &str, members: &[(SocketAddr, SocketAddr)], id: usize) -> Self {
+            use std::process::{Command, Stdio};
+            let members = members
+                .iter()
+                .map(|(a, r)| format!("{}/{}", a, r))
+                .collect::<Vec<_>>()
+                .join(",");
+            let mut child = Command::new(std::env::current_exe().unwrap())
+                .args(["--exact", "test::resolver::replica_process"])
+                .args(["--ignored", "--nocapture", "--test-threads=1"])
+                .env(ROLE, role)
+                .env(MEMBERS, members)
+                .env(ID, id.to_string())
+                .env(PADDR, "127.0.0.1:1")
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .spawn()
+                .unwrap();
+            let stdin = child.stdin.take().unwrap();
+            let stdout = std::io::BufReader::new(child.stdout.take().unwrap());
+            Proc { child, stdin, stdout }
+        }
+
+        fn member(members: &[(SocketAddr, SocketAddr)], id: usize) -> Self {
+            let mut t = Proc::spawn("member", members, id);
+            t.wait_for("ready");
+            t
+        }
+
+        // the test harness prints to stdout too, skip it's output
+        fn wait_for(&mut self, line: &str) {
+            use std::io::BufRead;
+            let mut buf = String::new();
+            loop {
+                buf.clear();
+                if self.stdout.read_line(&mut buf).unwrap() == 0 {
+                    panic!("child {} exited", self.child.id())
+                }
+                if buf.trim() == line {
+                    break;
+                }
+            }
+        }
+
+        fn publish(&mut self, path: &Path) {
+            use std::io::Write;
+            writeln!(self.stdin, "{}", path).unwrap();
+            self.wait_for("ok")
+        }
+
+        fn signal(&self, sig: &str) {
+            let status = std::process::Command::new("kill")
+                .arg(format!("-{}", sig))
+                .arg(self.child.id().to_string())
+                .status()
+                .unwrap();
+            assert!(status.success())
+        }
+    }
+
+    #[cfg(unix)]
+    impl Drop for Proc {
+        fn drop(&mut self) {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    #[ignore]
+    fn replica_process() {
+        use std::io::BufRead;
+        let role = match std::env::var(ROLE) {
+            Ok(role) => role,
+            Err(_) => return,
+        };
+        let members = std::env::var(MEMBERS)
+            .unwrap()
+            .split(',')
+            .map(|m| {
+                let (addr, replica_addr) = m.split_once('/').unwrap();
+                (addr.parse().unwrap(), replica_addr.parse().unwrap())
+            })
+            .collect::<Vec<(SocketAddr, SocketAddr)>>();
+        let id: usize = std::env::var(ID).unwrap().parse().unwrap();
+        let paddr: SocketAddr = std::env::var(PADDR).unwrap().parse().unwrap();
+        let rt = Runtime::new().unwrap();
+        let _guard = rt.enter();
+        let stdin = std::io::stdin();
+        match role.as_str() {
+            "member" => {
+                let _server = rt.block_on(start_member(&replicated_cfg(&members), id));
+                println!("ready");
+                // run until the test closes our stdin
+                for _ in stdin.lock().lines() {}
+            }
+            "writer" => {
+                let cfg = client_cfg(&[members[id].0]);
+                let w = ResolverWrite::new(cfg, DesiredAuth::Anonymous, paddr).unwrap();
+                for path in stdin.lock().lines() {
+                    let path = Path::from(path.unwrap());
+                    rt.block_on(retry_write(|| w.publish(iter::once(path.clone()))));
+                    println!("ok");
+                }
+            }
+            role => panic!("unknown role {}", role),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn replica_restart() {
+        let _ = env_logger::try_init();
+        let members = (0..3).map(|_| (free_addr(), free_addr())).collect::<Vec<_>>();
+        let addrs = members.iter().map(|(a, _)| *a).collect::<Vec<_>>();
+        let paddr: SocketAddr = "127.0.0.1:1".parse().unwrap();
+        let rt = Runtime::new().unwrap();
+        let mut procs = (0..3).map(|i| Proc::member(&members, i)).collect::<Vec<_>>();
+        let mut writer = Proc::spawn("writer", &members, 0);
+        let mut paths =
+            (0..3).map(|i| Path::from(format!("/app/v{}", i))).collect::<Vec<_>>();
+        for path in &paths {
+            writer.publish(path)
+        }
+        rt.block_on(async {
+            for a in &addrs {
+                wait_converged(*a, &paths, paddr).await
+            }
+        });
+        // v3 is committed by members 0 and 1 while member 2 is stopped
+        procs[2].signal("STOP");
+        paths.push(p("/app/v3"));
+        writer.publish(&paths[3]);
+        rt.block_on(async {
+            for a in &addrs[0..2] {
+                wait_converged(*a, &paths, paddr).await
+            }
+        });
+        drop(writer);
+        // member 1 restarts with nothing, and while member 0 is stopped
+        // member 2 campaigns with a log that is missing v3. It must not
+        // win the restarted member's vote.
+        procs[0].signal("STOP");
+        procs.remove(1);
+        procs.insert(1, Proc::member(&members, 1));
+        procs[2].signal("CONT");
+        std::thread::sleep(Duration::from_secs(5));
+        procs[0].signal("CONT");
+        rt.block_on(async {
+            for a in &addrs {
+                wait_converged(*a, &paths, paddr).await
+            }
+        });
+        drop(procs)
+    }
+
+    fn member_cfg(addr: SocketAddr) -> String {
+        format!(
+            r#"{{"pid_file": "", "addr": "{}", "max_connections": 768,
+                "hello_timeout": 10, "reader_ttl": 60, "writer_ttl": 120,
+                "auth": "Anonymous"}}"#,
+            addr
+        )
+    }
+
+    async fn run_register() {
+        let (paddr, caddr) = (free_addr(), free_addr());
+        let sock = std::env::temp_dir()
+            .join(format!("netidx-register-{}", thread_rng().gen::<u64>()));
+        let sock = sock.to_str().unwrap();
+        // the test id command maps everyone to "user"
+        let parent_cfg = ServerConfig::parse(&format!(
+            r#"{{"parent": null, "children": [],
+                 "member_servers": [{{
+                     "pid_file": "", "addr": "{}", "max_connections": 768,
+                     "hello_timeout": 10, "reader_ttl": 60, "writer_ttl": 120,
+                     "id_map_command": "../cfg/tls/id", "auth": {{"Local": "{}"}}
+                 }}],
+                 "perms": {{"/": {{"": "slr"}}, "/reg": {{"user": "r"}}}}}}"#,
+            paddr, sock
+        ))
+        .expect("parent server config");
+        let child_cfg = ServerConfig::parse(&format!(
+            r#"{{"parent": {{"path": "/reg", "ttl": 60,
+                             "addrs": [["{}", {{"Local": "{}"}}]]}},
+                 "register": {{"ttl": 2, "auth": "Local"}},
+                 "children": [], "member_servers": [{}], "perms": {{}}}}"#,
+            paddr,
+            sock,
+            member_cfg(caddr)
+        ))
+        .expect("child server config");
+        let _parent = start_member(&parent_cfg, 0).await;
+        // anonymous users may not register referrals, even if the
+        // permissions allow it, or if there are no permissions
+        let referral = |addr: SocketAddr| Referral {
+            path: p("/anon"),
+            ttl: Some(60),
+            addrs: Pooled::orphan(vec![(addr, Auth::Anonymous)]),
+        };
+        let aaddr = free_addr();
+        let anon_cfg = ServerConfig::parse(&format!(
+            r#"{{"parent": null, "children": [], "member_servers": [{}], "perms": {{}}}}"#,
+            member_cfg(aaddr)
+        ))
+        .expect("anonymous server config");
+        let _anon = start_member(&anon_cfg, 0).await;
+        let waddr: SocketAddr = "127.0.0.1:1".parse().unwrap();
+        for addr in [paddr, aaddr] {
+            let w =
+                ResolverWrite::new(client_cfg(&[addr]), DesiredAuth::Anonymous, waddr)
+                    .unwrap();
+            assert!(w.register_referral(referral(caddr)).await.is_err());
+            let r = ResolverRead::new(client_cfg(&[addr]), DesiredAuth::Anonymous);
+            assert!(!r.list(p("/")).await.unwrap().contains(&p("/anon")));
+        }
+        let child = start_member(&child_cfg, 0).await;
+        let w = ResolverWrite::new(client_cfg(&[caddr]), DesiredAuth::Anonymous, waddr)
+            .unwrap();
+        w.publish(iter::once(p("/reg/v"))).await.unwrap();
+        // the parent learns about the child without being configured with it
+        let r = ResolverRead::new(client_cfg(&[paddr]), DesiredAuth::Anonymous);
+        let mut registered = false;
+        for _ in 0..100 {
+            if r.list(p("/")).await.unwrap().contains(&p("/reg")) {
+                registered = true;
+                break;
+            }
+            time::sleep(Duration::from_millis(100)).await
+        }
+        assert!(registered);
+        let (publishers, resolved) = r.resolve(iter::once(p("/reg/v"))).await.unwrap();
+        assert_eq!(resolved.len(), 1);
+        assert_eq!(resolved[0].publishers.len(), 1);
+        assert_eq!(publishers[&resolved[0].publishers[0].id].addr, waddr);
+        // and forgets about it once it stops renewing
+        drop(child);
+        let r = ResolverRead::new(client_cfg(&[paddr]), DesiredAuth::Anonymous);
+        for _ in 0..100 {
+            if !r.list(p("/")).await.unwrap().contains(&p("/reg")) {
+                return;
+            }
+            time::sleep(Duration::from_millis(100)).await
+        }
+        panic!("the registration did not expire")
+    }
+
+    #[test]
+    fn register() {
+        let _ = env_logger::try_init();
+        Runtime::new().unwrap().block_on(run_register())
+    }
+
+    async fn run_resolve_cache() {
+        let addr = free_addr();
+        let cfg = ServerConfig::parse(&format!(
+            r#"{{"parent": null, "children": [], "member_servers": [{}], "perms": {{}}}}"#,
+            member_cfg(addr)
+        ))
+        .expect("server config");
+        let _server = start_member(&cfg, 0).await;
+        let waddr: SocketAddr = "127.0.0.1:1".parse().unwrap();
+        let w = ResolverWrite::new(client_cfg(&[addr]), DesiredAuth::Anonymous, waddr)
+            .unwrap();
+        let r = ResolverRead::new(client_cfg(&[addr]), DesiredAuth::Anonymous);
+        assert_eq!(r.cache_ttl(), None);
+        r.set_cache_ttl(Some(Duration::from_secs(3600)));
+        assert_eq!(r.cache_ttl(), Some(Duration::from_secs(240)));
+        let paths = [p("/app/v0"), p("/app/v1"), p("/app/v2")];
+        w.publish(paths[0..2].iter().cloned()).await.unwrap();
+        let published = |(publishers, resolved): (
+            Pooled<FxHashMap<PublisherId, Publisher>>,
+            Pooled<Vec<Resolved>>,
+        )| {
+            resolved
+                .iter()
+                .map(|r| {
+                    r.publishers.iter().all(|p| publishers[&p.id].addr == waddr)
+                        && !r.publishers.is_empty()
+                })
+                .collect::<Vec<_>>()
+        };
+        let res = r.resolve(paths.iter().cloned()).await.unwrap();
+        assert_eq!(published(res), vec![true, true, false]);
+        // cached results are returned even though they are stale, but
+        // missing paths are not cached
+        w.unpublish(iter::once(paths[0].clone())).await.unwrap();
+        w.publish(iter::once(paths[2].clone())).await.unwrap();
+        let res = r.resolve(paths.iter().cloned()).await.unwrap();
+        assert_eq!(published(res), vec![true, true, true]);
+        r.invalidate(&paths[0]);
+        let res = r.resolve(paths.iter().cloned()).await.unwrap();
+        assert_eq!(published(res), vec![false, true, true]);
+        // a change under a tracked path invalidates the cache
+        let mut ct = ChangeTracker::new(p("/app"));
+        assert!(r.check_changed(&mut ct).await.unwrap());
+        let res = r.resolve(paths.iter().cloned()).await.unwrap();
+        assert_eq!(published(res), vec![false, true, true]);
+        w.unpublish(iter::once(paths[1].clone())).await.unwrap();
+        let res = r.resolve(paths.iter().cloned()).await.unwrap();
+        assert_eq!(published(res), vec![false, true, true]);
+        assert!(r.check_changed(&mut ct).await.unwrap());
+        let res = r.resolve(paths.iter().cloned()).await.unwrap();
+        assert_eq!(published(res), vec![false, false, true]);
+    }
+
+    #[test]
+    fn resolve_cache() {
+        let _ = env_logger::try_init();
+        Runtime::new().unwrap().block_on(run_resolve_cache())
+    }
+
+    // forward connections to `to`, delaying everything sent to it by
+    // `delay` milliseconds
+    async fn delay_proxy(to: SocketAddr, delay: Arc<AtomicU64>) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        task::spawn(async move {
+            while let Ok((client, _)) = listener.accept().await {
+                let delay = delay.clone();
+                task::spawn(async move {
+                    let server = TcpStream::connect(to).await?;
+                    let (mut cr, mut cw) = client.into_split();
+                    let (mut sr, mut sw) = server.into_split();
+                    let up = async {
+                        let mut buf = [0u8; 4096];
+                        loop {
+                            let n = cr.read(&mut buf).await?;
+                            if n == 0 {
+                                break Ok::<_, anyhow::Error>(());
+                            }
+                            let d = delay.load(Ordering::Relaxed);
+                            time::sleep(Duration::from_millis(d)).await;
+                            sw.write_all(&buf[..n]).await?
+                        }
+                    };
+                    let down =
+                        async { Ok(io::copy(&mut sr, &mut cw).await.map(|_| ())?) };
+                    future::try_join(up, down).await.map(|_| ())
+                });
+            }
+        });
+        addr
+    }
+
+    async fn run_hedged_read() {
+        let addr = free_addr();
+        let cfg = ServerConfig::parse(&format!(
+            r#"{{"parent": null, "children": [], "member_servers": [{}], "perms": {{}}}}"#,
+            member_cfg(addr)
+        ))
+        .expect("server config");
+        let _server = start_member(&cfg, 0).await;
+        let delays = [Arc::new(AtomicU64::new(0)), Arc::new(AtomicU64::new(0))];
+        let proxies = [
+            delay_proxy(addr, delays[0].clone()).await,
+            delay_proxy(addr, delays[1].clone()).await,
+        ];
+        let waddr: SocketAddr = "127.0.0.1:1".parse().unwrap();
+        let w = ResolverWrite::new(client_cfg(&[addr]), DesiredAuth::Anonymous, waddr)
+            .unwrap();
+        w.publish(iter::once(p("/app/v0"))).await.unwrap();
+        let mut rcfg = client_cfg(&proxies);
+        rcfg.hedge_percentile = Some(50.);
+        let r = ResolverRead::new(rcfg, DesiredAuth::Anonymous);
+        for _ in 0..20 {
+            r.resolve(iter::once(p("/app/v0"))).await.unwrap();
+        }
+        // whichever server is slow, the read finishes long before the
+        // slow server answers
+        for slow in 0..2 {
+            delays[slow].store(2000, Ordering::Relaxed);
+            delays[1 - slow].store(0, Ordering::Relaxed);
+            // let the last slow request drain
+            time::sleep(Duration::from_millis(2500)).await;
+            for _ in 0..10 {
+                let f = r.resolve(iter::once(p("/app/v0")));
+                let (publishers, resolved) =
+                    time::timeout(Duration::from_secs(1), f).await.unwrap().unwrap();
+                let pref = &resolved[0].publishers[0];
+                assert_eq!(publishers[&pref.id].addr, waddr);
+            }
+        }
+    }
+
+    #[test]
+    fn hedged_read() {
+        let _ = env_logger::try_init();
+        Runtime::new().unwrap().block_on(run_hedged_read())
+    }
 }
 
 mod publisher {
@@ -378,11 +1095,12 @@ mod publisher {
             BindCfg, DesiredAuth, Event as PEvent, PublishFlags, Publisher, Val,
         },
         resolver_server::{config::Config as ServerConfig, Server},
-        subscriber::{Event, Subscriber, UpdatesFlags, Value},
+        subscriber::{Event, Subscriber, TreeSubscription, UpdatesFlags, Value},
     };
     use futures::{channel::mpsc, channel::oneshot, prelude::*, select_biased};
     use parking_lot::Mutex;
     use std::{
+        collections::BTreeMap,
         iter,
         net::{IpAddr, SocketAddr},
         sync::Arc,
@@ -586,4 +1304,148 @@ mod publisher {
             drop(server)
         })
     }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Limits {
+        max_qty: u64,
+        max_notional: f64,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Account {
+        name: String,
+        active: bool,
+        limits: Limits,
+        tags: Vec<String>,
+        balances: BTreeMap<String, i64>,
+    }
+
+    async fn wait_for(sub: &mut TreeSubscription<Account>, expected: &Account) {
+        time::timeout(Duration::from_secs(10), async {
+            while &sub.next().await.unwrap() != expected {}
+        })
+        .await
+        .unwrap()
+    }
+
+    #[test]
+    fn publish_tree() {
+        let _ = env_logger::try_init();
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let server_cfg = ServerConfig::load("../cfg/simple-server.json")
+                .expect("load simple server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            client_cfg.addrs[0].0 = *server.local_addr();
+            let publisher = Publisher::new(
+                client_cfg.clone(),
+                DesiredAuth::Anonymous,
+                "127.0.0.1/32".parse().unwrap(),
+                768,
+                3,
+            )
+            .await
+            .unwrap();
+            let subscriber = Subscriber::new(client_cfg, DesiredAuth::Anonymous).unwrap();
+            let mut a = Account {
+                name: "alice".into(),
+                active: true,
+                limits: Limits { max_qty: 100, max_notional: 1e6 },
+                tags: vec!["vip".into()],
+                balances: BTreeMap::from([("usd".into(), 42)]),
+            };
+            let mut tree = publisher.publish_tree("/tree/acct".into(), &a).unwrap();
+            let leaves = tree.leaves().map(|p| String::from(&**p)).collect::<Vec<_>>();
+            assert_eq!(
+                leaves,
+                vec![
+                    "/tree/acct/active",
+                    "/tree/acct/balances/usd",
+                    "/tree/acct/limits/max_notional",
+                    "/tree/acct/limits/max_qty",
+                    "/tree/acct/name",
+                    "/tree/acct/tags",
+                ]
+            );
+            publisher.flushed().await;
+            let mut sub = TreeSubscription::<Account>::new_with_poll_interval(
+                subscriber,
+                "/tree/acct".into(),
+                Duration::from_millis(100),
+            )
+            .await
+            .unwrap();
+            wait_for(&mut sub, &a).await;
+            a.limits.max_qty = 10;
+            a.tags.push("new".into());
+            let mut batch = publisher.start_batch();
+            tree.update(&mut batch, &a).unwrap();
+            batch.commit(None).await;
+            wait_for(&mut sub, &a).await;
+            // change the structure of the tree
+            a.balances = BTreeMap::from([("eur".into(), 7), ("jpy".into(), -3)]);
+            let mut batch = publisher.start_batch();
+            tree.update(&mut batch, &a).unwrap();
+            batch.commit(None).await;
+            assert!(publisher.id("/tree/acct/balances/usd").is_none());
+            publisher.flushed().await;
+            wait_for(&mut sub, &a).await;
+            drop(server)
+        })
+    }
+}
+
+mod blocking {
+    use crate::{
+        blocking::{Publisher, ResolverRead, Subscriber},
+        config::Config as ClientConfig,
+        path::Path,
+        resolver_client::DesiredAuth,
+        resolver_server::{config::Config as ServerConfig, Server},
+        subscriber::{Event, Value},
+    };
+    use std::time::Duration;
+    use tokio::runtime::Runtime;
+
+    #[test]
+    fn publish_subscribe() {
+        let _ = env_logger::try_init();
+        let timeout = Some(Duration::from_secs(10));
+        let rt = Runtime::new().unwrap();
+        let (server, cfg) = rt.block_on(async {
+            let server_cfg = ServerConfig::load("../cfg/simple-server.json")
+                .expect("load simple server config");
+            let mut client_cfg = ClientConfig::load("../cfg/simple-client.json")
+                .expect("load simple client config");
+            let server = Server::new(server_cfg, false, 0).await.expect("start server");
+            client_cfg.addrs[0].0 = *server.local_addr();
+            (server, client_cfg)
+        });
+        let bind = Some("127.0.0.1/32".parse().unwrap());
+        let publisher =
+            Publisher::new(cfg.clone(), DesiredAuth::Anonymous, bind).unwrap();
+        let val = publisher.publish(Path::from("/blocking/v"), 0u64).unwrap();
+        let mut writes = publisher.writes(val.id());
+        publisher.flushed();
+        let resolver = ResolverRead::new(cfg.clone(), DesiredAuth::Anonymous);
+        let paths = resolver.list(Path::from("/blocking")).unwrap();
+        assert_eq!(&*paths, &[Path::from("/blocking/v")]);
+        let subscriber = Subscriber::new(cfg, DesiredAuth::Anonymous).unwrap();
+        let mut dv = subscriber.subscribe_one(Path::from("/blocking/v"), timeout).unwrap();
+        assert_eq!(dv.last(), Event::Update(Value::U64(0)));
+        assert_eq!(dv.recv_update().unwrap(), Event::Update(Value::U64(0)));
+        for i in 1..10u64 {
+            publisher.update(&val, i);
+            let ev = dv.recv_update_timeout(timeout).unwrap();
+            assert_eq!(ev, Some(Event::Update(Value::U64(i))));
+        }
+        assert!(dv.write(Value::from("hello")));
+        let req = writes.recv_timeout(timeout).unwrap().unwrap();
+        assert_eq!(req.value, Value::from("hello"));
+        let ev = dv.recv_update_timeout(Some(Duration::from_millis(100))).unwrap();
+        assert_eq!(ev, None);
+        drop(server)
+    }
 }